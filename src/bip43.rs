@@ -289,7 +289,7 @@ impl DerivationStandard for Bip43 {
     fn deduce(derivation: &DerivationPath) -> Option<Bip43> {
         let mut iter = derivation.into_iter();
         let first = iter.next().map(HardenedIndex::try_from).transpose().ok()??;
-        let fourth = iter.nth(3).map(HardenedIndex::try_from);
+        let fourth = iter.nth(2).map(HardenedIndex::try_from);
         Some(match (first.child_number(), fourth) {
             (44, ..) => Bip43::Bip44,
             (84, ..) => Bip43::Bip84,
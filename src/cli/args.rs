@@ -24,7 +24,7 @@ use std::fmt::Debug;
 use std::path::PathBuf;
 use std::process::exit;
 
-use bpstd::XpubDerivable;
+use bpstd::{Network, XpubDerivable};
 use clap::Subcommand;
 use descriptors::Descriptor;
 use strict_encoding::Ident;
@@ -32,7 +32,7 @@ use strict_encoding::Ident;
 use crate::cli::{
     Config, DescrStdOpts, DescriptorOpts, ExecError, GeneralOpts, ResolverOpt, WalletOpts,
 };
-use crate::indexers::esplora;
+use crate::indexers::{bitcoind, cbf, esplora};
 use crate::{AnyIndexer, MayError, Wallet};
 
 /// Command-line arguments
@@ -95,22 +95,57 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
     }
 
     pub fn indexer(&self) -> Result<AnyIndexer, ExecError> {
-        let network = self.general.network.to_string();
-        Ok(match (&self.resolver.esplora, &self.resolver.electrum, &self.resolver.mempool) {
-            (None, Some(url), None) => AnyIndexer::Electrum(Box::new(electrum::Client::new(url)?)),
-            (Some(url), None, None) => AnyIndexer::Esplora(Box::new(esplora::Client::new_esplora(
-                &url.replace("{network}", &network),
-            )?)),
-            (None, None, Some(url)) => AnyIndexer::Mempool(Box::new(esplora::Client::new_mempool(
-                &url.replace("{network}", &network),
-            )?)),
-            _ => {
-                eprintln!(
-                    "Error: no blockchain indexer specified; use either --esplora --mempool or \
-                     --electrum argument"
-                );
-                exit(1);
-            }
+        let network = self.general.network;
+        let resolver = &self.resolver;
+        Ok(if let Some(url) = &resolver.electrum {
+            AnyIndexer::Electrum(Box::new(electrum::Client::new(url)?))
+        } else if let Some(url) = &resolver.esplora {
+            AnyIndexer::Esplora(Box::new(esplora::Client::new_esplora(
+                &url.replace("{network}", &network.to_string()),
+            )?))
+        } else if let Some(url) = &resolver.mempool {
+            AnyIndexer::Mempool(Box::new(esplora::Client::new_mempool(
+                &url.replace("{network}", &network.to_string()),
+            )?))
+        } else if let Some(url) = &resolver.bitcoind {
+            let port = match network {
+                Network::Mainnet => 8332,
+                Network::Testnet3 => 18332,
+                Network::Testnet4 => 48332,
+                Network::Signet => 38332,
+                Network::Regtest => 18443,
+            };
+            AnyIndexer::Bitcoind(Box::new(bitcoind::Client::with_birth_height(
+                &url.replace("{port}", &port.to_string()),
+                &resolver.bitcoind_wallet,
+                resolver.birth_height,
+            )?))
+        } else if let Some(addr) = &resolver.cbf {
+            let port = match network {
+                Network::Mainnet => 8333,
+                Network::Testnet3 => 18333,
+                Network::Testnet4 => 48333,
+                Network::Signet => 38333,
+                Network::Regtest => 18444,
+            };
+            let peer = cbf::Peer::connect(addr.replace("{port}", &port.to_string()), network)
+                .map_err(cbf::CbfError::Transport)?;
+            let client = cbf::Client::with_birth_height(peer, resolver.birth_height).with_progress(
+                |height, tip| {
+                    if height < tip {
+                        eprint!(".");
+                    } else {
+                        eprint!(" {tip} blocks scanned");
+                    }
+                },
+            );
+            AnyIndexer::Cbf(Box::new(client))
+        } else {
+            eprintln!(
+                "Error: no blockchain indexer specified; use either --esplora, --mempool, \
+                 --electrum, --bitcoind or --cbf argument"
+            );
+            exit(1);
         })
     }
 
@@ -160,16 +195,20 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
         if sync {
             let indexer = self.indexer()?;
             eprint!("Syncing");
-            if let MayError {
-                err: Some(errors), ..
-            } = wallet.update(&indexer)
-            {
+            let MayError { ok: info, err } = wallet.update(&indexer);
+            if let Some(errors) = err {
                 eprintln!(" partial, some requests has failed:");
                 for err in errors {
                     eprintln!("- {err}");
                 }
             } else {
-                eprintln!(" success");
+                eprintln!(" success, {} transactions updated", info.changed);
+            }
+            if !info.reorged.is_empty() {
+                eprintln!("Blockchain reorganization has affected the following transactions:");
+                for txid in info.reorged {
+                    eprintln!("- {txid}");
+                }
             }
         }
 
@@ -20,25 +20,37 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
 use std::{error, fs, io};
 
 use amplify::IoError;
-use bpstd::psbt::{Beneficiary, TxParams};
-use bpstd::{ConsensusEncode, Derive, IdxBase, Keychain, NormalIndex, Sats, Tx, XpubDerivable};
+use bpstd::psbt::Beneficiary;
+use bpstd::{
+    Address, ConsensusEncode, Derive, IdxBase, Keychain, NormalIndex, Outpoint, Sats, Tx, Txid,
+    XpubDerivable,
+};
 use colored::Colorize;
-use descriptors::{Descriptor, StdDescr};
+use descriptors::Descriptor;
 use psbt::{ConstructionError, Payment, Psbt, PsbtConstructor, PsbtVer, UnfinalizedInputs};
 use strict_encoding::Ident;
 
-use crate::cli::{Args, Config, DescriptorOpts, Exec};
+use crate::cli::{parse_xpub, Args, CoinControlOpts, Config, DescriptorOpts, Exec, TxOpts};
+use crate::coinselect::{Candidate, SelectionError, Strategy};
 use crate::wallet::fs::{LoadError, StoreError};
 use crate::wallet::Save;
 use crate::{
-    coinselect, AnyIndexerError, FsConfig, Indexer, OpType, Wallet, WalletAddr, WalletUtxo,
+    convert_key_expr, format_timestamp, parse_descriptor, vsize, AnyDescr, AnyIndexer,
+    AnyIndexerError, Bip329Record, Bip43, BumpError, Counterparty, CpfpError, DescrParseError,
+    FeeRate, FsConfig, HistoryFormat, Indexer, KeyVersion, LabelImportError, LabelRef,
+    LabelRefError, LabelType, MayError, NamedKey, NonWalletItem, OpType, PaymentError, Policy,
+    PolicyError, PolicyTarget, PriceTable, PriceTableError, PsbtDescriptor, SingleKey,
+    Slip132Error, Timelock, TxOptionsError, Wallet, WalletAddr, WalletFormat, WalletFormatError,
+    WalletPolicy, WifKey,
 };
 
 #[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
@@ -59,6 +71,54 @@ pub enum Command {
     Create {
         /// The name for the new wallet
         name: Ident,
+
+        /// Spending policy in miniscript policy language, like
+        /// `or(pk(A),and(pk(B),older(52560)))`, to compile into the wallet descriptor
+        /// instead of providing the descriptor explicitly
+        #[clap(long)]
+        policy: Option<Policy>,
+
+        /// Named key used by the policy, in `NAME=[FP/PATH]XPUB/<0;1>/*` format
+        #[clap(long = "key", requires = "policy")]
+        keys: Vec<NamedKey>,
+
+        /// Type of the descriptor to compile the policy into
+        #[clap(long, default_value_t = PolicyTarget::Tr)]
+        policy_target: PolicyTarget,
+    },
+
+    /// Create a named watch-only wallet from a file exported by other wallet software
+    #[display("import")]
+    Import {
+        /// Format of the wallet file
+        #[clap(short, long)]
+        format: WalletFormat,
+
+        /// Derivation standard of the descriptor to import if the file contains descriptors for
+        /// multiple script types, like `bip84` for P2WPKH or `bip86` for P2TR
+        #[clap(long)]
+        standard: Option<Bip43>,
+
+        /// BIP-329 JSON Lines file with labels to import into the wallet
+        #[clap(long)]
+        labels: Option<PathBuf>,
+
+        /// Wallet file to import
+        file: PathBuf,
+
+        /// The name for the new wallet. If not given, the name from the wallet file is used
+        name: Option<Ident>,
+    },
+
+    /// Export wallet in a format used by other wallet software
+    #[display("export")]
+    Export {
+        /// Format of the wallet file
+        #[clap(short, long)]
+        format: WalletFormat,
+
+        /// File to save the wallet. If not given, prints the wallet to STDOUT
+        file: Option<PathBuf>,
     },
 
     /// Generate a new wallet address(es)
@@ -131,6 +191,60 @@ pub enum BpCommand {
         /// Print information about individual UTXOs
         #[clap(short, long)]
         utxo: bool,
+
+        /// Print only addresses and UTXOs having label containing the given text
+        #[clap(long)]
+        label: Option<String>,
+    },
+
+    /// Exclude wallet outputs from coin selection
+    #[display("freeze")]
+    Freeze {
+        /// Address, all outputs on which must be frozen
+        #[clap(long)]
+        addr: Vec<Address>,
+
+        /// Transaction outputs to freeze
+        outpoints: Vec<Outpoint>,
+    },
+
+    /// Return previously frozen wallet outputs to coin selection
+    #[display("unfreeze")]
+    Unfreeze {
+        /// Address to unfreeze
+        #[clap(long)]
+        addr: Vec<Address>,
+
+        /// Transaction outputs to unfreeze
+        outpoints: Vec<Outpoint>,
+    },
+
+    /// Manage labels of wallet transactions, inputs, outputs and addresses
+    #[display("label")]
+    Label {
+        #[clap(subcommand)]
+        command: LabelCommand,
+    },
+
+    /// Get or set the gap limit used for the discovery of wallet addresses
+    #[display("gap-limit")]
+    GapLimit {
+        /// Keychain to get or set the gap limit for. If not given, all wallet keychains are used
+        #[clap(short, long)]
+        keychain: Option<Keychain>,
+
+        /// Number of consecutive unused addresses after which the address discovery on a
+        /// keychain stops. If not given, prints the current gap limits
+        #[clap(value_parser = clap::value_parser!(u32).range(1..))]
+        limit: Option<u32>,
+    },
+
+    /// Discard the wallet cache and re-discover wallet addresses and transactions from scratch
+    #[display("rescan")]
+    Rescan {
+        /// Gap limit to use for this rescan instead of the wallet gap limits, if they are lower
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        gap: Option<u32>,
     },
 
     /// Display history of wallet operations
@@ -143,17 +257,48 @@ pub enum BpCommand {
         /// Print operation details
         #[clap(long)]
         details: bool,
+
+        /// Print only transactions having label containing the given text, either assigned to
+        /// the transaction itself or to any of the addresses it involves
+        #[clap(long, conflicts_with = "format")]
+        label: Option<String>,
+
+        /// Export history in the given format instead of printing a table
+        #[clap(long)]
+        format: Option<HistoryFormat>,
+
+        /// CSV file with historical bitcoin prices used to add fiat value of the transactions to
+        /// the exported history. Each line must contain a date (`YYYY-MM-DD` or a UNIX timestamp)
+        /// and a price, separated by a comma
+        #[clap(long, requires = "format")]
+        prices: Option<PathBuf>,
     },
 
     /// Inspect transaction
     Tx { tx: Tx },
 
+    /// Display fee rates required for confirmation within different number of blocks
+    #[display("fees")]
+    Fees,
+
     /// Inspect PSBT file
     Inspect {
         /// Name of a PSBT file to inspect
         psbt: PathBuf,
     },
 
+    /// Convert extended public key between the standard and SLIP-132 (like `zpub`) forms
+    #[display("convert-key")]
+    ConvertKey {
+        /// Key version to convert the key into. If not given, the key is converted into the
+        /// standard `xpub` or `tpub` form
+        #[clap(long)]
+        to: Option<KeyVersion>,
+
+        /// Extended public key, optionally with its origin and derivation terminal
+        key: String,
+    },
+
     /// Compose a new PSBT for bitcoin payment
     #[display("construct")]
     Construct {
@@ -169,14 +314,203 @@ pub enum BpCommand {
         #[clap(long)]
         to: Vec<Beneficiary>,
 
-        /// Fee
-        fee: Sats,
+        /// Coin selection strategy
+        #[clap(long, default_value_t = Strategy::Auto)]
+        coinselect: Strategy,
+
+        /// Absolute fee, in satoshis
+        #[clap(
+            long,
+            required_unless_present_any = ["fee_rate", "target"],
+            conflicts_with_all = ["fee_rate", "target"]
+        )]
+        fee: Option<Sats>,
+
+        /// Fee rate, in satoshis per virtual byte. The fee is computed from the estimated size
+        /// of the signed transaction
+        #[clap(long, conflicts_with = "target")]
+        fee_rate: Option<FeeRate>,
+
+        /// Confirmation target, in blocks. The fee rate is taken from the estimations provided
+        /// by the indexer
+        #[clap(long)]
+        target: Option<u16>,
+
+        #[clap(flatten)]
+        coins: CoinControlOpts,
+
+        #[clap(flatten)]
+        tx: TxOpts,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose PSBT sweeping all funds controlled by an external descriptor or key into the
+    /// wallet
+    #[display("sweep")]
+    #[clap(group(clap::ArgGroup::new("source").required(true)))]
+    Sweep {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Sweep funds from an output descriptor, like `wsh(sortedmulti(2,KEY1,KEY2,KEY3))` or
+        /// `pkh(PUBKEY)`
+        #[clap(long, group = "source", value_parser = |s: &str| parse_descriptor(s).map(Box::new))]
+        from: Option<Box<AnyDescr>>,
+
+        /// Sweep funds from a single public key in hex, like the one of a paper wallet. All
+        /// script types the key may be used with are scanned
+        #[clap(long, group = "source")]
+        from_key: Option<SingleKey>,
+
+        /// Sweep funds from a single private key in wallet import format (WIF). Only the public
+        /// key is used, and all script types it may be used with are scanned. To sign the PSBT,
+        /// import the key with `bp-hot import`
+        #[clap(long, group = "source")]
+        from_wif: Option<WifKey>,
+
+        /// Sweep funds from wpkh(FROM_WPKH) descriptor
+        #[clap(
+            long,
+            group = "source",
+            value_parser = |s: &str| parse_xpub(s, Bip43::Bip84).map(Box::new)
+        )]
+        from_wpkh: Option<Box<XpubDerivable>>,
+
+        /// Sweep funds from tr(FROM_TR_KEY_ONLY) descriptor
+        #[clap(
+            long,
+            group = "source",
+            value_parser = |s: &str| parse_xpub(s, Bip43::Bip86).map(Box::new)
+        )]
+        from_tr_key_only: Option<Box<XpubDerivable>>,
+
+        /// Fee rate, in satoshis per virtual byte
+        #[clap(long, required_unless_present = "target", conflicts_with = "target")]
+        fee_rate: Option<FeeRate>,
+
+        /// Confirmation target, in blocks. The fee rate is taken from the estimations provided
+        /// by the indexer
+        #[clap(long)]
+        target: Option<u16>,
+
+        #[clap(flatten)]
+        tx: TxOpts,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose PSBT replacing an unconfirmed wallet transaction with the one paying higher fee
+    /// (RBF)
+    #[display("bump")]
+    Bump {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Coin selection strategy used if additional inputs are required to pay the fee
+        #[clap(long, default_value_t = Strategy::Auto)]
+        coinselect: Strategy,
+
+        /// New fee rate, in satoshis per virtual byte
+        #[clap(long, required_unless_present = "target", conflicts_with = "target")]
+        fee_rate: Option<FeeRate>,
+
+        /// Confirmation target, in blocks. The fee rate is taken from the estimations provided
+        /// by the indexer
+        #[clap(long)]
+        target: Option<u16>,
+
+        /// Id of the transaction to replace
+        txid: Txid,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose PSBT spending an unconfirmed wallet output with a fee accelerating confirmation
+    /// of its parent transaction (CPFP)
+    #[display("cpfp")]
+    Cpfp {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Coin selection strategy used if additional inputs are required to pay the fee
+        #[clap(long, default_value_t = Strategy::Auto)]
+        coinselect: Strategy,
+
+        /// Fee rate for the package of the parent and child transactions, in satoshis per
+        /// virtual byte
+        #[clap(long, required_unless_present = "target", conflicts_with = "target")]
+        fee_rate: Option<FeeRate>,
+
+        /// Confirmation target, in blocks. The fee rate is taken from the estimations provided
+        /// by the indexer
+        #[clap(long)]
+        target: Option<u16>,
+
+        /// Unconfirmed wallet output to spend
+        outpoint: Outpoint,
 
         /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
         psbt: Option<PathBuf>,
     },
 }
 
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum LabelCommand {
+    /// Assign label to a wallet item, replacing the existing one
+    #[display("set")]
+    Set {
+        /// Type of the labelled item
+        #[clap(value_name = "TYPE")]
+        ty: LabelType,
+
+        /// Transaction id, address or input/output in `txid:no` format
+        reference: String,
+
+        /// Label text
+        label: String,
+    },
+
+    /// Remove label from a wallet item
+    #[display("remove")]
+    Remove {
+        /// Type of the labelled item
+        #[clap(value_name = "TYPE")]
+        ty: LabelType,
+
+        /// Transaction id, address or input/output in `txid:no` format
+        reference: String,
+    },
+
+    /// List wallet labels
+    #[display("list")]
+    List {
+        /// List only labels of the given type
+        #[clap(long = "type", value_name = "TYPE")]
+        ty: Option<LabelType>,
+    },
+
+    /// Import labels from a BIP-329 JSON Lines file
+    #[display("import")]
+    Import {
+        /// File to read the labels from
+        file: PathBuf,
+    },
+
+    /// Export labels to a BIP-329 JSON Lines file
+    #[display("export")]
+    Export {
+        /// File to save the labels. If not given, prints labels to STDOUT
+        file: Option<PathBuf>,
+    },
+}
+
 #[derive(Debug, Display, Error, From)]
 #[non_exhaustive]
 #[display(inner)]
@@ -200,13 +534,88 @@ pub enum ExecError<L2: error::Error = Infallible> {
     #[from]
     Unfinalized(UnfinalizedInputs),
 
+    #[from]
+    CoinSelection(SelectionError),
+
+    #[from]
+    Payment(PaymentError),
+
+    #[from]
+    Bump(BumpError),
+
+    #[from]
+    Cpfp(CpfpError),
+
+    #[from]
+    TxOptions(TxOptionsError),
+
+    #[from]
+    NonWallet(NonWalletItem),
+
+    #[from]
+    PriceTable(PriceTableError),
+
+    #[from]
+    Policy(PolicyError),
+
+    #[from]
+    #[from(DescrParseError)]
+    WalletFormat(WalletFormatError),
+
+    #[from]
+    Slip132(Slip132Error),
+
+    #[from]
+    LabelRef(LabelRefError),
+
+    #[from]
+    LabelImport(LabelImportError),
+
     /// indexer failed with {0}
     #[from]
     #[cfg_attr(feature = "electrum", from(electrum::Error))]
     #[cfg_attr(feature = "electrum", from(crate::indexers::electrum::ElectrumError))]
     #[cfg_attr(feature = "esplora", from(esplora::Error))]
+    #[cfg_attr(feature = "bitcoind", from(crate::indexers::bitcoind::BitcoindError))]
+    #[cfg_attr(
+        feature = "cbf",
+        from(crate::indexers::cbf::CbfError<crate::indexers::cbf::P2pError>)
+    )]
     #[display(doc_comments)]
     Indexer(AnyIndexerError),
+
+    /// indexer has provided no fee rate estimation for confirmation within {0} blocks.
+    #[display(doc_comments)]
+    NoFeeEstimation(u16),
+
+    /// the key controls funds under several descriptors ({0}); sweep them one by one with the
+    /// `--from` argument.
+    #[display(doc_comments)]
+    SweepAmbiguous(String),
+}
+
+impl<O: DescriptorOpts> Args<Command, O> {
+    fn save_new_wallet<D>(
+        &self,
+        mut wallet: Wallet<XpubDerivable, D>,
+        name: &Ident,
+    ) -> Result<(), ExecError>
+    where
+        for<'de> D: Descriptor + serde::Serialize + serde::Deserialize<'de>,
+    {
+        let name = name.to_string();
+        wallet.set_fs_config(FsConfig {
+            path: self.general.wallet_dir(&name),
+            autosave: true,
+        })?;
+        wallet.set_name(name);
+        if let Err(err) = wallet.save() {
+            println!("error: {err}");
+        } else {
+            println!("success");
+        }
+        Ok(())
+    }
 }
 
 impl<O: DescriptorOpts> Exec for Args<Command, O> {
@@ -242,7 +651,7 @@ impl<O: DescriptorOpts> Exec for Args<Command, O> {
                         if config.default_wallet == name { "\t[default]" } else { "\t\t" }
                     );
                     let Ok((wallet, _warnings)) =
-                        Wallet::<XpubDerivable, StdDescr>::load(&entry.path(), true)
+                        Wallet::<XpubDerivable, AnyDescr>::load(&entry.path(), true)
                     else {
                         println!("# broken wallet descriptor");
                         continue;
@@ -262,24 +671,102 @@ impl<O: DescriptorOpts> Exec for Args<Command, O> {
                     println!("Default wallet is '{}'", config.default_wallet);
                 }
             }
-            Command::Create { name } => {
+            Command::Create {
+                name,
+                policy: Some(policy),
+                keys,
+                policy_target,
+            } => {
+                if self.wallet.descriptor_opts.is_some() {
+                    eprintln!("Error: wallet descriptor can't be provided together with a policy");
+                    exit(1);
+                }
+                let keys = keys
+                    .iter()
+                    .map(|named| (named.name.clone(), named.key.clone()))
+                    .collect::<BTreeMap<_, _>>();
+                let descr = policy.compile(&keys, *policy_target)?;
+                eprintln!("Policy {policy} compiled into {descr}");
+                let timelocks = descr.timelocks();
+                if !timelocks.is_empty() {
+                    let timelocks =
+                        timelocks.iter().map(Timelock::to_string).collect::<Vec<_>>().join(", ");
+                    eprintln!("Timelocked branches, spent with `--timelock` option: {timelocks}");
+                }
+                let mut wallet =
+                    Wallet::<XpubDerivable, AnyDescr>::new_layer1(descr, self.general.network);
+                wallet.set_policy(WalletPolicy {
+                    policy: policy.to_string(),
+                    target: *policy_target,
+                    keys,
+                });
+                print!("Saving the wallet as '{name}' ... ");
+                self.save_new_wallet(wallet, name)?;
+            }
+            Command::Import {
+                format,
+                standard,
+                labels,
+                file,
+                name,
+            } => {
+                if self.wallet.descriptor_opts.is_some() {
+                    eprintln!("Error: wallet descriptor can't be provided when importing a wallet");
+                    exit(1);
+                }
+                let import = format.import(&fs::read_to_string(file)?, *standard)?;
+                if import.network.is_testnet() != self.general.network.is_testnet() {
+                    eprintln!(
+                        "Error: the imported wallet is intended for {} network, while {} network \
+                         is used",
+                        import.network, self.general.network
+                    );
+                    exit(1);
+                }
+                let Some(name) = name
+                    .clone()
+                    .or_else(|| import.name.as_deref().and_then(|name| Ident::from_str(name).ok()))
+                else {
+                    eprintln!(
+                        "Error: the wallet file doesn't provide a name which can be used for the \
+                         wallet, please specify the name explicitly"
+                    );
+                    exit(1);
+                };
+                eprintln!("Importing {format} wallet with descriptor {}", import.descriptor);
+                let mut wallet = Wallet::<XpubDerivable, AnyDescr>::new_layer1(
+                    import.descriptor,
+                    self.general.network,
+                );
+                if let Some(path) = labels {
+                    let (records, _) =
+                        Bip329Record::read_jsonl(io::BufReader::new(File::open(path)?))?;
+                    let stats = wallet.import_labels(records);
+                    eprintln!("Imported {} labels", stats.imported);
+                }
+                print!("Saving the wallet as '{name}' ... ");
+                self.save_new_wallet(wallet, &name)?;
+            }
+            Command::Export { format, file } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let descr = parse_descriptor(&wallet.descriptor().to_string())?;
+                let data = format.export(wallet.name(), &descr, self.general.network)?;
+                match file {
+                    Some(file) => {
+                        fs::write(file, data)?;
+                        eprintln!("Wallet exported to {}", file.display());
+                    }
+                    None => print!("{data}"),
+                }
+            }
+            Command::Create { name, .. } => {
                 if !self.wallet.descriptor_opts.is_some() {
                     eprintln!("Error: you must provide an argument specifying wallet descriptor");
                     exit(1);
                 }
                 print!("Saving the wallet as '{name}' ... ");
-                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
-                let name = name.to_string();
-                wallet.set_fs_config(FsConfig {
-                    path: self.general.wallet_dir(&name),
-                    autosave: true,
-                })?;
-                wallet.set_name(name);
-                if let Err(err) = wallet.save() {
-                    println!("error: {err}");
-                } else {
-                    println!("success");
-                }
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                self.save_new_wallet(wallet, name)?;
             }
             Command::Address {
                 change,
@@ -369,6 +856,7 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
             BpCommand::Balance {
                 addr: false,
                 utxo: false,
+                ..
             } => {
                 let runtime = self.bp_wallet::<O::Descr>(&config)?;
                 println!("\nWallet total balance: {} ṩ", runtime.balance());
@@ -376,6 +864,7 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
             BpCommand::Balance {
                 addr: true,
                 utxo: false,
+                label,
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
                 println!("\nTerm.\t{:62}\t# used\tVol., ṩ\tBalance, ṩ", "Address");
@@ -387,11 +876,20 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                         volume,
                         balance,
                     } = info;
-                    println!("{terminal}\t{:62}\t{used}\t{volume}\t{balance}", addr.to_string());
+                    if !label.as_deref().map_or(true, |text| wallet.is_labelled_with([addr], text))
+                    {
+                        continue;
+                    }
+                    println!(
+                        "{terminal}\t{:62}\t{used}\t{volume}\t{balance}{}",
+                        addr.to_string(),
+                        label_suffix(wallet.label(addr))
+                    );
                 }
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    label: None,
                 };
                 self.sync = false;
                 self.exec(config, name)?;
@@ -399,19 +897,32 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
             BpCommand::Balance {
                 addr: false,
                 utxo: true,
+                label,
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
                 println!("Balance of {}", wallet.descriptor());
                 println!("\nHeight\t{:>12}\t{:68}\tAddress", "Amount, ṩ", "Outpoint");
                 for row in wallet.coins() {
+                    if !label
+                        .as_deref()
+                        .map_or(true, |text| wallet.is_coin_labelled_with(&row, text))
+                    {
+                        continue;
+                    }
                     println!(
-                        "{}\t{: >12}\t{:68}\t{}",
-                        row.height, row.amount, row.outpoint, row.address
+                        "{}\t{: >12}\t{:68}\t{}{}{}",
+                        row.height,
+                        row.amount,
+                        row.outpoint,
+                        row.address,
+                        if row.frozen { "\tfrozen" } else { "" },
+                        label_suffix(wallet.label(row.outpoint))
                     );
                 }
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    label: None,
                 };
                 self.sync = false;
                 self.exec(config, name)?;
@@ -419,66 +930,248 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
             BpCommand::Balance {
                 addr: true,
                 utxo: true,
+                label,
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
                 println!("Balance of {}", wallet.descriptor());
                 println!("\nHeight\t{:>12}\t{:68}", "Amount, ṩ", "Outpoint");
-                for (derived_addr, utxos) in wallet.address_coins() {
-                    println!("{}\t{}", derived_addr.addr, derived_addr.terminal);
+                for (derived_addr, mut utxos) in wallet.address_coins() {
+                    if let Some(text) = label {
+                        utxos.retain(|row| wallet.is_coin_labelled_with(row, text));
+                        if utxos.is_empty() {
+                            continue;
+                        }
+                    }
+                    println!(
+                        "{}\t{}{}",
+                        derived_addr.addr,
+                        derived_addr.terminal,
+                        label_suffix(wallet.label(derived_addr.addr))
+                    );
                     for row in utxos {
-                        println!("{}\t{: >12}\t{:68}", row.height, row.amount, row.outpoint);
+                        println!(
+                            "{}\t{: >12}\t{:68}{}{}",
+                            row.height,
+                            row.amount,
+                            row.outpoint,
+                            if row.frozen { "\tfrozen" } else { "" },
+                            label_suffix(wallet.label(row.outpoint))
+                        );
                     }
                     println!()
                 }
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    label: None,
                 };
                 self.sync = false;
                 self.exec(config, name)?;
             }
-            BpCommand::History { txid, details } => {
+            BpCommand::Freeze { addr, outpoints } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                for outpoint in outpoints {
+                    if !wallet.freeze(*outpoint)? {
+                        eprintln!("Output {outpoint} is already frozen");
+                    }
+                }
+                for addr in addr {
+                    if !wallet.freeze_addr(*addr) {
+                        eprintln!("Address {addr} is already frozen");
+                    }
+                }
+            }
+            BpCommand::Unfreeze { addr, outpoints } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                for outpoint in outpoints {
+                    if !wallet.unfreeze(*outpoint) {
+                        eprintln!("Output {outpoint} is not frozen");
+                    }
+                }
+                for addr in addr {
+                    if !wallet.unfreeze_addr(addr) {
+                        eprintln!("Address {addr} is not frozen");
+                    }
+                }
+            }
+            BpCommand::Label { command } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                match command {
+                    LabelCommand::Set {
+                        ty,
+                        reference,
+                        label,
+                    } => {
+                        let reference = LabelRef::with(*ty, reference)?;
+                        if let Some(old) = wallet.set_label(reference, label) {
+                            eprintln!("Replaced previous label '{old}'");
+                        }
+                    }
+                    LabelCommand::Remove { ty, reference } => {
+                        let reference = LabelRef::with(*ty, reference)?;
+                        if wallet.remove_label(&reference).is_none() {
+                            eprintln!("No label is assigned to {ty} {reference}");
+                        }
+                    }
+                    LabelCommand::List { ty } => {
+                        println!("\n{:<8}\t{:<68}\tLabel", "Type", "Reference");
+                        for (reference, label) in wallet.labels().filter(|(reference, _)| {
+                            ty.map_or(true, |ty| reference.label_type() == ty)
+                        }) {
+                            println!("{:<8}\t{reference:<68}\t{label}", reference.label_type());
+                        }
+                    }
+                    LabelCommand::Import { file } => {
+                        let (records, unknown) =
+                            Bip329Record::read_jsonl(io::BufReader::new(File::open(file)?))?;
+                        let stats = wallet.import_labels(records);
+                        eprintln!("Imported {} labels", stats.imported);
+                        if stats.skipped > 0 {
+                            eprintln!(
+                                "Skipped {} records referencing public keys, extended keys or \
+                                 having invalid references",
+                                stats.skipped
+                            );
+                        }
+                        if unknown > 0 {
+                            eprintln!("Skipped {unknown} records of unknown types");
+                        }
+                    }
+                    LabelCommand::Export { file } => {
+                        let records = wallet.export_labels();
+                        match file {
+                            Some(file) => {
+                                Bip329Record::write_jsonl(&records, File::create(file)?)?;
+                                eprintln!(
+                                    "Exported {} labels to {}",
+                                    records.len(),
+                                    file.display()
+                                );
+                            }
+                            None => Bip329Record::write_jsonl(&records, io::stdout().lock())?,
+                        }
+                    }
+                }
+            }
+            BpCommand::GapLimit { keychain, limit } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let keychains = match keychain {
+                    Some(keychain) if !wallet.keychains().contains(keychain) => {
+                        eprintln!(
+                            "Error: the specified keychain {keychain} is not a part of the \
+                             descriptor"
+                        );
+                        exit(1);
+                    }
+                    Some(keychain) => bset![*keychain],
+                    None => wallet.keychains(),
+                };
+                match limit {
+                    Some(limit) => wallet.descriptor_mut(|descr| {
+                        for keychain in keychains {
+                            descr.set_gap_limit(keychain, *limit);
+                        }
+                    }),
+                    None => {
+                        println!("\nKeychain\tGap limit");
+                        for keychain in keychains {
+                            println!("{keychain:>8}\t{}", wallet.gap_limit(keychain));
+                        }
+                    }
+                }
+            }
+            BpCommand::Rescan { gap } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = self.indexer()?;
+                eprint!("Rescanning");
+                let MayError { err, .. } = wallet.rescan(&indexer, *gap);
+                if let Some(errors) = err {
+                    eprintln!(" failed, the wallet cache is left unchanged:");
+                    for err in errors {
+                        eprintln!("- {err}");
+                    }
+                } else {
+                    eprintln!(" success");
+                }
+                println!(
+                    "\nFound {} wallet transactions; wallet total balance: {} ṩ",
+                    wallet.transactions().len(),
+                    wallet.balance()
+                );
+            }
+            BpCommand::History {
+                txid,
+                details,
+                label,
+                format,
+                prices,
+            } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if let Some(format) = format {
+                    let prices = match prices {
+                        Some(path) => Some(PriceTable::from_str(&fs::read_to_string(path)?)?),
+                        None => None,
+                    };
+                    let records = wallet.history_records(prices.as_ref());
+                    format.write(&records, io::stdout().lock())?;
+                    return Ok(());
+                }
                 println!("History of {}", wallet.descriptor());
                 println!(
-                    "\nHeight\t{:<1$}\t    Amount, ṩ\tFee rate, ṩ/vbyte",
+                    "\nHeight\t{:<19}\t{:<2$}\t    Amount, ṩ\t   Balance, ṩ\tFee rate, ṩ/vbyte",
+                    "Time (UTC)",
                     "Txid",
                     if *txid { 64 } else { 18 }
                 );
-                let mut rows = wallet.history().collect::<Vec<_>>();
-                rows.sort_by_key(|row| row.height);
-                for row in rows {
+                for row in wallet.history() {
+                    if !label.as_deref().map_or(true, |text| wallet.is_tx_labelled_with(&row, text))
+                    {
+                        continue;
+                    }
                     println!(
-                        "{}\t{}\t{}{: >12}\t{: >8.2}",
+                        "{}\t{:<19}\t{}\t{}{: >12}\t{: >12}\t{: >8.2}{}{}",
                         row.height,
+                        row.time.map(format_timestamp).unwrap_or_else(|| s!("-")),
                         if *txid { row.txid.to_string() } else { format!("{:#}", row.txid) },
                         row.operation,
                         row.amount,
-                        row.fee.sats() as f64 * 4.0 / row.weight as f64
+                        row.balance,
+                        row.fee.sats() as f64 * 4.0 / row.weight as f64,
+                        match row.replaced_by {
+                            Some(by) if *txid => format!("\treplaced by {by}"),
+                            Some(by) => format!("\treplaced by {by:#}"),
+                            None => s!(""),
+                        },
+                        label_suffix(wallet.label(row.txid))
                     );
                     if *details {
                         for (cp, value) in &row.own {
                             println!(
-                                "\t* {value: >-12}ṩ\t{}\t{cp}",
+                                "\t* {value: >-12}ṩ\t{}\t{cp}{}",
                                 if *value < 0 {
                                     "debit from"
                                 } else if row.operation == OpType::Credit {
                                     "credit to "
                                 } else {
                                     "change to "
-                                }
+                                },
+                                label_suffix(wallet.label(cp.addr))
                             );
                         }
                         for (cp, value) in &row.counterparties {
                             println!(
-                                "\t* {value: >-12}ṩ\t{}\t{cp}",
+                                "\t* {value: >-12}ṩ\t{}\t{cp}{}",
                                 if *value > 0 {
                                     "paid from "
                                 } else if row.operation == OpType::Credit {
                                     "change to "
                                 } else {
                                     "sent to   "
-                                }
+                                },
+                                label_suffix(match cp {
+                                    Counterparty::Address(addr) => wallet.label(*addr),
+                                    _ => None,
+                                })
                             );
                         }
                         println!("\t* {: >-12}ṩ\tminer fee", -row.fee.sats_i64());
@@ -492,6 +1185,19 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                     serde_yaml::to_string(&tx).expect("unable to generate YAML representation")
                 );
             }
+            BpCommand::Fees => {
+                let indexer = self.indexer()?;
+                let estimates = indexer.fee_estimates()?;
+                if estimates.is_empty() {
+                    println!("{} has not provided any fee rate estimations", indexer.name());
+                } else {
+                    println!("Fee rates estimated by {}:", indexer.name());
+                    println!("{:>8}\tFee rate", "Blocks");
+                }
+                for (target, fee_rate) in estimates.iter() {
+                    println!("{target:>8}\t{:.1} sat/vB", fee_rate.sat_per_vb());
+                }
+            }
             BpCommand::Inspect { psbt } => {
                 let psbt = psbt_read(psbt)?;
                 println!(
@@ -499,36 +1205,236 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                     serde_yaml::to_string(&psbt).expect("unable to generate YAML representation")
                 );
             }
+            BpCommand::ConvertKey { to, key } => {
+                let (converted, version) = convert_key_expr(key, *to)?;
+                if let Some(standard) = version.standard() {
+                    eprintln!("The key has {version} version used by {standard} standard");
+                }
+                println!("{converted}");
+            }
             BpCommand::Construct {
                 v2,
                 to: beneficiaries,
+                coinselect,
                 fee,
+                fee_rate,
+                target,
+                coins,
+                tx,
                 psbt: psbt_file,
             } => {
                 let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
-
-                // Do coin selection
-                let total_amount =
-                    beneficiaries.iter().try_fold(Sats::ZERO, |sats, b| match b.amount {
-                        Payment::Max => Err(()),
-                        Payment::Fixed(s) => sats.checked_add(s).ok_or(()),
-                    });
-                let coins: Vec<_> = match total_amount {
-                    Ok(sats) if sats > Sats::ZERO => {
-                        wallet.coinselect(sats + *fee, coinselect::all).collect()
+                let mut selector = *coinselect;
+                let mut control = coins.coin_control();
+                let tx_options = tx.tx_options();
+                // Validate options before doing any coin selection
+                let params = wallet.tx_params(Sats::ZERO, tx_options)?;
+                if let Some(lock_time) = params.lock_time {
+                    if !wallet.is_lock_time_reached(lock_time) {
+                        eprintln!(
+                            "Warning: lock time {} is not reached yet, the transaction can't be \
+                             mined until then",
+                            lock_time.to_consensus_u32()
+                        );
                     }
-                    _ => {
+                }
+
+                let fee_rate = match (fee_rate, target) {
+                    (Some(fee_rate), _) => Some(*fee_rate),
+                    (None, Some(target)) => Some(estimate_fee_rate(&self.indexer()?, *target)?),
+                    (None, None) => None,
+                };
+                let (mut psbt, fee) = if let Some(fee_rate) = fee_rate {
+                    let (psbt, _, fee) = wallet.construct_psbt_fee_rate(
+                        beneficiaries,
+                        fee_rate,
+                        &mut selector,
+                        &control,
+                        tx_options,
+                    )?;
+                    (psbt, fee)
+                } else {
+                    let fee = fee.unwrap_or_default();
+
+                    let aggregate =
+                        beneficiaries.iter().all(|b| b.amount == Payment::Fixed(Sats::ZERO));
+                    if aggregate && !control.manual {
                         eprintln!(
                             "Warning: you are not paying to anybody but just aggregating all your \
                              balances to a single UTXO",
                         );
-                        wallet.all_utxos().map(WalletUtxo::into_outpoint).collect()
+                        let coins = wallet.coin_candidates(|utxo| {
+                            wallet
+                                .utxo_address(utxo.outpoint)
+                                .is_ok_and(|addr| !control.is_excluded(utxo.outpoint, &addr))
+                        });
+                        control.include.extend(coins.iter().map(Candidate::outpoint));
                     }
+                    let (psbt, _, fee) = wallet.construct_psbt_fee(
+                        beneficiaries,
+                        fee,
+                        &mut selector,
+                        &control,
+                        tx_options,
+                    )?;
+                    (psbt, fee)
+                };
+                eprintln!(
+                    "Transaction fee: {} sats, estimated size {} vbytes ({})",
+                    fee.fee,
+                    fee.vsize(),
+                    fee.fee_rate()
+                );
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::Sweep {
+                v2,
+                from,
+                from_key,
+                from_wif,
+                from_wpkh,
+                from_tr_key_only,
+                fee_rate,
+                target,
+                tx,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let tx_options = tx.tx_options();
+                let descriptors = match (from, from_key, from_wif, from_wpkh, from_tr_key_only) {
+                    (Some(descr), ..) => vec![descr.as_ref().clone()],
+                    (_, Some(key), ..) => AnyDescr::with_single_key(key.clone()),
+                    (_, _, Some(wif), ..) => AnyDescr::with_single_key(wif.to_single_key()),
+                    (_, _, _, Some(xpub), _) => vec![AnyDescr::Wpkh(xpub.as_ref().clone().into())],
+                    (_, _, _, _, Some(xpub)) => {
+                        vec![AnyDescr::TrKey(xpub.as_ref().clone().into())]
+                    }
+                    _ => unreachable!("source descriptor is required by clap"),
                 };
 
-                // TODO: Support lock time and RBFs
-                let params = TxParams::with(*fee);
-                let (mut psbt, _) = wallet.construct_psbt(coins, beneficiaries, params)?;
+                let indexer = self.indexer()?;
+                let mut sources = Vec::with_capacity(descriptors.len());
+                for descr in descriptors {
+                    let mut source =
+                        Wallet::<XpubDerivable, AnyDescr>::new_layer1(descr, self.general.network);
+                    eprint!("Scanning {} with {}", source.descriptor(), indexer.name());
+                    let MayError { err, .. } = source.update(&indexer);
+                    if let Some(errors) = err {
+                        eprintln!(" partial, some requests has failed:");
+                        for err in errors {
+                            eprintln!("- {err}");
+                        }
+                    } else {
+                        eprintln!(" success");
+                    }
+                    sources.push(source);
+                }
+                // Single keys are scanned with all script types; the funds must be found with
+                // only one of them, since a transaction is constructed for a single descriptor.
+                let funded = sources
+                    .iter()
+                    .filter(|source| source.balance() > Sats::ZERO)
+                    .map(|source| source.descriptor().to_string())
+                    .collect::<Vec<_>>();
+                if funded.len() > 1 {
+                    return Err(ExecError::SweepAmbiguous(funded.join(", ")));
+                }
+                let mut source = sources
+                    .into_iter()
+                    .find(|source| source.balance() > Sats::ZERO || funded.is_empty())
+                    .expect("at least one descriptor is always scanned");
+                eprintln!(
+                    "Found {} spendable outputs with total balance of {} sats",
+                    source.utxos().count(),
+                    source.balance()
+                );
+
+                let fee_rate = match (fee_rate, target) {
+                    (Some(fee_rate), _) => *fee_rate,
+                    (None, Some(target)) => estimate_fee_rate(&indexer, *target)?,
+                    (None, None) => unreachable!("fee rate or target is required by clap"),
+                };
+                let keychain = if wallet.keychains().contains(&Keychain::INNER) {
+                    Keychain::INNER
+                } else {
+                    wallet.default_keychain()
+                };
+                source.tx_params(Sats::ZERO, tx_options)?;
+                let address = wallet.next_address(keychain, true);
+                eprintln!("Sweeping to {address}");
+                let (mut psbt, _, fee) = source.construct_sweep(address, fee_rate, tx_options)?;
+                eprintln!(
+                    "Transaction fee: {} sats, estimated size {} vbytes ({})",
+                    fee.fee,
+                    fee.vsize(),
+                    fee.fee_rate()
+                );
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::Bump {
+                v2,
+                coinselect,
+                fee_rate,
+                target,
+                txid,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut selector = *coinselect;
+
+                let fee_rate = match (fee_rate, target) {
+                    (Some(fee_rate), _) => *fee_rate,
+                    (None, Some(target)) => estimate_fee_rate(&self.indexer()?, *target)?,
+                    (None, None) => unreachable!("fee rate or target is required by clap"),
+                };
+                if wallet.transactions().get(txid).is_some_and(|tx| !tx.signals_rbf()) {
+                    eprintln!(
+                        "Warning: transaction {txid} does not signal replaceability (BIP125); the \
+                         replacement will be relayed only by nodes with full RBF enabled"
+                    );
+                }
+                let (mut psbt, _, fee) = wallet.bump_fee(*txid, fee_rate, &mut selector)?;
+                eprintln!(
+                    "Transaction fee: {} sats, estimated size {} vbytes ({})",
+                    fee.fee,
+                    fee.vsize(),
+                    fee.fee_rate()
+                );
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::Cpfp {
+                v2,
+                coinselect,
+                fee_rate,
+                target,
+                outpoint,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut selector = *coinselect;
+
+                let fee_rate = match (fee_rate, target) {
+                    (Some(fee_rate), _) => *fee_rate,
+                    (None, Some(target)) => estimate_fee_rate(&self.indexer()?, *target)?,
+                    (None, None) => unreachable!("fee rate or target is required by clap"),
+                };
+                let (mut psbt, _, fee) = wallet.cpfp(*outpoint, fee_rate, &mut selector)?;
+                let parent = &wallet.transactions()[&outpoint.txid];
+                eprintln!(
+                    "Transaction fee: {} sats, estimated size {} vbytes ({})",
+                    fee.fee,
+                    fee.vsize(),
+                    fee.fee_rate()
+                );
+                eprintln!(
+                    "Package fee: {} sats, estimated size {} vbytes ({})",
+                    parent.fee + fee.fee,
+                    vsize(parent.weight + fee.weight),
+                    FeeRate::from_fee(parent.fee + fee.fee, parent.weight + fee.weight)
+                );
                 psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
                 psbt_write_or_print(&psbt, psbt_file.as_deref())?;
             }
@@ -540,6 +1446,16 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
     }
 }
 
+fn estimate_fee_rate(indexer: &AnyIndexer, target: u16) -> Result<FeeRate, ExecError> {
+    let fee_rate =
+        indexer.fee_estimates()?.for_target(target).ok_or(ExecError::NoFeeEstimation(target))?;
+    eprintln!(
+        "Using fee rate {fee_rate} estimated by {} for confirmation within {target} blocks",
+        indexer.name()
+    );
+    Ok(fee_rate)
+}
+
 fn psbt_read(psbt_path: &Path) -> Result<Psbt, ExecError> {
     eprint!("Reading PSBT from file {} ... ", psbt_path.display());
     let mut psbt_file = File::open(psbt_path)?;
@@ -569,12 +1485,12 @@ fn psbt_write_or_print(psbt: &Psbt, psbt_path: Option<&Path>) -> Result<(), Exec
     Ok(())
 }
 
-fn psbt_finalize<D: Descriptor<K, V>, K, V>(
+fn psbt_finalize<D: PsbtDescriptor<K, V>, K, V>(
     psbt: &mut Psbt,
     descriptor: &D,
 ) -> Result<(), ExecError> {
     eprint!("Finalizing PSBT ... ");
-    let inputs = psbt.finalize(descriptor);
+    let inputs = descriptor.finalize_psbt(psbt);
     eprint!(
         "{} of {} inputs were finalized",
         inputs.to_string().bright_green(),
@@ -617,3 +1533,7 @@ fn psbt_extract(psbt: &Psbt, publish: bool, tx: Option<&Path>) -> Result<Tx, Exe
         }
     }
 }
+
+fn label_suffix(label: Option<&str>) -> String {
+    label.map(|label| format!("\t# {label}")).unwrap_or_default()
+}
@@ -31,6 +31,7 @@ pub use command::{BpCommand, Command, ExecError};
 pub use config::Config;
 pub use loglevel::LogLevel;
 pub use opts::{
-    DescrStdOpts, DescriptorOpts, GeneralOpts, ResolverOpt, WalletOpts, DATA_DIR, DATA_DIR_ENV,
+    parse_xpub, CoinControlOpts, DescrStdOpts, DescriptorOpts, GeneralOpts, ResolverOpt, TxOpts,
+    WalletOpts, DATA_DIR, DATA_DIR_ENV, DEFAULT_BITCOIND, DEFAULT_BITCOIND_WALLET, DEFAULT_CBF,
     DEFAULT_ELECTRUM, DEFAULT_ESPLORA,
 };
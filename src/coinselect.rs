@@ -20,8 +20,839 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::WalletUtxo;
+//! Coin selection algorithms.
+//!
+//! All algorithms operate on [`Candidate`]s, which are spendable wallet outputs complemented with
+//! the weight of the input spending them, and select a subset of them covering the
+//! [`SelectionParams::target`] amount together with the fees required to spend the selected
+//! inputs at [`SelectionParams::fee_rate`].
 
-// TODO: Use traits and structs with internal state
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use bpstd::{Idx, Keychain, NormalIndex, Outpoint, Sats, ScriptPubkey, Terminal};
+use descriptors::{Descriptor, SpkClass};
+
+use crate::{FeeRate, WalletUtxo};
+
+/// Number of iterations the branch-and-bound algorithm runs before giving up.
+pub const BNB_TOTAL_TRIES: usize = 100_000;
+
+/// Number of random subsets tried by the knapsack algorithm.
+pub const KNAPSACK_ITERATIONS: usize = 1000;
+
+/// Weight of transaction input fields which do not depend on the type of the spent output:
+/// previous outpoint, sequence number and a single-byte length of the signature script.
+pub(crate) const TXIN_BASE_WEIGHT: u32 = (32 + 4 + 4 + 1) * 4;
+/// Maximal length of a DER-encoded ECDSA signature with a sighash type byte.
+const ECDSA_SIG_LEN: u32 = 72;
+/// Length of a BIP340 signature with the default sighash type.
+const SCHNORR_SIG_LEN: u32 = 64;
+const COMPR_PK_LEN: u32 = 33;
 
 pub fn all(_: &WalletUtxo) -> bool { true }
+
+/// Estimates the maximal weight of a transaction input spending an output generated by the
+/// descriptor for a given terminal.
+///
+/// For the multisig descriptors the estimation assumes that all the keys sign, and for the
+/// taproot descriptors with multiple keys the script path spending is assumed.
+pub fn input_weight<K, V, D: Descriptor<K, V>>(descriptor: &D, terminal: Terminal) -> u32 {
+    fn witness(items: &[u32]) -> u32 {
+        varint_len(items.len() as u32) + items.iter().map(|len| varint_len(*len) + len).sum::<u32>()
+    }
+    fn multisig_items(keys: u32) -> Vec<u32> {
+        let mut items = vec![0];
+        items.extend((0..keys).map(|_| ECDSA_SIG_LEN));
+        items.push(keys * (COMPR_PK_LEN + 1) + 3);
+        items
+    }
+
+    match descriptor.class() {
+        SpkClass::P2tr => {
+            let keys = descriptor.xonly_keyset(terminal).len() as u32;
+            if keys <= 1 {
+                return TXIN_BASE_WEIGHT + witness(&[SCHNORR_SIG_LEN]);
+            }
+            let mut items = (0..keys).map(|_| SCHNORR_SIG_LEN).collect::<Vec<_>>();
+            items.push(keys * (32 + 2) + 1);
+            items.push(33);
+            TXIN_BASE_WEIGHT + witness(&items)
+        }
+        SpkClass::P2wpkh => TXIN_BASE_WEIGHT + witness(&[ECDSA_SIG_LEN, COMPR_PK_LEN]),
+        SpkClass::P2wsh => {
+            let keys = descriptor.legacy_keyset(terminal).len().max(1) as u32;
+            TXIN_BASE_WEIGHT + witness(&multisig_items(keys))
+        }
+        SpkClass::P2sh => {
+            let keys = descriptor.legacy_keyset(terminal).len().max(1) as u32;
+            if keys == 1 {
+                TXIN_BASE_WEIGHT + 23 * 4 + witness(&[ECDSA_SIG_LEN, COMPR_PK_LEN])
+            } else {
+                TXIN_BASE_WEIGHT + 35 * 4 + witness(&multisig_items(keys))
+            }
+        }
+        SpkClass::P2pkh => TXIN_BASE_WEIGHT + (2 + ECDSA_SIG_LEN + COMPR_PK_LEN) * 4,
+        SpkClass::Bare => {
+            let keys = descriptor.legacy_keyset(terminal).len().max(1) as u32;
+            TXIN_BASE_WEIGHT + (1 + keys * (1 + ECDSA_SIG_LEN)) * 4
+        }
+    }
+}
+
+/// Computes weight of a transaction output paying to a script generated by the descriptor.
+pub fn output_weight<K, V, D: Descriptor<K, V>>(descriptor: &D) -> u32 {
+    let script_len = match descriptor.class() {
+        SpkClass::P2tr | SpkClass::P2wsh => 34,
+        SpkClass::P2wpkh => 22,
+        SpkClass::P2sh => 23,
+        SpkClass::P2pkh => 25,
+        SpkClass::Bare => {
+            let terminal = Terminal::new(Keychain::OUTER, NormalIndex::ZERO);
+            descriptor.legacy_keyset(terminal).len().max(1) as u32 * (COMPR_PK_LEN + 1) + 3
+        }
+    };
+    (8 + varint_len(script_len) + script_len) * 4
+}
+
+/// Computes weight of a transaction output with a given script pubkey.
+pub fn txout_weight(script_pubkey: &ScriptPubkey) -> u32 {
+    let script_len = script_pubkey.len() as u32;
+    (8 + varint_len(script_len) + script_len) * 4
+}
+
+/// Computes weight of the transaction fields which do not belong to inputs or outputs: version,
+/// lock time, number of inputs and outputs and, for transactions spending outputs of segwit
+/// descriptors, segwit marker and flag.
+pub fn tx_overhead_weight<K, V, D: Descriptor<K, V>>(
+    descriptor: &D,
+    inputs: usize,
+    outputs: usize,
+) -> u32 {
+    let segwit = match descriptor.class() {
+        SpkClass::Bare | SpkClass::P2pkh => 0,
+        SpkClass::P2sh | SpkClass::P2wpkh | SpkClass::P2wsh | SpkClass::P2tr => 2,
+    };
+    (4 + 4 + varint_len(inputs as u32) + varint_len(outputs as u32)) * 4 + segwit
+}
+
+pub(crate) fn varint_len(value: u32) -> u32 {
+    match value {
+        0..=0xFC => 1,
+        0xFD..=0xFFFF => 3,
+        _ => 5,
+    }
+}
+
+/// Wallet output which may be selected for spending.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Candidate {
+    pub utxo: WalletUtxo,
+    /// Weight of the transaction input spending the output.
+    pub weight: u32,
+}
+
+impl Candidate {
+    /// Constructs candidate, estimating weight of the input spending the output using the wallet
+    /// descriptor.
+    pub fn with<K, V, D: Descriptor<K, V>>(utxo: WalletUtxo, descriptor: &D) -> Self {
+        Candidate {
+            utxo,
+            weight: input_weight(descriptor, utxo.terminal),
+        }
+    }
+
+    #[inline]
+    pub fn outpoint(&self) -> Outpoint { self.utxo.outpoint }
+
+    #[inline]
+    pub fn value(&self) -> Sats { self.utxo.value }
+
+    /// Value of the output minus the fee required for spending it at a given fee rate. May be
+    /// negative for the outputs which are not economical to spend.
+    pub fn effective_value(&self, fee_rate: FeeRate) -> i64 {
+        self.utxo.value.sats_i64() - fee_rate.fee_for_weight(self.weight).sats_i64()
+    }
+}
+
+/// Parameters of the coin selection.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SelectionParams {
+    /// Amount which has to be paid to the beneficiaries.
+    pub target: Sats,
+    /// Fee rate used to compute the fee for the transaction data, including the selected inputs.
+    pub fee_rate: FeeRate,
+    /// Weight of the transaction without the inputs and the change output.
+    pub base_weight: u32,
+    /// Absolute fee which has to be paid in addition to the fee computed from the fee rate.
+    pub fixed_fee: Sats,
+    /// Weight of the change output.
+    pub change_weight: u32,
+    /// Weight of the input which will spend the change output in the future.
+    pub change_spend_weight: u32,
+    /// Minimal value of the change output; if the change is below this value it is added to the
+    /// fee.
+    pub min_change: Sats,
+}
+
+impl SelectionParams {
+    /// Constructs parameters for a selection covering `target` amount and fees at a given rate.
+    pub fn with(target: Sats, fee_rate: FeeRate) -> Self {
+        SelectionParams {
+            target,
+            fee_rate,
+            base_weight: 0,
+            fixed_fee: Sats::ZERO,
+            change_weight: 0,
+            change_spend_weight: 0,
+            min_change: Sats::ZERO,
+        }
+    }
+
+    /// Constructs parameters for a selection covering `target` amount and a fixed absolute fee,
+    /// which doesn't depend on the number of the selected inputs.
+    pub fn with_fee(target: Sats, fee: Sats) -> Self {
+        SelectionParams {
+            fixed_fee: fee,
+            ..SelectionParams::with(target, FeeRate::ZERO)
+        }
+    }
+
+    /// Sets change output parameters using the descriptor of the wallet which will receive the
+    /// change.
+    pub fn change_descriptor<K, V, D: Descriptor<K, V>>(mut self, descriptor: &D) -> Self {
+        self.change_weight = output_weight(descriptor);
+        self.change_spend_weight =
+            input_weight(descriptor, Terminal::new(Keychain::INNER, NormalIndex::ZERO));
+        self.min_change = descriptor.class().dust_limit();
+        self
+    }
+
+    /// Amount which has to be covered by the effective value of the selected inputs.
+    pub fn effective_target(&self) -> Sats {
+        self.target + self.fixed_fee + self.fee_rate.fee_for_weight(self.base_weight)
+    }
+
+    /// Cost of creating a change output and spending it later.
+    pub fn cost_of_change(&self) -> Sats {
+        self.fee_rate.fee_for_weight(self.change_weight)
+            + self.fee_rate.fee_for_weight(self.change_spend_weight)
+    }
+}
+
+/// Result of a coin selection.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Selection {
+    pub inputs: Vec<Candidate>,
+}
+
+impl Selection {
+    pub fn outpoints(&self) -> impl Iterator<Item = Outpoint> + '_ {
+        self.inputs.iter().map(Candidate::outpoint)
+    }
+
+    /// Total value of the selected outputs.
+    pub fn value(&self) -> Sats { self.inputs.iter().map(Candidate::value).sum::<Sats>() }
+
+    /// Total weight of the inputs spending the selected outputs.
+    pub fn weight(&self) -> u32 { self.inputs.iter().map(|c| c.weight).sum() }
+
+    /// Fee of the transaction spending the selected outputs, not including change output.
+    pub fn fee(&self, params: &SelectionParams) -> Sats {
+        params.fixed_fee + params.fee_rate.fee_for_weight(params.base_weight + self.weight())
+    }
+
+    /// Value of the change output which has to be added to the transaction, if any. Returns
+    /// `None` if the excess of the selected value is too small for creating a change output;
+    /// in this case it is added to the fee.
+    pub fn change(&self, params: &SelectionParams) -> Option<Sats> {
+        let fee = params.fixed_fee
+            + params
+                .fee_rate
+                .fee_for_weight(params.base_weight + self.weight() + params.change_weight);
+        let change = self.value().checked_sub(params.target + fee)?;
+        (change.is_non_zero() && change >= params.min_change).then_some(change)
+    }
+}
+
+/// Errors happening during the coin selection.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SelectionError {
+    /// insufficient funds: the wallet has {available} available for spending, while {required}
+    /// are required.
+    InsufficientFunds { available: Sats, required: Sats },
+
+    /// no changeless combination of coins matching the required amount was found.
+    NoExactMatch,
+}
+
+/// Coin selection algorithm.
+pub trait CoinSelector {
+    /// Selects coins from the list of candidates according to the given parameters.
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError>;
+}
+
+/// Adds candidates in the given order until their effective value covers the target. Candidates
+/// with non-positive effective value are skipped.
+fn accumulate<'c>(
+    candidates: impl IntoIterator<Item = &'c Candidate>,
+    params: &SelectionParams,
+) -> Result<Selection, SelectionError> {
+    let target = params.effective_target().sats_i64();
+    let mut selection = Selection::default();
+    let mut value = 0i64;
+    for candidate in candidates {
+        if value >= target {
+            break;
+        }
+        let effective = candidate.effective_value(params.fee_rate);
+        if effective <= 0 {
+            continue;
+        }
+        value += effective;
+        selection.inputs.push(*candidate);
+    }
+    if value < target {
+        return Err(SelectionError::InsufficientFunds {
+            available: Sats::from(value.max(0) as u64),
+            required: params.effective_target(),
+        });
+    }
+    Ok(selection)
+}
+
+/// Selects the outputs with the largest value first.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError> {
+        let mut candidates = candidates.iter().collect::<Vec<_>>();
+        candidates.sort_by_key(|c| (std::cmp::Reverse(c.value()), c.outpoint()));
+        accumulate(candidates, params)
+    }
+}
+
+/// Selects the outputs mined earlier first; unconfirmed outputs are selected last.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct OldestFirst;
+
+impl CoinSelector for OldestFirst {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError> {
+        let mut candidates = candidates.iter().collect::<Vec<_>>();
+        candidates.sort_by_key(|c| {
+            let status = c.utxo.status;
+            (!status.is_mined(), status.map(|info| info.height), c.outpoint())
+        });
+        accumulate(candidates, params)
+    }
+}
+
+/// Selects outputs in a random order until the target is covered.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SingleRandomDraw {
+    rng: Rng,
+}
+
+impl SingleRandomDraw {
+    pub fn new() -> Self { default!() }
+
+    /// Constructs selector with a fixed seed, making selection results reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        SingleRandomDraw {
+            rng: Rng::with_seed(seed),
+        }
+    }
+}
+
+impl CoinSelector for SingleRandomDraw {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError> {
+        let mut candidates = candidates.iter().collect::<Vec<_>>();
+        self.rng.shuffle(&mut candidates);
+        accumulate(candidates, params)
+    }
+}
+
+/// Branch-and-bound algorithm searching for a set of outputs which covers the target without
+/// producing a change output, i.e. with the excess not exceeding the cost of change. Among the
+/// found solutions the one with the smallest excess is selected.
+///
+/// Fails with [`SelectionError::NoExactMatch`] if no such solution exists or it was not found in
+/// [`BNB_TOTAL_TRIES`] iterations.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct BranchAndBound;
+
+impl CoinSelector for BranchAndBound {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError> {
+        let target = params.effective_target().sats_i64();
+        let cost_of_change = params.cost_of_change().sats_i64();
+
+        let mut pool = candidates
+            .iter()
+            .map(|c| (c.effective_value(params.fee_rate), c))
+            .filter(|(effective, _)| *effective > 0)
+            .collect::<Vec<_>>();
+        pool.sort_by_key(|(effective, c)| (std::cmp::Reverse(*effective), c.outpoint()));
+        let mut available = pool.iter().map(|(effective, _)| effective).sum::<i64>();
+        if available < target {
+            return Err(SelectionError::InsufficientFunds {
+                available: Sats::from(available as u64),
+                required: params.effective_target(),
+            });
+        }
+
+        let mut selected = Vec::<usize>::new();
+        let mut best: Option<(Vec<usize>, i64)> = None;
+        let mut value = 0i64;
+        let mut index = 0usize;
+        for _ in 0..BNB_TOTAL_TRIES {
+            let excess = value - target;
+            let backtrack = if value + available < target
+                || excess > cost_of_change
+                || matches!(best, Some((_, best_excess)) if excess >= best_excess)
+            {
+                true
+            } else if value >= target {
+                best = Some((selected.clone(), excess));
+                if excess == 0 {
+                    break;
+                }
+                true
+            } else {
+                false
+            };
+
+            if backtrack {
+                let Some(last) = selected.pop() else {
+                    break;
+                };
+                // Return the outputs which were omitted after the last selected one back to the
+                // available set, and explore the branch where the last selected one is omitted.
+                for (effective, _) in &pool[last + 1..index] {
+                    available += effective;
+                }
+                value -= pool[last].0;
+                index = last;
+            } else {
+                let (effective, candidate) = pool[index];
+                available -= effective;
+                // Skip outputs equivalent to the previous omitted one, since this branch was
+                // already explored.
+                let prev = index.checked_sub(1).map(|prev| pool[prev]);
+                let equivalent = matches!(prev, Some((prev_effective, prev_candidate))
+                    if selected.last() != Some(&(index - 1))
+                        && prev_effective == effective
+                        && prev_candidate.weight == candidate.weight);
+                if !equivalent {
+                    selected.push(index);
+                    value += effective;
+                }
+            }
+            index += 1;
+        }
+
+        let (selected, _) = best.ok_or(SelectionError::NoExactMatch)?;
+        Ok(Selection {
+            inputs: selected.into_iter().map(|index| *pool[index].1).collect(),
+        })
+    }
+}
+
+/// Knapsack algorithm used by Bitcoin Core before the introduction of branch-and-bound: tries
+/// random subsets of the outputs smaller than the target, looking for the one with the smallest
+/// excess, and compares it with the smallest output larger than the target.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Knapsack {
+    rng: Rng,
+}
+
+impl Knapsack {
+    pub fn new() -> Self { default!() }
+
+    /// Constructs selector with a fixed seed, making selection results reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        Knapsack {
+            rng: Rng::with_seed(seed),
+        }
+    }
+
+    fn approximate_best_subset(&mut self, pool: &[(i64, &Candidate)], target: i64) -> Vec<bool> {
+        let mut best = vec![true; pool.len()];
+        let mut best_value = pool.iter().map(|(effective, _)| effective).sum::<i64>();
+        for _ in 0..KNAPSACK_ITERATIONS {
+            if best_value == target {
+                break;
+            }
+            let mut included = vec![false; pool.len()];
+            let mut total = 0i64;
+            let mut reached = false;
+            for pass in 0..2 {
+                if reached {
+                    break;
+                }
+                for (i, (effective, _)) in pool.iter().enumerate() {
+                    let pick = if pass == 0 { self.rng.next_bool() } else { !included[i] };
+                    if !pick {
+                        continue;
+                    }
+                    total += effective;
+                    included[i] = true;
+                    if total >= target {
+                        reached = true;
+                        if total < best_value {
+                            best_value = total;
+                            best.clone_from(&included);
+                        }
+                        total -= effective;
+                        included[i] = false;
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+impl CoinSelector for Knapsack {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError> {
+        let target = params.effective_target().sats_i64();
+        let min_change = (params.min_change + params.cost_of_change()).sats_i64();
+
+        let mut candidates = candidates
+            .iter()
+            .map(|c| (c.effective_value(params.fee_rate), c))
+            .filter(|(effective, _)| *effective > 0)
+            .collect::<Vec<_>>();
+        self.rng.shuffle(&mut candidates);
+
+        let mut lowest_larger: Option<(i64, &Candidate)> = None;
+        let mut applicable = Vec::new();
+        let mut total_lower = 0i64;
+        for (effective, candidate) in candidates {
+            if effective == target {
+                return Ok(Selection {
+                    inputs: vec![*candidate],
+                });
+            } else if effective < target + min_change {
+                applicable.push((effective, candidate));
+                total_lower += effective;
+            } else if lowest_larger.map_or(true, |(lowest, _)| effective < lowest) {
+                lowest_larger = Some((effective, candidate));
+            }
+        }
+
+        if total_lower == target {
+            return Ok(Selection {
+                inputs: applicable.into_iter().map(|(_, c)| *c).collect(),
+            });
+        }
+        if total_lower < target {
+            return match lowest_larger {
+                Some((_, candidate)) => Ok(Selection {
+                    inputs: vec![*candidate],
+                }),
+                None => Err(SelectionError::InsufficientFunds {
+                    available: Sats::from(total_lower as u64),
+                    required: params.effective_target(),
+                }),
+            };
+        }
+
+        applicable.sort_by_key(|(effective, c)| (std::cmp::Reverse(*effective), c.outpoint()));
+        let mut best = self.approximate_best_subset(&applicable, target);
+        let mut best_value = value_of(&applicable, &best);
+        if best_value != target && total_lower >= target + min_change {
+            best = self.approximate_best_subset(&applicable, target + min_change);
+            best_value = value_of(&applicable, &best);
+        }
+
+        if let Some((lowest, candidate)) = lowest_larger {
+            if (best_value != target && best_value < target + min_change) || lowest <= best_value {
+                return Ok(Selection {
+                    inputs: vec![*candidate],
+                });
+            }
+        }
+        Ok(Selection {
+            inputs: applicable
+                .into_iter()
+                .zip(best)
+                .filter(|(_, included)| *included)
+                .map(|((_, c), _)| *c)
+                .collect(),
+        })
+    }
+}
+
+fn value_of(pool: &[(i64, &Candidate)], included: &[bool]) -> i64 {
+    pool.iter().zip(included).filter(|(_, included)| **included).map(|((v, _), _)| v).sum()
+}
+
+/// Coin selection strategy, which can be chosen at runtime.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum Strategy {
+    /// Try branch-and-bound first, falling back to knapsack if no changeless solution exists.
+    #[default]
+    #[display("auto")]
+    Auto,
+
+    /// Branch-and-bound selection avoiding change output.
+    #[cfg_attr(feature = "clap", value(name = "bnb"))]
+    #[display("bnb")]
+    BranchAndBound,
+
+    /// Knapsack selection with random subsets.
+    #[display("knapsack")]
+    Knapsack,
+
+    /// Select the outputs with the largest value first.
+    #[display("largest-first")]
+    LargestFirst,
+
+    /// Select the oldest outputs first.
+    #[display("oldest-first")]
+    OldestFirst,
+
+    /// Select random outputs until the amount is covered.
+    #[cfg_attr(feature = "clap", value(name = "random"))]
+    #[display("random")]
+    SingleRandomDraw,
+}
+
+impl CoinSelector for Strategy {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        params: &SelectionParams,
+    ) -> Result<Selection, SelectionError> {
+        match self {
+            Strategy::Auto => BranchAndBound
+                .select(candidates, params)
+                .or_else(|_| Knapsack::new().select(candidates, params)),
+            Strategy::BranchAndBound => BranchAndBound.select(candidates, params),
+            Strategy::Knapsack => Knapsack::new().select(candidates, params),
+            Strategy::LargestFirst => LargestFirst.select(candidates, params),
+            Strategy::OldestFirst => OldestFirst.select(candidates, params),
+            Strategy::SingleRandomDraw => SingleRandomDraw::new().select(candidates, params),
+        }
+    }
+}
+
+/// Xorshift pseudo-random number generator used to randomize coin selection. Not suitable for
+/// any cryptographic purposes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+struct Rng(u64);
+
+impl Default for Rng {
+    fn default() -> Self { Rng::with_seed(RandomState::new().build_hasher().finish()) }
+}
+
+impl Rng {
+    fn with_seed(seed: u64) -> Self { Rng(seed.max(1)) }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn next_bool(&mut self) -> bool { self.next_u64() & 1 == 1 }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use bpstd::{BlockHash, Txid, XpubDerivable};
+    use descriptors::Wpkh;
+
+    use super::*;
+    use crate::{testing, BlockHeight, MiningInfo, TxStatus, UtxoStatus};
+
+    const FEE_RATE: FeeRate = FeeRate::MIN_RELAY;
+    /// Fee for spending a single wpkh input at [`FEE_RATE`].
+    const INPUT_FEE: u64 = 68;
+
+    fn descriptor() -> Wpkh<XpubDerivable> { Wpkh::from(testing::xpub()) }
+
+    /// Constructs candidate with a given effective value at [`FEE_RATE`], mined at `height` or
+    /// unconfirmed if the height is not given.
+    fn candidate(no: u8, effective: u64, height: Option<u32>) -> Candidate {
+        let status = match height {
+            Some(height) => TxStatus::Mined(MiningInfo {
+                height: BlockHeight::new(height).unwrap(),
+                time: height as u64 * 600,
+                block_hash: BlockHash::from([no; 32]),
+            }),
+            None => TxStatus::Mempool,
+        };
+        let utxo = WalletUtxo {
+            outpoint: Outpoint::new(Txid::from([no; 32]), 0),
+            value: Sats(effective + INPUT_FEE),
+            terminal: Terminal::new(Keychain::OUTER, NormalIndex::normal(no as u16)),
+            status,
+            state: if height.is_some() { UtxoStatus::Confirmed } else { UtxoStatus::Unconfirmed },
+        };
+        Candidate::with(utxo, &descriptor())
+    }
+
+    fn candidates() -> Vec<Candidate> {
+        vec![
+            candidate(1, 1000, Some(30)),
+            candidate(2, 2000, Some(20)),
+            candidate(3, 3000, None),
+            candidate(4, 5000, Some(10)),
+        ]
+    }
+
+    fn params(target: u64) -> SelectionParams {
+        SelectionParams::with(Sats(target), FEE_RATE).change_descriptor(&descriptor())
+    }
+
+    fn effective(selection: &Selection) -> BTreeSet<i64> {
+        selection.inputs.iter().map(|c| c.effective_value(FEE_RATE)).collect()
+    }
+
+    #[test]
+    fn weights() {
+        let candidate = candidate(1, 1000, None);
+        assert_eq!(candidate.weight, 272);
+        assert_eq!(candidate.effective_value(FEE_RATE), 1000);
+        let params = params(1000);
+        assert_eq!(params.change_weight, 124);
+        assert_eq!(params.change_spend_weight, 272);
+        assert_eq!(params.cost_of_change(), Sats(31 + 68));
+    }
+
+    #[test]
+    fn bnb_exact_match() {
+        let selection = BranchAndBound.select(&candidates(), &params(4000)).unwrap();
+        assert_eq!(effective(&selection), bset![1000, 3000]);
+        assert_eq!(selection.change(&params(4000)), None);
+        assert_eq!(selection.value() - selection.fee(&params(4000)), Sats(4000));
+
+        // Excess within the cost of change is added to the fee.
+        let selection = BranchAndBound.select(&candidates(), &params(3950)).unwrap();
+        assert_eq!(effective(&selection), bset![1000, 3000]);
+        assert_eq!(selection.change(&params(3950)), None);
+    }
+
+    #[test]
+    fn bnb_no_exact_match() {
+        assert_eq!(
+            BranchAndBound.select(&candidates(), &params(4500)),
+            Err(SelectionError::NoExactMatch)
+        );
+        // Auto strategy falls back to knapsack, which creates change.
+        let selection = Strategy::Auto.select(&candidates(), &params(4500)).unwrap();
+        assert!(selection.change(&params(4500)).is_some());
+    }
+
+    #[test]
+    fn change() {
+        let selection = LargestFirst.select(&candidates(), &params(4000)).unwrap();
+        assert_eq!(effective(&selection), bset![5000]);
+        // 5000 of effective value minus 4000 of target and 31 vbytes of change output
+        assert_eq!(selection.change(&params(4000)), Some(Sats(969)));
+
+        // Change below dust limit is added to the fee.
+        let selection = LargestFirst.select(&candidates(), &params(4800)).unwrap();
+        assert_eq!(effective(&selection), bset![5000]);
+        assert_eq!(selection.change(&params(4800)), None);
+        assert_eq!(selection.fee(&params(4800)), Sats(INPUT_FEE));
+
+        // Fixed fee doesn't depend on the number of inputs.
+        let params = SelectionParams::with_fee(Sats(4000), Sats(500));
+        let selection = LargestFirst.select(&candidates(), &params).unwrap();
+        assert_eq!(selection.fee(&params), Sats(500));
+        assert_eq!(selection.change(&params), Some(Sats(5068 - 4500)));
+    }
+
+    #[test]
+    fn ordering() {
+        let selection = OldestFirst.select(&candidates(), &params(5500)).unwrap();
+        assert_eq!(effective(&selection), bset![5000, 2000]);
+
+        let selection = OldestFirst.select(&candidates(), &params(8500)).unwrap();
+        assert_eq!(effective(&selection), bset![5000, 2000, 1000, 3000]);
+
+        let selection = Knapsack::with_seed(1).select(&candidates(), &params(2000)).unwrap();
+        assert_eq!(effective(&selection), bset![2000]);
+
+        for seed in 1..10 {
+            let selection =
+                SingleRandomDraw::with_seed(seed).select(&candidates(), &params(10000)).unwrap();
+            assert!(effective(&selection).iter().sum::<i64>() >= 10000);
+        }
+    }
+
+    #[test]
+    fn uneconomical_outputs() {
+        let mut candidates = candidates();
+        candidates.push(Candidate::with(
+            WalletUtxo {
+                value: Sats(INPUT_FEE),
+                ..candidate(5, 0, None).utxo
+            },
+            &descriptor(),
+        ));
+        let selection = LargestFirst.select(&candidates, &params(11000)).unwrap();
+        assert_eq!(selection.inputs.len(), 4);
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        for mut strategy in [
+            Strategy::Auto,
+            Strategy::BranchAndBound,
+            Strategy::Knapsack,
+            Strategy::LargestFirst,
+            Strategy::OldestFirst,
+            Strategy::SingleRandomDraw,
+        ] {
+            assert_eq!(
+                strategy.select(&candidates(), &params(11001)),
+                Err(SelectionError::InsufficientFunds {
+                    available: Sats(11000),
+                    required: Sats(11001),
+                }),
+                "{strategy}"
+            );
+            assert!(strategy.select(&[], &params(1)).is_err(), "{strategy}");
+        }
+    }
+}
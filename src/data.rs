@@ -21,15 +21,16 @@
 // limitations under the License.
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter, LowerHex};
-use std::num::{NonZeroU32, ParseIntError};
+use std::num::{NonZeroU32, ParseFloatError, ParseIntError};
 use std::str::FromStr;
 
 use amplify::hex;
 use amplify::hex::FromHex;
 use bpstd::{
     Address, BlockHash, BlockHeader, DerivedAddr, Keychain, LockTime, NormalIndex, Outpoint, Sats,
-    ScriptPubkey, SeqNo, SigScript, Terminal, TxVer, Txid, Witness,
+    ScriptPubkey, SeqNo, SigScript, Terminal, Tx, TxIn, TxOut, TxVer, Txid, VarIntArray, Witness,
 };
 use psbt::{Prevout, Utxo};
 
@@ -44,6 +45,7 @@ pub type BlockHeight = NonZeroU32;
 pub struct BlockInfo {
     pub mined: MiningInfo,
     pub header: BlockHeader,
+    /// Binary logarithm of the block difficulty.
     pub difficulty: u8,
     pub tx_count: u32,
     pub size: u32,
@@ -51,6 +53,37 @@ pub struct BlockInfo {
     pub mediantime: u32,
 }
 
+impl BlockInfo {
+    /// Constructs block information from a block header, leaving block statistics (transaction
+    /// count, size, weight and median time) empty.
+    pub fn with_header(height: BlockHeight, header: BlockHeader) -> Self {
+        BlockInfo {
+            mined: MiningInfo {
+                height,
+                time: header.time as u64,
+                block_hash: header.block_hash(),
+            },
+            header,
+            difficulty: difficulty_log2(header.bits),
+            tx_count: 0,
+            size: 0,
+            weight: 0,
+            mediantime: 0,
+        }
+    }
+}
+
+/// Computes binary logarithm of the block difficulty from the compact target representation.
+fn difficulty_log2(bits: u32) -> u8 {
+    let exponent = (bits >> 24) as i32;
+    let mantissa = (bits & 0x00ff_ffff) as f64;
+    if mantissa == 0.0 {
+        return 0;
+    }
+    let log = (0xffff as f64 / mantissa).log2() + 8.0 * (0x1d - exponent) as f64;
+    log.clamp(0.0, u8::MAX as f64) as u8
+}
+
 impl Ord for BlockInfo {
     fn cmp(&self, other: &Self) -> Ordering { self.mined.cmp(&other.mined) }
 }
@@ -183,6 +216,127 @@ impl FromStr for Inpoint {
     }
 }
 
+/// Transaction fee rate, measured in satoshis per virtual byte.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", try_from = "f64", into = "f64")
+)]
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default, Display)]
+#[display("{0} sat/vB")]
+pub struct FeeRate(f64);
+
+// Fee rate can't be constructed from NaN values, including deserialization, which is checked
+// by `TryFrom<f64>`, thus the equality is total.
+impl Eq for FeeRate {}
+
+impl TryFrom<f64> for FeeRate {
+    type Error = FeeRateParseError;
+
+    fn try_from(sat_per_vb: f64) -> Result<Self, Self::Error> {
+        if !sat_per_vb.is_finite() || sat_per_vb < 0.0 {
+            return Err(FeeRateParseError::Negative(sat_per_vb.to_string()));
+        }
+        Ok(FeeRate(sat_per_vb))
+    }
+}
+
+impl From<FeeRate> for f64 {
+    fn from(fee_rate: FeeRate) -> Self { fee_rate.0 }
+}
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0.0);
+
+    /// Minimal fee rate accepted for relay by the nodes with the default configuration.
+    pub const MIN_RELAY: FeeRate = FeeRate(1.0);
+
+    /// Constructs fee rate from a value in satoshis per virtual byte. Negative and non-finite
+    /// values are replaced with zero.
+    pub fn from_sat_per_vb(sat_per_vb: f64) -> Self {
+        if sat_per_vb.is_finite() && sat_per_vb > 0.0 {
+            FeeRate(sat_per_vb)
+        } else {
+            FeeRate::ZERO
+        }
+    }
+
+    /// Computes fee rate of a transaction with a given fee and weight.
+    pub fn from_fee(fee: Sats, weight: u32) -> Self {
+        if weight == 0 {
+            return FeeRate::ZERO;
+        }
+        FeeRate::from_sat_per_vb(fee.sats() as f64 / vsize(weight) as f64)
+    }
+
+    pub fn sat_per_vb(self) -> f64 { self.0 }
+
+    /// Computes fee which has to be paid for data of a given weight, rounding it up.
+    pub fn fee_for_weight(self, weight: u32) -> Sats {
+        Sats::from((vsize(weight) as f64 * self.0).ceil() as u64)
+    }
+}
+
+/// Fee rate estimations for different confirmation targets, measured in number of blocks.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct FeeEstimates(BTreeMap<u16, FeeRate>);
+
+impl FeeEstimates {
+    pub fn new() -> Self { default!() }
+
+    pub fn insert(&mut self, target: u16, fee_rate: FeeRate) -> Option<FeeRate> {
+        self.0.insert(target, fee_rate)
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, FeeRate)> + '_ {
+        self.0.iter().map(|(target, fee_rate)| (*target, *fee_rate))
+    }
+
+    /// Returns fee rate required for a confirmation within `target` blocks: the estimation for
+    /// the largest known target not exceeding the requested one. Returns `None` if all known
+    /// targets are larger than the requested one, since their estimations don't guarantee a
+    /// confirmation within `target` blocks.
+    pub fn for_target(&self, target: u16) -> Option<FeeRate> {
+        self.0.range(..=target).next_back().map(|(_, fee_rate)| *fee_rate)
+    }
+}
+
+impl FromIterator<(u16, FeeRate)> for FeeEstimates {
+    fn from_iter<T: IntoIterator<Item = (u16, FeeRate)>>(iter: T) -> Self {
+        FeeEstimates(iter.into_iter().collect())
+    }
+}
+
+/// Converts weight units into virtual bytes, rounding up.
+pub fn vsize(weight: u32) -> u32 { weight.div_ceil(4) }
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum FeeRateParseError {
+    /// invalid fee rate value. Details: {0}
+    #[from]
+    InvalidValue(ParseFloatError),
+
+    /// fee rate must be a finite non-negative number, while {0} was given.
+    Negative(String),
+}
+
+impl FromStr for FeeRate {
+    type Err = FeeRateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate = f64::from_str(s.trim_end_matches("sat/vB").trim())?;
+        FeeRate::try_from(rate).map_err(|_| FeeRateParseError::Negative(s.to_owned()))
+    }
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(serde::Serialize, serde::Deserialize),
@@ -199,9 +353,74 @@ pub struct WalletTx {
     pub weight: u32,
     pub version: TxVer,
     pub locktime: LockTime,
+    /// Transaction which replaced this one by spending some of its inputs (RBF), if any.
+    pub replaced_by: Option<Txid>,
+    /// Time when the wallet has first seen the transaction unconfirmed, as a UNIX timestamp.
+    pub first_seen: Option<u64>,
 }
 
 impl WalletTx {
+    /// Detects whether the transaction was replaced by a conflicting one.
+    pub fn is_replaced(&self) -> bool { self.replaced_by.is_some() }
+
+    /// Detects whether the transaction signals replaceability according to BIP125, i.e. has at
+    /// least one input with a sequence number below `0xFFFFFFFE`.
+    pub fn signals_rbf(&self) -> bool { self.inputs.iter().any(TxCredit::signals_rbf) }
+
+    /// Returns timestamp of the block which has mined the transaction or, for unconfirmed
+    /// transactions, the time when the transaction was first seen.
+    pub fn time(&self) -> Option<u64> {
+        match self.status {
+            TxStatus::Mined(info) => Some(info.time),
+            _ => self.first_seen,
+        }
+    }
+
+    /// Change of the wallet balance made by the transaction.
+    pub fn own_balance_change(&self) -> i64 {
+        let received =
+            self.outputs.iter().filter(|o| o.is_ourself()).map(|o| o.value.sats_i64()).sum::<i64>();
+        let spent =
+            self.inputs.iter().filter(|i| i.is_ourself()).map(|i| i.value.sats_i64()).sum::<i64>();
+        received - spent
+    }
+
+    /// Detects whether the transaction is known to the network (i.e. mined or present in a
+    /// mempool) and was not replaced by some other transaction.
+    pub fn is_live(&self) -> bool {
+        self.status.is_mined() || (self.status != TxStatus::Unknown && !self.is_replaced())
+    }
+
+    /// Reconstructs the transaction from the data kept by the wallet. Returns `None` if the
+    /// result doesn't match the transaction id, for instance when some of the input data were
+    /// not provided by the indexer.
+    pub fn to_tx(&self) -> Option<Tx> {
+        let inputs = self.inputs.iter().map(|credit| TxIn {
+            prev_output: credit.outpoint,
+            sig_script: credit.script_sig.clone(),
+            sequence: credit.sequence,
+            witness: credit.witness.clone(),
+        });
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|debit| {
+                let script_pubkey = match &debit.beneficiary {
+                    Party::Wallet(derived) => derived.addr.script_pubkey(),
+                    party => party.script_pubkey()?,
+                };
+                Some(TxOut::new(script_pubkey, debit.value))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let tx = Tx {
+            version: self.version,
+            inputs: VarIntArray::try_from_iter(inputs).ok()?,
+            outputs: VarIntArray::try_from(outputs).ok()?,
+            lock_time: self.locktime,
+        };
+        (tx.txid() == self.txid).then_some(tx)
+    }
+
     pub fn credits(&self) -> impl Iterator<Item = &TxCredit> {
         self.inputs.iter().filter(|c| c.is_external())
     }
@@ -316,6 +535,7 @@ impl TxCredit {
     pub fn is_ourself(&self) -> bool { self.payer.is_ourself() }
     pub fn is_external(&self) -> bool { !self.is_ourself() }
     pub fn derived_addr(&self) -> Option<DerivedAddr> { self.payer.derived_addr() }
+    pub fn signals_rbf(&self) -> bool { self.sequence.to_consensus_u32() < 0xFFFF_FFFE }
 }
 
 #[cfg_attr(
@@ -328,14 +548,100 @@ pub struct TxDebit {
     pub outpoint: Outpoint,
     pub beneficiary: Party,
     pub value: Sats,
-    // TODO: Add multiple spends (for RBFs) and mining info
-    pub spent: Option<Inpoint>,
+    /// All known transactions spending the output. There may be more than a single spend in case
+    /// of conflicting (RBF) transactions.
+    pub spends: Vec<TxSpend>,
 }
 
 impl TxDebit {
     pub fn is_ourself(&self) -> bool { self.beneficiary.is_ourself() }
     pub fn is_external(&self) -> bool { !self.is_ourself() }
     pub fn derived_addr(&self) -> Option<DerivedAddr> { self.beneficiary.derived_addr() }
+
+    /// Returns the spend which won among the conflicting ones: either the mined spend, or a spend
+    /// present in the mempool which was not replaced.
+    pub fn spender(&self) -> Option<&TxSpend> {
+        self.spends
+            .iter()
+            .find(|spend| spend.status.is_mined())
+            .or_else(|| self.spends.iter().find(|spend| spend.is_live()))
+    }
+
+    /// Input of the transaction spending the output, if any.
+    pub fn spent(&self) -> Option<Inpoint> { self.spender().map(|spend| spend.inpoint) }
+}
+
+/// Transaction input spending a wallet output.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TxSpend {
+    pub inpoint: Inpoint,
+    /// Status of the spending transaction.
+    pub status: TxStatus,
+    /// Transaction which replaced the spending transaction, if any.
+    pub replaced_by: Option<Txid>,
+}
+
+impl TxSpend {
+    /// Detects whether the spending transaction is mined or present in a mempool and was not
+    /// replaced.
+    pub fn is_live(&self) -> bool {
+        self.status.is_mined() || (self.status != TxStatus::Unknown && self.replaced_by.is_none())
+    }
+}
+
+/// Spending status of a wallet transaction output.
+///
+/// Spends by the transactions which are neither mined nor present in the mempool (i.e. having
+/// [`TxStatus::Unknown`]) are not taken into account.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+pub enum UtxoStatus {
+    /// Output of a mined transaction which is not spent.
+    #[display("confirmed")]
+    Confirmed,
+
+    /// Output of an unconfirmed transaction which is not spent.
+    #[display("unconfirmed")]
+    Unconfirmed,
+
+    /// Output spent by an unconfirmed transaction.
+    #[display("spent-unconfirmed")]
+    SpentUnconfirmed,
+
+    /// Output spent by a mined transaction.
+    #[display("spent")]
+    SpentConfirmed,
+}
+
+impl UtxoStatus {
+    /// Detects status of an output from the status of the transaction which created it and
+    /// the status of the transaction spending it, if any.
+    pub fn with(status: TxStatus, spender: Option<TxStatus>) -> Self {
+        match (status, spender) {
+            (_, Some(TxStatus::Mined(_))) => UtxoStatus::SpentConfirmed,
+            (_, Some(TxStatus::Mempool | TxStatus::Channel)) => UtxoStatus::SpentUnconfirmed,
+            (TxStatus::Mined(_), _) => UtxoStatus::Confirmed,
+            _ => UtxoStatus::Unconfirmed,
+        }
+    }
+
+    /// Detects whether the output can be spent by a new transaction.
+    pub fn is_spendable(self) -> bool {
+        matches!(self, UtxoStatus::Confirmed | UtxoStatus::Unconfirmed)
+    }
+
+    pub fn is_spent(self) -> bool { !self.is_spendable() }
+
+    pub fn is_confirmed(self) -> bool { self == UtxoStatus::Confirmed }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -343,11 +649,15 @@ pub struct WalletUtxo {
     pub outpoint: Outpoint,
     pub value: Sats,
     pub terminal: Terminal,
+    /// Status of the transaction which created the output.
     pub status: TxStatus,
+    pub state: UtxoStatus,
     // TODO: Add layer 2
 }
 
 impl WalletUtxo {
+    #[inline]
+    pub fn is_spendable(&self) -> bool { self.state.is_spendable() }
     #[inline]
     pub fn to_prevout(&self) -> Prevout { Prevout::new(self.outpoint, self.value) }
     pub fn into_outpoint(self) -> Outpoint { self.outpoint }
@@ -419,3 +729,43 @@ impl WalletAddr<i64> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fee_rate_parse() {
+        assert_eq!(FeeRate::from_str("2.5"), Ok(FeeRate(2.5)));
+        assert_eq!(FeeRate::from_str("1 sat/vB"), Ok(FeeRate::MIN_RELAY));
+        assert_eq!(FeeRate::from_str(&FeeRate(3.0).to_string()), Ok(FeeRate(3.0)));
+        assert!(FeeRate::from_str("-1").is_err());
+        assert!(FeeRate::from_str("NaN").is_err());
+        assert!(FeeRate::from_str("inf").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn fee_rate_serde() {
+        let estimates = FeeEstimates::from_iter([(1, FeeRate(10.0)), (6, FeeRate(2.5))]);
+        let yaml = serde_yaml::to_string(&estimates).unwrap();
+        assert_eq!(serde_yaml::from_str::<FeeEstimates>(&yaml).unwrap(), estimates);
+        assert!(serde_yaml::from_str::<FeeRate>(".nan").is_err());
+        assert!(serde_yaml::from_str::<FeeRate>(".inf").is_err());
+        assert!(serde_yaml::from_str::<FeeRate>("-1.0").is_err());
+    }
+
+    #[test]
+    fn fee_estimates_target() {
+        let estimates = FeeEstimates::from_iter([(2, FeeRate(10.0)), (6, FeeRate(2.5))]);
+        assert_eq!(estimates.for_target(1), None);
+        assert_eq!(estimates.for_target(2), Some(FeeRate(10.0)));
+        assert_eq!(estimates.for_target(5), Some(FeeRate(10.0)));
+        assert_eq!(estimates.for_target(1008), Some(FeeRate(2.5)));
+
+        // P2P transport provides only the minimal relay fee for the longest target.
+        let estimates = FeeEstimates::from_iter([(1008, FeeRate::MIN_RELAY)]);
+        assert_eq!(estimates.for_target(6), None);
+        assert_eq!(FeeEstimates::new().for_target(6), None);
+    }
+}
@@ -0,0 +1,1121 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of output descriptors given as strings, as defined in [BIP-380].
+//!
+//! [BIP-380]: https://github.com/bitcoin/bips/blob/master/bip-0380.mediawiki
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::iter;
+use std::str::FromStr;
+
+use amplify::num::u7;
+use bpstd::{
+    ConsensusEncode, ControlBlock, Derive, DerivedScript, InternalPk, KeyOrigin, Keychain,
+    LeafInfo, LeafScript, LeafVer, LegacyPk, LockTime, NormalIndex, PubkeyHash, RedeemScript,
+    ScriptPubkey, SigScript, TapDerivation, TapLeafHash, TapMerklePath, TapTree, Terminal,
+    WPubkeyHash, Witness, WitnessScript, XOnlyPk, XkeyParseError, XpubAccount, XpubDerivable,
+};
+use descriptors::{Descriptor, LegacyKeySig, SpkClass, StdDescr, TaprootKeySig};
+use indexmap::IndexMap;
+use psbt::{Input, Output, Psbt};
+use sha2::{Digest, Sha256};
+
+use crate::coinselect::{input_weight, varint_len, TXIN_BASE_WEIGHT};
+use crate::keys::check_key_version;
+use crate::miniscript::{
+    key_bytes, parse_key, push_data, split_args, stack_size, Satisfier, ScriptContext,
+};
+use crate::{
+    parse_xpub_derivable, Bip43, DerivationStandard, DescrKey, KeyVersion, Miniscript, SingleKey,
+    Slip132Error, TapNode, Timelock,
+};
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!\
+                             ^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Errors parsing output descriptor.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum DescrParseError {
+    /// descriptor contains invalid character '{0}'.
+    InvalidChar(char),
+
+    /// descriptor checksum '{0}' is invalid; the checksum must be 8 characters long.
+    InvalidChecksum(String),
+
+    /// descriptor checksum '{found}' doesn't match the descriptor, which has checksum
+    /// '{expected}'.
+    ChecksumMismatch { expected: String, found: String },
+
+    /// invalid descriptor syntax in '{0}'.
+    Syntax(String),
+
+    /// unknown descriptor type '{0}'.
+    UnknownType(String),
+
+    /// script type for the key '{0}' can't be deduced from the key version or its derivation
+    /// path; please provide the descriptor in full form, like `wpkh(KEY)`.
+    NoScript(String),
+
+    /// key '{0}' is a multisig key; multisig descriptors must list keys of all cosigners, like
+    /// `wsh(sortedmulti(2,KEY1,KEY2,KEY3))`.
+    MultisigKey(String),
+
+    /// {0} descriptors are not supported; supported descriptors are pkh, wpkh, sh(wpkh), wsh,
+    /// sh(wsh) and tr.
+    Unsupported(String),
+
+    /// unsupported script fragment '{0}'; scripts may use pk, pkh, multi, sortedmulti, multi_a,
+    /// sortedmulti_a, older, after, and_v, or_d and or_i fragments and v: wrapper.
+    Fragment(String),
+
+    /// script expression '{0}' is not a valid miniscript, or it can't be used as a whole
+    /// spending script.
+    InvalidScript(String),
+
+    /// invalid threshold {0} for {1} keys.
+    InvalidThreshold(usize, usize),
+
+    /// invalid timelock value '{0}'; it must be a number between 1 and 2147483647.
+    InvalidTimelock(String),
+
+    /// invalid public key '{0}'.
+    InvalidPubkey(String),
+
+    /// key '{0}' has {1} version, which is used with a different script type; use the key in
+    /// xpub or tpub form, or a descriptor matching the key version.
+    KeyVersion(String, KeyVersion),
+
+    /// key '{0}' can't be used in this descriptor; segwit descriptors require compressed keys,
+    /// and x-only keys are allowed only inside tr().
+    KeyContext(String),
+
+    /// invalid descriptor key - {0}
+    #[from]
+    #[from(XkeyParseError)]
+    Key(Slip132Error),
+}
+
+fn polymod(c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ val;
+    for (bit, gen) in [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd]
+        .into_iter()
+        .enumerate()
+    {
+        if c0 & (1 << bit) != 0 {
+            c ^= gen;
+        }
+    }
+    c
+}
+
+/// Computes BIP-380 checksum of a descriptor string, which must not contain a checksum itself.
+pub fn descriptor_checksum(descr: &str) -> Result<String, DescrParseError> {
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut cls_count = 0u8;
+    for ch in descr.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or(DescrParseError::InvalidChar(ch))? as u64;
+        c = polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = polymod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect())
+}
+
+/// Appends BIP-380 checksum to a descriptor string.
+pub fn descriptor_with_checksum(descr: &str) -> Result<String, DescrParseError> {
+    Ok(format!("{descr}#{}", descriptor_checksum(descr)?))
+}
+
+/// Checks the checksum of a descriptor string, if present, and returns the descriptor without
+/// the checksum.
+pub fn strip_descriptor_checksum(s: &str) -> Result<&str, DescrParseError> {
+    let Some((descr, checksum)) = s.split_once('#') else {
+        return Ok(s);
+    };
+    if checksum.len() != 8 {
+        return Err(DescrParseError::InvalidChecksum(checksum.to_owned()));
+    }
+    let expected = descriptor_checksum(descr)?;
+    if expected != checksum {
+        return Err(DescrParseError::ChecksumMismatch {
+            expected,
+            found: checksum.to_owned(),
+        });
+    }
+    Ok(descr)
+}
+
+/// Splits descriptor expression `name(args)` into its name and arguments.
+fn split_expr(s: &str) -> Option<(&str, &str)> { s.strip_suffix(')')?.split_once('(') }
+
+/// Output descriptor of any type supported by the wallet.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", try_from = "DescrRepr", into = "DescrRepr")
+)]
+pub enum AnyDescr {
+    /// `pkh(KEY)`: pay to public key hash.
+    Pkh(DescrKey),
+    /// `wpkh(KEY)`: pay to witness public key hash.
+    Wpkh(DescrKey),
+    /// `sh(wpkh(KEY))`: pay to witness public key hash nested into script hash.
+    ShWpkh(DescrKey),
+    /// `wsh(SCRIPT)`: pay to witness script hash.
+    Wsh(Miniscript),
+    /// `sh(wsh(SCRIPT))`: pay to witness script hash nested into script hash.
+    ShWsh(Miniscript),
+    /// `tr(KEY)`: taproot output without spending scripts.
+    TrKey(DescrKey),
+    /// `tr(KEY,TREE)`: taproot output with a script tree.
+    Tr(DescrKey, TapNode),
+}
+
+/// Serialized form of [`AnyDescr`], compatible with the serialization of the descriptors
+/// library: the descriptor type followed by the descriptor arguments.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+enum DescrRepr {
+    Pkh(String),
+    Wpkh(String),
+    ShWpkh(String),
+    Wsh(String),
+    ShWsh(String),
+    TrKey(String),
+    Tr(String),
+}
+
+#[cfg(feature = "serde")]
+impl From<AnyDescr> for DescrRepr {
+    fn from(descr: AnyDescr) -> Self {
+        match descr {
+            AnyDescr::Pkh(key) => DescrRepr::Pkh(key.to_string()),
+            AnyDescr::Wpkh(key) => DescrRepr::Wpkh(key.to_string()),
+            AnyDescr::ShWpkh(key) => DescrRepr::ShWpkh(key.to_string()),
+            AnyDescr::Wsh(script) => DescrRepr::Wsh(script.to_string()),
+            AnyDescr::ShWsh(script) => DescrRepr::ShWsh(script.to_string()),
+            AnyDescr::TrKey(key) => DescrRepr::TrKey(key.to_string()),
+            AnyDescr::Tr(key, tree) => DescrRepr::Tr(format!("{key},{tree}")),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<DescrRepr> for AnyDescr {
+    type Error = DescrParseError;
+
+    fn try_from(repr: DescrRepr) -> Result<Self, Self::Error> {
+        parse_descriptor(&match repr {
+            DescrRepr::Pkh(args) => format!("pkh({args})"),
+            DescrRepr::Wpkh(args) => format!("wpkh({args})"),
+            DescrRepr::ShWpkh(args) => format!("sh(wpkh({args}))"),
+            DescrRepr::Wsh(args) => format!("wsh({args})"),
+            DescrRepr::ShWsh(args) => format!("sh(wsh({args}))"),
+            DescrRepr::TrKey(args) | DescrRepr::Tr(args) => format!("tr({args})"),
+        })
+    }
+}
+
+impl From<StdDescr> for AnyDescr {
+    fn from(descr: StdDescr) -> Self {
+        match descr {
+            StdDescr::Wpkh(wpkh) => AnyDescr::Wpkh(wpkh.into_key().into()),
+            StdDescr::TrKey(tr) => AnyDescr::TrKey(tr.into_internal_key().into()),
+            _ => unreachable!("descriptors library has no other descriptor types"),
+        }
+    }
+}
+
+/// Leaf of a derived script tree: control block, leaf script and depth of the leaf.
+type TapLeafInfo = (ControlBlock, LeafScript, u8);
+
+/// Signatures and timelock context of a PSBT input, used to satisfy spending scripts.
+struct InputSatisfier {
+    sigs: HashMap<Vec<u8>, Vec<u8>>,
+    /// Input sequence number and transaction lock time; when not known, timelocks are assumed
+    /// to be satisfied.
+    timelocks: Option<(u32, u32)>,
+}
+
+impl Satisfier for InputSatisfier {
+    fn signature(&self, key: &[u8]) -> Option<Vec<u8>> { self.sigs.get(key).cloned() }
+
+    fn check_after(&self, lock: u32) -> bool {
+        const THRESHOLD: u32 = 500_000_000;
+        let Some((sequence, lock_time)) = self.timelocks else {
+            return true;
+        };
+        sequence != u32::MAX && (lock < THRESHOLD) == (lock_time < THRESHOLD) && lock_time >= lock
+    }
+
+    fn check_older(&self, lock: u32) -> bool {
+        const DISABLE_FLAG: u32 = 1 << 31;
+        const TYPE_FLAG: u32 = 1 << 22;
+        let Some((sequence, _)) = self.timelocks else {
+            return true;
+        };
+        sequence & DISABLE_FLAG == 0
+            && sequence & TYPE_FLAG == lock & TYPE_FLAG
+            && sequence & 0xFFFF >= lock & 0xFFFF
+    }
+}
+
+/// Constructs P2WSH script pubkey for the witness script.
+///
+/// `WitnessScript::to_script_pubkey` of the consensus library hashes the script with double
+/// SHA256, while BIP-141 requires a single one.
+fn p2wsh(witness_script: &WitnessScript) -> ScriptPubkey {
+    ScriptPubkey::p2wsh(<[u8; 32]>::from(Sha256::digest(witness_script.as_slice())))
+}
+
+fn push_script(items: &[&[u8]]) -> SigScript {
+    let mut script = vec![];
+    for item in items {
+        push_data(&mut script, item);
+    }
+    SigScript::from_unsafe(script)
+}
+
+impl AnyDescr {
+    /// Constructs all descriptors which may lock funds to a single key, like the key of a paper
+    /// wallet: `pkh`, and for compressed keys also `sh(wpkh)`, `wpkh` and `tr`.
+    pub fn with_single_key(key: SingleKey) -> Vec<AnyDescr> {
+        if !key.key.compressed {
+            return vec![AnyDescr::Pkh(key.into())];
+        }
+        let xonly = SingleKey {
+            xonly: true,
+            ..key.clone()
+        };
+        vec![
+            AnyDescr::Pkh(key.clone().into()),
+            AnyDescr::ShWpkh(key.clone().into()),
+            AnyDescr::Wpkh(key.into()),
+            AnyDescr::TrKey(xonly.into()),
+        ]
+    }
+
+    /// Returns all keys used by the descriptor, in the order of their appearance.
+    pub fn descr_keys(&self) -> Vec<&DescrKey> {
+        match self {
+            AnyDescr::Pkh(key)
+            | AnyDescr::Wpkh(key)
+            | AnyDescr::ShWpkh(key)
+            | AnyDescr::TrKey(key) => vec![key],
+            AnyDescr::Wsh(script) | AnyDescr::ShWsh(script) => script.keys(),
+            AnyDescr::Tr(key, tree) => iter::once(key)
+                .chain(tree.leaves().into_iter().flat_map(Miniscript::keys))
+                .collect(),
+        }
+    }
+
+    /// Constructs descriptor of the same type using keys produced by the function from the
+    /// original ones.
+    pub fn map_keys(&self, f: impl Fn(&DescrKey) -> DescrKey) -> AnyDescr {
+        match self {
+            AnyDescr::Pkh(key) => AnyDescr::Pkh(f(key)),
+            AnyDescr::Wpkh(key) => AnyDescr::Wpkh(f(key)),
+            AnyDescr::ShWpkh(key) => AnyDescr::ShWpkh(f(key)),
+            AnyDescr::Wsh(script) => AnyDescr::Wsh(script.map_keys(&f)),
+            AnyDescr::ShWsh(script) => AnyDescr::ShWsh(script.map_keys(&f)),
+            AnyDescr::TrKey(key) => AnyDescr::TrKey(f(key)),
+            AnyDescr::Tr(key, tree) => AnyDescr::Tr(f(key), tree.map_keys(&f)),
+        }
+    }
+
+    /// Returns all spending scripts of the descriptor.
+    pub fn scripts(&self) -> Vec<&Miniscript> {
+        match self {
+            AnyDescr::Wsh(script) | AnyDescr::ShWsh(script) => vec![script],
+            AnyDescr::Tr(_, tree) => tree.leaves(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns witness script for segwit v0 script descriptors.
+    fn witness_script(&self, terminal: Terminal) -> Option<WitnessScript> {
+        match self {
+            AnyDescr::Wsh(script) | AnyDescr::ShWsh(script) => Some(WitnessScript::from_unsafe(
+                script.to_script_bytes(ScriptContext::Segwit, terminal),
+            )),
+            _ => None,
+        }
+    }
+
+    fn expect_witness_script(&self, terminal: Terminal) -> WitnessScript {
+        self.witness_script(terminal).expect("wsh descriptor")
+    }
+
+    /// Detects derivation terminal of a PSBT input or output from the origins of its keys.
+    fn detect_terminal<'a>(
+        &self,
+        origins: impl IntoIterator<Item = &'a KeyOrigin>,
+    ) -> Option<Terminal> {
+        let keys = self.descr_keys();
+        origins.into_iter().find_map(|origin| keys.iter().find_map(|key| key.terminal(origin)))
+    }
+
+    /// Constructs signature script and witness spending an output derived for the terminal.
+    fn satisfy(
+        &self,
+        terminal: Terminal,
+        satisfier: &InputSatisfier,
+    ) -> Option<(SigScript, Witness)> {
+        let ctx = ScriptContext::Segwit;
+        let single = |key: &DescrKey| {
+            let key = key_bytes(key, ctx, terminal);
+            let sig = satisfier.signature(&key)?;
+            Some(vec![sig, key])
+        };
+        let script = |script: &Miniscript| {
+            let mut stack = script.satisfy(ctx, terminal, satisfier)?;
+            stack.push(script.to_script_bytes(ctx, terminal));
+            Some(stack)
+        };
+        Some(match self {
+            AnyDescr::Pkh(key) => {
+                let stack = single(key)?;
+                (push_script(&[&stack[0], &stack[1]]), empty!())
+            }
+            AnyDescr::Wpkh(key) => (empty!(), Witness::from_consensus_stack(single(key)?)),
+            AnyDescr::ShWpkh(key) => {
+                let stack = single(key)?;
+                let hash = WPubkeyHash::from(key.derive_compr(terminal));
+                let redeem_script = RedeemScript::p2sh_wpkh(hash);
+                (push_script(&[redeem_script.as_slice()]), Witness::from_consensus_stack(stack))
+            }
+            AnyDescr::Wsh(ms) => (empty!(), Witness::from_consensus_stack(script(ms)?)),
+            AnyDescr::ShWsh(ms) => {
+                let redeem_script = p2wsh(&self.witness_script(terminal)?);
+                let witness = Witness::from_consensus_stack(script(ms)?);
+                (push_script(&[redeem_script.as_slice()]), witness)
+            }
+            AnyDescr::TrKey(_) | AnyDescr::Tr(..) => return None,
+        })
+    }
+
+    /// Constructs taproot script path witness spending an output derived for the terminal,
+    /// using the cheapest of the satisfiable leaves. Signatures are provided for each leaf.
+    fn satisfy_script_path(
+        &self,
+        terminal: Terminal,
+        satisfier: impl Fn(TapLeafHash) -> InputSatisfier,
+    ) -> Option<Witness> {
+        let AnyDescr::Tr(key, tree) = self else {
+            return None;
+        };
+        let internal_pk = InternalPk::from_unchecked(key.derive_xonly(terminal));
+        let (root, leaves) = tree.derive(terminal);
+        let parity = internal_pk.to_output_pk(Some(root)).1;
+        leaves
+            .into_iter()
+            .filter_map(|leaf| {
+                let satisfier = satisfier(leaf.script.tap_leaf_hash());
+                let mut stack =
+                    leaf.miniscript.satisfy(ScriptContext::Tapscript, terminal, &satisfier)?;
+                let path = TapMerklePath::try_from(leaf.path).ok()?;
+                let control_block =
+                    ControlBlock::with(LeafVer::TapScript, internal_pk, parity, path);
+                stack.push(leaf.script.script.to_vec());
+                stack.push(control_block.consensus_serialize());
+                Some(stack)
+            })
+            .min_by_key(stack_size)
+            .map(Witness::from_consensus_stack)
+    }
+
+    /// Derives the leaves of the script tree for the terminal, together with the internal key,
+    /// merkle root and output key parity.
+    fn tap_tree(&self, terminal: Terminal) -> Option<(InternalPk, Vec<TapLeafInfo>)> {
+        let AnyDescr::Tr(key, tree) = self else {
+            return None;
+        };
+        let internal_pk = InternalPk::from_unchecked(key.derive_xonly(terminal));
+        let (root, leaves) = tree.derive(terminal);
+        let parity = internal_pk.to_output_pk(Some(root)).1;
+        let leaves = leaves
+            .into_iter()
+            .filter_map(|leaf| {
+                let path = TapMerklePath::try_from(leaf.path).ok()?;
+                let control_block =
+                    ControlBlock::with(LeafVer::TapScript, internal_pk, parity, path);
+                Some((control_block, leaf.script, leaf.depth))
+            })
+            .collect();
+        Some((internal_pk, leaves))
+    }
+}
+
+impl Derive<DerivedScript> for AnyDescr {
+    fn default_keychain(&self) -> Keychain {
+        self.keychains().first().copied().unwrap_or(Keychain::OUTER)
+    }
+
+    fn keychains(&self) -> BTreeSet<Keychain> {
+        self.descr_keys()
+            .into_iter()
+            .find(|key| key.as_xpub().is_some())
+            .map(DescrKey::keychains)
+            .unwrap_or_else(|| bset![Keychain::OUTER])
+    }
+
+    fn derive(
+        &self,
+        keychain: impl Into<Keychain>,
+        index: impl Into<NormalIndex>,
+    ) -> DerivedScript {
+        let terminal = Terminal::new(keychain, index.into());
+        match self {
+            AnyDescr::Pkh(key) => DerivedScript::Bare(ScriptPubkey::p2pkh(PubkeyHash::from(
+                key.derive_legacy(terminal),
+            ))),
+            AnyDescr::Wpkh(key) => DerivedScript::Bare(ScriptPubkey::p2wpkh(WPubkeyHash::from(
+                key.derive_compr(terminal),
+            ))),
+            AnyDescr::ShWpkh(key) => DerivedScript::Bip13(RedeemScript::p2sh_wpkh(
+                WPubkeyHash::from(key.derive_compr(terminal)),
+            )),
+            // The witness script is added to PSBTs by `PsbtDescriptor`, since the hash of the
+            // witness script can't be computed by the consensus library (see `p2wsh`).
+            AnyDescr::Wsh(_) => DerivedScript::Bare(p2wsh(&self.expect_witness_script(terminal))),
+            AnyDescr::ShWsh(_) => DerivedScript::Bip13(RedeemScript::from_unsafe(
+                p2wsh(&self.expect_witness_script(terminal)).to_vec(),
+            )),
+            AnyDescr::TrKey(key) => DerivedScript::TaprootKeyOnly(InternalPk::from_unchecked(
+                key.derive_xonly(terminal),
+            )),
+            // The descriptors library can't compute merkle root of trees with multiple leaves,
+            // so the taproot data are added to PSBTs by `PsbtDescriptor` instead.
+            AnyDescr::Tr(key, tree) => {
+                let internal_pk = InternalPk::from_unchecked(key.derive_xonly(terminal));
+                let (root, _) = tree.derive(terminal);
+                DerivedScript::Bare(ScriptPubkey::p2tr(internal_pk, Some(root)))
+            }
+        }
+    }
+}
+
+impl Descriptor<XpubDerivable> for AnyDescr {
+    fn class(&self) -> SpkClass {
+        match self {
+            AnyDescr::Pkh(_) => SpkClass::P2pkh,
+            AnyDescr::Wpkh(_) => SpkClass::P2wpkh,
+            AnyDescr::ShWpkh(_) | AnyDescr::ShWsh(_) => SpkClass::P2sh,
+            AnyDescr::Wsh(_) => SpkClass::P2wsh,
+            AnyDescr::TrKey(_) | AnyDescr::Tr(..) => SpkClass::P2tr,
+        }
+    }
+
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a XpubDerivable>
+    where XpubDerivable: 'a {
+        self.descr_keys().into_iter().filter_map(DescrKey::as_xpub)
+    }
+
+    fn vars<'a>(&'a self) -> impl Iterator<Item = &'a ()>
+    where (): 'a {
+        iter::empty()
+    }
+
+    fn xpubs(&self) -> impl Iterator<Item = &XpubAccount> {
+        self.descr_keys().into_iter().filter_map(DescrKey::xpub_spec)
+    }
+
+    fn legacy_keyset(&self, terminal: Terminal) -> IndexMap<LegacyPk, KeyOrigin> {
+        if self.is_taproot() {
+            return IndexMap::new();
+        }
+        self.descr_keys()
+            .into_iter()
+            .map(|key| (key.derive_legacy(terminal), key.origin(terminal)))
+            .collect()
+    }
+
+    fn xonly_keyset(&self, terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation> {
+        let mut map = IndexMap::new();
+        let (internal_key, tree) = match self {
+            AnyDescr::TrKey(key) => (key, None),
+            AnyDescr::Tr(key, tree) => (key, Some(tree)),
+            _ => return map,
+        };
+        map.insert(internal_key.derive_xonly(terminal), TapDerivation {
+            leaf_hashes: vec![],
+            origin: internal_key.origin(terminal),
+        });
+        for leaf in tree.map(|tree| tree.derive(terminal).1).unwrap_or_default() {
+            let leaf_hash = leaf.script.tap_leaf_hash();
+            for key in leaf.miniscript.keys() {
+                let derivation =
+                    map.entry(key.derive_xonly(terminal)).or_insert_with(|| TapDerivation {
+                        leaf_hashes: vec![],
+                        origin: key.origin(terminal),
+                    });
+                if !derivation.leaf_hashes.contains(&leaf_hash) {
+                    derivation.leaf_hashes.push(leaf_hash);
+                }
+            }
+        }
+        map
+    }
+
+    fn legacy_witness(
+        &self,
+        keysigs: HashMap<&KeyOrigin, LegacyKeySig>,
+    ) -> Option<(SigScript, Witness)> {
+        let terminal = self.detect_terminal(keysigs.keys().copied())?;
+        let satisfier = InputSatisfier {
+            sigs: keysigs.values().map(|ks| (ks.key.to_vec(), ks.sig.to_vec())).collect(),
+            timelocks: None,
+        };
+        self.satisfy(terminal, &satisfier)
+    }
+
+    /// Constructs key path witness. Script path spending requires information about the leaf
+    /// the signatures are made for, thus it is supported by [`PsbtDescriptor::finalize_input`]
+    /// only.
+    fn taproot_witness(&self, keysigs: HashMap<&KeyOrigin, TaprootKeySig>) -> Option<Witness> {
+        let key = match self {
+            AnyDescr::TrKey(key) | AnyDescr::Tr(key, _) => key,
+            _ => return None,
+        };
+        keysigs.iter().find_map(|(origin, ks)| {
+            let terminal = key.terminal(origin)?;
+            (key.derive_xonly(terminal) == ks.key)
+                .then(|| Witness::from_consensus_stack([ks.sig.to_vec()]))
+        })
+    }
+}
+
+/// Descriptor which can add to PSBTs the data not provided by [`Descriptor`], and finalize PSBT
+/// inputs spending its outputs.
+pub trait PsbtDescriptor<K = XpubDerivable, V = ()>: Descriptor<K, V> + Sized {
+    /// Adds to a PSBT input spending output derived for the terminal the data which are not
+    /// filled in by [`Psbt::construct_input`].
+    fn complete_input(&self, _input: &mut Input, _terminal: Terminal) {}
+
+    /// Adds to a PSBT change output derived for the terminal the data which are not filled in
+    /// by [`Psbt::construct_change`].
+    fn complete_output(&self, _output: &mut Output, _terminal: Terminal) {}
+
+    /// Estimates the maximal weight of a transaction input spending an output derived for the
+    /// terminal.
+    fn input_weight(&self, terminal: Terminal) -> u32 { input_weight(self, terminal) }
+
+    /// Returns timelocks of the spending branches which are satisfied only by transactions with
+    /// a specific lock time or input sequence number.
+    fn timelocks(&self) -> BTreeSet<Timelock> { bset![] }
+
+    /// Detects whether the outputs are spent with witness, and thus PSBT inputs spending them
+    /// don't need to provide the full previous transaction (BIP174 `PSBT_IN_NON_WITNESS_UTXO`).
+    fn is_segwit(&self) -> bool {
+        !matches!(self.class(), SpkClass::Bare | SpkClass::P2pkh | SpkClass::P2sh)
+    }
+
+    /// Finalizes PSBT input, checking timelocks of the spending scripts against the input
+    /// sequence number and the transaction lock time. Returns whether the input was finalized.
+    fn finalize_input(&self, input: &mut Input, _lock_time: LockTime) -> bool {
+        input.finalize(self)
+    }
+
+    /// Finalizes all PSBT inputs which can be finalized, returning their number.
+    fn finalize_psbt(&self, psbt: &mut Psbt) -> usize {
+        let lock_time = psbt.lock_time();
+        psbt.inputs_mut().map(|input| self.finalize_input(input, lock_time) as usize).sum()
+    }
+}
+
+impl PsbtDescriptor for StdDescr {}
+impl PsbtDescriptor for descriptors::Wpkh {}
+impl PsbtDescriptor for descriptors::TrKey {}
+
+impl PsbtDescriptor for AnyDescr {
+    fn is_segwit(&self) -> bool { !matches!(self, AnyDescr::Pkh(_)) }
+
+    /// Estimates the weight from the largest satisfaction of the spending scripts; for the
+    /// taproot descriptors the largest of the key path and script path spendings is used.
+    fn input_weight(&self, terminal: Terminal) -> u32 {
+        fn item(len: u32) -> u32 { varint_len(len) + len }
+        fn script_witness(satisfaction: usize, script_len: usize) -> u32 {
+            1 + satisfaction as u32 + item(script_len as u32)
+        }
+        match self {
+            AnyDescr::Wsh(script) | AnyDescr::ShWsh(script) => {
+                let ctx = ScriptContext::Segwit;
+                let script_len = script.to_script_bytes(ctx, terminal).len();
+                let sig_script = if matches!(self, AnyDescr::ShWsh(_)) { 35 * 4 } else { 0 };
+                TXIN_BASE_WEIGHT
+                    + sig_script
+                    + script_witness(script.max_satisfaction_size(ctx), script_len)
+            }
+            AnyDescr::Tr(_, tree) => {
+                let key_path = 1 + 1 + 64;
+                let script_path = tree
+                    .derive(terminal)
+                    .1
+                    .into_iter()
+                    .map(|leaf| {
+                        let satisfaction =
+                            leaf.miniscript.max_satisfaction_size(ScriptContext::Tapscript);
+                        let control_block = 33 + 32 * leaf.path.len() as u32;
+                        script_witness(satisfaction, leaf.script.script.len()) + item(control_block)
+                    })
+                    .max()
+                    .unwrap_or_default();
+                TXIN_BASE_WEIGHT + script_path.max(key_path)
+            }
+            _ => input_weight(self, terminal),
+        }
+    }
+
+    fn timelocks(&self) -> BTreeSet<Timelock> {
+        self.scripts().into_iter().flat_map(Miniscript::timelocks).collect()
+    }
+
+    fn complete_input(&self, input: &mut Input, terminal: Terminal) {
+        if let Some(witness_script) = self.witness_script(terminal) {
+            input.witness_script = Some(witness_script);
+        }
+        let Some((internal_pk, leaves)) = self.tap_tree(terminal) else {
+            return;
+        };
+        input.tap_internal_key = Some(internal_pk);
+        input.tap_merkle_root = match self {
+            AnyDescr::Tr(_, tree) => Some(tree.derive(terminal).0),
+            _ => None,
+        };
+        input.tap_leaf_script =
+            leaves.into_iter().map(|(control_block, script, _)| (control_block, script)).collect();
+    }
+
+    fn complete_output(&self, output: &mut Output, terminal: Terminal) {
+        if let Some(witness_script) = self.witness_script(terminal) {
+            output.witness_script = Some(witness_script);
+        }
+        let Some((internal_pk, leaves)) = self.tap_tree(terminal) else {
+            return;
+        };
+        output.tap_internal_key = Some(internal_pk);
+        output.tap_tree = leaves
+            .into_iter()
+            .map(|(_, script, depth)| {
+                Some(LeafInfo {
+                    depth: u7::try_from(depth).ok()?,
+                    script,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .and_then(|leaves| TapTree::from_leaves(leaves).ok());
+    }
+
+    fn finalize_input(&self, input: &mut Input, lock_time: LockTime) -> bool {
+        if input.is_finalized() {
+            return false;
+        }
+        let origins = input
+            .bip32_derivation
+            .values()
+            .chain(input.tap_bip32_derivation.values().map(|derivation| &derivation.origin));
+        let Some(terminal) = self.detect_terminal(origins) else {
+            return false;
+        };
+        let timelocks = Some((
+            input
+                .sequence_number
+                .unwrap_or(bpstd::SeqNo::from_consensus_u32(u32::MAX))
+                .to_consensus_u32(),
+            lock_time.to_consensus_u32(),
+        ));
+
+        let satisfaction = if self.is_taproot() {
+            let key_path =
+                input.tap_key_sig.map(|sig| Witness::from_consensus_stack([sig.to_vec()]));
+            key_path
+                .or_else(|| {
+                    self.satisfy_script_path(terminal, |leaf_hash| InputSatisfier {
+                        sigs: input
+                            .tap_script_sig
+                            .iter()
+                            .filter(|((_, hash), _)| *hash == leaf_hash)
+                            .map(|((pk, _), sig)| (pk.to_byte_array().to_vec(), sig.to_vec()))
+                            .collect(),
+                        timelocks,
+                    })
+                })
+                .map(|witness| (empty!(), witness))
+        } else {
+            let satisfier = InputSatisfier {
+                sigs: input
+                    .partial_sigs
+                    .iter()
+                    .map(|(pk, sig)| (pk.to_vec(), sig.to_vec()))
+                    .collect(),
+                timelocks,
+            };
+            self.satisfy(terminal, &satisfier)
+        };
+        let Some((sig_script, witness)) = satisfaction else {
+            return false;
+        };
+
+        input.final_script_sig = Some(sig_script);
+        input.final_witness = Some(witness);
+        // Same fields as cleared by `Input::finalize`
+        input.partial_sigs.clear();
+        input.sighash_type = None;
+        input.redeem_script = None;
+        input.witness_script = None;
+        input.bip32_derivation.clear();
+        input.ripemd160.clear();
+        input.sha256.clear();
+        input.hash160.clear();
+        input.hash256.clear();
+        input.tap_key_sig = None;
+        input.tap_script_sig.clear();
+        input.tap_leaf_script.clear();
+        input.tap_bip32_derivation.clear();
+        input.tap_internal_key = None;
+        input.tap_merkle_root = None;
+        true
+    }
+}
+
+impl Display for AnyDescr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AnyDescr::Pkh(key) => write!(f, "pkh({key})"),
+            AnyDescr::Wpkh(key) => write!(f, "wpkh({key})"),
+            AnyDescr::ShWpkh(key) => write!(f, "sh(wpkh({key}))"),
+            AnyDescr::Wsh(script) => write!(f, "wsh({script})"),
+            AnyDescr::ShWsh(script) => write!(f, "sh(wsh({script}))"),
+            AnyDescr::TrKey(key) => write!(f, "tr({key})"),
+            AnyDescr::Tr(key, tree) => write!(f, "tr({key},{tree})"),
+        }
+    }
+}
+
+impl FromStr for AnyDescr {
+    type Err = DescrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { parse_descriptor(s) }
+}
+
+/// Constructs descriptor from a bare key expression, deducing the script type from the SLIP-132
+/// key version or, for `xpub` and `tpub` keys, from the key origin derivation path.
+fn descriptor_from_key(s: &str) -> Result<AnyDescr, DescrParseError> {
+    let (key, version) = parse_xpub_derivable(s)?;
+    let standard = version.standard().or_else(|| Bip43::deduce(&key.origin().to_derivation()));
+    let key = DescrKey::from(key);
+    match standard {
+        Some(Bip43::Bip44) => Ok(AnyDescr::Pkh(key)),
+        Some(Bip43::Bip49) => Ok(AnyDescr::ShWpkh(key)),
+        Some(Bip43::Bip84) => Ok(AnyDescr::Wpkh(key)),
+        Some(Bip43::Bip86) => Ok(AnyDescr::TrKey(key)),
+        Some(Bip43::Bip48Nested | Bip43::Bip48Native) => {
+            Err(DescrParseError::MultisigKey(s.to_owned()))
+        }
+        _ => Err(DescrParseError::NoScript(s.to_owned())),
+    }
+}
+
+/// Parses output descriptor string, optionally followed by a checksum.
+///
+/// Extended keys must have origin and derivation terminal, like
+/// `[fingerprint/84h/0h/0h]xpub.../<0;1>/*`; SLIP-132 keys like `zpub` are accepted as well.
+/// Single keys are given in hex, optionally with an origin. A bare extended key without a script
+/// expression is converted into a descriptor matching the key version or the derivation
+/// standard of the key origin.
+pub fn parse_descriptor(s: &str) -> Result<AnyDescr, DescrParseError> {
+    let descr = strip_descriptor_checksum(s.trim())?;
+    if !descr.contains('(') {
+        return descriptor_from_key(descr);
+    }
+    let syntax = || DescrParseError::Syntax(s.to_owned());
+    let (name, args) = split_expr(descr).ok_or_else(syntax)?;
+    let segwit = ScriptContext::Segwit;
+    match name {
+        "pkh" => {
+            check_key_version(args, &[Bip43::Bip44])?;
+            let key = DescrKey::from_str(args)?;
+            if key.is_xonly() {
+                return Err(DescrParseError::KeyContext(args.to_owned()));
+            }
+            Ok(AnyDescr::Pkh(key))
+        }
+        "wpkh" => Ok(AnyDescr::Wpkh(parse_key(args, segwit, &[Bip43::Bip84])?)),
+        "sh" => match split_expr(args) {
+            Some(("wpkh", key)) => Ok(AnyDescr::ShWpkh(parse_key(key, segwit, &[Bip43::Bip49])?)),
+            Some(("wsh", script)) => Ok(AnyDescr::ShWsh(Miniscript::parse(script, segwit)?)),
+            Some((inner, _)) => Err(DescrParseError::Unsupported(format!("sh({inner})"))),
+            None => Err(syntax()),
+        },
+        "wsh" => Ok(AnyDescr::Wsh(Miniscript::parse(args, segwit)?)),
+        "tr" => match split_args(args).ok_or_else(syntax)?.as_slice() {
+            [key] => Ok(AnyDescr::TrKey(parse_key(key, ScriptContext::Tapscript, &[])?)),
+            [key, tree] => Ok(AnyDescr::Tr(
+                parse_key(key, ScriptContext::Tapscript, &[])?,
+                TapNode::parse(tree)?,
+            )),
+            _ => Err(syntax()),
+        },
+        "pk" | "combo" | "multi" | "sortedmulti" | "multi_a" | "sortedmulti_a" | "addr" | "raw"
+        | "rawtr" => Err(DescrParseError::Unsupported(name.to_owned())),
+        _ => Err(DescrParseError::UnknownType(name.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::hex::{FromHex, ToHex};
+    use bpstd::{AddressNetwork, Idx};
+
+    use super::*;
+    use crate::util::address_with;
+
+    const KEY1: &str = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+    const KEY2: &str = "03774ae7f858a9411e5ef4246b70c65aac5649980be5c17891bbec17895da008cb";
+    const KEY3: &str = "03d01115d548e7561b15c38f004d734633687cf4419620095bc5b0f47070afe85a";
+    const SINGLE: &str = "03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd";
+    const XONLY1: &str = "669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0";
+    const XONLY2: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    fn script_pubkey(descr: &str) -> String {
+        let descr = parse_descriptor(descr).unwrap();
+        descr.derive(Keychain::OUTER, NormalIndex::ZERO).to_script_pubkey().to_hex()
+    }
+
+    #[test]
+    fn checksum_bip380() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(strip_descriptor_checksum("raw(deadbeef)#89f8spxm"), Ok("raw(deadbeef)"));
+        for invalid in [
+            "raw(deadbeef)#",
+            "raw(deadbeef)#89f8spxmx",
+            "raw(deadbeef)#89f8spx",
+            "raw(deadbeef)#89f8spxn",
+            "raw(deedbeef)#89f8spxm",
+            "raw(deadbeef)##9f8spxm",
+            "raw(Ü)#00000000",
+        ] {
+            assert!(strip_descriptor_checksum(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(
+            parse_descriptor("raw(deadbeef)#89f8spxm"),
+            Err(DescrParseError::Unsupported(s!("raw")))
+        );
+    }
+
+    #[test]
+    fn script_vectors() {
+        let multi = format!("multi(2,{KEY1},{KEY2},{KEY3})");
+        for (descr, spk) in [
+            (format!("pkh({SINGLE})"), "76a9149a1c78a507689f6f54b847ad1cef1e614ee23f1e88ac"),
+            (format!("wpkh({SINGLE})"), "00149a1c78a507689f6f54b847ad1cef1e614ee23f1e"),
+            (format!("sh(wpkh({SINGLE}))"), "a91484ab21b1b2fd065d4504ff693d832434b6108d7b87"),
+            (
+                format!("wsh({multi})"),
+                "0020773d709598b76c4e3b575c08aad40658963f9322affc0f8c28d1d9a68d0c944a",
+            ),
+            (
+                format!("wsh(sorted{multi})"),
+                "0020ec566436042722eb23f0a043b896b10fe2443f4d15cc606dcfc14fa48187fa25",
+            ),
+            (format!("sh(wsh({multi}))"), "a91494710ea1ce24ee0a7851b50fe5224e9edd68631387"),
+            (
+                format!("tr({})", &SINGLE[2..]),
+                "512077aab6e066f8a7419c5ab714c12c67d25007ed55a43cadcacb4d7a970a093f11",
+            ),
+            (
+                format!("tr({},pk({XONLY1}))", &SINGLE[2..]),
+                "512017cf18db381d836d8923b1bdb246cfcd818da1a9f0e6e7907f187f0b2f937754",
+            ),
+        ] {
+            assert_eq!(script_pubkey(&descr), spk, "{descr}");
+        }
+    }
+
+    #[test]
+    fn account_vectors() {
+        for (descr, addr) in [
+            (
+                "pkh([73c5da0a/44h/0h/0h]xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj/<0;1>/*)",
+                "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA",
+            ),
+            (
+                "sh(wpkh([73c5da0a/49h/0h/0h]ypub6Ww3ibxVfGzLrAH1PNcjyAWenMTbbAosGNB6VvmSEgytSER9azLDWCxoJwW7Ke7icmizBMXrzBx9979FfaHxHcrArf3zbeJJJUZPf663zsP/<0;1>/*))",
+                "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf",
+            ),
+            (
+                "wpkh([73c5da0a/84h/0h/0h]zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs/<0;1>/*)",
+                "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+            ),
+            (
+                "tr([73c5da0a/86h/0h/0h]xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/<0;1>/*)",
+                "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+            ),
+        ] {
+            let descr = parse_descriptor(descr).unwrap();
+            let spk = descr.derive(Keychain::OUTER, NormalIndex::ZERO).to_script_pubkey();
+            let address = address_with(&spk, AddressNetwork::Mainnet).unwrap();
+            assert_eq!(address.to_string(), addr);
+        }
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        for descr in [
+            format!("pkh([d34db33f/0h]{SINGLE})"),
+            format!("wsh(and_v(v:pk({KEY1}),older(144)))"),
+            format!("wsh(or_d(pk({KEY1}),and_v(v:pkh({KEY2}),after(840000))))"),
+            format!("sh(wsh(or_i(pk({KEY1}),and_v(v:pk({KEY2}),older(4032)))))"),
+            format!("tr({XONLY1},{{pk({XONLY2}),sortedmulti_a(1,{XONLY1},{XONLY2})}})"),
+            format!("tr({XONLY1},{{multi_a(2,{XONLY1},{XONLY2}),{{pk({XONLY2}),pk({XONLY1})}}}})"),
+        ] {
+            let parsed = parse_descriptor(&descr).unwrap();
+            assert_eq!(parsed.to_string(), descr);
+            #[cfg(feature = "serde")]
+            {
+                let json = serde_json::to_string(&parsed).unwrap();
+                assert_eq!(serde_json::from_str::<AnyDescr>(&json).unwrap(), parsed);
+            }
+        }
+    }
+
+    /// Wallet descriptor files written by the versions of the wallet supporting only the
+    /// descriptors of the descriptors library, which serialized [`StdDescr`], must still load.
+    #[test]
+    #[cfg(feature = "serde")]
+    fn std_descr_wallet_file() {
+        use bpstd::Network;
+
+        use crate::{testing, WalletDescr};
+
+        const WPKH: &str = r#"network = "testnet3"
+
+[generator]
+wpkh = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*"
+"#;
+        const TR_KEY: &str = r#"network = "testnet3"
+
+[generator]
+trKey = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*"
+"#;
+
+        for (file, expected) in [(WPKH, "wpkh"), (TR_KEY, "tr")] {
+            let wallet_descr =
+                toml::from_str::<WalletDescr<XpubDerivable, AnyDescr>>(file).unwrap();
+            let expected = parse_descriptor(&format!("{expected}({})", testing::TPUB)).unwrap();
+            assert_eq!(wallet_descr.generator(), &expected);
+            assert_eq!(wallet_descr.network(), Network::Testnet3);
+        }
+    }
+
+    #[test]
+    fn invalid_descriptors() {
+        let uncompressed = "04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235";
+        assert!(parse_descriptor(&format!("pkh({uncompressed})")).is_ok());
+        for descr in [
+            format!("wpkh({uncompressed})"),
+            format!("wpkh({XONLY1})"),
+            format!("wsh(pk({XONLY1}))"),
+            format!("tr({XONLY1},multi(1,{KEY1}))"),
+            format!("wsh(multi_a(1,{KEY1}))"),
+            format!("wsh(and_v(v:pk({KEY1}),sortedmulti(1,{KEY2})))"),
+            format!("wsh(multi(0,{KEY1},{KEY2}))"),
+            format!("wsh(multi(3,{KEY1},{KEY2}))"),
+            format!("combo({KEY1})"),
+        ] {
+            assert!(parse_descriptor(&descr).is_err(), "{descr}");
+        }
+        assert_eq!(
+            parse_descriptor(&format!("wsh(multi(3,{KEY1},{KEY2}))")),
+            Err(DescrParseError::InvalidThreshold(3, 2))
+        );
+    }
+
+    #[test]
+    fn satisfaction() {
+        let terminal = Terminal::new(Keychain::OUTER, NormalIndex::ZERO);
+        let key = |hex: &str| Vec::<u8>::from_hex(hex).unwrap();
+        let satisfier = |sigs: &[(&str, &[u8])], timelocks| InputSatisfier {
+            sigs: sigs.iter().map(|(k, sig)| (key(k), sig.to_vec())).collect(),
+            timelocks,
+        };
+
+        let descr = parse_descriptor(&format!("wsh(sortedmulti(2,{KEY1},{KEY2},{KEY3}))")).unwrap();
+        let ws = descr.witness_script(terminal).unwrap();
+        let (_, witness) =
+            descr.satisfy(terminal, &satisfier(&[(KEY3, b"sig3"), (KEY2, b"sig2")], None)).unwrap();
+        assert_eq!(
+            witness,
+            Witness::from_consensus_stack([
+                vec![],
+                b"sig2".to_vec(),
+                b"sig3".to_vec(),
+                ws.to_vec()
+            ])
+        );
+        assert!(descr.satisfy(terminal, &satisfier(&[(KEY1, b"sig1")], None)).is_none());
+
+        let descr =
+            parse_descriptor(&format!("wsh(or_d(pk({KEY1}),and_v(v:pk({KEY2}),older(144))))"))
+                .unwrap();
+        let sigs = [(KEY2, b"sig2".as_slice())];
+        assert!(descr.satisfy(terminal, &satisfier(&sigs, Some((144, 0)))).is_some());
+        assert!(descr.satisfy(terminal, &satisfier(&sigs, Some((100, 0)))).is_none());
+        assert!(descr.satisfy(terminal, &satisfier(&sigs, Some((u32::MAX, 0)))).is_none());
+        let sigs = [(KEY1, b"sig1".as_slice())];
+        assert!(descr.satisfy(terminal, &satisfier(&sigs, Some((u32::MAX, 0)))).is_some());
+
+        let descr = parse_descriptor(&format!(
+            "tr({},{{pk({XONLY1}),{{pk({XONLY2}),pk({XONLY1})}}}})",
+            &SINGLE[2..]
+        ))
+        .unwrap();
+        let witness = descr
+            .satisfy_script_path(terminal, |_| satisfier(&[(XONLY2, &[1u8; 64])], None))
+            .unwrap();
+        let elements = witness.elements().collect::<Vec<_>>();
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0], &[1u8; 64]);
+        assert_eq!(elements[1].to_hex(), format!("20{XONLY2}ac"));
+        assert_eq!(elements[2].len(), 33 + 32 * 2);
+    }
+}
@@ -0,0 +1,335 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of the wallet transaction history for accounting purposes.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
+
+use bpstd::{DerivedAddr, Sats, Txid};
+
+use crate::util::parse_date;
+use crate::{format_timestamp, BlockHeight, Counterparty, Layer2Tx, OpType, TxRow, TxStatus};
+
+/// Format of the exported transaction history.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum HistoryFormat {
+    /// Comma-separated values, suitable for spreadsheets and accounting software.
+    #[display("csv")]
+    Csv,
+
+    /// JSON array of history records.
+    #[display("json")]
+    Json,
+
+    /// YAML list of history records.
+    #[display("yaml")]
+    Yaml,
+}
+
+/// Errors parsing fiat price table.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PriceTableError {
+    /// line {0} of the price table must contain a date and a price separated by a comma.
+    InvalidLine(usize),
+
+    /// line {0} of the price table contains invalid date '{1}'; the date must be given either in
+    /// `YYYY-MM-DD` format or as a UNIX timestamp.
+    InvalidDate(usize, String),
+
+    /// line {0} of the price table contains invalid price '{1}'.
+    InvalidPrice(usize, String),
+
+    /// price table contains no prices.
+    Empty,
+}
+
+/// Historical fiat prices of bitcoin, used to compute fiat value of the wallet transactions.
+///
+/// The table is read from a CSV file, where each line contains a date (in `YYYY-MM-DD` format or
+/// as a UNIX timestamp) and the price of a single bitcoin at that date. Empty lines, comments
+/// starting with `#` and a header line are ignored, as well as any columns following the price.
+/// A transaction is valued using the latest price known at the time of the transaction.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PriceTable(BTreeMap<u64, f64>);
+
+impl FromStr for PriceTable {
+    type Err = PriceTableError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut prices = BTreeMap::new();
+        for (no, line) in s.lines().enumerate().map(|(no, line)| (no + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut cols = line.split(',').map(|col| col.trim().trim_matches('"'));
+            let (Some(date), Some(price)) = (cols.next(), cols.next()) else {
+                return Err(PriceTableError::InvalidLine(no));
+            };
+            let Ok(price) = f64::from_str(price) else {
+                if prices.is_empty() && no == 1 {
+                    // Header line
+                    continue;
+                }
+                return Err(PriceTableError::InvalidPrice(no, price.to_owned()));
+            };
+            if !price.is_finite() || price < 0.0 {
+                return Err(PriceTableError::InvalidPrice(no, price.to_string()));
+            }
+            let timestamp = parse_date(date)
+                .or_else(|| u64::from_str(date).ok())
+                .ok_or_else(|| PriceTableError::InvalidDate(no, date.to_owned()))?;
+            prices.insert(timestamp, price);
+        }
+        if prices.is_empty() {
+            return Err(PriceTableError::Empty);
+        }
+        Ok(PriceTable(prices))
+    }
+}
+
+impl PriceTable {
+    /// Returns the latest known price at the given time, if any.
+    pub fn price_at(&self, timestamp: u64) -> Option<f64> {
+        self.0.range(..=timestamp).next_back().map(|(_, price)| *price)
+    }
+
+    /// Computes fiat value of the provided amount at the given time, rounded to cents.
+    pub fn value_at(&self, amount: Sats, timestamp: u64) -> Option<f64> {
+        let value = self.price_at(timestamp)? * amount.sats() as f64 / Sats::BTC.sats() as f64;
+        Some((value * 100.0).round() / 100.0)
+    }
+}
+
+/// Single transaction of the exported wallet history.
+#[derive(Clone, PartialEq, Debug, serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct HistoryRecord {
+    /// UTC date and time of the transaction.
+    pub date: Option<String>,
+    pub height: TxStatus<BlockHeight>,
+    pub txid: Txid,
+    pub direction: OpType,
+    pub amount: Sats,
+    pub fee: Sats,
+    /// Wallet balance after the transaction.
+    pub balance: i64,
+    /// Fiat value of the transaction amount at the time of the transaction, negative for
+    /// debits.
+    pub fiat_value: Option<f64>,
+    /// Label assigned to the transaction by the user.
+    pub label: Option<String>,
+    pub counterparties: Vec<(Counterparty, i64)>,
+    pub own: Vec<(DerivedAddr, i64)>,
+}
+
+impl HistoryRecord {
+    pub fn with<L2: Layer2Tx>(
+        row: TxRow<L2>,
+        label: Option<String>,
+        prices: Option<&PriceTable>,
+    ) -> Self {
+        let fiat_value = prices.zip(row.time).and_then(|(p, time)| p.value_at(row.amount, time));
+        HistoryRecord {
+            date: row.time.map(format_timestamp),
+            height: row.height,
+            txid: row.txid,
+            direction: row.operation,
+            amount: row.amount,
+            fee: row.fee,
+            balance: row.balance,
+            fiat_value: fiat_value.map(|value| match row.operation {
+                OpType::Debit if value != 0.0 => -value,
+                _ => value,
+            }),
+            label,
+            counterparties: row.counterparties,
+            own: row.own,
+        }
+    }
+}
+
+impl HistoryFormat {
+    const CSV_HEADER: &'static str =
+        "date,height,txid,direction,amount,fee,balance,fiat_value,label,counterparties,own";
+
+    /// Serializes wallet history records into the writer using this format.
+    pub fn write(self, records: &[HistoryRecord], mut writer: impl io::Write) -> io::Result<()> {
+        match self {
+            HistoryFormat::Csv => {
+                writeln!(writer, "{}", Self::CSV_HEADER)?;
+                for record in records {
+                    writeln!(writer, "{}", csv_line(record))?;
+                }
+                Ok(())
+            }
+            HistoryFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, records)?;
+                writeln!(writer)
+            }
+            HistoryFormat::Yaml => serde_yaml::to_writer(writer, records).map_err(io::Error::other),
+        }
+    }
+}
+
+fn csv_line(record: &HistoryRecord) -> String {
+    let parties = |list: Vec<String>| list.join("; ");
+    [
+        record.date.clone().unwrap_or_default(),
+        record.height.to_string(),
+        record.txid.to_string(),
+        match record.direction {
+            OpType::Credit => s!("credit"),
+            OpType::Debit => s!("debit"),
+        },
+        record.amount.sats().to_string(),
+        record.fee.sats().to_string(),
+        record.balance.to_string(),
+        record.fiat_value.map(|value| format!("{value:.2}")).unwrap_or_default(),
+        record.label.clone().unwrap_or_default(),
+        parties(
+            record.counterparties.iter().map(|(cp, value)| format!("{cp} {value:+}")).collect(),
+        ),
+        parties(record.own.iter().map(|(addr, value)| format!("{addr} {value:+}")).collect()),
+    ]
+    .iter()
+    .map(|field| csv_escape(field))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bpstd::{Keychain, Outpoint, ScriptPubkey, TxOut};
+
+    use super::*;
+    use crate::indexers::memory::MemoryIndexer;
+    use crate::{testing, Indexer, LabelRef};
+
+    #[test]
+    fn price_table() {
+        let prices = PriceTable::from_str(
+            "date,price\n# comment\n\n2024-01-01,40000.5\n\"1704153600\",\"45000\",extra\n",
+        )
+        .unwrap();
+        let jan1 = 1704067200;
+        assert_eq!(prices.price_at(jan1 - 1), None);
+        assert_eq!(prices.price_at(jan1), Some(40000.5));
+        assert_eq!(prices.price_at(jan1 + 86399), Some(40000.5));
+        assert_eq!(prices.price_at(jan1 + 86400), Some(45000.0));
+        assert_eq!(prices.price_at(u64::MAX), Some(45000.0));
+        assert_eq!(prices.value_at(Sats(12_345), jan1), Some(4.94));
+        assert_eq!(prices.value_at(Sats::from_btc(2), jan1 - 1), None);
+
+        assert_eq!(PriceTable::from_str("# no prices\n"), Err(PriceTableError::Empty));
+        assert_eq!(PriceTable::from_str("2024-01-01"), Err(PriceTableError::InvalidLine(1)));
+        assert_eq!(
+            PriceTable::from_str("2024-01-01,1\n2024-01-02,x"),
+            Err(PriceTableError::InvalidPrice(2, s!("x")))
+        );
+        assert_eq!(
+            PriceTable::from_str("2024-13-01,1"),
+            Err(PriceTableError::InvalidDate(1, s!("2024-13-01")))
+        );
+        assert_eq!(
+            PriceTable::from_str("2024-01-01,-1"),
+            Err(PriceTableError::InvalidPrice(1, s!("-1")))
+        );
+    }
+
+    #[test]
+    fn history_records() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let foreign = ScriptPubkey::from_unsafe(vec![0x51]);
+        let coin = indexer.fund(foreign.clone(), Sats::from_btc(2));
+        let funding = testing::tx([coin], [
+            TxOut::new(script, Sats::from_btc(1)),
+            TxOut::new(foreign.clone(), Sats(99_990_000)),
+        ]);
+        let spending = testing::tx([Outpoint::new(funding.txid(), 0)], [TxOut::new(
+            foreign,
+            Sats(90_000_000),
+        )]);
+        indexer.publish(&funding).unwrap();
+        indexer.publish(&spending).unwrap();
+        indexer.mine();
+        wallet.update(&indexer).into_result().unwrap();
+        wallet.set_label(LabelRef::Tx(spending.txid()), "rent, \"march\"");
+
+        let prices = PriceTable::from_str("0,50000").unwrap();
+        let records = wallet.history_records(Some(&prices));
+        assert_eq!(records.len(), 2);
+        let (received, spent) = (&records[0], &records[1]);
+        assert_eq!(received.txid, funding.txid());
+        assert_eq!(received.direction, OpType::Credit);
+        assert_eq!(received.amount, Sats::from_btc(1));
+        assert_eq!(received.balance, 100_000_000);
+        assert_eq!(received.fiat_value, Some(50000.0));
+        assert_eq!(received.label, None);
+        assert_eq!(spent.txid, spending.txid());
+        assert_eq!(spent.direction, OpType::Debit);
+        assert_eq!(spent.amount, Sats(90_000_000));
+        assert_eq!(spent.balance, 0);
+        assert_eq!(spent.fiat_value, Some(-45000.0));
+        assert_eq!(spent.label.as_deref(), Some("rent, \"march\""));
+        assert!(spent.date.is_some());
+        assert!(wallet.history_records(None).iter().all(|record| record.fiat_value.is_none()));
+
+        let mut csv = vec![];
+        HistoryFormat::Csv.write(&records[1..], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(HistoryFormat::CSV_HEADER));
+        let line = lines.next().unwrap();
+        assert_eq!(lines.next(), None);
+        let prefix = format!(
+            "{},{},{},debit,90000000,10000000,0,-45000.00,\"rent, \"\"march\"\"\",",
+            spent.date.as_ref().unwrap(),
+            spent.height,
+            spending.txid()
+        );
+        assert!(line.starts_with(&prefix), "{line}");
+        assert!(line.ends_with(" -100000000"), "{line}");
+    }
+
+    #[test]
+    fn csv_escaping() {
+        assert_eq!(csv_escape("plain text"), "plain text");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_escape("cr\r"), "\"cr\r\"");
+    }
+}
@@ -21,22 +21,30 @@
 // limitations under the License.
 
 use std::env::VarError;
+use std::fmt::Display;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{env, fs};
 
 use amplify::hex::ToHex;
 use amplify::{Display, IoError};
 use bip39::Mnemonic;
-use bpstd::signers::TestnetRefSigner;
-use bpstd::{HardenedIndex, SighashCache, Tx, XprivAccount};
+use bpstd::{
+    HardenedIndex, SighashCache, Sign, Tx, XkeyOrigin, XkeyParseError, Xpriv, XprivAccount,
+};
 use clap::Subcommand;
 use colored::Colorize;
-use psbt::Psbt;
+use psbt::{Psbt, Signer};
 
+use crate::hot::signer::{sign_legacy, WifSigner, XprivSigner};
 use crate::hot::{calculate_entropy, DataError, SecureIo, Seed, SeedType};
-use crate::Bip43;
+use crate::{
+    xpriv_to_version, AnyDescr, Bip43, DerivationStandard, KeyVersion, Slip132Xpub, WifKey,
+};
 
 const SEED_PASSWORD_ENVVAR: &str = "SEED_PASSWORD";
+/// Length of the longest private key in WIF, which is the one for the compressed public key.
+const WIF_MAX_LEN: usize = 52;
 
 /// Command-line arguments
 #[derive(Parser)]
@@ -95,6 +103,25 @@ pub enum HotCommand {
         output_file: PathBuf,
     },
 
+    /// Import an existing private key and save it as a signing account, for instance for signing
+    /// PSBTs sweeping funds from an old or a paper wallet. The key may be an extended private key
+    /// with its origin (`[fp/path]xprv`), a master extended private key, or a single private key
+    /// in wallet import format (WIF). The key is read from the terminal without echoing it.
+    #[display("import")]
+    Import {
+        /// Do not ask for a password and default to an empty-line password. For testing purposes
+        /// only
+        #[clap(short = 'N', long, conflicts_with = "mainnet")]
+        no_password: bool,
+
+        /// The key is used for bitcoin mainnet. Must match the network of the imported key
+        #[clap(long)]
+        mainnet: bool,
+
+        /// Output file for storing account-based extended private key
+        output_file: PathBuf,
+    },
+
     /// Print information about seed or the signing account
     #[display("info")]
     Info {
@@ -143,6 +170,11 @@ impl HotArgs {
                 mainnet,
                 output_file,
             } => derive(&seed_file, scheme, account, mainnet, &output_file, no_password)?,
+            HotCommand::Import {
+                no_password,
+                mainnet,
+                output_file,
+            } => import(mainnet, &output_file, no_password)?,
             HotCommand::Info {
                 file,
                 print_private,
@@ -168,8 +200,7 @@ fn get_password(
             match env::var(varname) {
                 Ok(password) => return Ok(password),
                 Err(VarError::NotUnicode(_)) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
+                    return Err(std::io::Error::other(
                         "password set by environment is not a valid unicode string",
                     ));
                 }
@@ -186,7 +217,7 @@ fn get_password(
         if !accept_weak && (password.is_empty() || entropy < 64.0) {
             eprintln!("Entropy is too low, please try with a different password");
             if password_envvar.is_some() {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "low password entropy"));
+                return Err(std::io::Error::other("low password entropy"));
             } else {
                 continue;
             }
@@ -225,6 +256,8 @@ fn info(file: &Path, print_private: bool) -> Result<(), IoError> {
         info_seed(seed, print_private)
     } else if let Ok(account) = XprivAccount::read(file, &password) {
         info_account(account, print_private)
+    } else if let Ok(wif) = WifKey::read(file, &password) {
+        info_wif(wif, print_private)
     } else {
         eprintln!("{} can't detect file format for `{}`", "Error:".bright_red(), file.display());
     }
@@ -256,6 +289,10 @@ fn info_seed(seed: Seed, print_private: bool) {
 
 fn info_account(account: XprivAccount, print_private: bool) {
     let xpub = account.to_xpub_account();
+    // SLIP-132 version of the keys, if the derivation path follows a standard having one
+    let testnet = xpub.xpub().is_testnet();
+    let standard = KeyVersion::with_standard(None, testnet);
+    let version = KeyVersion::with_standard(Bip43::deduce(&xpub.to_derivation()), testnet);
     println!("\n{} {}", "Account:".bright_white(), xpub);
     println!(
         "{:-18} {}",
@@ -271,10 +308,35 @@ fn info_account(account: XprivAccount, print_private: bool) {
             "  - xpriv:".bright_white(),
             account_xpriv.to_string().black().dimmed()
         );
-        // TODO: Add Zpriv etc
+        if version != standard {
+            let slip132 = xpriv_to_version(account_xpriv, version).expect("same network");
+            let title = format!("  - {}:", version.to_string().replace("pub", "prv"));
+            println!("{:-18} {}", title.bright_white(), slip132.black().dimmed());
+        }
     }
     println!("{:-18} {}", "  - xpub:".bright_white(), xpub.to_string().bright_green());
-    // TODO: Add Zpub etc
+    if version != standard {
+        let slip132 = Slip132Xpub {
+            xpub: *xpub.xpub(),
+            version: standard,
+        }
+        .to_version(version)
+        .expect("same network");
+        println!("{:-18} {}", format!("  - {version}:").bright_white(), slip132.bright_green());
+    }
+}
+
+fn info_wif(wif: WifKey, print_private: bool) {
+    println!("\n{} {}", "Key:".bright_white(), wif.to_single_key());
+    println!("{:-18} {}", "  - mainnet:", if wif.testnet { "no" } else { "yes" });
+    println!("{:-18} {}", "  - compressed:", if wif.compressed { "yes" } else { "no" });
+    if print_private {
+        println!("{:-18} {}", "  - wif:".bright_white(), wif.to_string().black().dimmed());
+    }
+    println!("{}", "  - descriptors:".bright_white());
+    for descr in AnyDescr::with_single_key(wif.to_single_key()) {
+        println!("    {}", descr.to_string().bright_green());
+    }
 }
 
 fn derive(
@@ -307,13 +369,114 @@ fn derive(
     Ok(())
 }
 
+fn import(mainnet: bool, output_file: &Path, no_password: bool) -> Result<(), DataError> {
+    let key = rpassword::prompt_password("Extended private key or WIF private key: ")?;
+    let key = key.trim();
+    let account = if key.starts_with('[') {
+        XprivAccount::from_str(key)?
+    } else if key.len() > WIF_MAX_LEN {
+        // Without origin only master keys can be used, since the origin of other keys can't be
+        // restored.
+        let xpriv = Xpriv::from_str(key)?;
+        if xpriv.depth() != 0 {
+            return Err(XkeyParseError::NoOrigin.into());
+        }
+        XprivAccount::new(xpriv, XkeyOrigin::new(xpriv.fingerprint(), empty!()))
+    } else {
+        let wif = WifKey::from_str(key)?;
+        return import_wif(wif, mainnet, output_file, no_password);
+    };
+    check_network(account.xpriv().is_testnet(), mainnet, no_password)?;
+
+    let account_password = if !mainnet && no_password {
+        s!("")
+    } else {
+        get_password(None, "Account password:", !mainnet)?
+    };
+
+    account.write(output_file, &account_password)?;
+    XprivAccount::read(output_file, &account_password).inspect_err(|_| {
+        eprintln!("Unable to save account file");
+        let _ = fs::remove_file(output_file);
+    })?;
+
+    info_account(account, false);
+
+    Ok(())
+}
+
+fn import_wif(
+    wif: WifKey,
+    mainnet: bool,
+    output_file: &Path,
+    no_password: bool,
+) -> Result<(), DataError> {
+    check_network(wif.testnet, mainnet, no_password)?;
+    let account_password = if !mainnet && no_password {
+        s!("")
+    } else {
+        get_password(None, "Account password:", !mainnet)?
+    };
+
+    wif.write(output_file, &account_password)?;
+    WifKey::read(output_file, &account_password).inspect_err(|_| {
+        eprintln!("Unable to save account file");
+        let _ = fs::remove_file(output_file);
+    })?;
+
+    info_wif(wif, false);
+
+    Ok(())
+}
+
+/// Checks that the network of an imported key matches the one requested by the user, and that
+/// mainnet keys are always protected with a password.
+fn check_network(testnet: bool, mainnet: bool, no_password: bool) -> Result<(), DataError> {
+    if !testnet && no_password {
+        return Err(DataError::UnencryptedMainnet);
+    }
+    if testnet == mainnet {
+        return Err(DataError::NetworkMismatch);
+    }
+    Ok(())
+}
+
 fn sign(psbt_file: &Path, account_file: &Path, no_password: bool) -> Result<(), DataError> {
     eprintln!("Signing {} with {}", psbt_file.display(), account_file.display());
     let password = if no_password { s!("") } else { rpassword::prompt_password("Password: ")? };
+    if let Ok(wif) = WifKey::read(account_file, &password) {
+        let testnet = wif.testnet;
+        return sign_psbt(
+            psbt_file,
+            wif.to_single_key(),
+            testnet,
+            &password,
+            &WifSigner::new(&wif),
+        );
+    }
     let account = XprivAccount::read(account_file, &password)?;
+    let testnet = account.xpriv().is_testnet();
+    let key = account.to_xpub_account();
+    sign_psbt(psbt_file, key, testnet, &password, &XprivSigner::new(&account))
+}
 
-    eprintln!("Signing key: {}", account.to_xpub_account());
-    eprintln!("Signing using testnet signer");
+fn sign_psbt<S: Signer>(
+    psbt_file: &Path,
+    key: impl Display,
+    testnet: bool,
+    password: &str,
+    signer: &S,
+) -> Result<(), DataError>
+where
+    for<'s> &'s S: Sign,
+{
+    // Mainnet keys must never be kept unencrypted, even if the file was created by some other
+    // tool.
+    if !testnet && password.is_empty() {
+        return Err(DataError::UnencryptedMainnet);
+    }
+    eprintln!("Signing key: {key}");
+    eprintln!("Signing using {} key", if testnet { "testnet" } else { "mainnet" });
 
     let data = fs::read(psbt_file)?;
     let mut psbt = Psbt::deserialize(&data)?;
@@ -321,8 +484,7 @@ fn sign(psbt_file: &Path, account_file: &Path, no_password: bool) -> Result<(),
     eprintln!("PSBT version: {:#}", psbt.version);
     eprintln!("Transaction id: {}", psbt.txid());
 
-    let signer = TestnetRefSigner::new(&account);
-    let sig_count = psbt.sign(&signer)?;
+    let sig_count = psbt.sign(signer)? + sign_legacy(&mut psbt, &signer)?;
 
     fs::write(psbt_file, psbt.serialize(psbt.version))?;
     eprintln!(
@@ -40,9 +40,12 @@ mod io {
     use aes_gcm::aead::{Aead, Nonce, OsRng};
     use aes_gcm::{AeadCore, Aes256Gcm, KeyInit};
     use amplify::IoError;
+    use bpstd::XkeyParseError;
     use psbt::{PsbtError, SignError};
     use sha2::{Digest, Sha256};
 
+    use crate::WifError;
+
     pub fn encrypt(source: Vec<u8>, key: impl AsRef<[u8]>) -> Vec<u8> {
         let key = Sha256::digest(key.as_ref());
         let key = aes_gcm::Key::<Aes256Gcm>::from_slice(key.as_slice());
@@ -78,6 +81,23 @@ mod io {
         #[display("invalid account key password.")]
         AccountPassword,
 
+        #[display("invalid extended private key: {0}")]
+        #[from]
+        AccountKey(XkeyParseError),
+
+        #[display("invalid private key: {0}")]
+        #[from]
+        PrivateKey(WifError),
+
+        #[display("mainnet keys can't be stored or used without a password.")]
+        UnencryptedMainnet,
+
+        #[display(
+            "the key network doesn't match the requested one; `--mainnet` flag must be used with \
+             mainnet keys only."
+        )]
+        NetworkMismatch,
+
         #[from]
         Psbt(PsbtError),
 
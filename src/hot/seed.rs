@@ -30,7 +30,7 @@ use rand::RngCore;
 
 use crate::bip43::DerivationStandard;
 use crate::hot::{decrypt, encrypt, DataError, SecureIo};
-use crate::Bip43;
+use crate::{Bip43, WifKey};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 #[repr(u16)]
@@ -128,3 +128,18 @@ impl SecureIo for XprivAccount {
         fs::write(file, encrypt(self.to_string().into_bytes(), password))
     }
 }
+
+impl SecureIo for WifKey {
+    fn read<P>(file: P, password: &str) -> Result<Self, DataError>
+    where P: AsRef<Path> {
+        let data = fs::read(file)?;
+        let data = decrypt(&data, password).map_err(|_| DataError::AccountPassword)?;
+        let s = String::from_utf8(data).map_err(|_| DataError::AccountPassword)?;
+        WifKey::from_str(&s).map_err(|_| DataError::AccountPassword)
+    }
+
+    fn write<P>(&self, file: P, password: &str) -> io::Result<()>
+    where P: AsRef<Path> {
+        fs::write(file, encrypt(self.to_string().into_bytes(), password))
+    }
+}
@@ -23,13 +23,16 @@
 use std::collections::HashSet;
 
 use amplify::Wrapper;
-use bpstd::secp256k1::{ecdsa, schnorr as bip340};
+use bpstd::secp256k1::{ecdsa, schnorr as bip340, Keypair, SECP256K1};
 use bpstd::{
-    Address, InternalKeypair, InternalPk, KeyOrigin, LegacyPk, Sats, Sighash, Sign, TapLeafHash,
-    TapMerklePath, TapNodeHash, TapSighash, XOnlyPk, Xpriv, XprivAccount,
+    Address, InternalKeypair, InternalPk, KeyOrigin, LegacyPk, LegacySig, Sats, Sighash,
+    SighashCache, Sign, TapLeafHash, TapMerklePath, TapNodeHash, TapSighash, Tx, XOnlyPk, Xpriv,
+    XprivAccount,
 };
 use descriptors::Descriptor;
-use psbt::{Psbt, Rejected, Signer};
+use psbt::{Input, Psbt, Rejected, SignError, Signer};
+
+use crate::WifKey;
 
 pub struct SignTxInfo {
     pub fee: Sats,
@@ -53,12 +56,26 @@ pub struct XprivSigner<'xpriv> {
 impl<'descr, 'me, D: Descriptor> Signer for ConsoleSigner<'descr, 'me, D>
 where Self: 'me
 {
-    type Sign<'s> = &'s XprivSigner<'s> where Self: 's + 'me;
+    type Sign<'s>
+        = &'s XprivSigner<'s>
+    where Self: 's + 'me;
 
     fn approve(&self, _psbt: &Psbt) -> Result<Self::Sign<'_>, Rejected> { Ok(&self.signer) }
 }
 
+impl<'xpriv> Signer for XprivSigner<'xpriv> {
+    type Sign<'s>
+        = &'s XprivSigner<'xpriv>
+    where Self: 's;
+
+    fn approve(&self, _psbt: &Psbt) -> Result<Self::Sign<'_>, Rejected> { Ok(self) }
+}
+
 impl<'xpriv> XprivSigner<'xpriv> {
+    /// Constructs signer producing signatures for all keys derived from the account, both for
+    /// the key path and all script paths.
+    pub fn new(account: &'xpriv XprivAccount) -> Self { XprivSigner { account } }
+
     fn derive_subkey(&self, origin: Option<&KeyOrigin>) -> Option<Xpriv> {
         let origin = origin?;
         if !self.account.origin().is_subset_of(origin) {
@@ -131,3 +148,174 @@ impl<'a, 'xpriv> Sign for &'a XprivSigner<'xpriv> {
 
     fn should_sign_key_path(&self, _index: usize) -> bool { true }
 }
+
+/// Signer producing signatures with a single private key, like the one of a paper wallet, for
+/// all inputs spending outputs locked to its public key.
+pub struct WifSigner<'key> {
+    key: &'key WifKey,
+}
+
+impl<'key> Signer for WifSigner<'key> {
+    type Sign<'s>
+        = &'s WifSigner<'key>
+    where Self: 's;
+
+    fn approve(&self, _psbt: &Psbt) -> Result<Self::Sign<'_>, Rejected> { Ok(self) }
+}
+
+impl<'key> WifSigner<'key> {
+    /// Constructs signer for the private key. Since single keys have no derivation, the inputs
+    /// are signed when their public key matches the key, whatever origin they have.
+    pub fn new(key: &'key WifKey) -> Self { WifSigner { key } }
+
+    fn keypair(&self) -> Keypair { Keypair::from_secret_key(SECP256K1, &self.key.secret) }
+}
+
+impl<'a, 'key> Sign for &'a WifSigner<'key> {
+    fn sign_ecdsa(
+        &self,
+        message: Sighash,
+        pk: LegacyPk,
+        _origin: Option<&KeyOrigin>,
+    ) -> Option<ecdsa::Signature> {
+        if self.key.to_legacy_pk() != pk {
+            return None;
+        }
+        Some(self.key.secret.sign_ecdsa(message.into()))
+    }
+
+    fn sign_bip340_key_only(
+        &self,
+        message: TapSighash,
+        pk: InternalPk,
+        _origin: Option<&KeyOrigin>,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Option<bip340::Signature> {
+        let keypair = self.keypair();
+        if XOnlyPk::from(keypair.x_only_public_key().0) != pk.to_xonly_pk() {
+            return None;
+        }
+        let output_pair = InternalKeypair::from(keypair).to_output_keypair(merkle_root).0;
+        Some(output_pair.sign_schnorr(message.into()))
+    }
+
+    fn sign_bip340_script_path(
+        &self,
+        message: TapSighash,
+        pk: XOnlyPk,
+        _origin: Option<&KeyOrigin>,
+    ) -> Option<bip340::Signature> {
+        let keypair = self.keypair();
+        if XOnlyPk::from(keypair.x_only_public_key().0) != pk {
+            return None;
+        }
+        Some(keypair.sign_schnorr(message.into()))
+    }
+
+    fn should_sign_script_path(
+        &self,
+        _index: usize,
+        _merkle_path: &TapMerklePath,
+        _leaf: TapLeafHash,
+    ) -> bool {
+        true
+    }
+
+    fn should_sign_key_path(&self, _index: usize) -> bool { true }
+}
+
+/// Signs inputs spending P2PKH outputs, returning the number of the produced signatures.
+///
+/// [`Psbt::sign`] computes segwit sighash for all inputs providing the witness UTXO, and thus
+/// skips P2PKH inputs constructed by the wallet, which always provide it.
+pub fn sign_legacy(psbt: &mut Psbt, signer: &impl Sign) -> Result<usize, SignError> {
+    let prevouts = psbt.inputs().map(Input::prev_txout).cloned().collect::<Vec<_>>();
+    let sig_hasher = SighashCache::new(Tx::from(psbt.to_unsigned_tx()), prevouts)
+        .expect("inputs and prevouts match algorithmically");
+    let mut sig_count = 0usize;
+    for (index, input) in psbt.inputs_mut().enumerate() {
+        let script_pubkey = input.prev_txout().script_pubkey.clone();
+        if !script_pubkey.is_p2pkh() || input.is_finalized() {
+            continue;
+        }
+        let sighash_type = input.sighash_type.unwrap_or_default();
+        let sighash =
+            sig_hasher.legacy_sighash(index, &script_pubkey, sighash_type.to_consensus_u32())?;
+        for (pk, origin) in &input.bip32_derivation {
+            let Some(sig) = signer.sign_ecdsa(sighash, *pk, Some(origin)) else {
+                continue;
+            };
+            input.partial_sigs.insert(*pk, LegacySig { sig, sighash_type });
+            sig_count += 1;
+        }
+    }
+    Ok(sig_count)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bpstd::secp256k1::XOnlyPublicKey;
+    use bpstd::{Keychain, Network, XpubDerivable};
+
+    use super::*;
+    use crate::indexers::memory::MemoryIndexer;
+    use crate::{testing, AnyDescr, FeeRate, PsbtDescriptor, Wallet};
+
+    const WIF: &str = "cMzLdeGd5vEqxB8B6VFQoRopQ3sLAAvEzDAoQgvX54xwofSWj1fx";
+    const WIF_UNCOMPRESSED: &str = "91gGn1HgSap6CbU12F6z3pJri26xzp7Ay1VW6NHCoEayNXwRpu2";
+
+    /// Checks that all signatures in the PSBT are valid for the sighashes of their inputs.
+    fn verify_sigs(psbt: &Psbt) {
+        let prevouts = psbt.inputs().map(Input::prev_txout).cloned().collect::<Vec<_>>();
+        let mut sig_hasher = SighashCache::new(Tx::from(psbt.to_unsigned_tx()), prevouts).unwrap();
+        for (index, input) in psbt.inputs().enumerate() {
+            let script_pubkey = &input.prev_txout().script_pubkey;
+            if let Some(sig) = input.tap_key_sig {
+                let sighash = sig_hasher.tap_sighash_key(index, sig.sighash_type).unwrap();
+                let output_key = XOnlyPublicKey::from_slice(&script_pubkey[2..]).unwrap();
+                SECP256K1.verify_schnorr(&sig.sig, &sighash.into(), &output_key).unwrap();
+            }
+            for (pk, sig) in &input.partial_sigs {
+                let sighash = if script_pubkey.is_p2pkh() {
+                    let sighash_type = sig.sighash_type.to_consensus_u32();
+                    sig_hasher.legacy_sighash(index, script_pubkey, sighash_type).unwrap()
+                } else {
+                    let script_code = input.script_code().unwrap();
+                    sig_hasher
+                        .segwit_sighash(index, &script_code, input.value(), sig.sighash_type)
+                        .unwrap()
+                };
+                SECP256K1.verify_ecdsa(&sighash.into(), &sig.sig, &pk.pubkey).unwrap();
+            }
+            assert!(input.tap_key_sig.is_some() || !input.partial_sigs.is_empty());
+        }
+    }
+
+    #[test]
+    fn sweep_wif() {
+        let address = testing::wallet().next_address(Keychain::INNER, true);
+        for wif in [WIF, WIF_UNCOMPRESSED] {
+            let key = WifKey::from_str(wif).unwrap();
+            let signer = WifSigner::new(&key);
+            for descr in AnyDescr::with_single_key(key.to_single_key()) {
+                let mut source =
+                    Wallet::<XpubDerivable, AnyDescr>::new_layer1(descr.clone(), Network::Regtest);
+                let mut indexer = MemoryIndexer::new();
+                let script = source.next_address(Keychain::OUTER, true).script_pubkey();
+                indexer.fund(script.clone(), Sats(100_000));
+                indexer.fund(script, Sats(50_000));
+                source.update(&indexer).into_result().unwrap();
+
+                let (mut psbt, _, _) =
+                    source.construct_sweep(address, FeeRate::MIN_RELAY, default!()).unwrap();
+                let sig_count =
+                    psbt.sign(&signer).unwrap() + sign_legacy(&mut psbt, &&signer).unwrap();
+                assert_eq!(sig_count, 2, "{descr}");
+                verify_sigs(&psbt);
+                assert_eq!(descr.finalize_psbt(&mut psbt), 2, "{descr}");
+            }
+        }
+    }
+}
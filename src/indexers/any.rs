@@ -19,10 +19,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bpstd::Tx;
+use std::collections::BTreeSet;
+
+use bpstd::{Tx, Txid};
 use descriptors::Descriptor;
 
-use crate::{Indexer, Layer2, MayError, WalletCache, WalletDescr};
+use crate::{FeeEstimates, Indexer, Layer2, MayError, WalletCache, WalletDescr};
 
 /// Type that contains any of the client types implementing the Indexer trait
 #[derive(From)]
@@ -39,6 +41,14 @@ pub enum AnyIndexer {
     #[cfg(feature = "mempool")]
     /// Mempool indexer
     Mempool(Box<super::esplora::Client>),
+    #[cfg(feature = "bitcoind")]
+    #[from]
+    /// Bitcoin Core RPC indexer
+    Bitcoind(Box<super::bitcoind::Client>),
+    #[cfg(feature = "cbf")]
+    #[from]
+    /// Compact block filter indexer using P2P connection to a node
+    Cbf(Box<super::cbf::Client<super::cbf::Peer>>),
 }
 
 impl AnyIndexer {
@@ -50,6 +60,10 @@ impl AnyIndexer {
             AnyIndexer::Esplora(_) => "esplora",
             #[cfg(feature = "mempool")]
             AnyIndexer::Mempool(_) => "mempool",
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(_) => "bitcoind",
+            #[cfg(feature = "cbf")]
+            AnyIndexer::Cbf(_) => "cbf",
         }
     }
 }
@@ -63,10 +77,25 @@ pub enum AnyIndexerError {
     #[from]
     #[from(electrum::Error)]
     Electrum(super::electrum::ElectrumError),
+    /// Esplora errors are much larger than the others, so they are boxed to keep the size of
+    /// results small.
     #[cfg(feature = "esplora")]
     #[display(inner)]
     #[from]
-    Esplora(esplora::Error),
+    Esplora(Box<esplora::Error>),
+    #[cfg(feature = "bitcoind")]
+    #[display(inner)]
+    #[from]
+    Bitcoind(super::bitcoind::BitcoindError),
+    #[cfg(feature = "cbf")]
+    #[display(inner)]
+    #[from]
+    Cbf(super::cbf::CbfError<super::cbf::P2pError>),
+}
+
+#[cfg(feature = "esplora")]
+impl From<esplora::Error> for AnyIndexerError {
+    fn from(err: esplora::Error) -> Self { AnyIndexerError::Esplora(Box::new(err)) }
 }
 
 impl Indexer for AnyIndexer {
@@ -101,6 +130,22 @@ impl Indexer for AnyIndexer {
                     err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
                 }
             }
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => {
+                let result = inner.create::<K, D, L2>(descr);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "cbf")]
+            AnyIndexer::Cbf(inner) => {
+                let result = inner.create::<K, D, L2>(descr);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
         }
     }
 
@@ -134,6 +179,71 @@ impl Indexer for AnyIndexer {
                     err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
                 }
             }
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => {
+                let result = inner.update::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "cbf")]
+            AnyIndexer::Cbf(inner) => {
+                let result = inner.update::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+        }
+    }
+
+    fn reorg<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => {
+                let result = inner.reorg::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => {
+                let result = inner.reorg::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => {
+                let result = inner.reorg::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => {
+                let result = inner.reorg::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "cbf")]
+            AnyIndexer::Cbf(inner) => {
+                let result = inner.reorg::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
         }
     }
 
@@ -145,6 +255,25 @@ impl Indexer for AnyIndexer {
             AnyIndexer::Esplora(inner) => inner.publish(tx).map_err(|e| e.into()),
             #[cfg(feature = "mempool")]
             AnyIndexer::Mempool(inner) => inner.publish(tx).map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.publish(tx).map_err(|e| e.into()),
+            #[cfg(feature = "cbf")]
+            AnyIndexer::Cbf(inner) => inner.publish(tx).map_err(|e| e.into()),
+        }
+    }
+
+    fn fee_estimates(&self) -> Result<FeeEstimates, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.fee_estimates().map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.fee_estimates().map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.fee_estimates().map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.fee_estimates().map_err(|e| e.into()),
+            #[cfg(feature = "cbf")]
+            AnyIndexer::Cbf(inner) => inner.fee_estimates().map_err(|e| e.into()),
         }
     }
 }
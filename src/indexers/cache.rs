@@ -0,0 +1,347 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bpstd::{BlockHash, DerivedAddr, Network, Outpoint, ScriptPubkey, Terminal, Txid};
+
+use crate::data::{Inpoint, TxSpend};
+use crate::util::address_with;
+use crate::{
+    BlockHeight, Layer2Cache, MiningInfo, Party, TxStatus, WalletAddr, WalletCache, WalletTx,
+};
+
+impl<L2: Layer2Cache> WalletCache<L2> {
+    /// Registers address as a known wallet address, if it wasn't known before.
+    pub(crate) fn register_addr(&mut self, derived: DerivedAddr) {
+        self.addr.entry(derived.terminal.keychain).or_default().insert(WalletAddr::from(derived));
+    }
+
+    /// Adds a new transaction to the cache or updates mining status of an already known one.
+    ///
+    /// Returns whether the cache was changed.
+    pub(crate) fn register_tx(&mut self, mut tx: WalletTx) -> bool {
+        match self.tx.get_mut(&tx.txid) {
+            Some(known) if known.status == tx.status => false,
+            Some(known) => {
+                known.status = tx.status;
+                if !known.status.is_mined() {
+                    known.first_seen.get_or_insert_with(now);
+                }
+                true
+            }
+            None => {
+                if !tx.status.is_mined() {
+                    tx.first_seen.get_or_insert_with(now);
+                }
+                self.tx.insert(tx.txid, tx);
+                true
+            }
+        }
+    }
+
+    /// Returns mining information for the blocks which contain wallet transactions, but are not
+    /// yet present in the cached block headers. Removes from the cache headers of the blocks
+    /// which do not contain any of the wallet transactions.
+    pub(crate) fn unindexed_blocks(&mut self) -> Vec<MiningInfo> {
+        let mut blocks = self
+            .tx
+            .values()
+            .filter_map(|tx| match tx.status {
+                TxStatus::Mined(info) => Some((info.block_hash, info)),
+                _ => None,
+            })
+            .collect::<HashMap<BlockHash, MiningInfo>>();
+        self.headers.retain(|info| blocks.contains_key(&info.mined.block_hash));
+        for info in &self.headers {
+            blocks.remove(&info.mined.block_hash);
+        }
+        blocks.into_values().collect()
+    }
+
+    /// Detects blocks containing wallet transactions which are no longer part of the best chain
+    /// and moves all transactions from these blocks back to the unknown status, restoring
+    /// outputs spent by them.
+    ///
+    /// The `block_hash` callback must return hash of the block at a given height in the
+    /// current best chain, or `None` if the chain is shorter than the height.
+    ///
+    /// If the last scanned block got orphaned, it is reset to the fork point, i.e. the most
+    /// recent known block which remains in the best chain, or to the genesis if there is none.
+    ///
+    /// Returns ids of the transactions affected by the reorganization.
+    pub(crate) fn rollback<E>(
+        &mut self,
+        network: Network,
+        mut block_hash: impl FnMut(BlockHeight) -> Result<Option<BlockHash>, E>,
+    ) -> Result<BTreeSet<Txid>, E> {
+        let mut blocks = self
+            .headers
+            .iter()
+            .map(|info| info.mined)
+            .chain(self.tx.values().filter_map(|tx| match tx.status {
+                TxStatus::Mined(info) => Some(info),
+                _ => None,
+            }))
+            .chain(Some(self.last_block).filter(|info| *info != MiningInfo::genesis()))
+            .map(|info| (info.block_hash, info))
+            .collect::<HashMap<_, _>>()
+            .into_values()
+            .collect::<Vec<_>>();
+        blocks.sort_by(|a, b| b.cmp(a));
+
+        // Blocks are checked starting from the most recent one; once we meet a block which is
+        // still in the best chain all blocks below it are in the best chain as well.
+        let mut orphaned = BTreeSet::new();
+        let mut fork_point = MiningInfo::genesis();
+        for info in blocks {
+            if block_hash(info.height)? == Some(info.block_hash) {
+                fork_point = info;
+                break;
+            }
+            orphaned.insert(info.block_hash);
+        }
+        if orphaned.is_empty() {
+            return Ok(none!());
+        }
+        if orphaned.contains(&self.last_block.block_hash) {
+            self.last_block = fork_point;
+        }
+
+        self.headers.retain(|info| !orphaned.contains(&info.mined.block_hash));
+        let mut affected = BTreeSet::new();
+        for tx in self.tx.values_mut() {
+            if matches!(tx.status, TxStatus::Mined(info) if orphaned.contains(&info.block_hash)) {
+                tx.status = TxStatus::Unknown;
+                affected.insert(tx.txid);
+            }
+        }
+        self.reindex(network);
+        Ok(affected)
+    }
+
+    /// Re-computes all information derived from the set of cached transactions and known wallet
+    /// addresses: transaction parties, UTXO set, spending inputs and address statistics.
+    pub(crate) fn reindex(&mut self, network: Network) {
+        let scripts = self
+            .addr
+            .values()
+            .flatten()
+            .map(|wallet_addr| {
+                let derived = DerivedAddr {
+                    addr: wallet_addr.addr,
+                    terminal: wallet_addr.terminal,
+                };
+                (wallet_addr.addr.script_pubkey(), derived)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut stats = scripts
+            .values()
+            .map(|derived| (derived.terminal, WalletAddr::<i64>::from(*derived)))
+            .collect::<BTreeMap<_, _>>();
+
+        self.resolve_replacements();
+
+        self.utxo.clear();
+        for tx in self.tx.values_mut() {
+            // Transactions which are neither mined nor known to the mempool, as well as replaced
+            // transactions, do not create or spend outputs, but still mark the addresses as used.
+            let known = tx.is_live();
+            for debit in &mut tx.outputs {
+                resolve_party(&mut debit.beneficiary, &scripts, network);
+                debit.spends.clear();
+                let Some(derived) = debit.derived_addr() else {
+                    continue;
+                };
+                let wallet_addr = addr_stats(&mut stats, derived);
+                wallet_addr.used = wallet_addr.used.saturating_add(1);
+                if !known {
+                    continue;
+                }
+                self.utxo.insert(debit.outpoint);
+                wallet_addr.volume.saturating_add_assign(debit.value);
+                wallet_addr.balance = wallet_addr
+                    .balance
+                    .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
+            }
+            for credit in &mut tx.inputs {
+                resolve_party(&mut credit.payer, &scripts, network);
+                if !known {
+                    continue;
+                }
+                let Some(derived) = credit.derived_addr() else {
+                    continue;
+                };
+                let wallet_addr = addr_stats(&mut stats, derived);
+                wallet_addr.balance = wallet_addr
+                    .balance
+                    .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
+            }
+        }
+
+        let spends = self
+            .tx
+            .values()
+            .flat_map(|tx| {
+                tx.inputs.iter().enumerate().filter(|(_, credit)| !credit.coinbase).map(
+                    |(vin, credit)| {
+                        (credit.outpoint, TxSpend {
+                            inpoint: Inpoint::new(tx.txid, vin as u32),
+                            status: tx.status,
+                            replaced_by: tx.replaced_by,
+                        })
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        for (outpoint, spend) in spends {
+            let Some(txout) = self
+                .tx
+                .get_mut(&outpoint.txid)
+                .and_then(|prev_tx| prev_tx.outputs.get_mut(outpoint.vout_usize()))
+            else {
+                continue;
+            };
+            if spend.status.is_mined() {
+                self.utxo.remove(&outpoint);
+            }
+            txout.spends.push(spend);
+        }
+
+        self.addr.clear();
+        for wallet_addr in stats.into_values() {
+            self.addr
+                .entry(wallet_addr.terminal.keychain)
+                .or_default()
+                .insert(wallet_addr.expect_transmute());
+        }
+    }
+
+    /// Detects conflicting transactions spending the same outputs and marks the losing ones as
+    /// replaced.
+    ///
+    /// Transactions are ranked as follows: mined transactions come first, then the ones known to
+    /// the network, and within each group the ones paying a larger absolute fee take precedence
+    /// (ties are broken by the txid). Going through this ranking, a transaction becomes a winner
+    /// unless it conflicts with an already selected winner, in which case it is marked as
+    /// replaced by that winner. Thus each set of mutually conflicting transactions has a single
+    /// winner, and a transaction conflicting on several outputs either wins or loses all of
+    /// them. Mined transactions are never marked as replaced, and transactions unknown to the
+    /// network are never selected as winners. Unconfirmed descendants of the replaced
+    /// transactions are marked as replaced as well.
+    fn resolve_replacements(&mut self) {
+        let mut spenders = HashMap::<Outpoint, Vec<Txid>>::new();
+        for tx in self.tx.values() {
+            for credit in tx.inputs.iter().filter(|credit| !credit.coinbase) {
+                spenders.entry(credit.outpoint).or_default().push(tx.txid);
+            }
+        }
+        let mut rivals = HashMap::<Txid, BTreeSet<Txid>>::new();
+        for txids in spenders.into_values().filter(|txids| txids.len() > 1) {
+            for txid in &txids {
+                rivals
+                    .entry(*txid)
+                    .or_default()
+                    .extend(txids.iter().filter(|rival| *rival != txid));
+            }
+        }
+
+        let mut ranking = rivals.keys().map(|txid| &self.tx[txid]).collect::<Vec<_>>();
+        ranking.sort_by_key(|tx| {
+            (
+                !tx.status.is_mined(),
+                tx.status == TxStatus::Unknown,
+                std::cmp::Reverse(tx.fee),
+                tx.txid,
+            )
+        });
+        let mut winners = BTreeSet::<Txid>::new();
+        let mut replaced = HashMap::<Txid, Txid>::new();
+        for tx in ranking {
+            let winner = rivals[&tx.txid].iter().find(|rival| winners.contains(*rival));
+            match winner {
+                Some(winner) if !tx.status.is_mined() => {
+                    replaced.insert(tx.txid, *winner);
+                }
+                Some(_) => {
+                    winners.insert(tx.txid);
+                }
+                None if tx.status != TxStatus::Unknown => {
+                    winners.insert(tx.txid);
+                }
+                None => {}
+            }
+        }
+
+        loop {
+            let descendants = self
+                .tx
+                .values()
+                .filter(|tx| !tx.status.is_mined() && !replaced.contains_key(&tx.txid))
+                .filter_map(|tx| {
+                    tx.inputs
+                        .iter()
+                        .find_map(|credit| replaced.get(&credit.outpoint.txid))
+                        .map(|winner| (tx.txid, *winner))
+                })
+                .collect::<Vec<_>>();
+            if descendants.is_empty() {
+                break;
+            }
+            replaced.extend(descendants);
+        }
+
+        for tx in self.tx.values_mut() {
+            tx.replaced_by = replaced.get(&tx.txid).copied();
+        }
+    }
+}
+
+fn resolve_party(
+    party: &mut Party,
+    scripts: &HashMap<ScriptPubkey, DerivedAddr>,
+    network: Network,
+) {
+    let Some(script) = party.script_pubkey() else {
+        return;
+    };
+    if let Some(derived) = scripts.get(&script) {
+        *party = Party::Wallet(*derived);
+    } else if party.is_unknown() {
+        if let Ok(addr) = address_with(&script, network) {
+            *party = Party::Counterparty(addr);
+        }
+    }
+}
+
+fn addr_stats(
+    stats: &mut BTreeMap<Terminal, WalletAddr<i64>>,
+    derived: DerivedAddr,
+) -> &mut WalletAddr<i64> {
+    stats.entry(derived.terminal).or_insert_with(|| WalletAddr::from(derived))
+}
+
+/// Current time as a UNIX timestamp.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,326 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! BIP158 basic block filters.
+
+use std::io::{Cursor, Read};
+
+use bpstd::{BlockHash, ConsensusDecode, ConsensusEncode, ScriptPubkey, VarInt};
+
+/// Golomb-Rice coding parameter of the basic filter.
+const P: u8 = 19;
+/// Inverse false-positive rate of the basic filter.
+const M: u64 = 784931;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display("invalid BIP158 filter data for block {0}")]
+pub struct FilterError(pub BlockHash);
+
+/// Golomb-coded set representing BIP158 basic filter for a block.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BasicFilter {
+    block_hash: BlockHash,
+    data: Vec<u8>,
+}
+
+impl BasicFilter {
+    /// Constructs filter from its serialized representation, as transmitted in `cfilter` P2P
+    /// message.
+    pub fn new(block_hash: BlockHash, data: Vec<u8>) -> Self { Self { block_hash, data } }
+
+    pub fn block_hash(&self) -> BlockHash { self.block_hash }
+
+    /// Returns serialized filter data.
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    /// Checks whether any of the scripts matches the filter.
+    ///
+    /// A match means that the block may contain an output with one of the scripts or spend
+    /// such an output; false positives happen with the probability of `1/784931` per script.
+    pub fn match_any<'s>(
+        &self,
+        scripts: impl IntoIterator<Item = &'s ScriptPubkey>,
+    ) -> Result<bool, FilterError> {
+        let mut reader = Cursor::new(self.data.as_slice());
+        let n = VarInt::consensus_decode(&mut reader)
+            .map_err(|_| FilterError(self.block_hash))?
+            .to_u64();
+        if n == 0 {
+            return Ok(false);
+        }
+
+        let (k0, k1) = self.key();
+        let f = n * M;
+        let mut queries = scripts
+            .into_iter()
+            .filter(|script| !script.is_empty())
+            .map(|script| hash_to_range(k0, k1, f, script.as_slice()))
+            .collect::<Vec<_>>();
+        if queries.is_empty() {
+            return Ok(false);
+        }
+        queries.sort_unstable();
+
+        let mut bits = BitReader::new(reader);
+        let mut queries = queries.into_iter().peekable();
+        let mut value = 0u64;
+        for _ in 0..n {
+            value += bits.read_golomb_rice().ok_or(FilterError(self.block_hash))?;
+            while queries.next_if(|query| *query < value).is_some() {}
+            match queries.peek() {
+                None => return Ok(false),
+                Some(query) if *query == value => return Ok(true),
+                Some(_) => {}
+            }
+        }
+        Ok(false)
+    }
+
+    /// SipHash key, which is the first 16 bytes of the block hash in its internal byte order.
+    fn key(&self) -> (u64, u64) {
+        let bytes = self.block_hash.consensus_serialize();
+        let k0 = u64::from_le_bytes(bytes[..8].try_into().expect("fixed size"));
+        let k1 = u64::from_le_bytes(bytes[8..16].try_into().expect("fixed size"));
+        (k0, k1)
+    }
+}
+
+fn hash_to_range(k0: u64, k1: u64, f: u64, item: &[u8]) -> u64 {
+    ((siphash24(k0, k1, item) as u128 * f as u128) >> 64) as u64
+}
+
+struct BitReader<R: Read> {
+    reader: R,
+    byte: u8,
+    offset: u8,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(reader: R) -> Self {
+        BitReader {
+            reader,
+            byte: 0,
+            offset: 8,
+        }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        if self.offset == 8 {
+            let mut buf = [0u8; 1];
+            self.reader.read_exact(&mut buf).ok()?;
+            self.byte = buf[0];
+            self.offset = 0;
+        }
+        let bit = self.byte & (0x80 >> self.offset) != 0;
+        self.offset += 1;
+        Some(bit)
+    }
+
+    fn read_golomb_rice(&mut self) -> Option<u64> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let mut remainder = 0u64;
+        for _ in 0..P {
+            remainder = (remainder << 1) | self.read_bit()? as u64;
+        }
+        Some((quotient << P) + remainder)
+    }
+}
+
+/// SipHash-2-4 function, as used by BIP158.
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v0 = k0 ^ 0x736f6d6570736575;
+    let mut v1 = k1 ^ 0x646f72616e646f6d;
+    let mut v2 = k0 ^ 0x6c7967656e657261;
+    let mut v3 = k1 ^ 0x7465646279746573;
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let m = u64::from_le_bytes(chunk.try_into().expect("fixed size"));
+        v3 ^= m;
+        sip_round(&mut v0, &mut v1, &mut v2, &mut v3);
+        sip_round(&mut v0, &mut v1, &mut v2, &mut v3);
+        v0 ^= m;
+    }
+    let mut last = [0u8; 8];
+    let tail = chunks.remainder();
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = data.len() as u8;
+    let m = u64::from_le_bytes(last);
+    v3 ^= m;
+    sip_round(&mut v0, &mut v1, &mut v2, &mut v3);
+    sip_round(&mut v0, &mut v1, &mut v2, &mut v3);
+    v0 ^= m;
+
+    v2 ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v0, &mut v1, &mut v2, &mut v3);
+    }
+    v0 ^ v1 ^ v2 ^ v3
+}
+
+fn sip_round(v0: &mut u64, v1: &mut u64, v2: &mut u64, v3: &mut u64) {
+    *v0 = v0.wrapping_add(*v1);
+    *v1 = v1.rotate_left(13) ^ *v0;
+    *v0 = v0.rotate_left(32);
+    *v2 = v2.wrapping_add(*v3);
+    *v3 = v3.rotate_left(16) ^ *v2;
+    *v0 = v0.wrapping_add(*v3);
+    *v3 = v3.rotate_left(21) ^ *v0;
+    *v2 = v2.wrapping_add(*v1);
+    *v1 = v1.rotate_left(17) ^ *v2;
+    *v2 = v2.rotate_left(32);
+}
+
+#[cfg(test)]
+impl BasicFilter {
+    /// Constructs filter for a block containing (or spending) outputs with the given scripts.
+    pub(crate) fn with_scripts<'s>(
+        block_hash: BlockHash,
+        scripts: impl IntoIterator<Item = &'s ScriptPubkey>,
+    ) -> Self {
+        let mut filter = BasicFilter {
+            block_hash,
+            data: vec![],
+        };
+        let (k0, k1) = filter.key();
+        let mut items = scripts
+            .into_iter()
+            .filter(|script| !script.is_empty())
+            .map(|script| script.as_slice())
+            .collect::<Vec<_>>();
+        items.sort_unstable();
+        items.dedup();
+        let f = items.len() as u64 * M;
+        let mut values =
+            items.into_iter().map(|item| hash_to_range(k0, k1, f, item)).collect::<Vec<_>>();
+        values.sort_unstable();
+
+        let mut bits = Vec::<bool>::new();
+        let mut last = 0u64;
+        for value in &values {
+            let delta = value - last;
+            last = *value;
+            bits.extend((0..delta >> P).map(|_| true));
+            bits.push(false);
+            bits.extend((0..P).rev().map(|bit| delta & (1 << bit) != 0));
+        }
+        filter.data = VarInt::with(values.len()).consensus_serialize();
+        filter.data.extend(bits.chunks(8).map(|chunk| {
+            chunk.iter().enumerate().fold(0u8, |byte, (no, bit)| byte | ((*bit as u8) << (7 - no)))
+        }));
+        filter
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use amplify::hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn siphash_vectors() {
+        // Test vectors from the SipHash reference implementation, with the key `00..0f` and the
+        // message consisting of bytes `00, 01, ..` of the given length
+        let k0 = 0x0706050403020100;
+        let k1 = 0x0f0e0d0c0b0a0908;
+        let message = (0u8..16).collect::<Vec<_>>();
+        for (len, hash) in [
+            (0, 0x726fdb47dd0e0e31),
+            (1, 0x74f839c593dc67fd),
+            (2, 0x0d6c8009d9a94f5a),
+            (3, 0x85676696d7fb7e2d),
+            (15, 0xa129ca6149be45e5),
+        ] {
+            assert_eq!(siphash24(k0, k1, &message[..len]), hash, "message length {len}");
+        }
+    }
+
+    #[test]
+    fn gcs_vectors() {
+        // Filters keyed with the testnet genesis block hash, cross-checked against an independent
+        // implementation of BIP158 SipHash and Golomb-Rice coding
+        let block_hash =
+            BlockHash::from_str("000000000933ea01ad0ee984209779baaac3ced90fa3f408719526f8d77f4943")
+                .unwrap();
+        let genesis_script = ScriptPubkey::from_hex(
+            "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
+        )
+        .unwrap();
+        let p2wpkh =
+            ScriptPubkey::from_hex("0014000102030405060708090a0b0c0d0e0f10111213").unwrap();
+        let op_true = ScriptPubkey::from_hex("51").unwrap();
+        let p2tr = ScriptPubkey::from_hex(
+            "51200101010101010101010101010101010101010101010101010101010101010101",
+        )
+        .unwrap();
+
+        let (k0, k1) = BasicFilter::new(block_hash, vec![]).key();
+        assert_eq!((k0, k1), (0x719526f8d77f4943, 0xaac3ced90fa3f408));
+        assert_eq!(hash_to_range(k0, k1, M, genesis_script.as_slice()), 1374);
+
+        let filter = BasicFilter::new(block_hash, Vec::from_hex("010055e0").unwrap());
+        assert_eq!(BasicFilter::with_scripts(block_hash, [&genesis_script]), filter);
+        assert!(filter.match_any([&genesis_script]).unwrap());
+        assert!(filter.match_any([&p2wpkh, &genesis_script]).unwrap());
+        assert!(!filter.match_any([&p2wpkh]).unwrap());
+        assert!(!filter.match_any([]).unwrap());
+
+        let filter = BasicFilter::new(block_hash, Vec::from_hex("030101c8ff36cfb0dc").unwrap());
+        assert_eq!(
+            BasicFilter::with_scripts(block_hash, [&p2wpkh, &op_true, &genesis_script]),
+            filter
+        );
+        assert!(filter.match_any([&op_true]).unwrap());
+        assert!(filter.match_any([&p2tr, &p2wpkh]).unwrap());
+        assert!(!filter.match_any([&p2tr]).unwrap());
+    }
+
+    #[test]
+    fn round_trip() {
+        let block_hash = BlockHash::from([7u8; 32]);
+        let scripts =
+            (0u8..50).map(|no| ScriptPubkey::from_unsafe(vec![0x51, no])).collect::<Vec<_>>();
+        let filter = BasicFilter::with_scripts(block_hash, &scripts);
+        for script in &scripts {
+            assert!(filter.match_any([script]).unwrap());
+        }
+        let other = ScriptPubkey::from_unsafe(vec![0x52]);
+        assert!(!filter.match_any([&other]).unwrap());
+
+        let empty = BasicFilter::with_scripts(block_hash, []);
+        assert!(!empty.match_any(&scripts).unwrap());
+    }
+
+    #[test]
+    fn invalid_data() {
+        let block_hash = BlockHash::from([7u8; 32]);
+        let script = ScriptPubkey::from_unsafe(vec![0x51]);
+        let filter = BasicFilter::new(block_hash, vec![0x05, 0xff]);
+        assert_eq!(filter.match_any([&script]), Err(FilterError(block_hash)));
+    }
+}
@@ -0,0 +1,140 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact filter transport serving headers, filters and blocks from files on disk.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bpstd::{BlockHash, BlockHeader, ConsensusDecode, ConsensusEncode, Tx};
+
+use super::{BasicFilter, FilterTransport};
+use crate::{FeeEstimates, FeeRate};
+
+const HEADER_LEN: usize = 80;
+
+/// Directory with a fixture of block headers, filters and blocks.
+///
+/// The directory must contain:
+/// - `headers` file with the consensus-serialized block headers of the best chain, starting with
+///   the genesis block;
+/// - `filters` directory with the serialized BIP158 basic filters named after the hashes of the
+///   blocks they belong to;
+/// - `blocks` directory with consensus-serialized blocks named after their hashes. Only blocks
+///   matching the filters are ever read, so other blocks may be omitted.
+///
+/// Published transactions are consensus-serialized into the `mempool` subdirectory, named after
+/// their txid.
+///
+/// Fee rate estimations are read from an optional `fees` file, containing lines with a
+/// confirmation target in blocks followed by the fee rate in sat/vB, separated by a space.
+///
+/// The files are read on each request, so the fixture can be modified between wallet syncs.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FixtureDir {
+    path: PathBuf,
+}
+
+impl FixtureDir {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FixtureDir {
+            path: path.as_ref().to_owned(),
+        }
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    fn headers(&self) -> io::Result<Vec<BlockHeader>> {
+        let data = fs::read(self.path.join("headers"))?;
+        if data.len() % HEADER_LEN != 0 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "incomplete block header"));
+        }
+        data.chunks_exact(HEADER_LEN)
+            .map(|chunk| {
+                BlockHeader::consensus_deserialize(chunk)
+                    .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+            })
+            .collect()
+    }
+}
+
+impl FilterTransport for FixtureDir {
+    type Error = io::Error;
+
+    fn tip_height(&mut self) -> Result<u32, Self::Error> {
+        match self.headers()?.len() {
+            0 => Err(io::Error::new(ErrorKind::InvalidData, "no genesis block header")),
+            len => Ok(len as u32 - 1),
+        }
+    }
+
+    fn block_header(&mut self, height: u32) -> Result<Option<BlockHeader>, Self::Error> {
+        Ok(self.headers()?.get(height as usize).copied())
+    }
+
+    fn filters(&mut self, start: u32, stop: u32) -> Result<Vec<BasicFilter>, Self::Error> {
+        let headers = self.headers()?;
+        let headers = headers.get(start as usize..=stop as usize).ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, "requested filters are beyond the chain tip")
+        })?;
+        headers
+            .iter()
+            .map(|header| {
+                let block_hash = header.block_hash();
+                let data = fs::read(self.path.join("filters").join(block_hash.to_string()))?;
+                Ok(BasicFilter::new(block_hash, data))
+            })
+            .collect()
+    }
+
+    fn block(&mut self, block_hash: BlockHash) -> Result<Vec<u8>, Self::Error> {
+        fs::read(self.path.join("blocks").join(block_hash.to_string()))
+    }
+
+    fn publish(&mut self, tx: &Tx) -> Result<(), Self::Error> {
+        let dir = self.path.join("mempool");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(tx.txid().to_string()), tx.consensus_serialize())
+    }
+
+    fn fee_estimates(&mut self) -> Result<FeeEstimates, Self::Error> {
+        let data = match fs::read_to_string(self.path.join("fees")) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(none!()),
+            res => res?,
+        };
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (target, fee_rate) = line.trim().split_once(' ').ok_or_else(|| {
+                    io::Error::new(ErrorKind::InvalidData, format!("invalid fee line '{line}'"))
+                })?;
+                let target =
+                    target.parse().map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                let fee_rate = FeeRate::from_str(fee_rate.trim())
+                    .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                Ok((target, fee_rate))
+            })
+            .collect()
+    }
+}
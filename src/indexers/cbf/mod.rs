@@ -0,0 +1,604 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Light client indexer using BIP157/158 compact block filters.
+//!
+//! Instead of asking a server about the history of each wallet address, the indexer downloads
+//! compact filters for every block and matches them against the wallet scripts locally; only
+//! the matching blocks are downloaded. Thus, no information about wallet addresses leaves the
+//! client.
+
+mod filter;
+mod fixture;
+mod p2p;
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Cursor;
+use std::num::NonZeroU32;
+
+use bpstd::{
+    BlockHash, BlockHeader, ConsensusDecode, ConsensusEncode, DerivedAddr, Outpoint, Sats,
+    ScriptPubkey, Tx, Txid, VarInt, Weight,
+};
+use descriptors::Descriptor;
+pub use filter::{BasicFilter, FilterError};
+pub use fixture::FixtureDir;
+pub use p2p::{P2pError, Peer, PROTOCOL_VERSION};
+
+use super::Lookahead;
+use crate::{
+    BlockInfo, FeeEstimates, Indexer, Layer2, Layer2Cache, MayError, MiningInfo, Party, TxCredit,
+    TxDebit, TxStatus, WalletCache, WalletDescr, WalletTx,
+};
+
+/// Number of block filters requested from the transport at once.
+const FILTER_BATCH: u32 = 1000;
+const MEDIAN_TIME_SPAN: u32 = 11;
+
+/// Source of block headers, compact block filters and blocks.
+///
+/// The main implementation is [`Peer`], which talks to a single node over the Bitcoin P2P
+/// protocol; [`FixtureDir`] serves the same data from the files on disk.
+pub trait FilterTransport {
+    type Error: std::error::Error;
+
+    /// Returns height of the best chain tip.
+    fn tip_height(&mut self) -> Result<u32, Self::Error>;
+
+    /// Returns header of the best chain block at a given height, or `None` if the height is
+    /// above the chain tip.
+    fn block_header(&mut self, height: u32) -> Result<Option<BlockHeader>, Self::Error>;
+
+    /// Returns BIP158 basic filters for all blocks in the `start..=stop` height range, in the
+    /// order of block heights.
+    fn filters(&mut self, start: u32, stop: u32) -> Result<Vec<BasicFilter>, Self::Error>;
+
+    /// Returns consensus-serialized block with a given hash, including witness data.
+    fn block(&mut self, block_hash: BlockHash) -> Result<Vec<u8>, Self::Error>;
+
+    fn publish(&mut self, tx: &Tx) -> Result<(), Self::Error>;
+
+    /// Returns fee rate estimations known to the transport. Since P2P protocol doesn't provide
+    /// fee estimations, the result may contain just the minimal fee rate accepted by the peer.
+    fn fee_estimates(&mut self) -> Result<FeeEstimates, Self::Error>;
+}
+
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CbfError<E: std::error::Error> {
+    /// compact filter transport error - {0}
+    Transport(E),
+
+    #[display(inner)]
+    Filter(FilterError),
+
+    /// block {0} provided by the transport is invalid.
+    InvalidBlock(BlockHash),
+
+    /// block header at height {0} is not known to the transport.
+    NoBlockHeader(u32),
+}
+
+impl<E: std::error::Error> From<FilterError> for CbfError<E> {
+    fn from(err: FilterError) -> Self { CbfError::Filter(err) }
+}
+
+/// Compact block filter indexer.
+///
+/// Since filters do not cover unconfirmed transactions, the indexer is able to see only
+/// transactions which are already mined. Addresses are discovered sequentially during the scan:
+/// the gap limit is applied relative to the last used address found in the blocks scanned so
+/// far. The value and the payer of inputs spending non-wallet outputs are not known; the fee
+/// of transactions containing such inputs is set to zero.
+///
+/// The transport is trusted to provide correct filters: they are not checked against the BIP157
+/// filter header chain, so a transport serving incorrect filters may hide wallet transactions.
+/// Downloaded blocks are checked only to match the block hashes of the transport header chain.
+#[derive(Debug)]
+pub struct Client<T: FilterTransport> {
+    transport: RefCell<T>,
+    birth_height: u32,
+    progress: Option<fn(u32, u32)>,
+}
+
+impl<T: FilterTransport> Client<T> {
+    /// Constructs indexer which scans the whole blockchain starting from the genesis block.
+    pub fn new(transport: T) -> Self { Self::with_birth_height(transport, 0) }
+
+    /// Constructs indexer which scans the blockchain starting from a given height, which must
+    /// be below the first transaction of the wallet.
+    pub fn with_birth_height(transport: T, birth_height: u32) -> Self {
+        Client {
+            transport: RefCell::new(transport),
+            birth_height,
+            progress: None,
+        }
+    }
+
+    /// Sets function reporting the scan progress, which is called after each batch of block
+    /// filters is scanned with the height of the last scanned block and the chain tip height.
+    pub fn with_progress(mut self, progress: fn(u32, u32)) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn birth_height(&self) -> u32 { self.birth_height }
+
+    pub fn into_transport(self) -> T { self.transport.into_inner() }
+
+    fn last_valid_block(
+        &self,
+        transport: &mut T,
+        mut last_block: MiningInfo,
+    ) -> Result<MiningInfo, CbfError<T::Error>> {
+        if last_block == MiningInfo::genesis() {
+            return Ok(last_block);
+        }
+        let tip = transport.tip_height().map_err(CbfError::Transport)?;
+        loop {
+            let height = last_block.height.get();
+            if height <= tip && get_header(transport, height)?.block_hash() == last_block.block_hash
+            {
+                return Ok(last_block);
+            }
+            if height <= self.birth_height.max(1) {
+                return Ok(MiningInfo::genesis());
+            }
+            let height = (height - 1).min(tip);
+            let header = get_header(transport, height)?;
+            last_block = BlockInfo::with_header(block_height(height), header).mined;
+        }
+    }
+
+    fn scan<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        changed: &mut usize,
+    ) -> Result<(), CbfError<T::Error>> {
+        let mut transport = self.transport.borrow_mut();
+        let transport = &mut *transport;
+        let tip = transport.tip_height().map_err(CbfError::Transport)?;
+        let start = if cache.last_block == MiningInfo::genesis() {
+            self.birth_height
+        } else {
+            self.birth_height.max(cache.last_block.height.get() + 1)
+        };
+
+        let mut lookahead = Lookahead::new(descriptor, cache);
+        let mut from = start;
+        while from <= tip {
+            let to = tip.min(from + FILTER_BATCH - 1);
+            let filters = transport.filters(from, to).map_err(CbfError::Transport)?;
+            for (height, filter) in (from..=to).zip(filters) {
+                if !filter.match_any(lookahead.scripts.keys())? {
+                    continue;
+                }
+                let (info, txs) = get_block(transport, height, filter.block_hash())?;
+                for tx in txs {
+                    let Some(tx) = wallet_tx(tx, info.mined, &lookahead.scripts, cache) else {
+                        continue;
+                    };
+                    for debit in &tx.outputs {
+                        if let Party::Unknown(script) = &debit.beneficiary {
+                            if let Some(derived) = lookahead.scripts.get(script).copied() {
+                                lookahead.register_use(derived, cache);
+                            }
+                        }
+                    }
+                    if cache.register_tx(tx) {
+                        *changed += 1;
+                    }
+                }
+                cache.headers.insert(info);
+            }
+            // Saving scan progress, so the interrupted scan can be resumed
+            let header = get_header(transport, to)?;
+            cache.last_block = BlockInfo::with_header(block_height(to), header).mined;
+            if let Some(progress) = self.progress {
+                progress(to, tip);
+            }
+            from = to + 1;
+        }
+
+        for info in cache.unindexed_blocks() {
+            let header = get_header(transport, info.height.get())?;
+            if header.block_hash() != info.block_hash {
+                return Err(CbfError::InvalidBlock(info.block_hash));
+            }
+            cache.headers.insert(BlockInfo {
+                mediantime: median_time(transport, info.height.get())?,
+                ..BlockInfo::with_header(info.height, header)
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<T: FilterTransport> Indexer for Client<T> {
+    type Error = CbfError<T::Error>;
+
+    fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+        let mut cache = WalletCache::new();
+        self.update::<K, D, L2>(descriptor, &mut cache).map(|_| cache)
+    }
+
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let mut changed = 0usize;
+        let result = self.scan::<K, D, L2>(descriptor, cache, &mut changed);
+        cache.reindex(descriptor.network());
+        match result {
+            Ok(()) => MayError::ok(changed),
+            Err(err) => MayError::err(changed, vec![err]),
+        }
+    }
+
+    fn reorg<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+        let mut transport = self.transport.borrow_mut();
+        let transport = &mut *transport;
+        // The scan must be resumed from the last block which remains in the best chain
+        match self.last_valid_block(transport, cache.last_block) {
+            Ok(last_block) => cache.last_block = last_block,
+            Err(err) => return MayError::err(none!(), vec![err]),
+        }
+
+        let result = cache.rollback(descriptor.network(), |height| {
+            transport
+                .block_header(height.get())
+                .map(|header| header.map(|header| header.block_hash()))
+        });
+        match result {
+            Ok(affected) => MayError::ok(affected),
+            Err(err) => MayError::err(none!(), vec![CbfError::Transport(err)]),
+        }
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
+        self.transport.borrow_mut().publish(tx).map_err(CbfError::Transport)
+    }
+
+    fn fee_estimates(&self) -> Result<FeeEstimates, Self::Error> {
+        self.transport.borrow_mut().fee_estimates().map_err(CbfError::Transport)
+    }
+}
+
+fn block_height(height: u32) -> NonZeroU32 { NonZeroU32::new(height).unwrap_or(NonZeroU32::MIN) }
+
+fn get_header<T: FilterTransport>(
+    transport: &mut T,
+    height: u32,
+) -> Result<BlockHeader, CbfError<T::Error>> {
+    transport
+        .block_header(height)
+        .map_err(CbfError::Transport)?
+        .ok_or(CbfError::NoBlockHeader(height))
+}
+
+fn median_time<T: FilterTransport>(
+    transport: &mut T,
+    height: u32,
+) -> Result<u32, CbfError<T::Error>> {
+    let start = height.saturating_sub(MEDIAN_TIME_SPAN - 1);
+    let mut times = (start..=height)
+        .map(|height| get_header(transport, height).map(|header| header.time))
+        .collect::<Result<Vec<_>, _>>()?;
+    times.sort_unstable();
+    Ok(times[times.len() / 2])
+}
+
+/// Downloads and parses block, returning its information and transactions.
+fn get_block<T: FilterTransport>(
+    transport: &mut T,
+    height: u32,
+    block_hash: BlockHash,
+) -> Result<(BlockInfo, Vec<Tx>), CbfError<T::Error>> {
+    let data = transport.block(block_hash).map_err(CbfError::Transport)?;
+    let parse = || -> Option<(BlockHeader, u32, Vec<Tx>)> {
+        let mut reader = Cursor::new(data.as_slice());
+        let header = BlockHeader::consensus_decode(&mut reader).ok()?;
+        let count = VarInt::consensus_decode(&mut reader).ok()?.to_usize();
+        let mut weight = reader.position() as u32 * 4;
+        let mut txs = Vec::with_capacity(count);
+        for _ in 0..count {
+            let tx = Tx::consensus_decode(&mut reader).ok()?;
+            weight += tx.weight_units().to_u32();
+            txs.push(tx);
+        }
+        (reader.position() as usize == data.len()).then_some((header, weight, txs))
+    };
+    let (header, weight, txs) = parse().ok_or(CbfError::InvalidBlock(block_hash))?;
+    if header.block_hash() != block_hash {
+        return Err(CbfError::InvalidBlock(block_hash));
+    }
+    let info = BlockInfo {
+        tx_count: txs.len() as u32,
+        size: data.len() as u32,
+        weight,
+        mediantime: median_time(transport, height)?,
+        ..BlockInfo::with_header(block_height(height), header)
+    };
+    Ok((info, txs))
+}
+
+/// Converts transaction into a [`WalletTx`], if it pays to or spends from the wallet.
+fn wallet_tx(
+    tx: Tx,
+    mined: MiningInfo,
+    scripts: &HashMap<ScriptPubkey, DerivedAddr>,
+    cache: &WalletCache<impl Layer2Cache>,
+) -> Option<WalletTx> {
+    let txid = tx.txid();
+    let size = tx.consensus_serialize().len() as u32;
+    let weight = tx.weight_units().to_u32();
+    let mut relevant = false;
+
+    let mut input_total = Some(Sats::ZERO);
+    let mut inputs = Vec::with_capacity(tx.inputs.len());
+    for input in tx.inputs {
+        let coinbase = input.prev_output.txid.is_coinbase();
+        let prevout = cache
+            .tx
+            .get(&input.prev_output.txid)
+            .and_then(|prev_tx| prev_tx.outputs.get(input.prev_output.vout_usize()));
+        let (payer, value) = match prevout {
+            _ if coinbase => (Party::Subsidy, Sats::ZERO),
+            Some(debit) => {
+                // Parties are assigned only when the cache gets reindexed after the scan, so the
+                // outputs received earlier in the same scan are matched by their scripts.
+                relevant |= debit.is_ourself()
+                    || debit
+                        .beneficiary
+                        .script_pubkey()
+                        .is_some_and(|script| scripts.contains_key(&script));
+                (debit.beneficiary.clone(), debit.value)
+            }
+            None => {
+                input_total = None;
+                (Party::Unknown(ScriptPubkey::new()), Sats::ZERO)
+            }
+        };
+        input_total = input_total.and_then(|total| total.checked_add(value));
+        inputs.push(TxCredit {
+            outpoint: input.prev_output,
+            payer,
+            sequence: input.sequence,
+            coinbase,
+            script_sig: input.sig_script,
+            witness: input.witness,
+            value,
+        });
+    }
+
+    let mut output_total = Sats::ZERO;
+    let mut outputs = Vec::with_capacity(tx.outputs.len());
+    for (no, txout) in tx.outputs.into_iter().enumerate() {
+        relevant |= scripts.contains_key(&txout.script_pubkey);
+        output_total += txout.value;
+        outputs.push(TxDebit {
+            outpoint: Outpoint::new(txid, no as u32),
+            beneficiary: Party::Unknown(txout.script_pubkey),
+            value: txout.value,
+            spends: vec![],
+        });
+    }
+
+    relevant.then(|| WalletTx {
+        txid,
+        status: TxStatus::Mined(mined),
+        inputs,
+        outputs,
+        fee: input_total.map(|total| total.saturating_sub(output_total)).unwrap_or_default(),
+        size,
+        weight,
+        version: tx.version,
+        locktime: tx.lock_time,
+        replaced_by: None,
+        first_seen: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use bpstd::{
+        BlockMerkleRoot, LockTime, SeqNo, SigScript, TxIn, TxOut, TxVer, VarIntArray, Witness,
+        XpubDerivable,
+    };
+    use descriptors::Wpkh;
+
+    use super::*;
+    use crate::{testing, NoLayer2};
+
+    /// Fixture directory with a chain of blocks, removed when dropped.
+    struct Chain {
+        dir: PathBuf,
+        headers: Vec<BlockHeader>,
+    }
+
+    impl Chain {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("bp-cbf-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("filters")).unwrap();
+            fs::create_dir_all(dir.join("blocks")).unwrap();
+            let mut chain = Chain {
+                dir,
+                headers: vec![],
+            };
+            chain.push(vec![]);
+            chain
+        }
+
+        /// Adds block with a coinbase and given transactions, spending outputs with the provided
+        /// scripts.
+        fn push(&mut self, txs: Vec<(Tx, Vec<ScriptPubkey>)>) -> BlockHash {
+            let height = self.headers.len() as u32;
+            let mut scripts = vec![];
+            let mut block_txs = vec![coinbase(height, ScriptPubkey::new())];
+            for (tx, spent) in txs {
+                scripts.extend(spent);
+                block_txs.push(tx);
+            }
+            scripts.extend(
+                block_txs
+                    .iter()
+                    .flat_map(|tx| tx.outputs.iter())
+                    .map(|out| out.script_pubkey.clone()),
+            );
+            let header = BlockHeader {
+                version: 0x20000000,
+                prev_block_hash: self
+                    .headers
+                    .last()
+                    .map(BlockHeader::block_hash)
+                    .unwrap_or(BlockHash::from([0u8; 32])),
+                merkle_root: BlockMerkleRoot::from([height as u8; 32]),
+                time: 1700000000 + height * 600,
+                bits: 0x207fffff,
+                nonce: block_txs.len() as u32,
+            };
+            let block_hash = header.block_hash();
+            let mut data = header.consensus_serialize();
+            data.extend(VarInt::with(block_txs.len()).consensus_serialize());
+            for tx in &block_txs {
+                data.extend(tx.consensus_serialize());
+            }
+            fs::write(self.dir.join("blocks").join(block_hash.to_string()), data).unwrap();
+            let filter = BasicFilter::with_scripts(block_hash, &scripts);
+            fs::write(self.dir.join("filters").join(block_hash.to_string()), filter.data())
+                .unwrap();
+            self.headers.push(header);
+            let headers =
+                self.headers.iter().flat_map(BlockHeader::consensus_serialize).collect::<Vec<_>>();
+            fs::write(self.dir.join("headers"), headers).unwrap();
+            block_hash
+        }
+
+        fn client(&self) -> Client<FixtureDir> { Client::new(FixtureDir::new(&self.dir)) }
+    }
+
+    impl Drop for Chain {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.dir); }
+    }
+
+    fn coinbase(height: u32, script: ScriptPubkey) -> Tx {
+        Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::from_checked(vec![TxIn {
+                prev_output: Outpoint::coinbase(),
+                sig_script: SigScript::from_unsafe(height.to_le_bytes().to_vec()),
+                sequence: SeqNo::from_consensus_u32(u32::MAX),
+                witness: Witness::new(),
+            }]),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(script, Sats::from_btc(1))]),
+            lock_time: LockTime::ZERO,
+        }
+    }
+
+    fn spend(prevout: Outpoint, script: ScriptPubkey, value: Sats) -> Tx {
+        testing::tx([prevout], [TxOut::new(script, value)])
+    }
+
+    #[test]
+    fn spend_in_same_scan() {
+        let descr = testing::descriptor();
+        let script = descr.addresses(0).next().unwrap().addr.script_pubkey();
+        let foreign = ScriptPubkey::from_unsafe(vec![0x51]);
+
+        let mut chain = Chain::new("spend");
+        let funding =
+            spend(Outpoint::new(Txid::from([1u8; 32]), 0), script.clone(), Sats::from_btc(2));
+        chain.push(vec![(funding.clone(), vec![foreign.clone()])]);
+        chain.push(vec![]);
+        // spending transaction doesn't pay to any of the wallet scripts
+        let spending = spend(Outpoint::new(funding.txid(), 0), foreign.clone(), Sats::from_btc(1));
+        chain.push(vec![(spending.clone(), vec![script])]);
+
+        let client = chain.client();
+        let cache = client
+            .create::<XpubDerivable, Wpkh<XpubDerivable>, NoLayer2>(&descr)
+            .into_result()
+            .unwrap();
+        assert_eq!(cache.tx.len(), 2);
+        assert!(cache.tx[&spending.txid()].inputs[0].is_ourself());
+        assert_eq!(cache.tx[&spending.txid()].fee, Sats::from_btc(1));
+        assert_eq!(cache.utxo.len(), 0);
+        assert_eq!(cache.last_block.height.get(), 3);
+        assert_eq!(cache.headers.len(), 2);
+    }
+
+    #[test]
+    fn incremental_update_and_reorg() {
+        let descr = testing::descriptor();
+        let script = descr.addresses(0).next().unwrap().addr.script_pubkey();
+        let foreign = ScriptPubkey::from_unsafe(vec![0x51]);
+
+        let mut chain = Chain::new("reorg");
+        let funding =
+            spend(Outpoint::new(Txid::from([1u8; 32]), 0), script.clone(), Sats::from_btc(2));
+        chain.push(vec![(funding.clone(), vec![foreign.clone()])]);
+
+        let client = chain.client();
+        let mut cache = client
+            .create::<XpubDerivable, Wpkh<XpubDerivable>, NoLayer2>(&descr)
+            .into_result()
+            .unwrap();
+        assert_eq!(cache.utxo.len(), 1);
+
+        // spending in a later block is found by the update, which scans only new blocks
+        let spending = spend(Outpoint::new(funding.txid(), 0), foreign.clone(), Sats::from_btc(1));
+        let block_hash = chain.push(vec![(spending.clone(), vec![script])]);
+        let changed = client
+            .update::<XpubDerivable, Wpkh<XpubDerivable>, NoLayer2>(&descr, &mut cache)
+            .into_result()
+            .unwrap();
+        assert_eq!(changed, 1);
+        assert_eq!(cache.utxo.len(), 0);
+        assert_eq!(cache.last_block.block_hash, block_hash);
+
+        // the block with the spending transaction gets replaced with an empty one
+        chain.headers.pop();
+        chain.push(vec![]);
+        let affected = client
+            .reorg::<XpubDerivable, Wpkh<XpubDerivable>, NoLayer2>(&descr, &mut cache)
+            .into_result()
+            .unwrap();
+        assert_eq!(affected, bset![spending.txid()]);
+        assert_eq!(cache.tx[&spending.txid()].status, TxStatus::Unknown);
+        assert_eq!(cache.last_block.height.get(), 1);
+        assert_eq!(cache.utxo.len(), 1);
+    }
+}
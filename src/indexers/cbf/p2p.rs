@@ -0,0 +1,414 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal Bitcoin P2P protocol client able to download block headers, BIP157 compact block
+//! filters and blocks from a single peer.
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::IoError;
+use bpstd::{
+    BlockHash, BlockHeader, ConsensusDecode, ConsensusDecodeError, ConsensusEncode, Network, Tx,
+    VarInt,
+};
+use sha2::{Digest, Sha256};
+
+use super::{BasicFilter, FilterTransport};
+use crate::{FeeEstimates, FeeRate};
+
+/// P2P protocol version supporting BIP157 compact filters.
+pub const PROTOCOL_VERSION: u32 = 70016;
+
+const NODE_WITNESS: u64 = 1 << 3;
+const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+const MSG_WITNESS_BLOCK: u32 = 0x40000002;
+const FILTER_TYPE_BASIC: u8 = 0;
+
+const MAX_HEADERS: usize = 2000;
+const MAX_FILTERS: u32 = 1000;
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Confirmation target for which the minimal fee rate accepted by the peer is reported.
+const MAX_FEE_TARGET: u16 = 1008;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum P2pError {
+    /// I/O error in communication with the peer - {0}
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// peer has sent a message for a different network.
+    WrongMagic,
+
+    /// peer has sent message `{0}` with invalid checksum.
+    Checksum(String),
+
+    /// peer has sent message `{0}` exceeding the maximal message size.
+    Oversized(String),
+
+    /// peer has sent invalid `{0}` message.
+    InvalidMessage(String),
+
+    /// peer doesn't serve segwit blocks and BIP157 compact block filters.
+    Unsupported,
+
+    /// peer doesn't have block {0}.
+    NotFound(BlockHash),
+
+    /// block headers sent by the peer do not connect to the known chain.
+    Disconnected,
+}
+
+/// Connection to a single peer serving BIP157 compact block filters.
+///
+/// The peer is trusted to provide the correct best chain and filters: neither proof of work nor
+/// filter headers are verified against other peers.
+#[derive(Debug)]
+pub struct Peer {
+    stream: TcpStream,
+    magic: [u8; 4],
+    headers: Vec<BlockHeader>,
+    hashes: Vec<BlockHash>,
+    fee_filter: Option<FeeRate>,
+}
+
+impl Peer {
+    /// Connects to a peer and performs version handshake, ensuring the peer serves compact block
+    /// filters.
+    pub fn connect(addr: impl ToSocketAddrs, network: Network) -> Result<Self, P2pError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut peer = Peer {
+            stream,
+            magic: magic(network),
+            headers: vec![],
+            hashes: vec![],
+            fee_filter: None,
+        };
+        peer.handshake()?;
+        peer.init_genesis(genesis_hash(network))?;
+        Ok(peer)
+    }
+
+    fn handshake(&mut self) -> Result<(), P2pError> {
+        let timestamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let user_agent = format!("/bp-wallet:{}/", env!("CARGO_PKG_VERSION"));
+        let mut payload = vec![];
+        payload.extend(PROTOCOL_VERSION.to_le_bytes());
+        payload.extend(0u64.to_le_bytes()); // services
+        payload.extend(timestamp.to_le_bytes());
+        for _ in 0..2 {
+            // services, IPv6 address and port of the receiving and sending nodes
+            payload.extend([0u8; 8 + 16 + 2]);
+        }
+        payload.extend(rand_nonce().to_le_bytes());
+        payload.extend(encode_var_bytes(user_agent.as_bytes()));
+        payload.extend(0u32.to_le_bytes()); // start height
+        payload.push(0); // do not relay transactions to us
+        self.send("version", &payload)?;
+
+        let (mut version, mut verack) = (false, false);
+        while !(version && verack) {
+            let (command, payload) = self.receive()?;
+            match command.as_str() {
+                "version" => {
+                    let services = payload
+                        .get(4..12)
+                        .map(|b| u64::from_le_bytes(b.try_into().expect("fixed size")))
+                        .ok_or(P2pError::InvalidMessage(command))?;
+                    if services & (NODE_WITNESS | NODE_COMPACT_FILTERS)
+                        != NODE_WITNESS | NODE_COMPACT_FILTERS
+                    {
+                        return Err(P2pError::Unsupported);
+                    }
+                    self.send("verack", &[])?;
+                    version = true;
+                }
+                "verack" => verack = true,
+                "feefilter" => self.process_fee_filter(&payload),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Requests the genesis block header, which can't be retrieved with block locators.
+    fn init_genesis(&mut self, genesis: BlockHash) -> Result<(), P2pError> {
+        let headers = self.get_headers(&[], genesis)?;
+        match headers.as_slice() {
+            [header] if header.block_hash() == genesis => {
+                self.headers.push(*header);
+                self.hashes.push(genesis);
+                Ok(())
+            }
+            _ => Err(P2pError::Disconnected),
+        }
+    }
+
+    fn send(&mut self, command: &str, payload: &[u8]) -> Result<(), P2pError> {
+        let mut name = [0u8; 12];
+        name[..command.len()].copy_from_slice(command.as_bytes());
+        let mut message = Vec::with_capacity(24 + payload.len());
+        message.extend(self.magic);
+        message.extend(name);
+        message.extend((payload.len() as u32).to_le_bytes());
+        message.extend(checksum(payload));
+        message.extend(payload);
+        self.stream.write_all(&message)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<(String, Vec<u8>), P2pError> {
+        let mut header = [0u8; 24];
+        self.stream.read_exact(&mut header)?;
+        if header[..4] != self.magic {
+            return Err(P2pError::WrongMagic);
+        }
+        let command = String::from_utf8_lossy(&header[4..16]).trim_end_matches('\0').to_owned();
+        let len = u32::from_le_bytes(header[16..20].try_into().expect("fixed size")) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(P2pError::Oversized(command));
+        }
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload)?;
+        if checksum(&payload) != header[20..24] {
+            return Err(P2pError::Checksum(command));
+        }
+        Ok((command, payload))
+    }
+
+    /// Waits for one of the given messages, replying to pings and ignoring all other messages.
+    fn expect(&mut self, commands: &[&str]) -> Result<(String, Vec<u8>), P2pError> {
+        loop {
+            let (command, payload) = self.receive()?;
+            if command == "ping" {
+                self.send("pong", &payload)?;
+            } else if command == "feefilter" {
+                self.process_fee_filter(&payload);
+            } else if commands.contains(&command.as_str()) {
+                return Ok((command, payload));
+            }
+        }
+    }
+
+    /// Remembers the minimal fee rate of transactions accepted by the peer, as announced by BIP133
+    /// `feefilter` message.
+    fn process_fee_filter(&mut self, payload: &[u8]) {
+        if let Some(sat_per_kvb) = payload.get(..8) {
+            let sat_per_kvb = u64::from_le_bytes(sat_per_kvb.try_into().expect("fixed size"));
+            self.fee_filter = Some(FeeRate::from_sat_per_vb(sat_per_kvb as f64 / 1000.0));
+        }
+    }
+
+    fn get_headers(
+        &mut self,
+        locator: &[BlockHash],
+        stop: BlockHash,
+    ) -> Result<Vec<BlockHeader>, P2pError> {
+        let mut payload = vec![];
+        payload.extend(PROTOCOL_VERSION.to_le_bytes());
+        payload.extend(VarInt::with(locator.len()).consensus_serialize());
+        for hash in locator {
+            payload.extend(hash.consensus_serialize());
+        }
+        payload.extend(stop.consensus_serialize());
+        self.send("getheaders", &payload)?;
+
+        let (command, payload) = self.expect(&["headers"])?;
+        let parse = || -> Result<Vec<BlockHeader>, ConsensusDecodeError> {
+            let mut reader = Cursor::new(payload.as_slice());
+            let count = VarInt::consensus_decode(&mut reader)?.to_usize();
+            let mut headers = Vec::with_capacity(count.min(MAX_HEADERS));
+            for _ in 0..count {
+                headers.push(BlockHeader::consensus_decode(&mut reader)?);
+                // transaction count, which is always zero
+                VarInt::consensus_decode(&mut reader)?;
+            }
+            Ok(headers)
+        };
+        parse().map_err(|_| P2pError::InvalidMessage(command))
+    }
+
+    /// Constructs block locator from the known headers chain.
+    fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = vec![];
+        let mut height = self.hashes.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.hashes[height]);
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Downloads new block headers from the peer, handling chain reorganizations.
+    fn sync_headers(&mut self) -> Result<(), P2pError> {
+        loop {
+            let headers = self.get_headers(&self.locator(), BlockHash::from([0u8; 32]))?;
+            let Some(first) = headers.first() else {
+                break;
+            };
+            let fork = self
+                .hashes
+                .iter()
+                .rposition(|hash| *hash == first.prev_block_hash)
+                .ok_or(P2pError::Disconnected)?;
+            self.headers.truncate(fork + 1);
+            self.hashes.truncate(fork + 1);
+            let count = headers.len();
+            for header in headers {
+                if Some(&header.prev_block_hash) != self.hashes.last() {
+                    return Err(P2pError::Disconnected);
+                }
+                self.hashes.push(header.block_hash());
+                self.headers.push(header);
+            }
+            if count < MAX_HEADERS {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FilterTransport for Peer {
+    type Error = P2pError;
+
+    fn tip_height(&mut self) -> Result<u32, Self::Error> {
+        self.sync_headers()?;
+        Ok(self.headers.len() as u32 - 1)
+    }
+
+    fn block_header(&mut self, height: u32) -> Result<Option<BlockHeader>, Self::Error> {
+        Ok(self.headers.get(height as usize).copied())
+    }
+
+    fn filters(&mut self, start: u32, stop: u32) -> Result<Vec<BasicFilter>, Self::Error> {
+        let mut filters = Vec::with_capacity(stop.saturating_sub(start) as usize + 1);
+        let mut from = start;
+        while from <= stop {
+            let to = stop.min(from + MAX_FILTERS - 1);
+            let stop_hash = *self.hashes.get(to as usize).ok_or(P2pError::Disconnected)?;
+            let mut payload = vec![FILTER_TYPE_BASIC];
+            payload.extend(from.to_le_bytes());
+            payload.extend(stop_hash.consensus_serialize());
+            self.send("getcfilters", &payload)?;
+
+            for height in from..=to {
+                let (command, payload) = self.expect(&["cfilter"])?;
+                let parse = || -> Result<(u8, BlockHash, Vec<u8>), ConsensusDecodeError> {
+                    let mut reader = Cursor::new(payload.as_slice());
+                    let filter_type = u8::consensus_decode(&mut reader)?;
+                    let block_hash = BlockHash::consensus_decode(&mut reader)?;
+                    let len = VarInt::consensus_decode(&mut reader)?.to_usize();
+                    let mut data = vec![0u8; len];
+                    reader.read_exact(&mut data)?;
+                    Ok((filter_type, block_hash, data))
+                };
+                let (filter_type, block_hash, data) =
+                    parse().map_err(|_| P2pError::InvalidMessage(command.clone()))?;
+                if filter_type != FILTER_TYPE_BASIC || block_hash != self.hashes[height as usize] {
+                    return Err(P2pError::InvalidMessage(command));
+                }
+                filters.push(BasicFilter::new(block_hash, data));
+            }
+            from = to + 1;
+        }
+        Ok(filters)
+    }
+
+    fn block(&mut self, block_hash: BlockHash) -> Result<Vec<u8>, Self::Error> {
+        let mut payload = VarInt::with(1usize).consensus_serialize();
+        payload.extend(MSG_WITNESS_BLOCK.to_le_bytes());
+        payload.extend(block_hash.consensus_serialize());
+        self.send("getdata", &payload)?;
+        match self.expect(&["block", "notfound"])? {
+            (command, payload) if command == "block" => Ok(payload),
+            _ => Err(P2pError::NotFound(block_hash)),
+        }
+    }
+
+    fn publish(&mut self, tx: &Tx) -> Result<(), Self::Error> {
+        self.send("tx", &tx.consensus_serialize())
+    }
+
+    /// Peers do not provide fee estimations, so the minimal fee rate accepted by the peer (or the
+    /// default minimal relay fee rate, if the peer has not announced it) is returned for the
+    /// longest confirmation target.
+    fn fee_estimates(&mut self) -> Result<FeeEstimates, Self::Error> {
+        let fee_rate = match self.fee_filter {
+            Some(fee_rate) if fee_rate > FeeRate::MIN_RELAY => fee_rate,
+            _ => FeeRate::MIN_RELAY,
+        };
+        Ok(FeeEstimates::from_iter([(MAX_FEE_TARGET, fee_rate)]))
+    }
+}
+
+fn magic(network: Network) -> [u8; 4] {
+    match network {
+        Network::Mainnet => [0xf9, 0xbe, 0xb4, 0xd9],
+        Network::Testnet3 => [0x0b, 0x11, 0x09, 0x07],
+        Network::Testnet4 => [0x1c, 0x16, 0x3f, 0x28],
+        Network::Signet => [0x0a, 0x03, 0xcf, 0x40],
+        Network::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+    }
+}
+
+fn genesis_hash(network: Network) -> BlockHash {
+    let hash = match network {
+        Network::Mainnet => "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        Network::Testnet3 => "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+        Network::Testnet4 => "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+        Network::Signet => "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+        Network::Regtest => "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+    };
+    BlockHash::from_str(hash).expect("hardcoded genesis hash")
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn encode_var_bytes(data: &[u8]) -> Vec<u8> {
+    let mut buf = VarInt::with(data.len()).consensus_serialize();
+    buf.extend(data);
+    buf
+}
+
+/// Nonce used to detect connections to self; doesn't have to be cryptographically secure.
+fn rand_nonce() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default()
+}
@@ -19,21 +19,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeSet, HashSet};
 use std::num::NonZeroU32;
 use std::str::FromStr;
 
-use bpstd::{Address, BlockHash, ConsensusEncode, Outpoint, Sats, Tx, TxIn, Txid, Weight};
+use bpstd::{
+    BlockHash, ConsensusEncode, DerivedAddr, Outpoint, Sats, ScriptPubkey, Tx, TxIn, Txid, Weight,
+};
 use descriptors::Descriptor;
-use electrum::{Client, ElectrumApi, Error, GetHistoryRes, Param};
+use electrum::{Client, ElectrumApi, Error, GetHistoryRes, Param, ScriptStatus};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 
-use super::BATCH_SIZE;
+use super::{Lookahead, FEE_TARGETS};
 use crate::{
-    Indexer, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr,
-    WalletCache, WalletDescr, WalletTx,
+    BlockHeight, BlockInfo, FeeEstimates, FeeRate, Indexer, Layer2, Layer2Cache, MayError,
+    MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletCache, WalletDescr, WalletTx,
 };
 
+/// Number of blocks used to compute median time past.
+const MEDIAN_TIME_SPAN: usize = 11;
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
 #[display(doc_comments)]
 pub enum ElectrumApiError {
@@ -48,6 +54,11 @@ pub enum ElectrumApiError {
     /// electrum indexer returned invalid previous transaction, which doesn't have an output spent
     /// by transaction {0} input {1:?}.
     PrevOutTxMismatch(Txid, TxIn),
+    /// electrum indexer returned no block header for the height {0}.
+    NoBlockHeader(BlockHeight),
+    /// electrum indexer returned block header at height {0} which doesn't match block {1}
+    /// containing wallet transactions.
+    BlockHeaderMismatch(BlockHeight, BlockHash),
 }
 
 #[derive(Debug, Display, Error, From)]
@@ -67,223 +78,418 @@ impl Indexer for Client {
         descriptor: &WalletDescr<K, D, L2::Descr>,
     ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
         let mut cache = WalletCache::new();
+        self.update::<K, D, L2>(descriptor, &mut cache).map(|_| cache)
+    }
+
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
         let mut errors = Vec::<ElectrumError>::new();
+        let mut changed = 0usize;
+
+        // Scan starts from the addresses known to the cache and continues until the gap limit
+        // after the last used address is reached on each of the keychains.
+        let mut lookahead = Lookahead::new(descriptor, cache);
+        let mut checked = HashSet::new();
+        let mut seen = HashSet::new();
+        'scan: loop {
+            let unchecked = lookahead.unchecked(&checked);
+            if unchecked.is_empty() {
+                break;
+            }
+            eprint!(".");
+            let scripts =
+                unchecked.iter().map(|derive| derive.addr.script_pubkey()).collect::<Vec<_>>();
+            let Ok(states) = script_states(self, &scripts).map_err(|err| errors.push(err.into()))
+            else {
+                break 'scan;
+            };
 
-        let mut address_index = BTreeMap::new();
-        for keychain in descriptor.keychains() {
-            let mut empty_count = 0usize;
-            eprint!(" keychain {keychain} ");
-            for derive in descriptor.addresses(keychain) {
-                let script = derive.addr.script_pubkey();
-
-                eprint!(".");
-                let mut txids = Vec::new();
-                let Ok(hres) =
-                    self.script_get_history(&script).map_err(|err| errors.push(err.into()))
-                else {
-                    break;
-                };
-                if hres.is_empty() {
-                    empty_count += 1;
-                    if empty_count >= BATCH_SIZE {
-                        break;
+            // History is requested only for the scripts which status differs from the one of
+            // the transactions known to the cache.
+            let mut updated = Vec::new();
+            for ((derive, script), state) in unchecked.into_iter().zip(scripts).zip(states) {
+                checked.insert(script.clone());
+                match state {
+                    ScriptState::Unused => continue,
+                    ScriptState::Status(status) if cached_status(cache, &derive) == status => {
+                        seen.extend(script_txids(cache, &derive));
                     }
-                    continue;
+                    ScriptState::Status(_) => updated.push(script),
                 }
+                lookahead.register_use(derive, cache);
+            }
+            if updated.is_empty() {
+                continue;
+            }
+            let Ok(histories) =
+                self.batch_script_get_history(&updated).map_err(|err| errors.push(err.into()))
+            else {
+                break 'scan;
+            };
 
-                empty_count = 0;
-
-                let mut process_history_entry =
-                    |hr: GetHistoryRes| -> Result<WalletTx, ElectrumError> {
-                        let txid = hr.tx_hash;
-                        txids.push(txid);
-
-                        // get the tx details (requires electrum verbose support)
-                        let tx_details = self.raw_call("blockchain.transaction.get", vec![
-                            Param::String(hr.tx_hash.to_string()),
-                            Param::Bool(true),
-                        ])?;
-
-                        let tx = tx_details
-                            .get("hex")
-                            .and_then(Value::as_str)
-                            .and_then(|s| Tx::from_str(s).ok())
-                            .ok_or(ElectrumApiError::InvalidTx(txid))?;
-
-                        // build TxStatus
-                        let status = if hr.height < 1 {
-                            TxStatus::Mempool
-                        } else {
-                            let block_hash = tx_details
-                                .get("blockhash")
-                                .and_then(Value::as_str)
-                                .and_then(|s| BlockHash::from_str(s).ok())
-                                .ok_or(ElectrumApiError::InvalidBlockHash(txid))?;
-                            let blocktime = tx_details
-                                .get("blocktime")
-                                .and_then(Value::as_u64)
-                                .ok_or(ElectrumApiError::InvalidBlockTime(txid))?;
-                            let height = NonZeroU32::try_from(hr.height as u32)
-                                .map_err(|_| ElectrumApiError::InvalidBlockHeight(txid))?;
-                            TxStatus::Mined(MiningInfo {
-                                height,
-                                time: blocktime,
-                                block_hash,
-                            })
-                        };
-                        let tx_size = tx.consensus_serialize().len();
-                        let weight = tx.weight_units().to_u32();
-
-                        // get inputs to build TxCredit's and total amount,
-                        // collecting indexer errors
-                        let mut input_total = Sats::ZERO;
-                        let mut inputs = Vec::with_capacity(tx.inputs.len());
-                        for input in tx.inputs {
-                            // get value from previous output tx
-                            let prev_tx = self.transaction_get(&input.prev_output.txid)?;
-                            let prev_out = prev_tx
-                                .outputs
-                                .get(input.prev_output.vout.into_usize())
-                                .ok_or_else(|| {
-                                    ElectrumApiError::PrevOutTxMismatch(txid, input.clone())
-                                })?;
-                            let value = prev_out.value;
-                            input_total += value;
-                            inputs.push(TxCredit {
-                                outpoint: input.prev_output,
-                                payer: Party::Unknown(prev_out.script_pubkey.clone()),
-                                sequence: input.sequence,
-                                coinbase: false,
-                                script_sig: input.sig_script,
-                                witness: input.witness,
-                                value,
-                            })
-                        }
-
-                        // get outputs and total amount, build TxDebit's
-                        let mut output_total = Sats::ZERO;
-                        let mut outputs = Vec::with_capacity(tx.outputs.len());
-                        for (no, txout) in tx.outputs.into_iter().enumerate() {
-                            output_total += txout.value;
-                            outputs.push(TxDebit {
-                                outpoint: Outpoint::new(txid, no as u32),
-                                beneficiary: Party::Unknown(txout.script_pubkey),
-                                value: txout.value,
-                                spent: None,
-                            })
-                        }
-
-                        // build the WalletTx
-                        return Ok(WalletTx {
-                            txid,
-                            status,
-                            inputs,
-                            outputs,
-                            fee: input_total - output_total,
-                            size: tx_size as u32,
-                            weight,
-                            version: tx.version,
-                            locktime: tx.lock_time,
-                        });
-                    };
-
-                // build wallet transactions from script tx history, collecting indexer errors
-                for hr in hres {
-                    match process_history_entry(hr) {
-                        Ok(tx) => {
-                            cache.tx.insert(tx.txid, tx);
+            // fetch only transactions which are new or have changed their mining status,
+            // collecting indexer errors
+            for hr in histories.into_iter().flatten() {
+                seen.insert(hr.tx_hash);
+                if is_known(cache, &hr) {
+                    continue;
+                }
+                match get_wallet_tx(self, hr) {
+                    Ok(tx) => {
+                        if cache.register_tx(tx) {
+                            changed += 1;
                         }
-                        Err(e) => errors.push(e.into()),
                     }
+                    Err(e) => errors.push(e),
                 }
-
-                let wallet_addr = WalletAddr::<i64>::from(derive);
-                address_index.insert(script, (wallet_addr, txids));
             }
         }
 
-        // TODO: Update headers & tip
-
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for debit in &mut tx.outputs {
-                    let Some(s) = debit.beneficiary.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        cache.utxo.insert(debit.outpoint);
-                        debit.beneficiary = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.used = wallet_addr.used.saturating_add(1);
-                        wallet_addr.volume.saturating_add_assign(debit.value);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
-                    } else if debit.beneficiary.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                debit.beneficiary = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
+        // Transactions which were evicted from the mempool. Without the full scan we can't tell
+        // which transactions are no longer reported, so the status is kept on errors.
+        if errors.is_empty() {
+            for tx in cache.tx.values_mut() {
+                if tx.status == TxStatus::Mempool && !seen.contains(&tx.txid) {
+                    tx.status = TxStatus::Unknown;
+                    changed += 1;
                 }
-                cache.tx.insert(tx.txid, tx);
             }
         }
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for credit in &mut tx.inputs {
-                    let Some(s) = credit.payer.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        credit.payer = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
-                    } else if credit.payer.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                credit.payer = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                    if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
-                        if let Some(txout) =
-                            prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
-                        {
-                            let outpoint = txout.outpoint;
-                            if tx.status.is_mined() {
-                                cache.utxo.remove(&outpoint);
-                            }
-                            txout.spent = Some(credit.outpoint.into())
-                        };
-                    }
+        match self.block_headers_subscribe() {
+            Ok(tip) => {
+                if let Ok(height) = BlockHeight::try_from(tip.height as u32) {
+                    cache.last_block = BlockInfo::with_header(height, tip.header).mined;
+                }
+            }
+            Err(err) => errors.push(err.into()),
+        }
+        for info in cache.unindexed_blocks() {
+            match get_block_info(self, info) {
+                Ok(block) => {
+                    cache.headers.insert(block);
                 }
-                cache.tx.insert(tx.txid, tx);
+                Err(err) => errors.push(err),
             }
-            cache
-                .addr
-                .entry(wallet_addr.terminal.keychain)
-                .or_default()
-                .insert(wallet_addr.expect_transmute());
         }
 
-        if errors.is_empty() { MayError::ok(cache) } else { MayError::err(cache, errors) }
+        cache.reindex(descriptor.network());
+
+        if errors.is_empty() {
+            MayError::ok(changed)
+        } else {
+            MayError::err(changed, errors)
+        }
     }
 
-    fn update<K, D: Descriptor<K>, L2: Layer2>(
+    fn reorg<K, D: Descriptor<K>, L2: Layer2>(
         &self,
-        _descr: &WalletDescr<K, D, L2::Descr>,
-        _cache: &mut WalletCache<L2::Cache>,
-    ) -> MayError<usize, Vec<Self::Error>> {
-        todo!()
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+        let tip = match self.block_headers_subscribe() {
+            Ok(tip) => tip,
+            Err(err) => return MayError::err(none!(), vec![err.into()]),
+        };
+        let result = cache.rollback(descriptor.network(), |height| {
+            let height = height.get() as usize;
+            if height > tip.height {
+                return Ok(None);
+            }
+            self.block_header(height).map(|header| Some(header.block_hash()))
+        });
+        match result {
+            Ok(affected) => MayError::ok(affected),
+            Err(err) => MayError::err(none!(), vec![err.into()]),
+        }
     }
 
     fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
         self.transaction_broadcast(tx)?;
         Ok(())
     }
+
+    fn fee_estimates(&self) -> Result<FeeEstimates, Self::Error> {
+        let mut estimates = FeeEstimates::new();
+        let rates = self.batch_estimate_fee(FEE_TARGETS.iter().map(|target| *target as usize))?;
+        for (target, btc_per_kb) in FEE_TARGETS.into_iter().zip(rates) {
+            // Electrum returns fee rate in BTC per kilobyte, or -1 if there is not enough data.
+            if btc_per_kb > 0.0 {
+                estimates.insert(target, FeeRate::from_sat_per_vb(btc_per_kb * 100_000.0));
+            }
+        }
+        Ok(estimates)
+    }
+}
+
+/// Status of a script history reported by the Electrum server.
+enum ScriptState {
+    /// The script has no history.
+    Unused,
+    /// Status hash of the script history.
+    Status(ScriptStatus),
+}
+
+/// Requests status of the script histories with `blockchain.scripthash.subscribe`.
+///
+/// For the scripts subscribed to during the previous updates the status from the latest
+/// notification received from the server is used. If there were no notifications since the
+/// subscription the status is unknown, so such scripts are re-subscribed together with the new
+/// ones in a single batch.
+fn script_states(client: &Client, scripts: &[ScriptPubkey]) -> Result<Vec<ScriptState>, Error> {
+    // Receive all notifications sent by the server before this request.
+    client.ping()?;
+    let mut states = Vec::with_capacity(scripts.len());
+    let mut subscribe = Vec::new();
+    for script in scripts {
+        let mut status = None;
+        loop {
+            match client.script_pop(script) {
+                Ok(Some(notified)) => status = Some(notified),
+                Ok(None) => break,
+                Err(Error::NotSubscribed(_)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        match status {
+            Some(status) => states.push(Some(ScriptState::Status(status))),
+            None => {
+                match client.script_unsubscribe(script) {
+                    Ok(_) | Err(Error::NotSubscribed(_)) => {}
+                    Err(err) => return Err(err),
+                }
+                states.push(None);
+                subscribe.push(script);
+            }
+        }
+    }
+    let mut statuses =
+        if subscribe.is_empty() { vec![] } else { client.batch_script_subscribe(subscribe)? }
+            .into_iter();
+    Ok(states
+        .into_iter()
+        .map(|state| {
+            state.unwrap_or_else(|| {
+                statuses.next().flatten().map_or(ScriptState::Unused, ScriptState::Status)
+            })
+        })
+        .collect())
+}
+
+/// Returns wallet transactions known to the cache which spend from or pay to the address.
+fn script_txids<'c, L2: Layer2Cache>(
+    cache: &'c WalletCache<L2>,
+    addr: &'c DerivedAddr,
+) -> impl Iterator<Item = Txid> + 'c {
+    cache
+        .tx
+        .values()
+        .filter(|tx| {
+            tx.inputs.iter().any(|credit| credit.derived_addr().as_ref() == Some(addr))
+                || tx.outputs.iter().any(|debit| debit.derived_addr().as_ref() == Some(addr))
+        })
+        .map(|tx| tx.txid)
+}
+
+/// Computes status of the address history from the transactions known to the cache, in the
+/// same way as the Electrum server does: SHA256 hash of `txid:height:` strings of the mined
+/// transactions in the blockchain order, followed by the mempool transactions, which have
+/// height -1 if they spend unconfirmed outputs and 0 otherwise.
+///
+/// The cache doesn't know the order of transactions within a block and which inputs of mempool
+/// transactions are unconfirmed, if they don't belong to the wallet. If the computed status
+/// differs from the one reported by the server because of this, the history is requested from
+/// the server, so the status is only used to avoid requests for the unchanged scripts.
+fn cached_status<L2: Layer2Cache>(cache: &WalletCache<L2>, addr: &DerivedAddr) -> ScriptStatus {
+    let mut history = script_txids(cache, addr)
+        .filter_map(|txid| {
+            let tx = &cache.tx[&txid];
+            let height = match tx.status {
+                TxStatus::Mined(info) => info.height.get() as i64,
+                TxStatus::Mempool => {
+                    let unconfirmed = tx.inputs.iter().any(|credit| {
+                        cache
+                            .tx
+                            .get(&credit.outpoint.txid)
+                            .is_some_and(|prev| !prev.status.is_mined())
+                    });
+                    -(unconfirmed as i64)
+                }
+                TxStatus::Channel | TxStatus::Unknown => return None,
+            };
+            Some((height, txid))
+        })
+        .collect::<Vec<_>>();
+    history.sort_by_key(|(height, txid)| (*height <= 0, *height, *txid));
+    let mut engine = Sha256::new();
+    for (height, txid) in history {
+        engine.update(format!("{txid}:{height}:"));
+    }
+    ScriptStatus::from(<[u8; 32]>::from(engine.finalize()))
+}
+
+/// Checks whether a transaction from the script history is already present in the cache with
+/// the same mining status.
+fn is_known<L2: Layer2Cache>(cache: &WalletCache<L2>, hr: &GetHistoryRes) -> bool {
+    let Some(tx) = cache.tx.get(&hr.tx_hash) else {
+        return false;
+    };
+    match tx.status {
+        TxStatus::Mined(info) => hr.height > 0 && info.height.get() == hr.height as u32,
+        TxStatus::Mempool => hr.height < 1,
+        TxStatus::Channel | TxStatus::Unknown => false,
+    }
+}
+
+/// Retrieves information about a block containing wallet transactions.
+///
+/// Since Electrum protocol doesn't provide block statistics, only the block header and median
+/// time past (computed from the headers of the preceding blocks) are filled in.
+fn get_block_info(client: &Client, info: MiningInfo) -> Result<BlockInfo, ElectrumError> {
+    let height = info.height.get() as usize;
+    let start = height.saturating_sub(MEDIAN_TIME_SPAN - 1);
+    let res = client.block_headers(start, height - start + 1)?;
+    let header = *res.headers.last().ok_or(ElectrumApiError::NoBlockHeader(info.height))?;
+    if header.block_hash() != info.block_hash {
+        return Err(ElectrumApiError::BlockHeaderMismatch(info.height, info.block_hash).into());
+    }
+    let mut times = res.headers.iter().map(|header| header.time).collect::<Vec<_>>();
+    times.sort_unstable();
+    Ok(BlockInfo {
+        mediantime: times[times.len() / 2],
+        ..BlockInfo::with_header(info.height, header)
+    })
+}
+
+/// Retrieves transaction from the script history and converts it into a [`WalletTx`].
+fn get_wallet_tx(client: &Client, hr: GetHistoryRes) -> Result<WalletTx, ElectrumError> {
+    let txid = hr.tx_hash;
+
+    // get the tx details (requires electrum verbose support)
+    let tx_details = client.raw_call("blockchain.transaction.get", vec![
+        Param::String(hr.tx_hash.to_string()),
+        Param::Bool(true),
+    ])?;
+
+    let tx = tx_details
+        .get("hex")
+        .and_then(Value::as_str)
+        .and_then(|s| Tx::from_str(s).ok())
+        .ok_or(ElectrumApiError::InvalidTx(txid))?;
+
+    // build TxStatus
+    let status = if hr.height < 1 {
+        TxStatus::Mempool
+    } else {
+        let block_hash = tx_details
+            .get("blockhash")
+            .and_then(Value::as_str)
+            .and_then(|s| BlockHash::from_str(s).ok())
+            .ok_or(ElectrumApiError::InvalidBlockHash(txid))?;
+        let blocktime = tx_details
+            .get("blocktime")
+            .and_then(Value::as_u64)
+            .ok_or(ElectrumApiError::InvalidBlockTime(txid))?;
+        let height = NonZeroU32::try_from(hr.height as u32)
+            .map_err(|_| ElectrumApiError::InvalidBlockHeight(txid))?;
+        TxStatus::Mined(MiningInfo {
+            height,
+            time: blocktime,
+            block_hash,
+        })
+    };
+    let tx_size = tx.consensus_serialize().len();
+    let weight = tx.weight_units().to_u32();
+
+    // get inputs to build TxCredit's and total amount,
+    // collecting indexer errors
+    let mut input_total = Sats::ZERO;
+    let mut inputs = Vec::with_capacity(tx.inputs.len());
+    for input in tx.inputs {
+        // get value from previous output tx
+        let prev_tx = client.transaction_get(&input.prev_output.txid)?;
+        let prev_out = prev_tx
+            .outputs
+            .get(input.prev_output.vout.into_usize())
+            .ok_or_else(|| ElectrumApiError::PrevOutTxMismatch(txid, input.clone()))?;
+        let value = prev_out.value;
+        input_total += value;
+        inputs.push(TxCredit {
+            outpoint: input.prev_output,
+            payer: Party::Unknown(prev_out.script_pubkey.clone()),
+            sequence: input.sequence,
+            coinbase: false,
+            script_sig: input.sig_script,
+            witness: input.witness,
+            value,
+        })
+    }
+
+    // get outputs and total amount, build TxDebit's
+    let mut output_total = Sats::ZERO;
+    let mut outputs = Vec::with_capacity(tx.outputs.len());
+    for (no, txout) in tx.outputs.into_iter().enumerate() {
+        output_total += txout.value;
+        outputs.push(TxDebit {
+            outpoint: Outpoint::new(txid, no as u32),
+            beneficiary: Party::Unknown(txout.script_pubkey),
+            value: txout.value,
+            spends: vec![],
+        })
+    }
+
+    // build the WalletTx
+    Ok(WalletTx {
+        txid,
+        status,
+        inputs,
+        outputs,
+        fee: input_total - output_total,
+        size: tx_size as u32,
+        weight,
+        version: tx.version,
+        locktime: tx.lock_time,
+        replaced_by: None,
+        first_seen: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use bpstd::TxOut;
+
+    use super::*;
+    use crate::indexers::memory::MemoryIndexer;
+    use crate::{testing, NoLayer2};
+
+    #[test]
+    fn cached_status_matches_server() {
+        let mut indexer = MemoryIndexer::new();
+        let descr = testing::descriptor();
+        let derived = descr.addresses(0).next().unwrap();
+        let script = derived.addr.script_pubkey();
+        let funding = indexer.fund(script.clone(), Sats::from_btc(1));
+        let height = indexer.tip_height();
+        let spending = testing::tx([funding], [TxOut::new(script.clone(), Sats(90_000_000))]);
+        let child = testing::tx([Outpoint::new(spending.txid(), 0)], [TxOut::new(
+            script,
+            Sats(80_000_000),
+        )]);
+        indexer.publish(&spending).unwrap();
+        indexer.publish(&child).unwrap();
+        let cache = indexer.create::<_, _, NoLayer2>(&descr).into_result().unwrap();
+
+        // status hash as defined by the Electrum protocol
+        let history =
+            format!("{}:{height}:{}:-1:{}:0:", funding.txid, child.txid(), spending.txid());
+        let expected = <[u8; 32]>::from(Sha256::digest(history));
+        assert_eq!(*cached_status(&cache, &derived), expected);
+
+        let unused = descr.addresses(1).next().unwrap();
+        let empty = <[u8; 32]>::from(Sha256::digest(""));
+        assert_eq!(*cached_status(&cache, &unused), empty);
+    }
 }
@@ -20,20 +20,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::num::NonZeroU32;
 use std::ops::{Deref, DerefMut};
 
-use bpstd::{Address, DerivedAddr, LockTime, Outpoint, SeqNo, Tx, TxVer, Witness};
+use bpstd::{
+    Address, BlockHash, BlockHeader, BlockMerkleRoot, DerivedAddr, LockTime, Outpoint, SeqNo, Tx,
+    TxVer, Txid, Witness,
+};
 use descriptors::Descriptor;
 use esplora::{BlockingClient, Error};
+use sha2::{Digest, Sha256};
 
 #[cfg(feature = "mempool")]
 use super::mempool::Mempool;
-use super::BATCH_SIZE;
+use super::Lookahead;
 use crate::{
-    Indexer, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr,
-    WalletCache, WalletDescr, WalletTx,
+    BlockInfo, FeeEstimates, FeeRate, Indexer, Layer2, Layer2Cache, MayError, MiningInfo, Party,
+    TxCredit, TxDebit, TxStatus, WalletCache, WalletDescr, WalletTx,
 };
 
 /// Represents a client for interacting with the Esplora indexer.
@@ -135,7 +139,7 @@ impl From<esplora::Tx> for WalletTx {
                     outpoint: Outpoint::new(tx.txid, n as u32),
                     beneficiary: Party::from(vout.scriptpubkey),
                     value: vout.value.into(),
-                    spent: None,
+                    spends: vec![],
                 })
                 .collect(),
             fee: tx.fee.into(),
@@ -143,10 +147,68 @@ impl From<esplora::Tx> for WalletTx {
             weight: tx.weight,
             version: TxVer::from_consensus_i32(tx.version),
             locktime: LockTime::from_consensus_u32(tx.locktime),
+            replaced_by: None,
+            first_seen: None,
         }
     }
 }
 
+/// Block information, as reported by the Esplora indexer.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct Block {
+    height: u32,
+    version: i32,
+    timestamp: u32,
+    tx_count: u32,
+    size: u32,
+    weight: u32,
+    merkle_root: BlockMerkleRoot,
+    previousblockhash: Option<BlockHash>,
+    mediantime: u32,
+    nonce: u32,
+    bits: u32,
+}
+
+impl From<Block> for BlockInfo {
+    fn from(block: Block) -> Self {
+        let header = BlockHeader {
+            version: block.version,
+            prev_block_hash: block.previousblockhash.unwrap_or(BlockHash::from([0u8; 32])),
+            merkle_root: block.merkle_root,
+            time: block.timestamp,
+            bits: block.bits,
+            nonce: block.nonce,
+        };
+        let height = NonZeroU32::try_from(block.height).unwrap_or(NonZeroU32::MIN);
+        BlockInfo {
+            tx_count: block.tx_count,
+            size: block.size,
+            weight: block.weight,
+            mediantime: block.mediantime,
+            ..BlockInfo::with_header(height, header)
+        }
+    }
+}
+
+/// Retrieves information about a block with a given hash.
+///
+/// # Arguments
+///
+/// * `client` - The Esplora client.
+/// * `block_hash` - The hash of the block.
+///
+/// # Errors
+///
+/// Returns an error if there was a problem retrieving the block information.
+#[allow(clippy::result_large_err)]
+fn get_block_info(client: &Client, block_hash: BlockHash) -> Result<BlockInfo, Error> {
+    let url = format!("{}/block/{}", client.url(), block_hash);
+    let block: Block = client.agent().get(&url).call()?.into_json()?;
+    Ok(block.into())
+}
+
 /// Retrieves all transactions associated with a given script hash.
 ///
 /// # Arguments
@@ -189,6 +251,75 @@ fn get_scripthash_txs_all(
     Ok(res)
 }
 
+/// Transaction statistics for a script, as reported by the Esplora indexer.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct ScriptStats {
+    chain_stats: TxoStats,
+    mempool_stats: TxoStats,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct TxoStats {
+    tx_count: usize,
+}
+
+/// Retrieves the number of mined and mempool transactions associated with a given script hash.
+///
+/// # Arguments
+///
+/// * `client` - The Esplora client.
+/// * `derive` - The derived address.
+///
+/// # Errors
+///
+/// Returns an error if there was a problem retrieving the statistics.
+#[allow(clippy::result_large_err)]
+fn get_scripthash_stats(client: &Client, derive: &DerivedAddr) -> Result<ScriptStats, Error> {
+    let url = match client.kind {
+        ClientKind::Esplora => {
+            let script = derive.addr.script_pubkey();
+            let script_hash = Sha256::digest(script.as_slice());
+            format!("{}/scripthash/{:x}", client.url(), script_hash)
+        }
+        #[cfg(feature = "mempool")]
+        ClientKind::Mempool => format!("{}/address/{}", client.url(), derive.addr),
+    };
+    let stats = client.agent().get(&url).call()?.into_json()?;
+    Ok(stats)
+}
+
+/// Collects ids of the transactions already known to the wallet cache for each address.
+fn known_txids<L2: Layer2Cache>(cache: &WalletCache<L2>) -> HashMap<Address, BTreeSet<Txid>> {
+    let mut txids = HashMap::<Address, BTreeSet<Txid>>::new();
+    for tx in cache.tx.values() {
+        let addrs = tx
+            .inputs
+            .iter()
+            .filter_map(TxCredit::derived_addr)
+            .chain(tx.outputs.iter().filter_map(TxDebit::derived_addr));
+        for derived in addrs {
+            txids.entry(derived.addr).or_default().insert(tx.txid);
+        }
+    }
+    txids
+}
+
+/// Counts mined and mempool transactions already known to the wallet cache for an address.
+fn known_stats<L2: Layer2Cache>(cache: &WalletCache<L2>, txids: &BTreeSet<Txid>) -> ScriptStats {
+    let mut stats = ScriptStats::default();
+    for txid in txids {
+        match cache.tx[txid].status {
+            TxStatus::Mined(_) => stats.chain_stats.tx_count += 1,
+            _ => stats.mempool_stats.tx_count += 1,
+        }
+    }
+    stats
+}
+
 impl Indexer for Client {
     type Error = Error;
 
@@ -197,121 +328,135 @@ impl Indexer for Client {
         descriptor: &WalletDescr<K, D, L2::Descr>,
     ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
         let mut cache = WalletCache::new();
-        let mut errors = vec![];
+        self.update::<K, D, L2>(descriptor, &mut cache).map(|_| cache)
+    }
 
-        let mut address_index = BTreeMap::new();
-        for keychain in descriptor.keychains() {
-            let mut empty_count = 0usize;
-            eprint!(" keychain {keychain} ");
-            for derive in descriptor.addresses(keychain) {
-                let script = derive.addr.script_pubkey();
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let mut errors = vec![];
+        let mut changed = 0usize;
 
+        // Scan starts from the addresses known to the cache and continues until the gap limit
+        // after the last used address is reached on each of the keychains.
+        let known = known_txids(cache);
+        let mut lookahead = Lookahead::new(descriptor, cache);
+        let mut checked = HashSet::new();
+        let mut seen = HashSet::new();
+        'scan: loop {
+            let unchecked = lookahead.unchecked(&checked);
+            if unchecked.is_empty() {
+                break;
+            }
+            for derive in unchecked {
                 eprint!(".");
-                let mut txids = Vec::new();
-                match get_scripthash_txs_all(self, &derive) {
+                let stats = match get_scripthash_stats(self, &derive) {
                     Err(err) => {
                         errors.push(err);
-                        break;
+                        break 'scan;
                     }
-                    Ok(txes) if txes.is_empty() => {
-                        empty_count += 1;
-                        if empty_count >= BATCH_SIZE {
-                            break;
-                        }
+                    Ok(stats) => stats,
+                };
+                checked.insert(derive.addr.script_pubkey());
+                if stats == ScriptStats::default() {
+                    continue;
+                }
+                lookahead.register_use(derive, cache);
+
+                // the script history has not changed since the last sync
+                if let Some(txids) = known.get(&derive.addr) {
+                    if known_stats(cache, txids) == stats {
+                        seen.extend(txids);
+                        continue;
+                    }
+                }
+                match get_scripthash_txs_all(self, &derive) {
+                    Err(err) => {
+                        errors.push(err);
+                        break 'scan;
                     }
                     Ok(txes) => {
-                        empty_count = 0;
-                        txids = txes.iter().map(|tx| tx.txid).collect();
-                        cache
-                            .tx
-                            .extend(txes.into_iter().map(WalletTx::from).map(|tx| (tx.txid, tx)));
+                        for tx in txes {
+                            seen.insert(tx.txid);
+                            if cache.register_tx(WalletTx::from(tx)) {
+                                changed += 1;
+                            }
+                        }
                     }
                 }
-
-                let wallet_addr = WalletAddr::<i64>::from(derive);
-                address_index.insert(script, (wallet_addr, txids));
             }
         }
 
-        // TODO: Update headers & tip
-
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for debit in &mut tx.outputs {
-                    let Some(s) = debit.beneficiary.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        cache.utxo.insert(debit.outpoint);
-                        debit.beneficiary = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.used = wallet_addr.used.saturating_add(1);
-                        wallet_addr.volume.saturating_add_assign(debit.value);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
-                    } else if debit.beneficiary.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                debit.beneficiary = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
+        // Transactions which were evicted from the mempool. Without the full scan we can't tell
+        // which transactions are no longer reported, so the status is kept on errors.
+        if errors.is_empty() {
+            for tx in cache.tx.values_mut() {
+                if tx.status == TxStatus::Mempool && !seen.contains(&tx.txid) {
+                    tx.status = TxStatus::Unknown;
+                    changed += 1;
                 }
-                cache.tx.insert(tx.txid, tx);
             }
         }
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for credit in &mut tx.inputs {
-                    let Some(s) = credit.payer.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        credit.payer = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
-                    } else if credit.payer.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                credit.payer = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                    if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
-                        if let Some(txout) =
-                            prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
-                        {
-                            let outpoint = txout.outpoint;
-                            if tx.status.is_mined() {
-                                cache.utxo.remove(&outpoint);
-                            }
-                            txout.spent = Some(credit.outpoint.into())
-                        };
-                    }
+        match self.tip_hash() {
+            Ok(tip_hash) => match get_block_info(self, tip_hash) {
+                Ok(tip) => cache.last_block = tip.mined,
+                Err(err) => errors.push(err),
+            },
+            Err(err) => errors.push(err),
+        }
+        for info in cache.unindexed_blocks() {
+            match get_block_info(self, info.block_hash) {
+                Ok(block) => {
+                    cache.headers.insert(block);
                 }
-                cache.tx.insert(tx.txid, tx);
+                Err(err) => errors.push(err),
             }
-            cache
-                .addr
-                .entry(wallet_addr.terminal.keychain)
-                .or_default()
-                .insert(wallet_addr.expect_transmute());
         }
 
-        if errors.is_empty() { MayError::ok(cache) } else { MayError::err(cache, errors) }
+        cache.reindex(descriptor.network());
+
+        if errors.is_empty() {
+            MayError::ok(changed)
+        } else {
+            MayError::err(changed, errors)
+        }
     }
 
-    fn update<K, D: Descriptor<K>, L2: Layer2>(
+    fn reorg<K, D: Descriptor<K>, L2: Layer2>(
         &self,
-        _descr: &WalletDescr<K, D, L2::Descr>,
-        _cache: &mut WalletCache<L2::Cache>,
-    ) -> MayError<usize, Vec<Self::Error>> {
-        todo!()
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+        #[allow(clippy::result_large_err)]
+        let result =
+            cache.rollback(descriptor.network(), |height| match self.block_hash(height.get()) {
+                Ok(block_hash) => Ok(Some(block_hash)),
+                Err(Error::HeaderHeightNotFound(_)) => Ok(None),
+                Err(err) => Err(err),
+            });
+        match result {
+            Ok(affected) => MayError::ok(affected),
+            Err(err) => MayError::err(none!(), vec![err]),
+        }
     }
 
     fn publish(&self, tx: &Tx) -> Result<(), Self::Error> { self.inner.broadcast(tx) }
+
+    fn fee_estimates(&self) -> Result<FeeEstimates, Self::Error> {
+        match self.kind {
+            ClientKind::Esplora => Ok(self
+                .inner
+                .fee_estimates()?
+                .into_iter()
+                .filter_map(|(target, sat_per_vb)| {
+                    Some((target.parse().ok()?, FeeRate::from_sat_per_vb(sat_per_vb)))
+                })
+                .collect()),
+            #[cfg(feature = "mempool")]
+            ClientKind::Mempool => self.inner.recommended_fees().map(FeeEstimates::from),
+        }
+    }
 }
@@ -0,0 +1,194 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(any(feature = "electrum", feature = "esplora"))]
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+
+use bpstd::{DerivedAddr, IdxBase, Keychain, ScriptPubkey};
+use descriptors::Descriptor;
+
+use crate::wallet::AddrIter;
+use crate::{Layer2Cache, Layer2Descriptor, WalletCache, WalletDescr};
+
+/// Wallet scripts derived up to the gap limit, which is extended with new addresses as soon as
+/// a use of the previous ones is found during the sequential scan of the blockchain.
+pub(crate) struct Lookahead<'descr, K, D: Descriptor<K>> {
+    /// Address iterator, number of derived addresses and the gap limit for each keychain.
+    iters: BTreeMap<Keychain, (AddrIter<'descr, K, D>, u32, u32)>,
+    pub scripts: HashMap<ScriptPubkey, DerivedAddr>,
+}
+
+impl<'descr, K, D: Descriptor<K>> Lookahead<'descr, K, D> {
+    pub fn new(
+        descriptor: &'descr WalletDescr<K, D, impl Layer2Descriptor>,
+        cache: &mut WalletCache<impl Layer2Cache>,
+    ) -> Self {
+        let mut lookahead = Lookahead {
+            iters: descriptor
+                .keychains()
+                .into_iter()
+                .map(|keychain| {
+                    let gap_limit = descriptor.gap_limit(keychain);
+                    (keychain, (descriptor.addresses(keychain), 0, gap_limit))
+                })
+                .collect(),
+            scripts: HashMap::new(),
+        };
+        for keychain in descriptor.keychains() {
+            let addrs = cache.addr.get(&keychain).into_iter().flatten();
+            let known = addrs.clone().map(|addr| addr.terminal.index.child_number() + 1).max();
+            let used = addrs
+                .filter(|addr| addr.used > 0)
+                .map(|addr| addr.terminal.index.child_number() + 1)
+                .max()
+                .unwrap_or_default();
+            let count = known.unwrap_or_default().max(used + descriptor.gap_limit(keychain));
+            lookahead.extend(keychain, count, cache);
+        }
+        lookahead
+    }
+
+    /// Derives wallet addresses on a keychain until their number reaches `count`.
+    fn extend(
+        &mut self,
+        keychain: Keychain,
+        count: u32,
+        cache: &mut WalletCache<impl Layer2Cache>,
+    ) {
+        let Some((iter, derived, _)) = self.iters.get_mut(&keychain) else {
+            return;
+        };
+        while *derived < count {
+            let Some(addr) = iter.next() else {
+                break;
+            };
+            cache.register_addr(addr);
+            self.scripts.insert(addr.addr.script_pubkey(), addr);
+            *derived += 1;
+        }
+    }
+
+    /// Returns derived addresses whose scripts are not in the `checked` set, ordered by their
+    /// keychain and index.
+    #[cfg(any(feature = "electrum", feature = "esplora"))]
+    pub fn unchecked(&self, checked: &HashSet<ScriptPubkey>) -> Vec<DerivedAddr> {
+        let mut addrs = self
+            .scripts
+            .iter()
+            .filter(|(script, _)| !checked.contains(*script))
+            .map(|(_, derived)| *derived)
+            .collect::<Vec<_>>();
+        addrs.sort_by_key(|derived| derived.terminal);
+        addrs
+    }
+
+    /// Ensures the gap limit is maintained after a used address.
+    pub fn register_use(
+        &mut self,
+        derived: DerivedAddr,
+        cache: &mut WalletCache<impl Layer2Cache>,
+    ) {
+        let keychain = derived.terminal.keychain;
+        let Some((_, _, gap_limit)) = self.iters.get(&keychain) else {
+            return;
+        };
+        let count = derived.terminal.index.child_number() + 1 + gap_limit;
+        self.extend(keychain, count, cache);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeSet, HashSet};
+
+    use super::*;
+    use crate::{testing, NoLayer2, WalletAddr};
+
+    /// Scans addresses in the order of their indexes, like the indexers do, registering use of
+    /// the addresses with the provided indexes. Returns found indexes and the number of derived
+    /// addresses.
+    fn discover(gap_limit: u32, used: &[u32]) -> (BTreeSet<u32>, usize) {
+        let mut descr = testing::descriptor();
+        descr.set_gap_limit(0, gap_limit);
+        let mut cache = WalletCache::<NoLayer2>::new();
+        let mut lookahead = Lookahead::new(&descr, &mut cache);
+        let mut checked = HashSet::new();
+        let mut found = BTreeSet::new();
+        loop {
+            let mut pending = lookahead
+                .scripts
+                .iter()
+                .filter(|(script, derived)| {
+                    derived.terminal.keychain == Keychain::OUTER && !checked.contains(*script)
+                })
+                .map(|(script, derived)| (script.clone(), *derived))
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                break;
+            }
+            pending.sort_by_key(|(_, derived)| derived.terminal);
+            for (script, derived) in pending {
+                checked.insert(script);
+                let index = derived.terminal.index.child_number();
+                if used.contains(&index) {
+                    found.insert(index);
+                    lookahead.register_use(derived, &mut cache);
+                }
+            }
+        }
+        (found, cache.addr[&Keychain::OUTER].len())
+    }
+
+    #[test]
+    fn window_extension() {
+        assert_eq!(discover(5, &[]), (bset![], 5));
+        // a use within the window extends it past the used address
+        assert_eq!(discover(5, &[3]), (bset![3], 9));
+        assert_eq!(discover(5, &[3, 8, 13]), (bset![3, 8, 13], 19));
+        // gap of four unused addresses is below the limit
+        assert_eq!(discover(5, &[0, 5]), (bset![0, 5], 11));
+        // gap of exactly five unused addresses stops the discovery
+        assert_eq!(discover(5, &[0, 6]), (bset![0], 6));
+        assert_eq!(discover(1, &[0, 1, 2, 4]), (bset![0, 1, 2], 4));
+    }
+
+    #[test]
+    fn window_from_cache() {
+        let mut descr = testing::descriptor();
+        descr.set_gap_limit(0, 5);
+        descr.set_gap_limit(1, 3);
+        let mut cache = WalletCache::<NoLayer2>::new();
+        let used = descr.addresses(Keychain::OUTER).nth(7).unwrap();
+        cache.addr.entry(Keychain::OUTER).or_default().insert(WalletAddr {
+            used: 1,
+            ..WalletAddr::from(used)
+        });
+
+        // the window starts after the last used address known to the cache
+        let lookahead = Lookahead::new(&descr, &mut cache);
+        assert_eq!(cache.addr[&Keychain::OUTER].len(), 13);
+        assert_eq!(cache.addr[&Keychain::INNER].len(), 3);
+        assert_eq!(lookahead.scripts.len(), 16);
+        assert_eq!(lookahead.scripts[&used.addr.script_pubkey()], used);
+    }
+}
@@ -0,0 +1,787 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic in-memory indexer, simulating a blockchain and a mempool.
+//!
+//! The indexer doesn't require any network connection and is intended for writing tests of the
+//! wallet logic and for the offline use. Blocks and transactions can be constructed in code or
+//! loaded from a YAML or JSON fixture:
+//!
+//! ```yaml
+//! blocks:
+//!   - time: 1700000000 # optional, defaults to 10 minutes after the previous block
+//!     txs:
+//!       - 02000000000101... # consensus-serialized transactions in hex
+//! mempool:
+//!   - 02000000000101...
+//! ```
+//!
+//! Each block gets a coinbase transaction generated by the indexer, unless the first of the
+//! block transactions is a coinbase already. Transaction signatures, scripts and amounts are
+//! not validated; the indexer only checks that all spent outputs exist and are not spent by any
+//! other mined transaction. Mempool transactions conflicting with a newly published or mined
+//! transaction are evicted from the mempool together with their descendants, as with full RBF.
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(feature = "serde")]
+use std::{fs, io};
+
+#[cfg(feature = "serde")]
+use amplify::IoError;
+use bpstd::{
+    BlockHash, BlockHeader, BlockMerkleRoot, ConsensusEncode, DerivedAddr, LockTime, Outpoint,
+    Sats, ScriptPubkey, SeqNo, SigScript, Tx, TxIn, TxOut, TxVer, Txid, VarInt, VarIntArray,
+    Weight, Witness,
+};
+use descriptors::Descriptor;
+use sha2::{Digest, Sha256};
+
+use super::Lookahead;
+use crate::{
+    BlockHeight, BlockInfo, FeeEstimates, FeeRate, Indexer, Layer2, MayError, Party, TxCredit,
+    TxDebit, TxStatus, WalletCache, WalletDescr, WalletTx,
+};
+
+/// Time of the regtest genesis block.
+const GENESIS_TIME: u32 = 1296688602;
+/// Time between two consecutive blocks, unless specified explicitly.
+const BLOCK_INTERVAL: u32 = 600;
+/// Minimal difficulty, as used in regtest.
+const BITS: u32 = 0x207fffff;
+const MEDIAN_TIME_SPAN: usize = 11;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum MemoryError {
+    /// transaction {0} is already known.
+    KnownTx(Txid),
+
+    /// transaction {0} is a coinbase and can't be published.
+    Coinbase(Txid),
+
+    /// transaction spends unknown output {0}.
+    MissingInput(Outpoint),
+
+    /// transaction spends output {0} which is already spent by a mined transaction.
+    DoubleSpend(Outpoint),
+
+    /// can't disconnect the genesis block.
+    Genesis,
+
+    /// fixture contains invalid transaction `{0}`.
+    InvalidTx(String),
+
+    #[cfg(feature = "serde")]
+    /// I/O error accessing fixture file - {0}
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    #[cfg(feature = "serde")]
+    /// invalid fixture data - {0}
+    #[from]
+    Fixture(serde_yaml::Error),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Block {
+    header: BlockHeader,
+    txs: Vec<Tx>,
+}
+
+/// In-memory blockchain with a mempool, implementing [`Indexer`] API.
+#[derive(Debug)]
+pub struct MemoryIndexer {
+    blocks: Vec<Block>,
+    mempool: RefCell<Vec<Tx>>,
+    fees: FeeEstimates,
+}
+
+impl Default for MemoryIndexer {
+    fn default() -> Self { Self::new() }
+}
+
+impl MemoryIndexer {
+    /// Constructs blockchain containing only the regtest genesis block.
+    pub fn new() -> Self {
+        let header = BlockHeader {
+            version: 1,
+            prev_block_hash: BlockHash::from([0u8; 32]),
+            merkle_root: BlockMerkleRoot::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .expect("hardcoded merkle root"),
+            time: GENESIS_TIME,
+            bits: BITS,
+            nonce: 2,
+        };
+        MemoryIndexer {
+            blocks: vec![Block {
+                header,
+                txs: vec![],
+            }],
+            mempool: none!(),
+            fees: FeeEstimates::from_iter([(1, FeeRate::MIN_RELAY)]),
+        }
+    }
+
+    /// Loads blockchain from a YAML or JSON fixture.
+    #[cfg(feature = "serde")]
+    pub fn from_yaml(s: &str) -> Result<Self, MemoryError> {
+        let fixture: Fixture = serde_yaml::from_str(s)?;
+        let parse = |hex: String| Tx::from_str(&hex).map_err(|_| MemoryError::InvalidTx(hex));
+        let mut indexer = Self::new();
+        for block in fixture.blocks {
+            let time = block.time.unwrap_or_else(|| indexer.next_time());
+            let txs = block.txs.into_iter().map(parse).collect::<Result<Vec<_>, _>>()?;
+            indexer.push_block(time, txs)?;
+        }
+        for tx in fixture.mempool {
+            indexer.publish(&parse(tx)?)?;
+        }
+        if !fixture.fees.is_empty() {
+            indexer.fees = fixture.fees;
+        }
+        Ok(indexer)
+    }
+
+    /// Serializes blockchain and mempool into a YAML fixture.
+    #[cfg(feature = "serde")]
+    pub fn to_yaml(&self) -> String {
+        let fixture = Fixture {
+            blocks: self.blocks[1..]
+                .iter()
+                .map(|block| FixtureBlock {
+                    time: Some(block.header.time),
+                    txs: block.txs.iter().map(|tx| format!("{tx:x}")).collect(),
+                })
+                .collect(),
+            mempool: self.mempool.borrow().iter().map(|tx| format!("{tx:x}")).collect(),
+            fees: self.fees.clone(),
+        };
+        serde_yaml::to_string(&fixture).expect("fixture serialization can't fail")
+    }
+
+    /// Loads blockchain from a YAML or JSON fixture file.
+    #[cfg(feature = "serde")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MemoryError> {
+        Self::from_yaml(&fs::read_to_string(path)?)
+    }
+
+    /// Saves blockchain and mempool into a YAML fixture file.
+    #[cfg(feature = "serde")]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), MemoryError> {
+        fs::write(path, self.to_yaml())?;
+        Ok(())
+    }
+
+    /// Sets fee rate estimations reported by the indexer. By default the minimal relay fee rate
+    /// is reported for the next block.
+    pub fn set_fee_estimates(&mut self, fees: FeeEstimates) { self.fees = fees; }
+
+    pub fn tip_height(&self) -> u32 { self.blocks.len() as u32 - 1 }
+
+    pub fn tip_hash(&self) -> BlockHash { self.blocks[self.blocks.len() - 1].header.block_hash() }
+
+    pub fn block_hash(&self, height: u32) -> Option<BlockHash> {
+        self.blocks.get(height as usize).map(|block| block.header.block_hash())
+    }
+
+    /// Returns transactions of the block at a given height, including the coinbase.
+    pub fn block_txs(&self, height: u32) -> Option<&[Tx]> {
+        self.blocks.get(height as usize).map(|block| block.txs.as_slice())
+    }
+
+    pub fn mempool(&self) -> Vec<Tx> { self.mempool.borrow().clone() }
+
+    /// Finds mined or mempool transaction.
+    pub fn tx(&self, txid: Txid) -> Option<Tx> {
+        self.chain_txs()
+            .find(|tx| tx.txid() == txid)
+            .cloned()
+            .or_else(|| self.mempool.borrow().iter().find(|tx| tx.txid() == txid).cloned())
+    }
+
+    /// Mines a new block containing all mempool transactions. Returns hash of the new block.
+    pub fn mine(&mut self) -> BlockHash {
+        let txs = self.mempool.get_mut().clone();
+        self.add_block(txs).expect("mempool transactions are always valid")
+    }
+
+    /// Mines a number of empty blocks. Returns hash of the new chain tip.
+    pub fn mine_empty(&mut self, count: u32) -> BlockHash {
+        for _ in 0..count {
+            self.add_block(none!()).expect("empty block is always valid");
+        }
+        self.tip_hash()
+    }
+
+    /// Mines a new block with a coinbase paying the given amount to a script, including all the
+    /// mempool transactions into the block. Returns the outpoint of the coinbase output.
+    pub fn fund(&mut self, script: ScriptPubkey, value: Sats) -> Outpoint {
+        let coinbase = coinbase(self.tip_height() + 1, script, value);
+        let outpoint = Outpoint::new(coinbase.txid(), 0);
+        let mut txs = vec![coinbase];
+        txs.extend(self.mempool.get_mut().iter().cloned());
+        let time = self.next_time();
+        self.push_block(time, txs).expect("mempool transactions are always valid");
+        outpoint
+    }
+
+    /// Mines a new block with the given transactions, which may or may not be present in the
+    /// mempool. Returns hash of the new block.
+    pub fn add_block(&mut self, txs: Vec<Tx>) -> Result<BlockHash, MemoryError> {
+        let time = self.next_time();
+        self.push_block(time, txs)
+    }
+
+    /// Disconnects a number of blocks from the chain tip, returning their non-coinbase
+    /// transactions to the mempool. Together with [`Self::add_block`] allows simulating
+    /// blockchain reorganizations.
+    ///
+    /// Coinbase transactions of the disconnected blocks cease to exist, thus mempool
+    /// transactions spending their outputs are evicted together with their descendants.
+    pub fn disconnect(&mut self, count: u32) -> Result<Vec<BlockHash>, MemoryError> {
+        if count > self.tip_height() {
+            return Err(MemoryError::Genesis);
+        }
+        let mut disconnected = Vec::with_capacity(count as usize);
+        let mut coinbases = HashSet::new();
+        let mut txs = vec![];
+        for _ in 0..count {
+            let block = self.blocks.pop().expect("genesis is never disconnected");
+            disconnected.push(block.header.block_hash());
+            let mut block_txs = block.txs.into_iter();
+            coinbases.extend(block_txs.next().as_ref().map(Tx::txid));
+            txs.splice(0..0, block_txs);
+        }
+        let mempool = self.mempool.get_mut();
+        txs.append(mempool);
+        evict_descendants(&mut txs, coinbases);
+        *mempool = txs;
+        Ok(disconnected)
+    }
+
+    fn next_time(&self) -> u32 { self.blocks[self.blocks.len() - 1].header.time + BLOCK_INTERVAL }
+
+    fn chain_txs(&self) -> impl Iterator<Item = &Tx> {
+        self.blocks.iter().flat_map(|block| &block.txs)
+    }
+
+    fn push_block(&mut self, time: u32, mut txs: Vec<Tx>) -> Result<BlockHash, MemoryError> {
+        let height = self.tip_height() + 1;
+        if !txs.first().map(is_coinbase).unwrap_or_default() {
+            txs.insert(0, coinbase(height, ScriptPubkey::new(), Sats::ZERO));
+        }
+        for (no, tx) in txs.iter().enumerate().skip(1) {
+            self.check_tx(tx, &txs[..no])?;
+        }
+
+        let mined = txs.iter().map(Tx::txid).collect::<HashSet<_>>();
+        let mempool = self.mempool.get_mut();
+        mempool.retain(|tx| !mined.contains(&tx.txid()));
+        evict_conflicts(mempool, &txs);
+
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_block_hash: self.tip_hash(),
+            merkle_root: merkle_root(&txs),
+            time,
+            bits: BITS,
+            nonce: 0,
+        };
+        self.blocks.push(Block { header, txs });
+        Ok(header.block_hash())
+    }
+
+    /// Checks that the transaction is not known and spends existing outputs which are not yet
+    /// spent by the mined transactions or by the `pending` ones.
+    fn check_tx(&self, tx: &Tx, pending: &[Tx]) -> Result<(), MemoryError> {
+        let txid = tx.txid();
+        if is_coinbase(tx) {
+            return Err(MemoryError::Coinbase(txid));
+        }
+        let mempool = self.mempool.borrow();
+        if self.chain_txs().chain(pending).any(|known| known.txid() == txid) {
+            return Err(MemoryError::KnownTx(txid));
+        }
+        for input in &tx.inputs {
+            let outpoint = input.prev_output;
+            let exists = self.chain_txs().chain(pending).chain(mempool.iter()).any(|prev| {
+                prev.txid() == outpoint.txid && prev.outputs.len() > outpoint.vout_usize()
+            });
+            if !exists {
+                return Err(MemoryError::MissingInput(outpoint));
+            }
+            if self
+                .chain_txs()
+                .chain(pending)
+                .any(|other| other.inputs.iter().any(|txin| txin.prev_output == outpoint))
+            {
+                return Err(MemoryError::DoubleSpend(outpoint));
+            }
+        }
+        Ok(())
+    }
+
+    fn block_info(&self, height: u32) -> BlockInfo {
+        let block = &self.blocks[height as usize];
+        let base = 80 + VarInt::with(block.txs.len()).consensus_serialize().len() as u32;
+        let start = (height as usize + 1).saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times = self.blocks[start..=height as usize]
+            .iter()
+            .map(|block| block.header.time)
+            .collect::<Vec<_>>();
+        times.sort_unstable();
+        BlockInfo {
+            tx_count: block.txs.len() as u32,
+            size: base
+                + block.txs.iter().map(|tx| tx.consensus_serialize().len() as u32).sum::<u32>(),
+            weight: base * 4 + block.txs.iter().map(|tx| tx.weight_units().to_u32()).sum::<u32>(),
+            mediantime: times[times.len() / 2],
+            ..BlockInfo::with_header(height.try_into().unwrap_or(BlockHeight::MIN), block.header)
+        }
+    }
+}
+
+impl Indexer for MemoryIndexer {
+    type Error = MemoryError;
+
+    fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+        let mut cache = WalletCache::new();
+        self.update::<K, D, L2>(descriptor, &mut cache).map(|_| cache)
+    }
+
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let mempool = self.mempool.borrow();
+        let prevouts = self
+            .chain_txs()
+            .chain(mempool.iter())
+            .flat_map(|tx| {
+                let txid = tx.txid();
+                tx.outputs
+                    .iter()
+                    .enumerate()
+                    .map(move |(vout, txout)| (Outpoint::new(txid, vout as u32), txout))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut changed = 0usize;
+        let mut seen = HashSet::new();
+        let mut lookahead = Lookahead::new(descriptor, cache);
+        let blocks = self.blocks.iter().enumerate().map(|(height, block)| {
+            (TxStatus::Mined(self.block_info(height as u32).mined), &block.txs)
+        });
+        for (status, txs) in blocks.chain([(TxStatus::Mempool, &*mempool)]) {
+            for tx in txs {
+                let Some(tx) = wallet_tx(tx, status, &prevouts, &lookahead.scripts) else {
+                    continue;
+                };
+                for debit in &tx.outputs {
+                    if let Party::Unknown(script) = &debit.beneficiary {
+                        if let Some(derived) = lookahead.scripts.get(script).copied() {
+                            lookahead.register_use(derived, cache);
+                        }
+                    }
+                }
+                seen.insert(tx.txid);
+                if cache.register_tx(tx) {
+                    changed += 1;
+                }
+            }
+        }
+
+        // Transactions which were evicted from the mempool
+        for tx in cache.tx.values_mut() {
+            if !seen.contains(&tx.txid) && tx.status != TxStatus::Unknown {
+                tx.status = TxStatus::Unknown;
+                changed += 1;
+            }
+        }
+
+        cache.last_block = self.block_info(self.tip_height()).mined;
+        for info in cache.unindexed_blocks() {
+            let height = info.height.get();
+            if self.block_hash(height) == Some(info.block_hash) {
+                cache.headers.insert(self.block_info(height));
+            }
+        }
+        cache.reindex(descriptor.network());
+
+        MayError::ok(changed)
+    }
+
+    fn reorg<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+        let result = cache.rollback(descriptor.network(), |height| {
+            Ok::<_, MemoryError>(self.block_hash(height.get()))
+        });
+        match result {
+            Ok(affected) => MayError::ok(affected),
+            Err(err) => MayError::err(none!(), vec![err]),
+        }
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
+        self.check_tx(tx, &[])?;
+        let mut mempool = self.mempool.borrow_mut();
+        if mempool.iter().any(|known| known.txid() == tx.txid()) {
+            return Err(MemoryError::KnownTx(tx.txid()));
+        }
+        evict_conflicts(&mut mempool, std::slice::from_ref(tx));
+        mempool.push(tx.clone());
+        Ok(())
+    }
+
+    fn fee_estimates(&self) -> Result<FeeEstimates, Self::Error> { Ok(self.fees.clone()) }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct Fixture {
+    #[serde(default)]
+    blocks: Vec<FixtureBlock>,
+    #[serde(default)]
+    mempool: Vec<String>,
+    #[serde(default, skip_serializing_if = "FeeEstimates::is_empty")]
+    fees: FeeEstimates,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct FixtureBlock {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time: Option<u32>,
+    #[serde(default)]
+    txs: Vec<String>,
+}
+
+fn is_coinbase(tx: &Tx) -> bool {
+    tx.inputs.len() == 1 && tx.inputs[0].prev_output.txid.is_coinbase()
+}
+
+fn coinbase(height: u32, script: ScriptPubkey, value: Sats) -> Tx {
+    // BIP34 height commitment, making coinbase transactions unique
+    let mut script_sig = vec![4u8];
+    script_sig.extend(height.to_le_bytes());
+    Tx {
+        version: TxVer::V2,
+        inputs: VarIntArray::from_checked(vec![TxIn {
+            prev_output: Outpoint::coinbase(),
+            sig_script: SigScript::from_unsafe(script_sig),
+            sequence: SeqNo::from_consensus_u32(u32::MAX),
+            witness: Witness::new(),
+        }]),
+        outputs: VarIntArray::from_checked(vec![TxOut::new(script, value)]),
+        lock_time: LockTime::ZERO,
+    }
+}
+
+fn merkle_root(txs: &[Tx]) -> BlockMerkleRoot {
+    let mut level = txs.iter().map(|tx| tx.txid().consensus_serialize()).collect::<Vec<_>>();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut engine = Sha256::new();
+                engine.update(&pair[0]);
+                engine.update(pair.get(1).unwrap_or(&pair[0]));
+                Sha256::digest(engine.finalize()).to_vec()
+            })
+            .collect();
+    }
+    let mut root = [0u8; 32];
+    if let Some(hash) = level.first() {
+        root.copy_from_slice(hash);
+    }
+    BlockMerkleRoot::from(root)
+}
+
+/// Removes from the mempool transactions spending the same outputs as any of the given ones,
+/// together with all their descendants.
+fn evict_conflicts(mempool: &mut Vec<Tx>, txs: &[Tx]) {
+    let mut spent = txs
+        .iter()
+        .flat_map(|tx| tx.inputs.iter().map(|txin| txin.prev_output))
+        .collect::<HashSet<_>>();
+    let mut evicted = HashSet::<Txid>::new();
+    mempool.retain(|tx| {
+        let conflicts = tx.inputs.iter().any(|txin| {
+            spent.contains(&txin.prev_output) || evicted.contains(&txin.prev_output.txid)
+        });
+        if conflicts {
+            evicted.insert(tx.txid());
+        } else {
+            spent.extend(tx.inputs.iter().map(|txin| txin.prev_output));
+        }
+        !conflicts
+    });
+}
+
+/// Removes from the mempool transactions spending outputs of the given transactions, together
+/// with all their descendants.
+fn evict_descendants(mempool: &mut Vec<Tx>, mut evicted: HashSet<Txid>) {
+    mempool.retain(|tx| {
+        let descendant = tx.inputs.iter().any(|txin| evicted.contains(&txin.prev_output.txid));
+        if descendant {
+            evicted.insert(tx.txid());
+        }
+        !descendant
+    });
+}
+
+/// Converts transaction into a [`WalletTx`], if it pays to or spends from the wallet.
+fn wallet_tx(
+    tx: &Tx,
+    status: TxStatus,
+    prevouts: &HashMap<Outpoint, &TxOut>,
+    scripts: &HashMap<ScriptPubkey, DerivedAddr>,
+) -> Option<WalletTx> {
+    let txid = tx.txid();
+    let mut relevant = false;
+
+    let mut input_total = Sats::ZERO;
+    let mut inputs = Vec::with_capacity(tx.inputs.len());
+    for input in &tx.inputs {
+        let coinbase = input.prev_output.txid.is_coinbase();
+        let (payer, value) = match prevouts.get(&input.prev_output) {
+            _ if coinbase => (Party::Subsidy, Sats::ZERO),
+            Some(prevout) => {
+                relevant |= scripts.contains_key(&prevout.script_pubkey);
+                (Party::Unknown(prevout.script_pubkey.clone()), prevout.value)
+            }
+            None => (Party::Unknown(ScriptPubkey::new()), Sats::ZERO),
+        };
+        input_total += value;
+        inputs.push(TxCredit {
+            outpoint: input.prev_output,
+            payer,
+            sequence: input.sequence,
+            coinbase,
+            script_sig: input.sig_script.clone(),
+            witness: input.witness.clone(),
+            value,
+        });
+    }
+
+    let mut output_total = Sats::ZERO;
+    let mut outputs = Vec::with_capacity(tx.outputs.len());
+    for (no, txout) in tx.outputs.iter().enumerate() {
+        relevant |= scripts.contains_key(&txout.script_pubkey);
+        output_total += txout.value;
+        outputs.push(TxDebit {
+            outpoint: Outpoint::new(txid, no as u32),
+            beneficiary: Party::Unknown(txout.script_pubkey.clone()),
+            value: txout.value,
+            spends: vec![],
+        });
+    }
+
+    relevant.then(|| WalletTx {
+        txid,
+        status,
+        inputs,
+        outputs,
+        fee: input_total.saturating_sub(output_total),
+        size: tx.consensus_serialize().len() as u32,
+        weight: tx.weight_units().to_u32(),
+        version: tx.version,
+        locktime: tx.lock_time,
+        replaced_by: None,
+        first_seen: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use bpstd::Keychain;
+
+    use super::*;
+    use crate::{testing, MiningInfo, NoLayer2};
+
+    fn foreign() -> ScriptPubkey { ScriptPubkey::from_unsafe(vec![0x51]) }
+
+    #[test]
+    fn fund_and_spend() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let funding = indexer.fund(script, Sats::from_btc(1));
+        indexer.mine_empty(2);
+
+        assert!(wallet.update(&indexer).into_result().unwrap().reorged.is_empty());
+        assert_eq!(wallet.balance(), Sats::from_btc(1));
+        assert_eq!(wallet.transactions().len(), 1);
+
+        let spending = testing::tx([funding], [TxOut::new(foreign(), Sats(90_000_000))]);
+        indexer.publish(&spending).unwrap();
+        wallet.update(&indexer).into_result().unwrap();
+        assert_eq!(wallet.balance(), Sats::ZERO);
+        assert_eq!(wallet.transactions()[&spending.txid()].status, TxStatus::Mempool);
+        assert_eq!(wallet.transactions()[&spending.txid()].fee, Sats(10_000_000));
+
+        indexer.mine();
+        wallet.update(&indexer).into_result().unwrap();
+        assert!(wallet.transactions()[&spending.txid()].status.is_mined());
+        assert_eq!(indexer.mempool(), vec![]);
+    }
+
+    #[test]
+    fn publish_checks() {
+        let mut indexer = MemoryIndexer::new();
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        let missing = Outpoint::new(Txid::from([1u8; 32]), 0);
+
+        let tx = testing::tx([missing], [TxOut::new(foreign(), Sats(1000))]);
+        assert!(
+            matches!(indexer.publish(&tx), Err(MemoryError::MissingInput(outpoint)) if outpoint == missing)
+        );
+
+        let coinbase = indexer.block_txs(1).unwrap()[0].clone();
+        assert!(matches!(indexer.publish(&coinbase), Err(MemoryError::Coinbase(_))));
+
+        let tx = testing::tx([funding], [TxOut::new(foreign(), Sats(1000))]);
+        indexer.publish(&tx).unwrap();
+        assert!(matches!(indexer.publish(&tx), Err(MemoryError::KnownTx(_))));
+
+        // replacement evicts the original transaction and its descendants
+        let child = testing::tx([Outpoint::new(tx.txid(), 0)], [TxOut::new(foreign(), Sats(500))]);
+        indexer.publish(&child).unwrap();
+        let replacement = testing::tx([funding], [TxOut::new(foreign(), Sats(900))]);
+        indexer.publish(&replacement).unwrap();
+        assert_eq!(indexer.mempool(), vec![replacement.clone()]);
+
+        indexer.mine();
+        let double_spend = testing::tx([funding], [TxOut::new(foreign(), Sats(800))]);
+        assert!(matches!(indexer.add_block(vec![double_spend]), Err(MemoryError::DoubleSpend(_))));
+    }
+
+    #[test]
+    fn disconnect_evicts_coinbase_spenders() {
+        let mut indexer = MemoryIndexer::new();
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        let other = indexer.fund(foreign(), Sats::from_btc(1));
+        let tx = testing::tx([funding], [TxOut::new(foreign(), Sats(1000))]);
+        let child = testing::tx([Outpoint::new(tx.txid(), 0)], [TxOut::new(foreign(), Sats(500))]);
+        let unrelated = testing::tx([other], [TxOut::new(foreign(), Sats(1000))]);
+        indexer.add_block(vec![tx.clone()]).unwrap();
+        indexer.publish(&child).unwrap();
+        indexer.publish(&unrelated).unwrap();
+
+        // the first coinbase disappears, while the second one remains mined
+        assert_eq!(indexer.disconnect(2).unwrap().len(), 2);
+        assert_eq!(indexer.tip_height(), 1);
+        assert!(indexer.disconnect(2).is_err());
+        indexer.disconnect(1).unwrap();
+        assert_eq!(indexer.mempool(), vec![]);
+
+        // mining doesn't panic on the transactions spending the disconnected coinbase
+        indexer.mine();
+        indexer.fund(foreign(), Sats::from_btc(1));
+        assert_eq!(indexer.tip_height(), 2);
+        assert_eq!(indexer.tx(tx.txid()), None);
+    }
+
+    #[test]
+    fn disconnect_keeps_transactions() {
+        let mut indexer = MemoryIndexer::new();
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        let tx = testing::tx([funding], [TxOut::new(foreign(), Sats(1000))]);
+        indexer.add_block(vec![tx.clone()]).unwrap();
+        indexer.disconnect(1).unwrap();
+        assert_eq!(indexer.mempool(), vec![tx]);
+    }
+
+    #[test]
+    fn reorg() {
+        let mut indexer = MemoryIndexer::new();
+        let descr = testing::descriptor();
+        let script = descr.addresses(0).next().unwrap().addr.script_pubkey();
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        let tx = testing::tx([funding], [TxOut::new(script, Sats(1000))]);
+        indexer.add_block(vec![tx.clone()]).unwrap();
+        indexer.mine_empty(1);
+        let mut cache = indexer.create::<_, _, NoLayer2>(&descr).into_result().unwrap();
+        assert!(cache.tx[&tx.txid()].status.is_mined());
+        assert_eq!(cache.last_block.height.get(), 3);
+
+        // transaction returns to the mempool after the reorganization
+        let fork_point = indexer.disconnect(2).unwrap();
+        let affected = indexer.reorg::<_, _, NoLayer2>(&descr, &mut cache).into_result().unwrap();
+        assert_eq!(affected, bset![tx.txid()]);
+        assert_eq!(cache.tx[&tx.txid()].status, TxStatus::Unknown);
+        // no block known to the cache remains in the best chain
+        assert!(!fork_point.contains(&cache.last_block.block_hash));
+        assert_eq!(cache.last_block, MiningInfo::genesis());
+
+        indexer.mine_empty(3);
+        indexer.update::<_, _, NoLayer2>(&descr, &mut cache).into_result().unwrap();
+        assert_eq!(cache.tx[&tx.txid()].status, TxStatus::Mempool);
+        assert_eq!(cache.last_block.height.get(), 4);
+
+        // transaction disappears, together with the coinbase it spends
+        indexer.disconnect(4).unwrap();
+        let affected = indexer.reorg::<_, _, NoLayer2>(&descr, &mut cache).into_result().unwrap();
+        assert!(affected.is_empty());
+        indexer.update::<_, _, NoLayer2>(&descr, &mut cache).into_result().unwrap();
+        assert_eq!(cache.tx[&tx.txid()].status, TxStatus::Unknown);
+        assert_eq!(cache.utxo.len(), 0);
+        assert_eq!(cache.last_block.height.get(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn yaml_fixture() {
+        let mut indexer = MemoryIndexer::new();
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        indexer.mine_empty(1);
+        indexer.publish(&testing::tx([funding], [TxOut::new(foreign(), Sats(1000))])).unwrap();
+        indexer.set_fee_estimates(FeeEstimates::from_iter([(1, FeeRate::from_sat_per_vb(5.0))]));
+
+        let yaml = indexer.to_yaml();
+        let loaded = MemoryIndexer::from_yaml(&yaml).unwrap();
+        assert_eq!(loaded.tip_hash(), indexer.tip_hash());
+        assert_eq!(loaded.mempool(), indexer.mempool());
+        assert_eq!(loaded.fee_estimates().unwrap(), indexer.fee_estimates().unwrap());
+        assert_eq!(loaded.to_yaml(), yaml);
+
+        assert!(MemoryIndexer::from_yaml("mempool: [zz]").is_err());
+    }
+}
@@ -23,6 +23,8 @@
 use bpstd::Txid;
 use esplora::BlockingClient;
 
+use crate::{FeeEstimates, FeeRate};
+
 impl super::esplora::Client {
     /// Creates a new mempool client with the specified URL.
     ///
@@ -45,6 +47,33 @@ impl super::esplora::Client {
     }
 }
 
+/// Fee rates recommended by the mempool server, in satoshis per virtual byte.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct RecommendedFees {
+    pub fastest_fee: f64,
+    pub half_hour_fee: f64,
+    pub hour_fee: f64,
+    pub economy_fee: f64,
+    pub minimum_fee: f64,
+}
+
+impl From<RecommendedFees> for FeeEstimates {
+    fn from(fees: RecommendedFees) -> Self {
+        [
+            (1, fees.fastest_fee),
+            (3, fees.half_hour_fee),
+            (6, fees.hour_fee),
+            (144, fees.economy_fee),
+            (1008, fees.minimum_fee),
+        ]
+        .into_iter()
+        .map(|(target, sat_per_vb)| (target, FeeRate::from_sat_per_vb(sat_per_vb)))
+        .collect()
+    }
+}
+
 pub trait Mempool {
     #[allow(clippy::result_large_err)]
     fn address_txs(
@@ -52,6 +81,9 @@ pub trait Mempool {
         address: &str,
         last_seen: Option<Txid>,
     ) -> Result<Vec<esplora::Tx>, esplora::Error>;
+
+    #[allow(clippy::result_large_err)]
+    fn recommended_fees(&self) -> Result<RecommendedFees, esplora::Error>;
 }
 
 impl Mempool for BlockingClient {
@@ -82,4 +114,12 @@ impl Mempool for BlockingClient {
         let resp = agent.get(&url).call()?.into_json()?;
         Ok(resp)
     }
+
+    /// Retrieves fee rates recommended by the mempool server for the next block, half an hour,
+    /// an hour, economy and minimum confirmation times.
+    fn recommended_fees(&self) -> Result<RecommendedFees, esplora::Error> {
+        let url = format!("{}/v1/fees/recommended", self.url());
+        let resp = self.agent().get(&url).call()?.into_json()?;
+        Ok(resp)
+    }
 }
@@ -26,18 +26,42 @@ pub mod electrum;
 pub mod esplora;
 #[cfg(feature = "mempool")]
 pub mod mempool;
-#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+#[cfg(feature = "bitcoind")]
+pub mod bitcoind;
+#[cfg(feature = "cbf")]
+pub mod cbf;
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "mempool",
+    feature = "bitcoind",
+    feature = "cbf"
+))]
 mod any;
+pub mod memory;
+mod cache;
+mod lookahead;
 
-#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+use std::collections::BTreeSet;
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "mempool",
+    feature = "bitcoind",
+    feature = "cbf"
+))]
 pub use any::{AnyIndexer, AnyIndexerError};
-use bpstd::Tx;
+use bpstd::{Tx, Txid};
 use descriptors::Descriptor;
+use lookahead::Lookahead;
 
-use crate::{Layer2, MayError, WalletCache, WalletDescr};
+use crate::{FeeEstimates, Layer2, MayError, WalletCache, WalletDescr};
 
-#[cfg(any(feature = "electrum", feature = "esplora"))]
-const BATCH_SIZE: usize = 10;
+/// Confirmation targets, in blocks, for which fee rates are requested from the backends which
+/// provide estimations for a single target at a time.
+#[cfg(any(feature = "electrum", feature = "bitcoind"))]
+const FEE_TARGETS: [u16; 8] = [1, 2, 3, 6, 12, 24, 144, 1008];
 
 pub trait Indexer {
     type Error;
@@ -53,5 +77,27 @@ pub trait Indexer {
         cache: &mut WalletCache<L2::Cache>,
     ) -> MayError<usize, Vec<Self::Error>>;
 
+    /// Detects blockchain reorganizations affecting cached wallet transactions and rolls back
+    /// transactions from the orphaned blocks to [`crate::TxStatus::Unknown`] status. Their
+    /// actual status is restored by a subsequent call to [`Indexer::update`].
+    ///
+    /// Returns ids of the transactions affected by the reorganization. The default
+    /// implementation is for the indexers which can't detect reorganizations; it reports no
+    /// affected transactions.
+    fn reorg<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        _descr: &WalletDescr<K, D, L2::Descr>,
+        _cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+        MayError::ok(none!())
+    }
+
     fn publish(&self, tx: &Tx) -> Result<(), Self::Error>;
+
+    /// Returns fee rates required for the transaction to be mined within different numbers of
+    /// blocks. Targets for which the backend has no data are omitted.
+    ///
+    /// The default implementation is for the indexers which don't provide fee estimations; it
+    /// returns no estimations.
+    fn fee_estimates(&self) -> Result<FeeEstimates, Self::Error> { Ok(none!()) }
 }
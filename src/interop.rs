@@ -0,0 +1,713 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Import and export of watch-only wallets in the file formats used by other wallet software.
+
+use bpstd::{IdxBase, Keychain, Network, XpubDerivable};
+use descriptors::Descriptor;
+
+use crate::{
+    descriptor_with_checksum, parse_descriptor, AnyDescr, Bip43, DescrKey, DescrParseError,
+    Miniscript,
+};
+
+/// File format of a wallet exported by other wallet software.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum WalletFormat {
+    /// Bitcoin Core `listdescriptors` JSON output.
+    #[display("core")]
+    Core,
+
+    /// Sparrow output descriptor text file.
+    #[display("sparrow")]
+    Sparrow,
+
+    /// Specter Desktop wallet JSON file.
+    #[display("specter")]
+    Specter,
+
+    /// Coldcard generic JSON wallet export.
+    #[display("coldcard")]
+    Coldcard,
+
+    /// Coldcard multisig setup text file.
+    #[display("coldcard-multisig")]
+    ColdcardMultisig,
+}
+
+/// Errors importing or exporting wallet in a format of other wallet software.
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum WalletFormatError {
+    /// invalid {0} wallet file: {1}
+    Json(WalletFormat, serde_json::Error),
+
+    /// {0} wallet file doesn't contain required field '{1}'.
+    Missing(WalletFormat, &'static str),
+
+    /// {0} wallet file contains no descriptors which can be used by the wallet.
+    NoDescriptor(WalletFormat),
+
+    /// {0} wallet file contains no {1} descriptor.
+    NoStandard(WalletFormat, Bip43),
+
+    /// {0} wallet file has invalid multisig policy '{1}'; it must have 'M of N' form, where N
+    /// is the number of the listed keys.
+    InvalidPolicy(WalletFormat, String),
+
+    /// {0} wallets can't be imported or exported in this format.
+    Unsupported(String),
+
+    #[from]
+    #[display(inner)]
+    Descr(DescrParseError),
+}
+
+/// Wallet read from a file exported by other wallet software.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct WalletImport {
+    /// Wallet name, if provided by the file.
+    pub name: Option<String>,
+    /// Network the wallet is used on. For the formats not specifying the network it is deduced
+    /// from the extended keys, defaulting to testnet3 for the test networks.
+    pub network: Network,
+    /// Wallet descriptor, always using both receive and change keychains.
+    pub descriptor: AnyDescr,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct CoreWallet {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wallet_name: Option<String>,
+    descriptors: Vec<CoreDescriptor>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct CoreDescriptor {
+    desc: String,
+    #[serde(default)]
+    timestamp: u64,
+    #[serde(default)]
+    active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    internal: Option<bool>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct SpecterWallet {
+    label: String,
+    #[serde(default)]
+    blockheight: u32,
+    descriptor: String,
+    #[serde(default)]
+    devices: Vec<SpecterDevice>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct SpecterDevice {
+    #[serde(rename = "type")]
+    ty: String,
+    label: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct ColdcardWallet {
+    chain: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xfp: Option<String>,
+    #[serde(default)]
+    account: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bip44: Option<ColdcardAccount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bip49: Option<ColdcardAccount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bip84: Option<ColdcardAccount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bip86: Option<ColdcardAccount>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct ColdcardAccount {
+    name: String,
+    deriv: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xfp: Option<String>,
+    xpub: String,
+}
+
+/// Returns the BIP-43 derivation standard matching the script type of the descriptor.
+fn descr_standard(descr: &AnyDescr) -> Option<Bip43> {
+    match descr {
+        AnyDescr::Pkh(_) => Some(Bip43::Bip44),
+        AnyDescr::ShWpkh(_) => Some(Bip43::Bip49),
+        AnyDescr::Wpkh(_) => Some(Bip43::Bip84),
+        AnyDescr::TrKey(_) => Some(Bip43::Bip86),
+        AnyDescr::ShWsh(_) => Some(Bip43::Bip48Nested),
+        AnyDescr::Wsh(_) => Some(Bip43::Bip48Native),
+        AnyDescr::Tr(..) => None,
+    }
+}
+
+/// Returns the first extended key of the descriptor.
+fn descr_key(descr: &AnyDescr) -> Result<&XpubDerivable, WalletFormatError> {
+    descr.keys().next().ok_or_else(|| WalletFormatError::Unsupported(descr.to_string()))
+}
+
+/// Replaces the keychains of all extended keys, keeping the rest of the descriptor.
+fn with_keychains(descr: &AnyDescr, keychains: &'static [Keychain]) -> AnyDescr {
+    descr.map_keys(|key| match key {
+        DescrKey::Xpub(xpub) => {
+            XpubDerivable::new_custom(xpub.xpub(), xpub.origin().clone(), keychains).into()
+        }
+        DescrKey::Single(_) => key.clone(),
+    })
+}
+
+/// Parses descriptor used by other wallet software, which may provide a descriptor for the
+/// receive keychain only, expecting the change keychain to be derived in the same way.
+fn parse_external_descriptor(s: &str) -> Result<AnyDescr, DescrParseError> {
+    let descr = parse_descriptor(&s.replace("/{0,1}/*", "/<0;1>/*"))?;
+    if descr.keys().any(|key| key.keychains().to_string() != "0") {
+        return Ok(descr);
+    }
+    Ok(descr.map_keys(|key| match key {
+        DescrKey::Xpub(xpub) => {
+            XpubDerivable::new_standard(xpub.xpub(), xpub.origin().clone()).into()
+        }
+        DescrKey::Single(_) => key.clone(),
+    }))
+}
+
+fn key_network(descr: &AnyDescr) -> Network {
+    match descr_key(descr) {
+        Ok(key) if !key.xpub().is_testnet() => Network::Mainnet,
+        _ => Network::Testnet3,
+    }
+}
+
+impl WalletFormat {
+    /// Reads wallet from the file content in this format.
+    ///
+    /// If the file contains descriptors for multiple script types, the one matching the provided
+    /// derivation standard is used; with no standard given, P2WPKH descriptor is preferred.
+    pub fn import(
+        self,
+        data: &str,
+        standard: Option<Bip43>,
+    ) -> Result<WalletImport, WalletFormatError> {
+        let json = |err| WalletFormatError::Json(self, err);
+        let (name, network, candidates) = match self {
+            WalletFormat::Core => {
+                let wallet: CoreWallet = serde_json::from_str(data).map_err(json)?;
+                let descrs = wallet
+                    .descriptors
+                    .iter()
+                    .filter(|descr| descr.internal != Some(true))
+                    .map(|descr| parse_external_descriptor(&descr.desc));
+                (wallet.wallet_name, None, descrs.collect::<Vec<_>>())
+            }
+            WalletFormat::Sparrow => {
+                let descrs = data
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(parse_external_descriptor);
+                (None, None, descrs.collect())
+            }
+            WalletFormat::Specter => {
+                let wallet: SpecterWallet = serde_json::from_str(data).map_err(json)?;
+                (Some(wallet.label), None, vec![parse_external_descriptor(&wallet.descriptor)])
+            }
+            WalletFormat::Coldcard => {
+                let wallet: ColdcardWallet = serde_json::from_str(data).map_err(json)?;
+                let network = match wallet.chain.as_str() {
+                    "BTC" => Network::Mainnet,
+                    "XRT" => Network::Regtest,
+                    _ => Network::Testnet3,
+                };
+                let mut descrs = vec![];
+                for (standard, account) in [
+                    (Bip43::Bip44, &wallet.bip44),
+                    (Bip43::Bip49, &wallet.bip49),
+                    (Bip43::Bip84, &wallet.bip84),
+                    (Bip43::Bip86, &wallet.bip86),
+                ] {
+                    let Some(account) = account else { continue };
+                    let xfp = account
+                        .xfp
+                        .as_ref()
+                        .or(wallet.xfp.as_ref())
+                        .ok_or(WalletFormatError::Missing(self, "xfp"))?;
+                    let path = account.deriv.trim_start_matches('m').trim_start_matches('/');
+                    let key = format!("[{}/{path}]{}/<0;1>/*", xfp.to_lowercase(), account.xpub);
+                    descrs.push(parse_descriptor(&match standard {
+                        Bip43::Bip44 => format!("pkh({key})"),
+                        Bip43::Bip49 => format!("sh(wpkh({key}))"),
+                        Bip43::Bip84 => format!("wpkh({key})"),
+                        _ => format!("tr({key})"),
+                    }));
+                }
+                (None, Some(network), descrs)
+            }
+            WalletFormat::ColdcardMultisig => {
+                let (name, descr) = self.import_coldcard_multisig(data)?;
+                (name, None, vec![Ok(descr)])
+            }
+        };
+
+        let mut err = None;
+        let mut descrs = vec![];
+        for res in candidates {
+            match res {
+                Ok(descr) => descrs.push(descr),
+                Err(e) => err = err.or(Some(e)),
+            }
+        }
+        if descrs.is_empty() {
+            return Err(err
+                .map(WalletFormatError::from)
+                .unwrap_or(WalletFormatError::NoDescriptor(self)));
+        }
+        let descriptor = match standard {
+            Some(standard) => descrs
+                .into_iter()
+                .find(|descr| descr_standard(descr) == Some(standard))
+                .ok_or(WalletFormatError::NoStandard(self, standard))?,
+            None => {
+                let pos = descrs
+                    .iter()
+                    .position(|descr| descr_standard(descr) == Some(Bip43::Bip84))
+                    .unwrap_or_default();
+                descrs.swap_remove(pos)
+            }
+        };
+        Ok(WalletImport {
+            name,
+            network: network.unwrap_or_else(|| key_network(&descriptor)),
+            descriptor,
+        })
+    }
+
+    /// Writes wallet with the given name and descriptor in this format.
+    pub fn export(
+        self,
+        name: &str,
+        descr: &AnyDescr,
+        network: Network,
+    ) -> Result<String, WalletFormatError> {
+        let json = |err| WalletFormatError::Json(self, err);
+        let receive =
+            descriptor_with_checksum(&with_keychains(descr, &[Keychain::OUTER]).to_string())?;
+        let change =
+            descriptor_with_checksum(&with_keychains(descr, &[Keychain::INNER]).to_string())?;
+        let key = descr_key(descr)?;
+        let xfp = key.origin().master_fp().to_string().to_uppercase();
+
+        Ok(match self {
+            WalletFormat::Core => {
+                let wallet = CoreWallet {
+                    wallet_name: Some(name.to_owned()),
+                    descriptors: [(receive, false), (change, true)]
+                        .into_iter()
+                        .map(|(desc, internal)| CoreDescriptor {
+                            desc,
+                            timestamp: 0,
+                            active: true,
+                            internal: Some(internal),
+                        })
+                        .collect(),
+                };
+                serde_json::to_string_pretty(&wallet).map_err(json)? + "\n"
+            }
+            WalletFormat::Sparrow => {
+                format!(
+                    "# Receive and change descriptor (BIP389):\n{}\n\n# Receive descriptor \
+                     (Bitcoin Core):\n{receive}\n\n# Change descriptor (Bitcoin Core):\n{change}\n",
+                    descriptor_with_checksum(&descr.to_string())?
+                )
+            }
+            WalletFormat::Specter => {
+                let wallet = SpecterWallet {
+                    label: name.to_owned(),
+                    blockheight: 0,
+                    descriptor: receive,
+                    devices: vec![SpecterDevice {
+                        ty: s!("other"),
+                        label: xfp,
+                    }],
+                };
+                serde_json::to_string_pretty(&wallet).map_err(json)? + "\n"
+            }
+            WalletFormat::Coldcard => {
+                let name = match descr {
+                    AnyDescr::Pkh(_) => "p2pkh",
+                    AnyDescr::ShWpkh(_) => "p2sh-p2wpkh",
+                    AnyDescr::Wpkh(_) => "p2wpkh",
+                    AnyDescr::TrKey(_) => "p2tr",
+                    _ => return Err(WalletFormatError::Unsupported(descr.to_string())),
+                };
+                let account = Some(ColdcardAccount {
+                    name: name.to_owned(),
+                    deriv: format!("m{:#}", key.origin().as_derivation()),
+                    xfp: Some(xfp.clone()),
+                    xpub: key.xpub().to_string(),
+                });
+                let (mut bip44, mut bip49, mut bip84, mut bip86) = (None, None, None, None);
+                match descr {
+                    AnyDescr::Pkh(_) => bip44 = account,
+                    AnyDescr::ShWpkh(_) => bip49 = account,
+                    AnyDescr::Wpkh(_) => bip84 = account,
+                    _ => bip86 = account,
+                }
+                let wallet = ColdcardWallet {
+                    chain: match network {
+                        Network::Mainnet => s!("BTC"),
+                        Network::Regtest => s!("XRT"),
+                        _ => s!("XTN"),
+                    },
+                    xfp: Some(xfp),
+                    account: key
+                        .origin()
+                        .derivation()
+                        .last()
+                        .map(IdxBase::child_number)
+                        .unwrap_or_default(),
+                    bip44,
+                    bip49,
+                    bip84,
+                    bip86,
+                };
+                serde_json::to_string_pretty(&wallet).map_err(json)? + "\n"
+            }
+            WalletFormat::ColdcardMultisig => export_coldcard_multisig(name, descr)?,
+        })
+    }
+
+    /// Reads Coldcard multisig setup file, which lists `XFP: XPUB` lines for all cosigners,
+    /// each using the derivation path given by the preceding `Derivation:` line.
+    fn import_coldcard_multisig(
+        self,
+        data: &str,
+    ) -> Result<(Option<String>, AnyDescr), WalletFormatError> {
+        let mut name = None;
+        let mut policy = None;
+        let mut format = s!("P2SH");
+        let mut derivation = None;
+        let mut keys = vec![];
+        for (field, value) in data
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+        {
+            let value = value.trim();
+            match field.trim().to_lowercase().as_str() {
+                "name" => name = Some(value.to_owned()),
+                "policy" => policy = Some(value.to_owned()),
+                "format" => format = value.to_uppercase(),
+                "derivation" => derivation = Some(value.trim_start_matches('m').to_owned()),
+                xfp if xfp.len() == 8 && xfp.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    let path = derivation
+                        .as_ref()
+                        .ok_or(WalletFormatError::Missing(self, "Derivation"))?;
+                    keys.push(format!("[{xfp}{path}]{value}/<0;1>/*"));
+                }
+                _ => {}
+            }
+        }
+        let policy = policy.ok_or(WalletFormatError::Missing(self, "Policy"))?;
+        let threshold = policy
+            .split_once(" of ")
+            .and_then(|(m, n)| {
+                Some((m.trim().parse::<usize>().ok()?, n.trim().parse::<usize>().ok()?))
+            })
+            .filter(|(m, n)| *n == keys.len() && (1..=*n).contains(m))
+            .map(|(m, _)| m)
+            .ok_or_else(|| WalletFormatError::InvalidPolicy(self, policy.clone()))?;
+        let script = format!("sortedmulti({threshold},{})", keys.join(","));
+        let descr = match format.as_str() {
+            "P2WSH" => format!("wsh({script})"),
+            "P2SH-P2WSH" | "P2WSH-P2SH" => format!("sh(wsh({script}))"),
+            _ => return Err(DescrParseError::Unsupported(s!("sh(sortedmulti)")).into()),
+        };
+        Ok((name, parse_descriptor(&descr)?))
+    }
+}
+
+/// Writes Coldcard multisig setup file for a descriptor with a single `sortedmulti` script.
+fn export_coldcard_multisig(name: &str, descr: &AnyDescr) -> Result<String, WalletFormatError> {
+    let (format, script) = match descr {
+        AnyDescr::Wsh(script) => ("P2WSH", script),
+        AnyDescr::ShWsh(script) => ("P2SH-P2WSH", script),
+        _ => return Err(WalletFormatError::Unsupported(descr.to_string())),
+    };
+    let Miniscript::Multi {
+        threshold,
+        keys,
+        sorted: true,
+    } = script
+    else {
+        return Err(WalletFormatError::Unsupported(descr.to_string()));
+    };
+    let mut file = format!(
+        "# Coldcard Multisig setup file\n#\nName: {name}\nPolicy: {threshold} of {}\nFormat: \
+         {format}\n",
+        keys.len()
+    );
+    for key in keys {
+        let xpub =
+            key.as_xpub().ok_or_else(|| WalletFormatError::Unsupported(descr.to_string()))?;
+        let origin = xpub.origin();
+        file += &format!(
+            "\nDerivation: m{:#}\n{}: {}\n",
+            origin.as_derivation(),
+            origin.master_fp().to_string().to_uppercase(),
+            xpub.xpub()
+        );
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XPUB44: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+    const XPUB86: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+    const ZPUB84: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+    /// The same key as [`ZPUB84`], with the standard version.
+    const XPUB84: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    /// The same key as [`ZPUB84`], with the version of multisig P2WSH keys.
+    const ZPUB84_MULTI: &str = "Zpub739WFCnqb8H6bozqRWNgL4NwrVvUUDaa5UodTovoPXuLnoVJTvkwKA6b5ioUif4ntuhU53ob9LUdZ66F3uNGoX8S6gzjGa1yvYFtkDRknR2";
+
+    fn multisig(script: &str) -> AnyDescr {
+        let closing = ")".repeat(script.matches('(').count());
+        let descr = format!(
+            "{script}(2,[0f056943/48h/0h/0h/2h]{XPUB44}/<0;1>/*,[6ba6cfd0/48h/0h/0h/2h]{XPUB86}/\
+             <0;1>/*,[747b698e/48h/0h/0h/2h]{XPUB84}/<0;1>/*){closing}"
+        );
+        parse_descriptor(&descr).unwrap()
+    }
+
+    fn single_sig() -> Vec<AnyDescr> {
+        [
+            format!("pkh([73c5da0a/44h/0h/0h]{XPUB44}/<0;1>/*)"),
+            format!("sh(wpkh([73c5da0a/49h/0h/0h]{XPUB44}/<0;1>/*))"),
+            format!("wpkh([73c5da0a/84h/0h/0h]{ZPUB84}/<0;1>/*)"),
+            format!("tr([73c5da0a/86h/0h/0h]{XPUB86}/<0;1>/*)"),
+        ]
+        .iter()
+        .map(|descr| parse_descriptor(descr).unwrap())
+        .collect()
+    }
+
+    fn roundtrip(format: WalletFormat, descr: &AnyDescr) {
+        let data = format.export("Savings", descr, Network::Mainnet).unwrap();
+        let import = format.import(&data, descr_standard(descr)).unwrap();
+        assert_eq!(&import.descriptor, descr, "{format}: {data}");
+        assert_eq!(import.network, Network::Mainnet);
+        // Sparrow and Coldcard generic files don't store wallet names.
+        if !matches!(format, WalletFormat::Sparrow | WalletFormat::Coldcard) {
+            assert_eq!(import.name.as_deref(), Some("Savings"), "{format}");
+        }
+    }
+
+    #[test]
+    fn descriptor_formats() {
+        let mut descrs = single_sig();
+        descrs.push(multisig("wsh(sortedmulti"));
+        descrs.push(multisig("sh(wsh(multi"));
+        descrs.push(
+            parse_descriptor(&format!(
+                "tr([73c5da0a/86h/0h/0h]{XPUB86}/<0;1>/*,and_v(v:pk([73c5da0a/44h/0h/0h]{XPUB44}/\
+                 <0;1>/*),older(52560)))"
+            ))
+            .unwrap(),
+        );
+        for descr in &descrs {
+            for format in [WalletFormat::Core, WalletFormat::Sparrow, WalletFormat::Specter] {
+                roundtrip(format, descr);
+            }
+        }
+    }
+
+    #[test]
+    fn coldcard() {
+        for descr in single_sig() {
+            roundtrip(WalletFormat::Coldcard, &descr);
+        }
+        let descr = multisig("wsh(sortedmulti");
+        assert!(WalletFormat::Coldcard.export("Savings", &descr, Network::Mainnet).is_err());
+    }
+
+    #[test]
+    fn coldcard_multisig() {
+        for script in ["wsh(sortedmulti", "sh(wsh(sortedmulti"] {
+            roundtrip(WalletFormat::ColdcardMultisig, &multisig(script));
+        }
+        for descr in single_sig().iter().chain([&multisig("wsh(multi")]) {
+            assert!(WalletFormat::ColdcardMultisig
+                .export("Savings", descr, Network::Mainnet)
+                .is_err());
+        }
+
+        let file = format!(
+            "# Coldcard Multisig setup file (created on 0F056943)\n#\nName: Vault\nPolicy: 2 of \
+             3\nDerivation: m/48'/0'/0'/2'\nFormat: P2WSH\n\n0F056943: {XPUB44}\n6BA6CFD0: \
+             {XPUB86}\n747B698E: {ZPUB84_MULTI}\n"
+        );
+        let import = WalletFormat::ColdcardMultisig.import(&file, None).unwrap();
+        assert_eq!(import.name.as_deref(), Some("Vault"));
+        assert_eq!(import.descriptor, multisig("wsh(sortedmulti"));
+
+        let invalid = file.replace("2 of 3", "2 of 4");
+        assert!(matches!(
+            WalletFormat::ColdcardMultisig.import(&invalid, None),
+            Err(WalletFormatError::InvalidPolicy(_, policy)) if policy == "2 of 4"
+        ));
+        let legacy = file.replace("P2WSH", "P2SH");
+        assert!(matches!(
+            WalletFormat::ColdcardMultisig.import(&legacy, None),
+            Err(WalletFormatError::Descr(DescrParseError::Unsupported(_)))
+        ));
+    }
+
+    #[test]
+    fn core_listdescriptors() {
+        let file = format!(
+            r#"{{
+  "wallet_name": "Savings",
+  "descriptors": [
+    {{
+      "desc": "pkh([73c5da0a/44h/0h/0h]{XPUB44}/0/*)#5l2aanww",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": false,
+      "range": [0, 999],
+      "next": 0
+    }},
+    {{
+      "desc": "pkh([73c5da0a/44h/0h/0h]{XPUB44}/1/*)#9t0uqx7k",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": true,
+      "range": [0, 999],
+      "next": 0
+    }},
+    {{
+      "desc": "sh(wpkh([73c5da0a/49h/0h/0h]{XPUB44}/0/*))#76caeve5",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": false,
+      "range": [0, 999],
+      "next": 0
+    }},
+    {{
+      "desc": "sh(wpkh([73c5da0a/49h/0h/0h]{XPUB44}/1/*))#tmktpnvt",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": true,
+      "range": [0, 999],
+      "next": 0
+    }},
+    {{
+      "desc": "tr([73c5da0a/86h/0h/0h]{XPUB86}/0/*)#se42yddx",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": false,
+      "range": [0, 999],
+      "next": 0
+    }},
+    {{
+      "desc": "tr([73c5da0a/86h/0h/0h]{XPUB86}/1/*)#pdsteca7",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": true,
+      "range": [0, 999],
+      "next": 0
+    }},
+    {{
+      "desc": "wpkh([73c5da0a/84h/0h/0h]{XPUB84}/0/*)#afwvtk2s",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": false,
+      "range": [0, 1001],
+      "next": 3
+    }},
+    {{
+      "desc": "wpkh([73c5da0a/84h/0h/0h]{XPUB84}/1/*)#vatdkr6g",
+      "timestamp": 1697040000,
+      "active": true,
+      "internal": true,
+      "range": [0, 999],
+      "next": 1
+    }}
+  ]
+}}
+"#
+        );
+        let import = WalletFormat::Core.import(&file, None).unwrap();
+        assert_eq!(import.name.as_deref(), Some("Savings"));
+        assert_eq!(import.network, Network::Mainnet);
+        assert_eq!(import.descriptor, single_sig()[2]);
+
+        let import = WalletFormat::Core.import(&file, Some(Bip43::Bip86)).unwrap();
+        assert_eq!(import.descriptor, single_sig()[3]);
+        assert!(matches!(
+            WalletFormat::Core.import(&file, Some(Bip43::Bip48Native)),
+            Err(WalletFormatError::NoStandard(_, Bip43::Bip48Native))
+        ));
+    }
+
+    #[test]
+    fn sparrow_descriptor_file() {
+        let file = format!(
+            "# Receive and change descriptor \
+             (BIP389):\nwpkh([73c5da0a/84'/0'/0']{XPUB84}/<0;1>/*)#hpg6d6w2\n\n# Receive \
+             descriptor (Bitcoin Core):\nwpkh([73c5da0a/84'/0'/0']{XPUB84}/0/*)#wc3n3van\n\n# \
+             Change descriptor (Bitcoin Core):\nwpkh([73c5da0a/84'/0'/0']{XPUB84}/1/*)#lv5jvedt\n"
+        );
+        let import = WalletFormat::Sparrow.import(&file, None).unwrap();
+        assert_eq!(import.name, None);
+        assert_eq!(import.network, Network::Mainnet);
+        assert_eq!(import.descriptor, single_sig()[2]);
+    }
+
+    #[test]
+    fn specter_wallet_file() {
+        let file = format!(
+            r#"{{"label": "Vault", "blockheight": 812345, "descriptor": "wsh(sortedmulti(2,[0f056943/48h/0h/0h/2h]{XPUB44}/0/*,[6ba6cfd0/48h/0h/0h/2h]{XPUB86}/0/*,[747b698e/48h/0h/0h/2h]{XPUB84}/0/*))#a07csvkx", "devices": [{{"type": "coldcard", "label": "Coldcard"}}, {{"type": "trezor", "label": "Trezor"}}, {{"type": "specter", "label": "Specter DIY"}}]}}"#
+        );
+        let import = WalletFormat::Specter.import(&file, None).unwrap();
+        assert_eq!(import.name.as_deref(), Some("Vault"));
+        assert_eq!(import.network, Network::Mainnet);
+        assert_eq!(import.descriptor, multisig("wsh(sortedmulti"));
+    }
+}
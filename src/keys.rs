@@ -0,0 +1,422 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keys used in output descriptors.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use bpstd::secp256k1::{SecretKey, SECP256K1};
+use bpstd::{
+    base58, CompressedPk, DerivationPath, Derive, KeyOrigin, Keychain, LegacyPk, PubkeyHash,
+    Terminal, XOnlyPk, XpubAccount, XpubDerivable, XpubFp,
+};
+
+use crate::{parse_xpub_derivable, Bip43, DescrParseError, Slip132Xpub};
+
+/// Public key used in an output descriptor.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, From)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", try_from = "String", into = "String")
+)]
+pub enum DescrKey {
+    /// Extended public key with origin, from which a key is derived for each terminal.
+    #[from]
+    Xpub(XpubDerivable),
+
+    /// Single public key, which is the same for all terminals.
+    #[from]
+    Single(SingleKey),
+}
+
+/// Single public key with an optional origin, like the keys of paper wallets.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SingleKey {
+    pub origin: Option<KeyOrigin>,
+    pub key: LegacyPk,
+    /// Whether the key is given in x-only form, which is allowed only inside `tr()`.
+    pub xonly: bool,
+}
+
+impl SingleKey {
+    /// Constructs single key without origin.
+    pub fn new(key: LegacyPk) -> Self {
+        SingleKey {
+            origin: None,
+            key,
+            xonly: false,
+        }
+    }
+
+    /// Returns origin of the key; keys without origin use fingerprint of the key itself and an
+    /// empty derivation path.
+    pub fn to_origin(&self) -> KeyOrigin {
+        self.origin.clone().unwrap_or_else(|| {
+            let hash = <[u8; 20]>::from(PubkeyHash::from(self.key));
+            let fp = [hash[0], hash[1], hash[2], hash[3]];
+            KeyOrigin::new(XpubFp::from(fp), DerivationPath::new())
+        })
+    }
+}
+
+/// Private key in wallet import format (WIF), as used by paper wallets and legacy wallets.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct WifKey {
+    pub secret: SecretKey,
+    /// Whether the key is used with compressed public key.
+    pub compressed: bool,
+    pub testnet: bool,
+}
+
+/// Errors parsing private key in wallet import format.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum WifError {
+    /// invalid base58 encoding of the private key - {0}
+    #[from]
+    Base58(base58::Error),
+
+    /// unknown private key version {0:#04x}.
+    UnknownVersion(u8),
+
+    /// invalid private key data length {0}.
+    InvalidLength(usize),
+
+    /// private key is not a valid secp256k1 secret key.
+    InvalidKey,
+}
+
+impl WifKey {
+    const MAINNET: u8 = 0x80;
+    const TESTNET: u8 = 0xEF;
+
+    /// Returns public key matching the private key.
+    pub fn to_legacy_pk(&self) -> LegacyPk {
+        let pubkey = self.secret.public_key(SECP256K1);
+        if self.compressed {
+            LegacyPk::compressed(pubkey)
+        } else {
+            LegacyPk::uncompressed(pubkey)
+        }
+    }
+
+    /// Returns public key matching the private key as a descriptor key without origin.
+    pub fn to_single_key(&self) -> SingleKey { SingleKey::new(self.to_legacy_pk()) }
+}
+
+impl Display for WifKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut data = Vec::with_capacity(34);
+        data.push(if self.testnet { Self::TESTNET } else { Self::MAINNET });
+        data.extend(self.secret.secret_bytes());
+        if self.compressed {
+            data.push(0x01);
+        }
+        f.write_str(&base58::encode_check(&data))
+    }
+}
+
+impl FromStr for WifKey {
+    type Err = WifError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base58::decode_check(s)?;
+        let compressed = match data.len() {
+            33 => false,
+            34 if data[33] == 0x01 => true,
+            len => return Err(WifError::InvalidLength(len)),
+        };
+        let testnet = match data[0] {
+            Self::MAINNET => false,
+            Self::TESTNET => true,
+            version => return Err(WifError::UnknownVersion(version)),
+        };
+        let secret = SecretKey::from_slice(&data[1..33]).map_err(|_| WifError::InvalidKey)?;
+        Ok(WifKey {
+            secret,
+            compressed,
+            testnet,
+        })
+    }
+}
+
+/// Checks that SLIP-132 version of an extended key in the key expression, if any, implies one
+/// of the derivation standards. Single keys and keys with the standard `xpub` and `tpub`
+/// versions are always accepted.
+pub(crate) fn check_key_version(s: &str, standards: &[Bip43]) -> Result<(), DescrParseError> {
+    let key = s.rsplit_once(']').map(|(_, key)| key).unwrap_or(s);
+    let key = key.split('/').next().unwrap_or(key);
+    // Invalid keys are reported by the key parser.
+    let Ok(xpub) = Slip132Xpub::from_str(key) else {
+        return Ok(());
+    };
+    match xpub.version.standard() {
+        Some(standard) if !standards.contains(&standard) => {
+            Err(DescrParseError::KeyVersion(s.to_owned(), xpub.version))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl DescrKey {
+    /// Returns extended key, unless the key is a single key.
+    pub fn as_xpub(&self) -> Option<&XpubDerivable> {
+        match self {
+            DescrKey::Xpub(xpub) => Some(xpub),
+            DescrKey::Single(_) => None,
+        }
+    }
+
+    /// Returns extended key specification, unless the key is a single key.
+    pub fn xpub_spec(&self) -> Option<&XpubAccount> { self.as_xpub().map(XpubDerivable::spec) }
+
+    /// Checks whether the key is given in compressed (or x-only) form.
+    pub fn is_compressed(&self) -> bool {
+        match self {
+            DescrKey::Xpub(_) => true,
+            DescrKey::Single(single) => single.key.compressed,
+        }
+    }
+
+    /// Checks whether the key is a single key given in x-only form.
+    pub fn is_xonly(&self) -> bool { matches!(self, DescrKey::Single(single) if single.xonly) }
+
+    /// Returns keychains the key can be derived for; single keys use only the default keychain.
+    pub fn keychains(&self) -> BTreeSet<Keychain> {
+        match self {
+            DescrKey::Xpub(xpub) => Derive::<CompressedPk>::keychains(xpub),
+            DescrKey::Single(_) => bset![Keychain::OUTER],
+        }
+    }
+
+    /// Derives public key for the terminal.
+    pub fn derive_legacy(&self, terminal: Terminal) -> LegacyPk {
+        match self {
+            DescrKey::Xpub(xpub) => xpub.derive(terminal.keychain, terminal.index),
+            DescrKey::Single(single) => single.key,
+        }
+    }
+
+    /// Derives compressed public key for the terminal.
+    ///
+    /// # Panics
+    ///
+    /// If the key is an uncompressed single key, which is prevented by the descriptor parser
+    /// for all descriptors using this method.
+    pub fn derive_compr(&self, terminal: Terminal) -> CompressedPk {
+        let key = self.derive_legacy(terminal);
+        assert!(key.compressed, "uncompressed key in segwit descriptor");
+        CompressedPk::from(key.pubkey)
+    }
+
+    /// Derives x-only public key for the terminal.
+    pub fn derive_xonly(&self, terminal: Terminal) -> XOnlyPk {
+        XOnlyPk::from(self.derive_legacy(terminal).pubkey)
+    }
+
+    /// Returns origin of the key derived for the terminal.
+    pub fn origin(&self, terminal: Terminal) -> KeyOrigin {
+        match self {
+            DescrKey::Xpub(xpub) => KeyOrigin::with(xpub.origin().clone(), terminal),
+            DescrKey::Single(single) => single.to_origin(),
+        }
+    }
+
+    /// Detects terminal of a key with the given origin, if the key is derived from this one.
+    pub fn terminal(&self, origin: &KeyOrigin) -> Option<Terminal> {
+        match self {
+            DescrKey::Xpub(xpub) if xpub.origin().is_subset_of(origin) => {
+                let derivation = origin.derivation();
+                if derivation.len() != xpub.origin().derivation().len() + 2 {
+                    return None;
+                }
+                derivation.terminal()
+            }
+            DescrKey::Xpub(_) => None,
+            DescrKey::Single(single) if single.to_origin() == *origin => {
+                Some(Terminal::new(Keychain::OUTER, default!()))
+            }
+            DescrKey::Single(_) => None,
+        }
+    }
+}
+
+impl Display for SingleKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(origin) = &self.origin {
+            write!(f, "[{origin}]")?;
+        }
+        if self.xonly {
+            write!(f, "{}", XOnlyPk::from(self.key.pubkey))
+        } else {
+            // Display of `LegacyPk` always uses compressed form
+            f.write_str(&self.key.to_vec().to_hex())
+        }
+    }
+}
+
+impl FromStr for SingleKey {
+    type Err = DescrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || DescrParseError::InvalidPubkey(s.to_owned());
+        let (origin, hex) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, hex) = rest.split_once(']').ok_or_else(err)?;
+                (Some(KeyOrigin::from_str(origin).map_err(|_| err())?), hex)
+            }
+            None => (None, s),
+        };
+        let bytes = Vec::<u8>::from_hex(hex).map_err(|_| err())?;
+        let (key, xonly) = match bytes.len() {
+            32 => {
+                let xonly = XOnlyPk::from_bytes(&bytes).map_err(|_| err())?;
+                let mut even = vec![0x02];
+                even.extend(xonly.to_byte_array());
+                (LegacyPk::from_bytes(even).map_err(|_| err())?, true)
+            }
+            33 | 65 => (LegacyPk::from_bytes(&bytes).map_err(|_| err())?, false),
+            _ => return Err(err()),
+        };
+        Ok(SingleKey { origin, key, xonly })
+    }
+}
+
+impl Display for DescrKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DescrKey::Xpub(xpub) => Display::fmt(xpub, f),
+            DescrKey::Single(single) => Display::fmt(single, f),
+        }
+    }
+}
+
+impl FromStr for DescrKey {
+    type Err = DescrParseError;
+
+    /// Parses extended key with origin, which may use SLIP-132 version, or a single key in hex
+    /// form with an optional origin.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = s.rsplit_once(']').map(|(_, key)| key).unwrap_or(s);
+        if key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return SingleKey::from_str(s).map(DescrKey::from);
+        }
+        Ok(parse_xpub_derivable(s)?.0.into())
+    }
+}
+
+impl TryFrom<String> for DescrKey {
+    type Error = DescrParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> { DescrKey::from_str(&s) }
+}
+
+impl From<DescrKey> for String {
+    fn from(key: DescrKey) -> Self { key.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AnyDescr;
+
+    const SECRET: &str = "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d";
+    const PUBKEY: &str = "02d0de0aaeaefad02b8bdc8a01a1b8b11c696bd3d66a2c5f10780d95b7df42645c";
+
+    #[test]
+    fn wif() {
+        let secret = SecretKey::from_slice(&Vec::<u8>::from_hex(SECRET).unwrap()).unwrap();
+        for (s, compressed, testnet) in [
+            ("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ", false, false),
+            ("KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617", true, false),
+            ("91gGn1HgSap6CbU12F6z3pJri26xzp7Ay1VW6NHCoEayNXwRpu2", false, true),
+            ("cMzLdeGd5vEqxB8B6VFQoRopQ3sLAAvEzDAoQgvX54xwofSWj1fx", true, true),
+        ] {
+            let wif = WifKey::from_str(s).unwrap();
+            assert_eq!(wif, WifKey {
+                secret,
+                compressed,
+                testnet
+            });
+            assert_eq!(wif.to_string(), s);
+        }
+
+        let wif = WifKey::from_str("KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617").unwrap();
+        assert_eq!(wif.to_single_key().to_string(), PUBKEY);
+        let wif = WifKey::from_str("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ").unwrap();
+        assert_eq!(
+            wif.to_single_key().to_string(),
+            "04d0de0aaeaefad02b8bdc8a01a1b8b11c696bd3d66a2c5f10780d95b7df42645cd85228a6fb29940e858e7e\
+             55842ae2bd115d1ed7cc0e82d934e929c97648cb0a"
+        );
+
+        assert!(matches!(
+            WifKey::from_str("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTK"),
+            Err(WifError::Base58(_))
+        ));
+        // Compressed key flag with a wrong value
+        assert_eq!(
+            WifKey::from_str("KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvWxyf5d"),
+            Err(WifError::InvalidLength(34))
+        );
+        assert_eq!(
+            WifKey::from_str("1Qdz59dFBGz4VxavNuyXqCQvCrReA7W5dHeg87tihqqWYuW8cSa"),
+            Err(WifError::UnknownVersion(0))
+        );
+        // Zero secret key
+        assert_eq!(
+            WifKey::from_str("KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73Nd2Mcv1"),
+            Err(WifError::InvalidKey)
+        );
+        // Extended public keys are not private keys
+        assert_eq!(
+            WifKey::from_str(
+                "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V"
+            ),
+            Err(WifError::InvalidLength(78))
+        );
+    }
+
+    #[test]
+    fn single_key_descriptors() {
+        let wif = WifKey::from_str("KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617").unwrap();
+        let descriptors = AnyDescr::with_single_key(wif.to_single_key())
+            .iter()
+            .map(AnyDescr::to_string)
+            .collect::<Vec<_>>();
+        let xonly = &PUBKEY[2..];
+        assert_eq!(descriptors, [
+            format!("pkh({PUBKEY})"),
+            format!("sh(wpkh({PUBKEY}))"),
+            format!("wpkh({PUBKEY})"),
+            format!("tr({xonly})"),
+        ]);
+
+        let wif = WifKey::from_str("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ").unwrap();
+        let descriptors = AnyDescr::with_single_key(wif.to_single_key());
+        assert_eq!(descriptors, [AnyDescr::Pkh(wif.to_single_key().into())]);
+    }
+}
@@ -0,0 +1,258 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wallet labels and their import and export in [BIP-329] format.
+//!
+//! [BIP-329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+
+use std::fmt::{self, Display, Formatter};
+#[cfg(feature = "serde")]
+use std::io;
+use std::str::FromStr;
+
+use bpstd::{Address, Outpoint, Txid};
+
+/// Type of the labelled item, as defined by BIP-329.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "lowercase")
+)]
+pub enum LabelType {
+    /// Transaction.
+    #[display("tx")]
+    Tx,
+
+    /// Address.
+    #[display("addr")]
+    Addr,
+
+    /// Public key; not supported by the wallet.
+    #[cfg_attr(feature = "clap", value(skip))]
+    #[display("pubkey")]
+    Pubkey,
+
+    /// Transaction input.
+    #[display("input")]
+    Input,
+
+    /// Transaction output.
+    #[display("output")]
+    Output,
+
+    /// Extended public key; not supported by the wallet.
+    #[cfg_attr(feature = "clap", value(skip))]
+    #[display("xpub")]
+    Xpub,
+}
+
+/// Errors parsing label reference.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum LabelRefError {
+    /// labels of type '{0}' are not supported by the wallet.
+    Unsupported(LabelType),
+
+    /// invalid {0} reference '{1}'.
+    Invalid(LabelType, String),
+}
+
+/// Reference to a labelled wallet item.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, From)]
+pub enum LabelRef {
+    /// Transaction.
+    #[from]
+    Tx(Txid),
+    /// Address.
+    #[from]
+    Addr(Address),
+    /// Transaction input, referenced by the transaction id and the input number.
+    Input(Outpoint),
+    /// Transaction output.
+    #[from]
+    Output(Outpoint),
+}
+
+impl LabelRef {
+    /// Parses reference to an item of the given type, as used in BIP-329 `ref` field.
+    pub fn with(ty: LabelType, s: &str) -> Result<Self, LabelRefError> {
+        let invalid = || LabelRefError::Invalid(ty, s.to_owned());
+        Ok(match ty {
+            LabelType::Tx => LabelRef::Tx(Txid::from_str(s).map_err(|_| invalid())?),
+            LabelType::Addr => LabelRef::Addr(Address::from_str(s).map_err(|_| invalid())?),
+            LabelType::Input => LabelRef::Input(Outpoint::from_str(s).map_err(|_| invalid())?),
+            LabelType::Output => LabelRef::Output(Outpoint::from_str(s).map_err(|_| invalid())?),
+            LabelType::Pubkey | LabelType::Xpub => return Err(LabelRefError::Unsupported(ty)),
+        })
+    }
+
+    pub fn label_type(&self) -> LabelType {
+        match self {
+            LabelRef::Tx(_) => LabelType::Tx,
+            LabelRef::Addr(_) => LabelType::Addr,
+            LabelRef::Input(_) => LabelType::Input,
+            LabelRef::Output(_) => LabelType::Output,
+        }
+    }
+}
+
+impl Display for LabelRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelRef::Tx(txid) => Display::fmt(txid, f),
+            LabelRef::Addr(addr) => Display::fmt(addr, f),
+            LabelRef::Input(inpoint) => Display::fmt(inpoint, f),
+            LabelRef::Output(outpoint) => Display::fmt(outpoint, f),
+        }
+    }
+}
+
+/// Single record of a BIP-329 label file.
+#[cfg(feature = "serde")]
+#[derive(Clone, Eq, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Bip329Record {
+    #[serde(rename = "type")]
+    pub ty: LabelType,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Whether the output can be spent; used only for outputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+#[cfg(feature = "serde")]
+impl Bip329Record {
+    /// Reads records from a BIP-329 JSON Lines file.
+    ///
+    /// Following BIP-329, records with an unknown `type` are skipped; their number is returned
+    /// together with the read records.
+    pub fn read_jsonl(reader: impl io::BufRead) -> Result<(Vec<Self>, usize), LabelImportError> {
+        let mut records = vec![];
+        let mut unknown = 0usize;
+        for (no, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let err = |err| LabelImportError::Json(no + 1, err);
+            let value = serde_json::from_str::<serde_json::Value>(&line).map_err(err)?;
+            let ty = value.get("type").cloned().unwrap_or_default();
+            if ty.is_string() && serde_json::from_value::<LabelType>(ty).is_err() {
+                unknown += 1;
+                continue;
+            }
+            records.push(serde_json::from_value(value).map_err(err)?);
+        }
+        Ok((records, unknown))
+    }
+
+    /// Writes records in BIP-329 JSON Lines format.
+    pub fn write_jsonl(records: &[Self], mut writer: impl io::Write) -> io::Result<()> {
+        for record in records {
+            serde_json::to_writer(&mut writer, record)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors importing labels in BIP-329 format.
+#[cfg(feature = "serde")]
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum LabelImportError {
+    /// unable to read labels due to {0}
+    #[from]
+    Io(io::Error),
+
+    /// invalid BIP-329 record on line {0}: {1}
+    Json(usize, serde_json::Error),
+}
+
+/// Statistics of the labels imported in BIP-329 format.
+#[cfg(feature = "serde")]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct LabelImport {
+    /// Number of the imported labels.
+    pub imported: usize,
+    /// Number of the records which were skipped since they reference items not supported by
+    /// the wallet or are invalid.
+    pub skipped: usize,
+}
+
+#[cfg(test)]
+#[cfg(feature = "serde")]
+mod test {
+    use super::*;
+
+    const TXID: &str = "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd";
+
+    #[test]
+    fn bip329_unknown_types() {
+        let data = format!(
+            r#"{{"type":"tx","ref":"{TXID}","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}}
+
+{{"type":"utxo","ref":"{TXID}:0","label":"Unknown type"}}
+{{"type":"output","ref":"{TXID}:1","label":"Output","spendable":false}}
+{{"type":"xpub","ref":"xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8","label":"Xpub"}}
+"#
+        );
+        let (records, unknown) = Bip329Record::read_jsonl(data.as_bytes()).unwrap();
+        assert_eq!(unknown, 1);
+        assert_eq!(records.iter().map(|r| r.ty).collect::<Vec<_>>(), vec![
+            LabelType::Tx,
+            LabelType::Output,
+            LabelType::Xpub
+        ]);
+        assert_eq!(records[1].spendable, Some(false));
+
+        let mut buf = vec![];
+        Bip329Record::write_jsonl(&records, &mut buf).unwrap();
+        assert_eq!(Bip329Record::read_jsonl(buf.as_slice()).unwrap(), (records, 0));
+    }
+
+    #[test]
+    fn bip329_invalid_records() {
+        for data in [
+            r#"{"ref":"label without type"}"#,
+            r#"{"type":1,"ref":"type is not a string"}"#,
+            r#"{"type":"tx"}"#,
+            r#"{"type":"tx","ref":"#,
+        ] {
+            let input = format!("{{\"type\":\"addr\",\"ref\":\"a\"}}\n{data}\n");
+            assert!(
+                matches!(
+                    Bip329Record::read_jsonl(input.as_bytes()),
+                    Err(LabelImportError::Json(2, _))
+                ),
+                "{data}"
+            );
+        }
+    }
+}
@@ -43,24 +43,66 @@ pub mod cli;
 #[cfg(feature = "hot")]
 pub mod hot;
 mod bip43;
+mod descr;
+mod keys;
+mod miniscript;
+mod policy;
+#[cfg(feature = "serde")]
+mod export;
+#[cfg(feature = "serde")]
+mod interop;
+mod labels;
+mod slip132;
+#[cfg(test)]
+mod testing;
 
 pub use bip43::{Bip43, DerivationStandard, ParseBip43Error};
 pub use data::{
-    BlockHeight, BlockInfo, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr, WalletTx,
-    WalletUtxo,
+    vsize, BlockHeight, BlockInfo, FeeEstimates, FeeRate, FeeRateParseError, Inpoint,
+    InpointParseError, MiningInfo, Party, TxCredit, TxDebit, TxSpend, TxStatus, UtxoStatus,
+    WalletAddr, WalletTx, WalletUtxo,
+};
+pub use descr::{
+    descriptor_checksum, descriptor_with_checksum, parse_descriptor, strip_descriptor_checksum,
+    AnyDescr, DescrParseError, PsbtDescriptor,
 };
+#[cfg(feature = "serde")]
+pub use export::{HistoryFormat, HistoryRecord, PriceTable, PriceTableError};
 #[cfg(all(feature = "cli", feature = "hot"))]
 pub use hot::{HotArgs, HotCommand};
 #[cfg(feature = "hot")]
 pub use hot::{Seed, SeedType};
 pub use indexers::Indexer;
-#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "mempool",
+    feature = "bitcoind",
+    feature = "cbf"
+))]
 pub use indexers::{AnyIndexer, AnyIndexerError};
+#[cfg(feature = "serde")]
+pub use interop::{WalletFormat, WalletFormatError, WalletImport};
+pub use keys::{DescrKey, SingleKey, WifError, WifKey};
+#[cfg(feature = "serde")]
+pub use labels::{Bip329Record, LabelImport, LabelImportError};
+pub use labels::{LabelRef, LabelRefError, LabelType};
 pub use layer2::{
     Layer2, Layer2Cache, Layer2Coin, Layer2Data, Layer2Descriptor, Layer2Tx, NoLayer2,
 };
+pub use miniscript::{Miniscript, ScriptContext, TapNode, Timelock};
+pub use policy::{
+    NamedKey, NamedKeyParseError, Policy, PolicyError, PolicyParseError, PolicyTarget, WalletPolicy,
+};
 pub use rows::{CoinRow, Counterparty, OpType, TxRow};
-pub use util::MayError;
+pub use slip132::{
+    convert_key_expr, parse_xpub_derivable, xpriv_to_version, KeyVersion, Slip132Error, Slip132Xpub,
+};
+pub use util::{format_timestamp, MayError};
 #[cfg(feature = "fs")]
 pub use wallet::{fs, FsConfig};
-pub use wallet::{Save, Wallet, WalletCache, WalletData, WalletDescr};
+pub use wallet::{
+    BumpError, CoinControl, CpfpError, FeeInfo, NonWalletItem, PaymentError, Save, TxOptions,
+    TxOptionsError, UpdateInfo, Wallet, WalletCache, WalletData, WalletDescr, DEFAULT_GAP_LIMIT,
+    SEQ_NO_FINAL, SEQ_NO_FINAL_LOCK_TIME, SEQ_NO_RBF,
+};
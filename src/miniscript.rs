@@ -0,0 +1,999 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Spending scripts of output descriptors, written in a subset of [miniscript], and taproot
+//! script trees made of them.
+//!
+//! [miniscript]: https://bitcoin.sipa.be/miniscript/
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::Wrapper;
+use bpstd::opcodes::*;
+use bpstd::{
+    LeafScript, RedeemScript, ScriptHash, TapBranchHash, TapLeafHash, TapNodeHash, TapScript,
+    Terminal,
+};
+
+use crate::keys::check_key_version;
+use crate::{Bip43, DescrKey, DescrParseError};
+
+/// Maximal number of keys in `multi` fragment.
+pub(crate) const MAX_MULTI_KEYS: usize = 20;
+/// Maximal number of keys in `multi_a` fragment.
+const MAX_MULTI_A_KEYS: usize = 999;
+/// Maximal depth of a taproot script tree.
+const MAX_TAP_TREE_DEPTH: u8 = 128;
+
+/// Context in which a script is used, defining encoding of keys and allowed fragments.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ScriptContext {
+    /// Witness script of segwit v0 output.
+    Segwit,
+    /// Leaf script of taproot output.
+    Tapscript,
+}
+
+/// Script expression in the subset of miniscript supported by the wallet.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Miniscript {
+    /// `pk(KEY)`: signature for the key.
+    Pk(DescrKey),
+
+    /// `pkh(KEY)`: the key matching its hash and a signature for it.
+    Pkh(DescrKey),
+
+    /// `multi(k,KEY,...)` or `sortedmulti(k,KEY,...)`: signatures for `k` of the keys, checked
+    /// with `OP_CHECKMULTISIG`. Allowed in segwit v0 scripts only.
+    Multi {
+        threshold: usize,
+        keys: Vec<DescrKey>,
+        sorted: bool,
+    },
+
+    /// `multi_a(k,KEY,...)` or `sortedmulti_a(k,KEY,...)`: signatures for `k` of the keys,
+    /// checked with `OP_CHECKSIGADD`. Allowed in taproot scripts only.
+    MultiA {
+        threshold: usize,
+        keys: Vec<DescrKey>,
+        sorted: bool,
+    },
+
+    /// `after(n)`: absolute timelock, in blocks or UNIX time.
+    After(u32),
+
+    /// `older(n)`: relative timelock, in BIP-68 format.
+    Older(u32),
+
+    /// `v:X`: the expression followed by `OP_VERIFY`.
+    Verify(Box<Miniscript>),
+
+    /// `and_v(X,Y)`: both expressions, where the first one has verify type.
+    AndV(Box<Miniscript>, Box<Miniscript>),
+
+    /// `or_d(X,Z)`: the first expression or, if it is dissatisfied, the second one.
+    OrD(Box<Miniscript>, Box<Miniscript>),
+
+    /// `or_i(X,Z)`: one of the expressions, selected by the satisfaction.
+    OrI(Box<Miniscript>, Box<Miniscript>),
+}
+
+/// Timelock of a script spending branch.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+pub enum Timelock {
+    /// Absolute timelock `after(n)`, in blocks or UNIX time, which is satisfied by the
+    /// transaction lock time.
+    #[display("after({0})")]
+    After(u32),
+
+    /// Relative timelock `older(n)`, in BIP-68 format, which is satisfied by the input sequence
+    /// number.
+    #[display("older({0})")]
+    Older(u32),
+}
+
+impl Timelock {
+    /// Type flag of BIP-68 relative timelocks, indicating timelocks in units of 512 seconds.
+    pub const RELATIVE_TIME_FLAG: u32 = 1 << 22;
+
+    /// Checks whether the timelock is given in blocks rather than in time units.
+    pub fn is_height_based(self) -> bool {
+        match self {
+            Timelock::After(lock) => lock < 500_000_000,
+            Timelock::Older(lock) => lock & Self::RELATIVE_TIME_FLAG == 0,
+        }
+    }
+}
+
+impl FromStr for Timelock {
+    type Err = DescrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, lock) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| DescrParseError::Syntax(s.to_owned()))?;
+        match name {
+            "after" => parse_lock(lock).map(Timelock::After),
+            "older" => parse_lock(lock).map(Timelock::Older),
+            _ => Err(DescrParseError::Fragment(s.to_owned())),
+        }
+    }
+}
+
+/// Basic type of miniscript expression.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Type {
+    /// Expression pushing non-zero value on success and zero value on dissatisfaction.
+    B,
+    /// Expression which either succeeds without pushing anything, or aborts the execution.
+    V,
+}
+
+/// Provider of signatures and of the information about transaction timelocks, used to satisfy
+/// spending scripts.
+pub(crate) trait Satisfier {
+    /// Returns serialized signature for a key, given in the same form as it is used in the
+    /// script.
+    fn signature(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Checks whether the transaction satisfies absolute timelock.
+    fn check_after(&self, lock: u32) -> bool;
+
+    /// Checks whether the input satisfies relative timelock.
+    fn check_older(&self, lock: u32) -> bool;
+}
+
+/// Witness stack items, starting from the bottom of the stack.
+pub(crate) type Stack = Vec<Vec<u8>>;
+
+/// Computes size of the witness stack items.
+pub(crate) fn stack_size(stack: &Stack) -> usize { stack.iter().map(|item| item.len() + 1).sum() }
+
+/// Appends minimal push of the data to the script.
+pub(crate) fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        len if len < OP_PUSHDATA1 as usize => script.push(len as u8),
+        len if len <= 0xFF => script.extend([OP_PUSHDATA1, len as u8]),
+        len => {
+            script.push(OP_PUSHDATA2);
+            script.extend((len as u16).to_le_bytes());
+        }
+    }
+    script.extend(data);
+}
+
+/// Appends minimal push of a non-negative number to the script.
+fn push_int(script: &mut Vec<u8>, value: u32) {
+    match value {
+        0 => script.push(OP_PUSHBYTES_0),
+        1..=16 => script.push(OP_PUSHNUM_1 + value as u8 - 1),
+        _ => {
+            let mut num = value.to_le_bytes().to_vec();
+            while num.last() == Some(&0) {
+                num.pop();
+            }
+            if num.last().is_some_and(|byte| byte & 0x80 != 0) {
+                num.push(0);
+            }
+            push_data(script, &num)
+        }
+    }
+}
+
+/// Computes HASH160 of the data.
+fn hash160(data: &[u8]) -> [u8; 20] {
+    ScriptHash::from(&RedeemScript::from_unsafe(data.to_vec())).into()
+}
+
+/// Returns the cheapest of the satisfactions.
+fn cheapest(a: Option<Stack>, b: Option<Stack>) -> Option<Stack> {
+    match (a, b) {
+        (Some(a), Some(b)) if stack_size(&b) < stack_size(&a) => Some(b),
+        (Some(a), _) => Some(a),
+        (None, b) => b,
+    }
+}
+
+/// Splits expression arguments at the commas which are not enclosed into brackets.
+pub(crate) fn split_args(s: &str) -> Option<Vec<&str>> {
+    let mut args = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (pos, c) in s.char_indices() {
+        match c {
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                args.push(s[start..pos].trim());
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    args.push(s[start..].trim());
+    Some(args)
+}
+
+impl ScriptContext {
+    /// Derivation standards which may be implied by SLIP-132 versions of extended keys used in
+    /// scripts of this context.
+    fn key_standards(self) -> &'static [Bip43] {
+        match self {
+            ScriptContext::Segwit => &[Bip43::Bip48Native, Bip43::Bip48Nested],
+            ScriptContext::Tapscript => &[],
+        }
+    }
+}
+
+/// Parses key used in a descriptor of the given context, which SLIP-132 version, if any, must
+/// imply one of the derivation standards.
+pub(crate) fn parse_key(
+    s: &str,
+    ctx: ScriptContext,
+    standards: &[Bip43],
+) -> Result<DescrKey, DescrParseError> {
+    check_key_version(s, standards)?;
+    let key = DescrKey::from_str(s)?;
+    if !key.is_compressed() || (ctx == ScriptContext::Segwit && key.is_xonly()) {
+        return Err(DescrParseError::KeyContext(s.to_owned()));
+    }
+    Ok(key)
+}
+
+fn parse_lock(s: &str) -> Result<u32, DescrParseError> {
+    match u32::from_str(s) {
+        Ok(lock) if (1..0x8000_0000).contains(&lock) => Ok(lock),
+        _ => Err(DescrParseError::InvalidTimelock(s.to_owned())),
+    }
+}
+
+impl Miniscript {
+    /// Parses script expression for the given context.
+    ///
+    /// Besides the miniscript fragments, `sortedmulti` and `sortedmulti_a` are accepted as the
+    /// whole script expression.
+    pub fn parse(s: &str, ctx: ScriptContext) -> Result<Self, DescrParseError> {
+        let script = Self::parse_fragment(s.trim(), ctx, true)?;
+        if script.ty() != Some(Type::B) {
+            return Err(DescrParseError::InvalidScript(s.to_owned()));
+        }
+        Ok(script)
+    }
+
+    fn parse_fragment(s: &str, ctx: ScriptContext, top: bool) -> Result<Self, DescrParseError> {
+        let unsupported = || DescrParseError::Fragment(s.to_owned());
+        let syntax = || DescrParseError::Syntax(s.to_owned());
+
+        let (wrappers, expr) = match s.split_once(':') {
+            Some((wrappers, expr)) if !wrappers.contains('(') => (wrappers, expr),
+            _ => ("", s),
+        };
+        if !wrappers.is_empty() {
+            if wrappers != "v" {
+                return Err(unsupported());
+            }
+            let inner = Self::parse_fragment(expr, ctx, false)?;
+            return Ok(Miniscript::Verify(Box::new(inner)));
+        }
+
+        let (name, args) =
+            expr.strip_suffix(')').and_then(|s| s.split_once('(')).ok_or_else(syntax)?;
+        let args = split_args(args).ok_or_else(syntax)?;
+        let segwit = ctx == ScriptContext::Segwit;
+        Ok(match (name.trim(), args.as_slice()) {
+            ("pk", [key]) => Miniscript::Pk(parse_key(key, ctx, ctx.key_standards())?),
+            ("pkh", [key]) => Miniscript::Pkh(parse_key(key, ctx, ctx.key_standards())?),
+            ("after", [lock]) => Miniscript::After(parse_lock(lock)?),
+            ("older", [lock]) => Miniscript::Older(parse_lock(lock)?),
+            ("and_v", [x, y]) => Miniscript::AndV(
+                Box::new(Self::parse_fragment(x, ctx, false)?),
+                Box::new(Self::parse_fragment(y, ctx, false)?),
+            ),
+            ("or_d", [x, z]) => Miniscript::OrD(
+                Box::new(Self::parse_fragment(x, ctx, false)?),
+                Box::new(Self::parse_fragment(z, ctx, false)?),
+            ),
+            ("or_i", [x, z]) => Miniscript::OrI(
+                Box::new(Self::parse_fragment(x, ctx, false)?),
+                Box::new(Self::parse_fragment(z, ctx, false)?),
+            ),
+            (name @ ("multi" | "sortedmulti" | "multi_a" | "sortedmulti_a"), [k, keys @ ..]) => {
+                let sorted = name.starts_with("sorted");
+                let tap = name.ends_with("_a");
+                if (sorted && !top) || tap == segwit {
+                    return Err(unsupported());
+                }
+                let max = if tap { MAX_MULTI_A_KEYS } else { MAX_MULTI_KEYS };
+                let threshold = usize::from_str(k).map_err(|_| syntax())?;
+                if threshold == 0 || threshold > keys.len() || keys.len() > max {
+                    return Err(DescrParseError::InvalidThreshold(threshold, keys.len()));
+                }
+                let keys = keys
+                    .iter()
+                    .map(|key| parse_key(key, ctx, ctx.key_standards()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if tap {
+                    Miniscript::MultiA {
+                        threshold,
+                        keys,
+                        sorted,
+                    }
+                } else {
+                    Miniscript::Multi {
+                        threshold,
+                        keys,
+                        sorted,
+                    }
+                }
+            }
+            _ => return Err(unsupported()),
+        })
+    }
+
+    fn ty(&self) -> Option<Type> {
+        match self {
+            Miniscript::Pk(_)
+            | Miniscript::Pkh(_)
+            | Miniscript::Multi { .. }
+            | Miniscript::MultiA { .. }
+            | Miniscript::After(_)
+            | Miniscript::Older(_) => Some(Type::B),
+            Miniscript::Verify(x) if x.ty()? == Type::B => Some(Type::V),
+            Miniscript::AndV(x, y) if x.ty()? == Type::V => y.ty(),
+            Miniscript::OrD(x, z) if x.is_dissatisfiable() && z.ty()? == Type::B => Some(Type::B),
+            Miniscript::OrI(x, z) if x.ty()? == z.ty()? => x.ty(),
+            Miniscript::Verify(_) | Miniscript::AndV(..) | Miniscript::OrD(..) => None,
+            Miniscript::OrI(..) => None,
+        }
+    }
+
+    /// Checks whether the expression has a known non-malleable dissatisfaction, as required for
+    /// the first argument of `or_d`.
+    pub(crate) fn is_dissatisfiable(&self) -> bool {
+        matches!(
+            self,
+            Miniscript::Pk(_)
+                | Miniscript::Pkh(_)
+                | Miniscript::Multi { .. }
+                | Miniscript::MultiA { .. }
+        )
+    }
+
+    /// Returns all keys used by the script, in the order of their appearance.
+    pub fn keys(&self) -> Vec<&DescrKey> {
+        let mut keys = vec![];
+        self.visit(&mut |script| match script {
+            Miniscript::Pk(key) | Miniscript::Pkh(key) => keys.push(key),
+            Miniscript::Multi { keys: k, .. } | Miniscript::MultiA { keys: k, .. } => {
+                keys.extend(k)
+            }
+            _ => {}
+        });
+        keys
+    }
+
+    /// Returns all absolute timelocks (`after`) used by the script.
+    pub fn absolute_timelocks(&self) -> Vec<u32> {
+        let mut locks = vec![];
+        self.visit(&mut |script| {
+            if let Miniscript::After(lock) = script {
+                locks.push(*lock);
+            }
+        });
+        locks
+    }
+
+    /// Returns all relative timelocks (`older`) used by the script.
+    pub fn relative_timelocks(&self) -> Vec<u32> {
+        let mut locks = vec![];
+        self.visit(&mut |script| {
+            if let Miniscript::Older(lock) = script {
+                locks.push(*lock);
+            }
+        });
+        locks
+    }
+
+    /// Returns all timelocks used by the script.
+    pub fn timelocks(&self) -> Vec<Timelock> {
+        let absolute = self.absolute_timelocks().into_iter().map(Timelock::After);
+        absolute.chain(self.relative_timelocks().into_iter().map(Timelock::Older)).collect()
+    }
+
+    /// Constructs the same script using keys produced by the function from the original ones.
+    pub fn map_keys(&self, f: &impl Fn(&DescrKey) -> DescrKey) -> Miniscript {
+        let map = |x: &Miniscript| Box::new(x.map_keys(f));
+        match self {
+            Miniscript::Pk(key) => Miniscript::Pk(f(key)),
+            Miniscript::Pkh(key) => Miniscript::Pkh(f(key)),
+            Miniscript::Multi {
+                threshold,
+                keys,
+                sorted,
+            } => Miniscript::Multi {
+                threshold: *threshold,
+                keys: keys.iter().map(f).collect(),
+                sorted: *sorted,
+            },
+            Miniscript::MultiA {
+                threshold,
+                keys,
+                sorted,
+            } => Miniscript::MultiA {
+                threshold: *threshold,
+                keys: keys.iter().map(f).collect(),
+                sorted: *sorted,
+            },
+            Miniscript::After(lock) => Miniscript::After(*lock),
+            Miniscript::Older(lock) => Miniscript::Older(*lock),
+            Miniscript::Verify(x) => Miniscript::Verify(map(x)),
+            Miniscript::AndV(x, y) => Miniscript::AndV(map(x), map(y)),
+            Miniscript::OrD(x, z) => Miniscript::OrD(map(x), map(z)),
+            Miniscript::OrI(x, z) => Miniscript::OrI(map(x), map(z)),
+        }
+    }
+
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Miniscript)) {
+        f(self);
+        match self {
+            Miniscript::Verify(x) => x.visit(f),
+            Miniscript::AndV(x, y) | Miniscript::OrD(x, y) | Miniscript::OrI(x, y) => {
+                x.visit(f);
+                y.visit(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns serialized keys of a multisig fragment in the order they are used in the script.
+    fn multi_keys(
+        keys: &[DescrKey],
+        sorted: bool,
+        ctx: ScriptContext,
+        terminal: Terminal,
+    ) -> Vec<Vec<u8>> {
+        let mut keys = keys.iter().map(|key| key_bytes(key, ctx, terminal)).collect::<Vec<_>>();
+        if sorted {
+            keys.sort();
+        }
+        keys
+    }
+
+    /// Encodes the script for the given derivation terminal.
+    pub fn to_script_bytes(&self, ctx: ScriptContext, terminal: Terminal) -> Vec<u8> {
+        let mut script = vec![];
+        self.encode(ctx, terminal, &mut script);
+        script
+    }
+
+    fn encode(&self, ctx: ScriptContext, terminal: Terminal, script: &mut Vec<u8>) {
+        match self {
+            Miniscript::Pk(key) => {
+                push_data(script, &key_bytes(key, ctx, terminal));
+                script.push(OP_CHECKSIG);
+            }
+            Miniscript::Pkh(key) => {
+                script.extend([OP_DUP, OP_HASH160]);
+                push_data(script, &hash160(&key_bytes(key, ctx, terminal)));
+                script.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+            }
+            Miniscript::Multi {
+                threshold,
+                keys,
+                sorted,
+            } => {
+                push_int(script, *threshold as u32);
+                for key in Self::multi_keys(keys, *sorted, ctx, terminal) {
+                    push_data(script, &key);
+                }
+                push_int(script, keys.len() as u32);
+                script.push(OP_CHECKMULTISIG);
+            }
+            Miniscript::MultiA {
+                threshold,
+                keys,
+                sorted,
+            } => {
+                for (no, key) in Self::multi_keys(keys, *sorted, ctx, terminal).iter().enumerate() {
+                    push_data(script, key);
+                    script.push(if no == 0 { OP_CHECKSIG } else { OP_CHECKSIGADD });
+                }
+                push_int(script, *threshold as u32);
+                script.push(OP_NUMEQUAL);
+            }
+            Miniscript::After(lock) => {
+                push_int(script, *lock);
+                script.push(OP_CLTV);
+            }
+            Miniscript::Older(lock) => {
+                push_int(script, *lock);
+                script.push(OP_CSV);
+            }
+            Miniscript::Verify(x) => {
+                x.encode(ctx, terminal, script);
+                // All the fragments end with an opcode, so the last byte is never a part of
+                // pushed data.
+                match script.last_mut() {
+                    Some(op)
+                        if [OP_CHECKSIG, OP_CHECKMULTISIG, OP_EQUAL, OP_NUMEQUAL].contains(op) =>
+                    {
+                        // The verify versions of these opcodes follow them
+                        *op += 1
+                    }
+                    _ => script.push(OP_VERIFY),
+                }
+            }
+            Miniscript::AndV(x, y) => {
+                x.encode(ctx, terminal, script);
+                y.encode(ctx, terminal, script);
+            }
+            Miniscript::OrD(x, z) => {
+                x.encode(ctx, terminal, script);
+                script.extend([OP_IFDUP, OP_NOTIF]);
+                z.encode(ctx, terminal, script);
+                script.push(OP_ENDIF);
+            }
+            Miniscript::OrI(x, z) => {
+                script.push(OP_IF);
+                x.encode(ctx, terminal, script);
+                script.push(OP_ELSE);
+                z.encode(ctx, terminal, script);
+                script.push(OP_ENDIF);
+            }
+        }
+    }
+
+    /// Constructs the cheapest satisfaction of the script, returning witness stack items
+    /// (without the script itself), or `None` if the script can't be satisfied.
+    pub(crate) fn satisfy(
+        &self,
+        ctx: ScriptContext,
+        terminal: Terminal,
+        satisfier: &impl Satisfier,
+    ) -> Option<Stack> {
+        match self {
+            Miniscript::Pk(key) => {
+                let sig = satisfier.signature(&key_bytes(key, ctx, terminal))?;
+                Some(vec![sig])
+            }
+            Miniscript::Pkh(key) => {
+                let key = key_bytes(key, ctx, terminal);
+                let sig = satisfier.signature(&key)?;
+                Some(vec![sig, key])
+            }
+            Miniscript::Multi {
+                threshold,
+                keys,
+                sorted,
+            } => {
+                let sigs = Self::multi_keys(keys, *sorted, ctx, terminal)
+                    .iter()
+                    .filter_map(|key| satisfier.signature(key))
+                    .take(*threshold)
+                    .collect::<Vec<_>>();
+                if sigs.len() < *threshold {
+                    return None;
+                }
+                let mut stack = vec![vec![]];
+                stack.extend(sigs);
+                Some(stack)
+            }
+            Miniscript::MultiA {
+                threshold,
+                keys,
+                sorted,
+            } => {
+                let mut count = 0;
+                let mut stack = Self::multi_keys(keys, *sorted, ctx, terminal)
+                    .iter()
+                    .map(|key| match satisfier.signature(key) {
+                        Some(sig) if count < *threshold => {
+                            count += 1;
+                            sig
+                        }
+                        _ => vec![],
+                    })
+                    .collect::<Vec<_>>();
+                if count < *threshold {
+                    return None;
+                }
+                // The first key is checked first, so its signature must be on the top
+                stack.reverse();
+                Some(stack)
+            }
+            Miniscript::After(lock) => satisfier.check_after(*lock).then(Vec::new),
+            Miniscript::Older(lock) => satisfier.check_older(*lock).then(Vec::new),
+            Miniscript::Verify(x) => x.satisfy(ctx, terminal, satisfier),
+            Miniscript::AndV(x, y) => {
+                let mut stack = y.satisfy(ctx, terminal, satisfier)?;
+                stack.extend(x.satisfy(ctx, terminal, satisfier)?);
+                Some(stack)
+            }
+            Miniscript::OrD(x, z) => {
+                let first = x.satisfy(ctx, terminal, satisfier);
+                let second = z.satisfy(ctx, terminal, satisfier).and_then(|mut stack| {
+                    stack.extend(x.dissatisfy(ctx, terminal)?);
+                    Some(stack)
+                });
+                cheapest(first, second)
+            }
+            Miniscript::OrI(x, z) => {
+                let first = x.satisfy(ctx, terminal, satisfier).map(|mut stack| {
+                    stack.push(vec![1]);
+                    stack
+                });
+                let second = z.satisfy(ctx, terminal, satisfier).map(|mut stack| {
+                    stack.push(vec![]);
+                    stack
+                });
+                cheapest(first, second)
+            }
+        }
+    }
+
+    /// Constructs dissatisfaction of the script, if it exists.
+    fn dissatisfy(&self, ctx: ScriptContext, terminal: Terminal) -> Option<Stack> {
+        match self {
+            Miniscript::Pk(_) => Some(vec![vec![]]),
+            Miniscript::Pkh(key) => Some(vec![vec![], key_bytes(key, ctx, terminal)]),
+            Miniscript::Multi { threshold, .. } => Some(vec![vec![]; threshold + 1]),
+            Miniscript::MultiA { keys, .. } => Some(vec![vec![]; keys.len()]),
+            _ => None,
+        }
+    }
+
+    /// Estimates the maximal size of the witness stack items satisfying the script.
+    pub(crate) fn max_satisfaction_size(&self, ctx: ScriptContext) -> usize {
+        let (sig, key) = match ctx {
+            ScriptContext::Segwit => (73, 34),
+            ScriptContext::Tapscript => (66, 33),
+        };
+        match self {
+            Miniscript::Pk(_) => sig,
+            Miniscript::Pkh(_) => sig + key,
+            Miniscript::Multi { threshold, .. } => 1 + threshold * sig,
+            Miniscript::MultiA {
+                threshold, keys, ..
+            } => threshold * sig + (keys.len() - threshold),
+            Miniscript::After(_) | Miniscript::Older(_) => 0,
+            Miniscript::Verify(x) => x.max_satisfaction_size(ctx),
+            Miniscript::AndV(x, y) => x.max_satisfaction_size(ctx) + y.max_satisfaction_size(ctx),
+            Miniscript::OrD(x, z) => {
+                let dissat = match &**x {
+                    Miniscript::Pkh(_) => 1 + key,
+                    Miniscript::Multi { threshold, .. } => threshold + 1,
+                    Miniscript::MultiA { keys, .. } => keys.len(),
+                    _ => 1,
+                };
+                x.max_satisfaction_size(ctx).max(z.max_satisfaction_size(ctx) + dissat)
+            }
+            Miniscript::OrI(x, z) => {
+                2 + x.max_satisfaction_size(ctx).max(z.max_satisfaction_size(ctx))
+            }
+        }
+    }
+}
+
+/// Serializes key for the terminal in the form used by scripts of the given context.
+pub(crate) fn key_bytes(key: &DescrKey, ctx: ScriptContext, terminal: Terminal) -> Vec<u8> {
+    match ctx {
+        ScriptContext::Segwit => key.derive_legacy(terminal).to_vec(),
+        ScriptContext::Tapscript => key.derive_xonly(terminal).to_byte_array().to_vec(),
+    }
+}
+
+impl Display for Miniscript {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn multi(f: &mut Formatter<'_>, threshold: usize, keys: &[DescrKey]) -> fmt::Result {
+            write!(f, "({threshold}")?;
+            for key in keys {
+                write!(f, ",{key}")?;
+            }
+            f.write_str(")")
+        }
+
+        match self {
+            Miniscript::Pk(key) => write!(f, "pk({key})"),
+            Miniscript::Pkh(key) => write!(f, "pkh({key})"),
+            Miniscript::Multi {
+                threshold,
+                keys,
+                sorted,
+            } => {
+                f.write_str(if *sorted { "sortedmulti" } else { "multi" })?;
+                multi(f, *threshold, keys)
+            }
+            Miniscript::MultiA {
+                threshold,
+                keys,
+                sorted,
+            } => {
+                f.write_str(if *sorted { "sortedmulti_a" } else { "multi_a" })?;
+                multi(f, *threshold, keys)
+            }
+            Miniscript::After(lock) => write!(f, "after({lock})"),
+            Miniscript::Older(lock) => write!(f, "older({lock})"),
+            Miniscript::Verify(x) => write!(f, "v:{x}"),
+            Miniscript::AndV(x, y) => write!(f, "and_v({x},{y})"),
+            Miniscript::OrD(x, z) => write!(f, "or_d({x},{z})"),
+            Miniscript::OrI(x, z) => write!(f, "or_i({x},{z})"),
+        }
+    }
+}
+
+/// Taproot script tree, in the form used by `tr()` descriptors: either a single leaf script or
+/// a pair of sub-trees given as `{A,B}`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum TapNode {
+    Leaf(Miniscript),
+    Branch(Box<TapNode>, Box<TapNode>),
+}
+
+/// Leaf of a taproot script tree derived for some terminal.
+pub(crate) struct DerivedLeaf<'tree> {
+    pub miniscript: &'tree Miniscript,
+    pub script: LeafScript,
+    pub depth: u8,
+    /// Hashes of the sibling nodes on the path from the leaf to the root.
+    pub path: Vec<TapBranchHash>,
+}
+
+impl TapNode {
+    /// Parses script tree expression.
+    pub fn parse(s: &str) -> Result<Self, DescrParseError> { Self::parse_depth(s.trim(), 0) }
+
+    fn parse_depth(s: &str, depth: u8) -> Result<Self, DescrParseError> {
+        if depth > MAX_TAP_TREE_DEPTH {
+            return Err(DescrParseError::Syntax(s.to_owned()));
+        }
+        let Some(inner) = s.strip_prefix('{') else {
+            return Miniscript::parse(s, ScriptContext::Tapscript).map(TapNode::Leaf);
+        };
+        let syntax = || DescrParseError::Syntax(s.to_owned());
+        let inner = inner.strip_suffix('}').ok_or_else(syntax)?;
+        match split_args(inner).ok_or_else(syntax)?.as_slice() {
+            [a, b] => Ok(TapNode::Branch(
+                Box::new(Self::parse_depth(a, depth + 1)?),
+                Box::new(Self::parse_depth(b, depth + 1)?),
+            )),
+            _ => Err(syntax()),
+        }
+    }
+
+    /// Returns leaf scripts of the tree in depth-first order.
+    pub fn leaves(&self) -> Vec<&Miniscript> {
+        match self {
+            TapNode::Leaf(script) => vec![script],
+            TapNode::Branch(a, b) => {
+                let mut leaves = a.leaves();
+                leaves.extend(b.leaves());
+                leaves
+            }
+        }
+    }
+
+    /// Constructs the same tree using keys produced by the function from the original ones.
+    pub fn map_keys(&self, f: &impl Fn(&DescrKey) -> DescrKey) -> TapNode {
+        match self {
+            TapNode::Leaf(script) => TapNode::Leaf(script.map_keys(f)),
+            TapNode::Branch(a, b) => {
+                TapNode::Branch(Box::new(a.map_keys(f)), Box::new(b.map_keys(f)))
+            }
+        }
+    }
+
+    /// Derives leaf scripts for the terminal, returning the merkle root of the tree and the
+    /// leaves in depth-first order.
+    pub(crate) fn derive(&self, terminal: Terminal) -> (TapNodeHash, Vec<DerivedLeaf<'_>>) {
+        self.derive_depth(terminal, 0)
+    }
+
+    fn derive_depth(&self, terminal: Terminal, depth: u8) -> (TapNodeHash, Vec<DerivedLeaf<'_>>) {
+        match self {
+            TapNode::Leaf(miniscript) => {
+                let bytes = miniscript.to_script_bytes(ScriptContext::Tapscript, terminal);
+                let script = LeafScript::from_tap_script(TapScript::from_unsafe(bytes));
+                let hash = TapLeafHash::with_leaf_script(&script);
+                (hash.into(), vec![DerivedLeaf {
+                    miniscript,
+                    script,
+                    depth,
+                    path: vec![],
+                }])
+            }
+            TapNode::Branch(a, b) => {
+                let (hash_a, mut leaves_a) = a.derive_depth(terminal, depth + 1);
+                let (hash_b, mut leaves_b) = b.derive_depth(terminal, depth + 1);
+                let sibling_a = TapBranchHash::from(hash_a.into_inner());
+                let sibling_b = TapBranchHash::from(hash_b.into_inner());
+                leaves_a.iter_mut().for_each(|leaf| leaf.path.push(sibling_b));
+                leaves_b.iter_mut().for_each(|leaf| leaf.path.push(sibling_a));
+                leaves_a.extend(leaves_b);
+                (TapBranchHash::with_nodes(hash_a, hash_b).into(), leaves_a)
+            }
+        }
+    }
+}
+
+impl Display for TapNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TapNode::Leaf(script) => Display::fmt(script, f),
+            TapNode::Branch(a, b) => write!(f, "{{{a},{b}}}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::hex::ToHex;
+    use bpstd::{Idx, Keychain, NormalIndex};
+
+    use super::*;
+
+    const KEY1: &str = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+    const KEY2: &str = "03774ae7f858a9411e5ef4246b70c65aac5649980be5c17891bbec17895da008cb";
+    const XONLY1: &str = "669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0";
+    const XONLY2: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    fn segwit(s: &str) -> Result<Miniscript, DescrParseError> {
+        Miniscript::parse(s, ScriptContext::Segwit)
+    }
+
+    fn tapscript(s: &str) -> Result<Miniscript, DescrParseError> {
+        Miniscript::parse(s, ScriptContext::Tapscript)
+    }
+
+    fn script_hex(s: &str, ctx: ScriptContext) -> String {
+        let terminal = Terminal::new(Keychain::OUTER, NormalIndex::ZERO);
+        Miniscript::parse(s, ctx).unwrap().to_script_bytes(ctx, terminal).to_hex()
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in [
+            format!("pk({KEY1})"),
+            format!("pkh({KEY1})"),
+            format!("multi(1,{KEY1},{KEY2})"),
+            format!("sortedmulti(2,{KEY1},{KEY2})"),
+            format!("and_v(v:pk({KEY1}),older(144))"),
+            format!("and_v(v:pkh({KEY1}),after(500000000))"),
+            format!("or_d(pk({KEY1}),and_v(v:pk({KEY2}),older(4032)))"),
+            format!("or_i(and_v(v:pk({KEY1}),after(100)),pk({KEY2}))"),
+            format!("or_d(multi(2,{KEY1},{KEY2}),and_v(v:pk({KEY1}),older(1)))"),
+        ] {
+            assert_eq!(segwit(&s).unwrap().to_string(), s);
+        }
+        for s in [
+            format!("pk({XONLY1})"),
+            format!("multi_a(1,{XONLY1},{XONLY2})"),
+            format!("sortedmulti_a(2,{XONLY1},{XONLY2})"),
+            format!("and_v(v:multi_a(1,{XONLY1},{XONLY2}),older(144))"),
+        ] {
+            assert_eq!(tapscript(&s).unwrap().to_string(), s);
+        }
+
+        let tree = format!("{{pk({XONLY1}),{{pk({XONLY2}),and_v(v:pk({XONLY1}),older(144))}}}}");
+        let node = TapNode::parse(&tree).unwrap();
+        assert_eq!(node.to_string(), tree);
+        assert_eq!(node.leaves().len(), 3);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(
+            script_hex(&format!("pk({KEY1})"), ScriptContext::Segwit),
+            format!("21{KEY1}ac")
+        );
+        assert_eq!(
+            script_hex(&format!("and_v(v:pk({KEY1}),older(144))"), ScriptContext::Segwit),
+            format!("21{KEY1}ad029000b2")
+        );
+        assert_eq!(
+            script_hex(&format!("multi(1,{KEY1},{KEY2})"), ScriptContext::Segwit),
+            format!("5121{KEY1}21{KEY2}52ae")
+        );
+        // Keys of sorted multisig are ordered lexicographically
+        assert_eq!(
+            script_hex(&format!("sortedmulti(1,{KEY1},{KEY2})"), ScriptContext::Segwit),
+            format!("5121{KEY2}21{KEY1}52ae")
+        );
+        assert_eq!(
+            script_hex(&format!("or_i(pk({KEY1}),pk({KEY2}))"), ScriptContext::Segwit),
+            format!("6321{KEY1}ac6721{KEY2}ac68")
+        );
+        assert_eq!(
+            script_hex(&format!("multi_a(2,{XONLY1},{XONLY2})"), ScriptContext::Tapscript),
+            format!("20{XONLY1}ac20{XONLY2}ba529c")
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let syntax = |s: String| Err(DescrParseError::Syntax(s));
+        let fragment = |s: String| Err(DescrParseError::Fragment(s));
+
+        assert_eq!(segwit(&format!("pk({KEY1}")), syntax(format!("pk({KEY1}")));
+        assert_eq!(segwit(&format!("pk({KEY1}))")), syntax(format!("pk({KEY1}))")));
+        assert_eq!(segwit(&format!("pk_k({KEY1})")), fragment(format!("pk_k({KEY1})")));
+        assert_eq!(segwit(&format!("c:pk({KEY1})")), fragment(format!("c:pk({KEY1})")));
+        assert_eq!(segwit(&format!("pk({KEY1},{KEY2})")), fragment(format!("pk({KEY1},{KEY2})")));
+        assert_eq!(segwit("older(0)"), Err(DescrParseError::InvalidTimelock(s!("0"))));
+        assert_eq!(
+            segwit("after(2147483648)"),
+            Err(DescrParseError::InvalidTimelock(s!("2147483648")))
+        );
+        assert_eq!(
+            segwit(&format!("multi(0,{KEY1})")),
+            Err(DescrParseError::InvalidThreshold(0, 1))
+        );
+        assert_eq!(
+            segwit(&format!("multi(3,{KEY1},{KEY2})")),
+            Err(DescrParseError::InvalidThreshold(3, 2))
+        );
+        let keys = vec![KEY1; MAX_MULTI_KEYS + 1].join(",");
+        assert_eq!(
+            segwit(&format!("multi(1,{keys})")),
+            Err(DescrParseError::InvalidThreshold(1, MAX_MULTI_KEYS + 1))
+        );
+
+        // Fragments of a wrong context
+        let s = format!("multi_a(1,{XONLY1})");
+        assert_eq!(segwit(&s), fragment(s));
+        let s = format!("multi(1,{KEY1})");
+        assert_eq!(tapscript(&s), fragment(s));
+        assert_eq!(
+            segwit(&format!("pk({XONLY1})")),
+            Err(DescrParseError::KeyContext(XONLY1.to_owned()))
+        );
+
+        // Sorted multisig can't be a part of a larger script
+        let s = format!("sortedmulti(1,{KEY1},{KEY2})");
+        assert_eq!(segwit(&format!("or_d(pk({KEY1}),{s})")), fragment(s));
+
+        // Type errors
+        for s in [
+            format!("v:pk({KEY1})"),
+            format!("and_v(pk({KEY1}),pk({KEY2}))"),
+            format!("or_d(v:pk({KEY1}),pk({KEY2}))"),
+            format!("or_d(older(1),pk({KEY2}))"),
+            format!("or_i(v:pk({KEY1}),pk({KEY2}))"),
+        ] {
+            assert_eq!(segwit(&s), Err(DescrParseError::InvalidScript(s.clone())));
+        }
+
+        // Malformed taproot trees
+        for s in [
+            format!("{{pk({XONLY1})}}"),
+            format!("{{pk({XONLY1}),pk({XONLY2}),pk({XONLY1})}}"),
+            format!("{{pk({XONLY1}),pk({XONLY2})"),
+        ] {
+            assert_eq!(TapNode::parse(&s), Err(DescrParseError::Syntax(s)));
+        }
+    }
+}
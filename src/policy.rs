@@ -0,0 +1,619 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Spending policies in miniscript policy language and their compilation into wallet
+//! descriptors.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::hex::FromHex;
+use bpstd::{XkeyParseError, XpubDerivable};
+
+use crate::miniscript::{split_args, MAX_MULTI_KEYS};
+use crate::{
+    parse_xpub_derivable, AnyDescr, DescrKey, Miniscript, ScriptContext, SingleKey, Slip132Error,
+    TapNode,
+};
+
+/// Maximal number of branches a threshold policy of arbitrary sub-policies is expanded into.
+const MAX_THRESH_BRANCHES: usize = 64;
+/// Provably unspendable x-only key from BIP-341, used as the internal key of taproot
+/// descriptors which policies have no key-only spending branch.
+const UNSPENDABLE_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Errors parsing spending policy.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PolicyParseError {
+    /// invalid policy expression '{0}'.
+    Syntax(String),
+
+    /// unknown policy fragment '{0}' with {1} argument(s).
+    UnknownFragment(String, usize),
+
+    /// invalid key name '{0}'; key names must consist of letters, digits and underscores.
+    InvalidKeyName(String),
+
+    /// invalid timelock value '{0}'; it must be a number between 1 and 2147483647.
+    InvalidTimelock(String),
+
+    /// invalid hash value '{0}'.
+    InvalidHash(String),
+
+    /// invalid threshold {0} for {1} sub-policies.
+    InvalidThreshold(usize, usize),
+
+    /// invalid weight '{0}' of a branch in `or` policy.
+    InvalidWeight(String),
+}
+
+/// Errors compiling spending policy into a wallet descriptor.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PolicyError {
+    /// policy uses key '{0}' which is not provided.
+    UnknownKey(String),
+
+    /// policy fragment '{0}' can't be compiled into a descriptor; hash locks, TRIVIAL and
+    /// UNSATISFIABLE policies are not supported.
+    Unsupported(Policy),
+
+    /// threshold policy '{0}' has too many combinations of sub-policies to be compiled; use a
+    /// threshold of keys only, or split it into `and` and `or` policies.
+    Complex(Policy),
+}
+
+/// Errors parsing named key.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum NamedKeyParseError {
+    /// named key must be given in `NAME=KEY` format.
+    NoName,
+
+    /// invalid key name '{0}'; key names must consist of letters, digits and underscores.
+    InvalidName(String),
+
+    /// invalid extended key - {0}
+    #[from]
+    #[from(XkeyParseError)]
+    Key(Slip132Error),
+}
+
+/// Type of the descriptor a policy is compiled into.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "lowercase")
+)]
+pub enum PolicyTarget {
+    /// Segwit v0 script descriptor, `wsh(...)`.
+    #[display("wsh")]
+    Wsh,
+
+    /// Taproot descriptor, `tr(...)`.
+    #[default]
+    #[display("tr")]
+    Tr,
+}
+
+/// Spending policy, expressed in miniscript policy language.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Policy {
+    /// Policy which can't be satisfied.
+    Unsatisfiable,
+    /// Policy which is always satisfied.
+    Trivial,
+    /// Signature with a named key.
+    Key(String),
+    /// Absolute timelock, in blocks or UNIX time.
+    After(u32),
+    /// Relative timelock, in BIP-68 format.
+    Older(u32),
+    /// SHA256 hash preimage.
+    Sha256(String),
+    /// Double SHA256 hash preimage.
+    Hash256(String),
+    /// RIPEMD160 hash preimage.
+    Ripemd160(String),
+    /// RIPEMD160 of SHA256 hash preimage.
+    Hash160(String),
+    /// Both sub-policies must be satisfied.
+    And(Box<Policy>, Box<Policy>),
+    /// Any of the weighted sub-policies must be satisfied. The weights give probabilities of
+    /// each branch being used.
+    Or((u32, Box<Policy>), (u32, Box<Policy>)),
+    /// At least a threshold number of sub-policies must be satisfied.
+    Thresh(usize, Vec<Policy>),
+}
+
+impl Policy {
+    /// Returns names of all keys used by the policy.
+    pub fn keys(&self) -> BTreeSet<&str> {
+        let mut keys = bset![];
+        self.visit(&mut |policy| {
+            if let Policy::Key(name) = policy {
+                keys.insert(name.as_str());
+            }
+        });
+        keys
+    }
+
+    /// Returns all absolute timelocks (`after`) used by the policy.
+    pub fn absolute_timelocks(&self) -> BTreeSet<u32> {
+        let mut locks = bset![];
+        self.visit(&mut |policy| {
+            if let Policy::After(lock) = policy {
+                locks.insert(*lock);
+            }
+        });
+        locks
+    }
+
+    /// Returns all relative timelocks (`older`) used by the policy.
+    pub fn relative_timelocks(&self) -> BTreeSet<u32> {
+        let mut locks = bset![];
+        self.visit(&mut |policy| {
+            if let Policy::Older(lock) = policy {
+                locks.insert(*lock);
+            }
+        });
+        locks
+    }
+
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Policy)) {
+        f(self);
+        match self {
+            Policy::And(a, b) | Policy::Or((_, a), (_, b)) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Policy::Thresh(_, subs) => subs.iter().for_each(|sub| sub.visit(f)),
+            _ => {}
+        }
+    }
+
+    /// Compiles the policy into a wallet descriptor of the given type, using the provided keys.
+    ///
+    /// For `wsh` descriptors the whole policy is compiled into a single script, where `or`
+    /// branches with higher weights are placed first. For `tr` descriptors the top-level `or`
+    /// branches become separate leaves of the script tree, with more probable leaves closer to
+    /// the root; the most probable single-key branch becomes the internal key, and if there is
+    /// none, the key-path spending is disabled with an unspendable internal key.
+    pub fn compile(
+        &self,
+        keys: &BTreeMap<String, XpubDerivable>,
+        target: PolicyTarget,
+    ) -> Result<AnyDescr, PolicyError> {
+        if let Some(name) = self.keys().into_iter().find(|name| !keys.contains_key(*name)) {
+            return Err(PolicyError::UnknownKey(name.to_owned()));
+        }
+        match target {
+            PolicyTarget::Wsh => {
+                Ok(AnyDescr::Wsh(self.compile_script(keys, ScriptContext::Segwit)?))
+            }
+            PolicyTarget::Tr => self.compile_tr(keys),
+        }
+    }
+
+    fn compile_tr(&self, keys: &BTreeMap<String, XpubDerivable>) -> Result<AnyDescr, PolicyError> {
+        let mut branches = vec![];
+        self.branches(1.0, &mut branches);
+        let internal = branches
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, policy))| matches!(policy, Policy::Key(_)))
+            .max_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b))
+            .map(|(pos, _)| pos);
+        let internal_key = match internal.map(|pos| branches.remove(pos)) {
+            Some((_, Policy::Key(name))) => DescrKey::from(keys[&name].clone()),
+            _ => DescrKey::from(
+                SingleKey::from_str(UNSPENDABLE_KEY).expect("hardcoded key must be valid"),
+            ),
+        };
+        // Huffman coding places the most probable leaves closest to the root.
+        let mut nodes = branches
+            .into_iter()
+            .map(|(prob, policy)| {
+                let script = policy.compile_script(keys, ScriptContext::Tapscript)?;
+                Ok((prob, TapNode::Leaf(script)))
+            })
+            .collect::<Result<Vec<_>, PolicyError>>()?;
+        while nodes.len() > 1 {
+            nodes.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+            let (prob_b, b) = nodes.pop().expect("at least two nodes");
+            let (prob_a, a) = nodes.pop().expect("at least two nodes");
+            nodes.push((prob_a + prob_b, TapNode::Branch(Box::new(a), Box::new(b))));
+        }
+        Ok(match nodes.pop() {
+            Some((_, tree)) => AnyDescr::Tr(internal_key, tree),
+            None => AnyDescr::TrKey(internal_key),
+        })
+    }
+
+    /// Splits the policy into alternative spending branches together with their probabilities.
+    fn branches(&self, prob: f64, branches: &mut Vec<(f64, Policy)>) {
+        match self {
+            Policy::Or((wa, a), (wb, b)) => {
+                let total = (*wa as f64) + (*wb as f64);
+                a.branches(prob * *wa as f64 / total, branches);
+                b.branches(prob * *wb as f64 / total, branches);
+            }
+            Policy::Thresh(1, subs) => {
+                subs.iter().for_each(|sub| sub.branches(prob / subs.len() as f64, branches))
+            }
+            policy => branches.push((prob, policy.clone())),
+        }
+    }
+
+    /// Compiles the policy into a single script for the given context.
+    fn compile_script(
+        &self,
+        keys: &BTreeMap<String, XpubDerivable>,
+        ctx: ScriptContext,
+    ) -> Result<Miniscript, PolicyError> {
+        Ok(match self {
+            Policy::Key(name) => Miniscript::Pk(keys[name].clone().into()),
+            Policy::After(lock) => Miniscript::After(*lock),
+            Policy::Older(lock) => Miniscript::Older(*lock),
+            Policy::And(a, b) => {
+                // Timelocks go last, such that the key checks are done by `OP_CHECKSIGVERIFY`.
+                let (a, b) = if a.is_timelock() && !b.is_timelock() { (b, a) } else { (a, b) };
+                let x = a.compile_script(keys, ctx)?;
+                let y = b.compile_script(keys, ctx)?;
+                Miniscript::AndV(Box::new(Miniscript::Verify(Box::new(x))), Box::new(y))
+            }
+            Policy::Or((wa, a), (wb, b)) => {
+                let (a, b) = if wb > wa { (b, a) } else { (a, b) };
+                let x = a.compile_script(keys, ctx)?;
+                let z = b.compile_script(keys, ctx)?;
+                if x.is_dissatisfiable() {
+                    Miniscript::OrD(Box::new(x), Box::new(z))
+                } else if z.is_dissatisfiable() {
+                    Miniscript::OrD(Box::new(z), Box::new(x))
+                } else {
+                    Miniscript::OrI(Box::new(x), Box::new(z))
+                }
+            }
+            Policy::Thresh(k, subs) => {
+                let names = subs
+                    .iter()
+                    .map(|sub| match sub {
+                        Policy::Key(name) => Some(DescrKey::from(keys[name].clone())),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+                match (names, ctx) {
+                    (Some(keys), ScriptContext::Segwit) if keys.len() <= MAX_MULTI_KEYS => {
+                        Miniscript::Multi {
+                            threshold: *k,
+                            keys,
+                            sorted: false,
+                        }
+                    }
+                    (Some(keys), ScriptContext::Tapscript) => Miniscript::MultiA {
+                        threshold: *k,
+                        keys,
+                        sorted: false,
+                    },
+                    _ => self.expand_thresh()?.compile_script(keys, ctx)?,
+                }
+            }
+            Policy::Unsatisfiable
+            | Policy::Trivial
+            | Policy::Sha256(_)
+            | Policy::Hash256(_)
+            | Policy::Ripemd160(_)
+            | Policy::Hash160(_) => return Err(PolicyError::Unsupported(self.clone())),
+        })
+    }
+
+    fn is_timelock(&self) -> bool { matches!(self, Policy::After(_) | Policy::Older(_)) }
+
+    /// Expands threshold policy into an equivalent `or` of `and` policies for each combination
+    /// of the sub-policies.
+    fn expand_thresh(&self) -> Result<Policy, PolicyError> {
+        let Policy::Thresh(k, subs) = self else {
+            return Ok(self.clone());
+        };
+        fn combinations(k: usize, subs: &[Policy], max: usize) -> Option<Vec<Vec<&Policy>>> {
+            if k == 0 {
+                return Some(vec![vec![]]);
+            }
+            let mut result = vec![];
+            for (pos, sub) in subs.iter().enumerate().take(subs.len() + 1 - k) {
+                for mut rest in combinations(k - 1, &subs[pos + 1..], max)? {
+                    rest.insert(0, sub);
+                    result.push(rest);
+                    if result.len() > max {
+                        return None;
+                    }
+                }
+            }
+            Some(result)
+        }
+        let combinations = combinations(*k, subs, MAX_THRESH_BRANCHES)
+            .ok_or_else(|| PolicyError::Complex(self.clone()))?;
+        let and = |subs: Vec<&Policy>| {
+            let mut iter = subs.into_iter().rev().cloned();
+            let last = iter.next().expect("threshold is never zero");
+            iter.fold(last, |acc, sub| Policy::And(Box::new(sub), Box::new(acc)))
+        };
+        let mut iter = combinations.into_iter().rev().map(and);
+        let last = iter.next().expect("threshold never exceeds number of sub-policies");
+        Ok(iter.fold(last, |acc, sub| Policy::Or((1, Box::new(sub)), (1, Box::new(acc)))))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_timelock(s: &str) -> Result<u32, PolicyParseError> {
+    match u32::from_str(s) {
+        Ok(lock) if (1..0x8000_0000).contains(&lock) => Ok(lock),
+        _ => Err(PolicyParseError::InvalidTimelock(s.to_owned())),
+    }
+}
+
+fn parse_hash(s: &str, len: usize) -> Result<String, PolicyParseError> {
+    match Vec::<u8>::from_hex(s) {
+        Ok(data) if data.len() == len => Ok(s.to_lowercase()),
+        _ => Err(PolicyParseError::InvalidHash(s.to_owned())),
+    }
+}
+
+fn parse_branch(s: &str) -> Result<(u32, Box<Policy>), PolicyParseError> {
+    match s.split_once('@') {
+        Some((weight, policy)) if !weight.contains('(') => {
+            let weight = u32::from_str(weight.trim())
+                .ok()
+                .filter(|w| *w > 0)
+                .ok_or_else(|| PolicyParseError::InvalidWeight(weight.to_owned()))?;
+            Ok((weight, Box::new(policy.parse()?)))
+        }
+        _ => Ok((1, Box::new(s.parse()?))),
+    }
+}
+
+impl FromStr for Policy {
+    type Err = PolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "UNSATISFIABLE" => return Ok(Policy::Unsatisfiable),
+            "TRIVIAL" => return Ok(Policy::Trivial),
+            _ => {}
+        }
+        let syntax = || PolicyParseError::Syntax(s.to_owned());
+        let (name, args) =
+            s.strip_suffix(')').and_then(|s| s.split_once('(')).ok_or_else(syntax)?;
+        let args = split_args(args).ok_or_else(syntax)?;
+        Ok(match (name.trim(), args.as_slice()) {
+            ("pk", [key]) if is_valid_name(key) => Policy::Key(key.to_string()),
+            ("pk", [key]) => return Err(PolicyParseError::InvalidKeyName(key.to_string())),
+            ("after", [lock]) => Policy::After(parse_timelock(lock)?),
+            ("older", [lock]) => Policy::Older(parse_timelock(lock)?),
+            ("sha256", [hash]) => Policy::Sha256(parse_hash(hash, 32)?),
+            ("hash256", [hash]) => Policy::Hash256(parse_hash(hash, 32)?),
+            ("ripemd160", [hash]) => Policy::Ripemd160(parse_hash(hash, 20)?),
+            ("hash160", [hash]) => Policy::Hash160(parse_hash(hash, 20)?),
+            ("and", [a, b]) => Policy::And(Box::new(a.parse()?), Box::new(b.parse()?)),
+            ("or", [a, b]) => Policy::Or(parse_branch(a)?, parse_branch(b)?),
+            ("thresh", [k, subs @ ..]) if !subs.is_empty() => {
+                let k = usize::from_str(k).map_err(|_| syntax())?;
+                if k == 0 || k > subs.len() {
+                    return Err(PolicyParseError::InvalidThreshold(k, subs.len()));
+                }
+                Policy::Thresh(k, subs.iter().map(|sub| sub.parse()).collect::<Result<_, _>>()?)
+            }
+            (name, args) => {
+                return Err(PolicyParseError::UnknownFragment(name.to_owned(), args.len()));
+            }
+        })
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::Unsatisfiable => f.write_str("UNSATISFIABLE"),
+            Policy::Trivial => f.write_str("TRIVIAL"),
+            Policy::Key(name) => write!(f, "pk({name})"),
+            Policy::After(lock) => write!(f, "after({lock})"),
+            Policy::Older(lock) => write!(f, "older({lock})"),
+            Policy::Sha256(hash) => write!(f, "sha256({hash})"),
+            Policy::Hash256(hash) => write!(f, "hash256({hash})"),
+            Policy::Ripemd160(hash) => write!(f, "ripemd160({hash})"),
+            Policy::Hash160(hash) => write!(f, "hash160({hash})"),
+            Policy::And(a, b) => write!(f, "and({a},{b})"),
+            Policy::Or((1, a), (1, b)) => write!(f, "or({a},{b})"),
+            Policy::Or((wa, a), (wb, b)) => write!(f, "or({wa}@{a},{wb}@{b})"),
+            Policy::Thresh(k, subs) => {
+                write!(f, "thresh({k}")?;
+                for sub in subs {
+                    write!(f, ",{sub}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// Extended public key with a name, under which it is used in a spending policy.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct NamedKey {
+    pub name: String,
+    pub key: XpubDerivable,
+}
+
+impl FromStr for NamedKey {
+    type Err = NamedKeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, key) = s.split_once('=').ok_or(NamedKeyParseError::NoName)?;
+        if !is_valid_name(name) {
+            return Err(NamedKeyParseError::InvalidName(name.to_owned()));
+        }
+        Ok(NamedKey {
+            name: name.to_owned(),
+            key: parse_xpub_derivable(key)?.0,
+        })
+    }
+}
+
+/// Spending policy the wallet descriptor was compiled from, together with the keys used by
+/// the policy.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct WalletPolicy {
+    /// Original policy text.
+    pub policy: String,
+    pub target: PolicyTarget,
+    pub keys: BTreeMap<String, XpubDerivable>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_descriptor, testing};
+
+    const KEYS: [(&str, &str); 3] = [
+        ("A", "[73c5da0a/44h/0h/0h]xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj/<0;1>/*"),
+        ("B", "[73c5da0a/86h/0h/0h]xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/<0;1>/*"),
+        ("C", testing::TPUB),
+    ];
+
+    fn keys() -> BTreeMap<String, XpubDerivable> {
+        KEYS.iter()
+            .map(|(name, key)| (name.to_string(), XpubDerivable::from_str(key).unwrap()))
+            .collect()
+    }
+
+    /// Compiles the policy, checking that the descriptor can be parsed back, and returns the
+    /// descriptor with the keys replaced by their names.
+    fn compile(policy: &str, target: PolicyTarget) -> String {
+        let keys = keys();
+        let descr = Policy::from_str(policy).unwrap().compile(&keys, target).unwrap();
+        let s = descr.to_string();
+        assert_eq!(parse_descriptor(&s).unwrap(), descr);
+        keys.iter().fold(s, |s, (name, key)| s.replace(&key.to_string(), name))
+    }
+
+    #[test]
+    fn parse_display() {
+        for policy in [
+            "pk(A)",
+            "or(pk(A),and(pk(B),older(52560)))",
+            "or(9@pk(A),1@and(pk(B),after(800000)))",
+            "thresh(2,pk(A),pk(B),\
+             sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855))",
+            "and(hash160(b472a266d0bd89c13706a4132ccfb16f7c3b9fcb),TRIVIAL)",
+        ] {
+            assert_eq!(Policy::from_str(policy).unwrap().to_string(), policy);
+        }
+        let policy = Policy::from_str(" or( 1@pk(A) , and(pk(B), older(144)) ) ").unwrap();
+        assert_eq!(policy.to_string(), "or(pk(A),and(pk(B),older(144)))");
+        assert_eq!(policy.keys(), bset!["A", "B"]);
+        assert_eq!(policy.relative_timelocks(), bset![144]);
+        assert_eq!(policy.absolute_timelocks(), bset![]);
+
+        assert_eq!(Policy::from_str("pk(A-1)"), Err(PolicyParseError::InvalidKeyName(s!("A-1"))));
+        assert_eq!(Policy::from_str("older(0)"), Err(PolicyParseError::InvalidTimelock(s!("0"))));
+        assert_eq!(
+            Policy::from_str("thresh(3,pk(A),pk(B))"),
+            Err(PolicyParseError::InvalidThreshold(3, 2))
+        );
+        assert_eq!(
+            Policy::from_str("or(0@pk(A),pk(B))"),
+            Err(PolicyParseError::InvalidWeight(s!("0")))
+        );
+        assert_eq!(
+            Policy::from_str("multi(1,A)"),
+            Err(PolicyParseError::UnknownFragment(s!("multi"), 2))
+        );
+        assert!(Policy::from_str("and(pk(A),pk(B)").is_err());
+    }
+
+    #[test]
+    fn compile_wsh() {
+        let wsh = PolicyTarget::Wsh;
+        assert_eq!(compile("pk(A)", wsh), "wsh(pk(A))");
+        assert_eq!(
+            compile("or(pk(A),and(pk(B),older(52560)))", wsh),
+            "wsh(or_d(pk(A),and_v(v:pk(B),older(52560))))"
+        );
+        assert_eq!(
+            compile("or(1@and(after(800000),pk(B)),9@pk(A))", wsh),
+            "wsh(or_d(pk(A),and_v(v:pk(B),after(800000))))"
+        );
+        assert_eq!(compile("thresh(2,pk(A),pk(B),pk(C))", wsh), "wsh(multi(2,A,B,C))");
+        assert_eq!(
+            compile("thresh(2,pk(A),pk(B),older(1000))", wsh),
+            "wsh(or_i(and_v(v:pk(A),pk(B)),or_i(and_v(v:pk(A),older(1000)),and_v(v:pk(B),\
+             older(1000)))))"
+        );
+    }
+
+    #[test]
+    fn compile_tr() {
+        let tr = PolicyTarget::Tr;
+        assert_eq!(compile("pk(A)", tr), "tr(A)");
+        assert_eq!(
+            compile("or(pk(A),and(pk(B),older(52560)))", tr),
+            "tr(A,and_v(v:pk(B),older(52560)))"
+        );
+        assert_eq!(
+            compile("or(1@pk(A),3@or(3@pk(B),1@and(pk(C),after(800000))))", tr),
+            "tr(B,{pk(A),and_v(v:pk(C),after(800000))})"
+        );
+        assert_eq!(
+            compile("thresh(2,pk(A),pk(B),pk(C))", tr),
+            format!("tr({UNSPENDABLE_KEY},multi_a(2,A,B,C))")
+        );
+        assert_eq!(
+            compile("or(and(pk(A),pk(B)),and(pk(C),older(144)))", tr),
+            format!("tr({UNSPENDABLE_KEY},{{and_v(v:pk(A),pk(B)),and_v(v:pk(C),older(144))}})")
+        );
+    }
+
+    #[test]
+    fn compile_errors() {
+        let keys = keys();
+        let policy = Policy::from_str("or(pk(A),pk(D))").unwrap();
+        assert_eq!(policy.compile(&keys, PolicyTarget::Wsh), Err(PolicyError::UnknownKey(s!("D"))));
+        let hash = "sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855)";
+        let policy = Policy::from_str(&format!("and(pk(A),{hash})")).unwrap();
+        assert_eq!(
+            policy.compile(&keys, PolicyTarget::Tr),
+            Err(PolicyError::Unsupported(Policy::from_str(hash).unwrap()))
+        );
+    }
+}
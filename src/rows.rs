@@ -20,13 +20,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::{self, Display, Formatter, LowerHex};
 use std::str::FromStr;
 
 use amplify::hex::FromHex;
 use bpstd::{Address, DerivedAddr, Outpoint, Sats, ScriptPubkey, Txid};
 
-use crate::{BlockHeight, Layer2Cache, Layer2Coin, Layer2Tx, Party, TxStatus, WalletCache};
+use crate::{
+    BlockHeight, Layer2Cache, Layer2Coin, Layer2Tx, Party, TxStatus, WalletCache, WalletTx,
+};
 
 #[cfg_attr(
     feature = "serde",
@@ -107,7 +110,9 @@ impl FromStr for Counterparty {
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct TxRow<L2: Layer2Tx> {
     pub height: TxStatus<BlockHeight>,
-    // TODO: Add date/time
+    /// Timestamp of the block mining the transaction or, for unconfirmed transactions, time when
+    /// it was first seen by the wallet.
+    pub time: Option<u64>,
     pub operation: OpType,
     pub counterparties: Vec<(Counterparty, i64)>,
     pub own: Vec<(DerivedAddr, i64)>,
@@ -117,7 +122,12 @@ pub struct TxRow<L2: Layer2Tx> {
     pub size: u32,
     pub total: Sats,
     pub amount: Sats,
-    pub balance: Sats,
+    /// Wallet balance after this transaction, accounting for all preceding transactions in chain
+    /// order. May be negative only if the wallet history is incomplete, for instance when a
+    /// transaction spends an output of a transaction which was rolled back by a reorg.
+    pub balance: i64,
+    /// Transaction which replaced this one, if any.
+    pub replaced_by: Option<Txid>,
     pub layer2: L2,
 }
 
@@ -136,33 +146,61 @@ pub struct TxRow<L2: Layer2Tx> {
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct CoinRow<L2: Layer2Coin> {
     pub height: TxStatus<BlockHeight>,
-    // TODO: Add date/time
+    /// Timestamp of the block mining the output or, for unconfirmed outputs, time when it was
+    /// first seen by the wallet.
+    pub time: Option<u64>,
     pub address: DerivedAddr,
     pub outpoint: Outpoint,
     pub amount: Sats,
+    /// Whether the output is frozen and excluded from coin selection.
+    pub frozen: bool,
     pub layer2: Vec<L2>,
 }
 
 impl<L2: Layer2Cache> WalletCache<L2> {
     pub fn coins(&self) -> impl Iterator<Item = CoinRow<L2::Coin>> + '_ {
-        self.utxo.iter().map(|outpoint| {
+        self.utxo.iter().filter_map(|outpoint| {
             let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
             let out = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
-            CoinRow {
+            if !self.txo_status(tx, out).is_spendable() {
+                return None;
+            }
+            Some(CoinRow {
                 height: tx.status.map(|info| info.height),
+                time: tx.time(),
                 outpoint: *outpoint,
                 address: out.derived_addr().expect("cache data inconsistency"),
                 amount: out.value,
+                frozen: false,
                 layer2: none!(), // TODO: Add support to WalletTx
-            }
+            })
         })
     }
 
+    /// Returns wallet transaction history in chain order: mined transactions sorted by their
+    /// height, followed by mempool, off-chain and transactions with unknown status, each sorted
+    /// by the time they were first seen. Transactions mined in the same block, or having the same
+    /// status, are ordered such that each of them follows the transactions it spends.
     pub fn history(&self) -> impl Iterator<Item = TxRow<L2::Tx>> + '_ {
-        self.tx.values().map(|tx| {
+        let mut txes = self.tx.values().collect::<Vec<_>>();
+        txes.sort_by_key(|tx| {
+            let (class, height) = history_group(tx);
+            let time = match tx.status {
+                TxStatus::Mined(info) => info.time,
+                _ => tx.first_seen.unwrap_or(u64::MAX),
+            };
+            (class, height, time)
+        });
+        let txes = sort_topologically(txes);
+        let mut balance = 0i64;
+        txes.into_iter().map(move |tx| {
+            if tx.is_live() {
+                balance += tx.own_balance_change();
+            }
             let (credit, debit) = tx.credited_debited();
             let mut row = TxRow {
                 height: tx.status.map(|info| info.height),
+                time: tx.time(),
                 operation: OpType::Credit,
                 counterparties: none!(),
                 own: none!(),
@@ -172,10 +210,10 @@ impl<L2: Layer2Cache> WalletCache<L2> {
                 size: tx.size,
                 total: tx.total_moved(),
                 amount: Sats::ZERO,
-                balance: Sats::ZERO,
+                balance,
+                replaced_by: tx.replaced_by,
                 layer2: none!(), // TODO: Add support to WalletTx
             };
-            // TODO: Add balance calculation
             row.own = tx
                 .inputs
                 .iter()
@@ -212,3 +250,86 @@ impl<L2: Layer2Cache> WalletCache<L2> {
         })
     }
 }
+
+/// Group of transactions in the history, within which transactions are ordered by their
+/// dependencies: status class and, for mined transactions, block height.
+fn history_group(tx: &WalletTx) -> (u8, u32) {
+    match tx.status {
+        TxStatus::Mined(info) => (0, info.height.get()),
+        TxStatus::Mempool => (1, 0),
+        TxStatus::Channel => (2, 0),
+        TxStatus::Unknown => (3, 0),
+    }
+}
+
+/// Reorders transactions such that every transaction follows the transactions from the same
+/// history group it spends, otherwise keeping the original order.
+fn sort_topologically(txes: Vec<&WalletTx>) -> Vec<&WalletTx> {
+    let pos = txes.iter().enumerate().map(|(i, tx)| (tx.txid, i)).collect::<HashMap<_, _>>();
+    let mut children = vec![Vec::new(); txes.len()];
+    let mut parents = vec![0usize; txes.len()];
+    for (i, tx) in txes.iter().enumerate() {
+        let spent = tx
+            .inputs
+            .iter()
+            .filter_map(|credit| pos.get(&credit.outpoint.txid).copied())
+            .filter(|p| *p != i && history_group(txes[*p]) == history_group(tx))
+            .collect::<BTreeSet<_>>();
+        for p in spent {
+            children[p].push(i);
+            parents[i] += 1;
+        }
+    }
+
+    // Picking the earliest transaction which has no unprocessed parents keeps the groups in
+    // their order, since each group always has such a transaction.
+    let mut ready = (0..txes.len()).filter(|i| parents[*i] == 0).collect::<BTreeSet<_>>();
+    let mut sorted = Vec::with_capacity(txes.len());
+    while let Some(i) = ready.pop_first() {
+        sorted.push(txes[i]);
+        for child in &children[i] {
+            parents[*child] -= 1;
+            if parents[*child] == 0 {
+                ready.insert(*child);
+            }
+        }
+    }
+    sorted
+}
+
+#[cfg(test)]
+mod test {
+    use bpstd::{Keychain, TxOut};
+
+    use super::*;
+    use crate::indexers::memory::MemoryIndexer;
+    use crate::{testing, Indexer};
+
+    #[test]
+    fn history_order() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let mut script = || wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script(), Sats::from_btc(1));
+        let parent = testing::tx([coin], [TxOut::new(script(), Sats(99_990_000))]);
+        // Child with txid sorting before the parent one
+        let child = (0..)
+            .map(|fee| {
+                let outpoint = Outpoint::new(parent.txid(), 0);
+                testing::tx([outpoint], [TxOut::new(script(), Sats(99_980_000 - fee))])
+            })
+            .find(|child| child.txid() < parent.txid())
+            .unwrap();
+        indexer.publish(&parent).unwrap();
+        indexer.publish(&child).unwrap();
+        indexer.mine();
+        wallet.update(&indexer).into_result().unwrap();
+
+        let history = wallet.history().collect::<Vec<_>>();
+        let txids = history.iter().map(|row| row.txid).collect::<Vec<_>>();
+        assert_eq!(txids, vec![coin.txid, parent.txid(), child.txid()]);
+        let balances = history.iter().map(|row| row.balance).collect::<Vec<_>>();
+        assert_eq!(balances[..2], [100_000_000, 99_990_000]);
+        assert_eq!(balances[2], wallet.balance().sats_i64());
+    }
+}
@@ -0,0 +1,443 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extended public keys using version prefixes defined by [SLIP-132], like `ypub` and `zpub`.
+//!
+//! [SLIP-132]: https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+
+use std::str::FromStr;
+
+use bpstd::{base58, XkeyDecodeError, XkeyParseError, Xpriv, Xpub, XpubDerivable};
+
+use crate::Bip43;
+
+/// Version of an extended public key, as defined by SLIP-132.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum KeyVersion {
+    /// Mainnet key for P2PKH or P2SH scripts, also used by descriptor-based wallets for any
+    /// script type.
+    #[display("xpub")]
+    #[cfg_attr(feature = "clap", value(name = "xpub"))]
+    Xpub,
+
+    /// Mainnet key for P2WPKH-in-P2SH scripts.
+    #[display("ypub")]
+    #[cfg_attr(feature = "clap", value(name = "ypub"))]
+    Ypub,
+
+    /// Mainnet key for P2WPKH scripts.
+    #[display("zpub")]
+    #[cfg_attr(feature = "clap", value(name = "zpub"))]
+    Zpub,
+
+    /// Mainnet key for multisig P2WSH-in-P2SH scripts.
+    #[display("Ypub")]
+    #[cfg_attr(feature = "clap", value(name = "Ypub"))]
+    YpubMulti,
+
+    /// Mainnet key for multisig P2WSH scripts.
+    #[display("Zpub")]
+    #[cfg_attr(feature = "clap", value(name = "Zpub"))]
+    ZpubMulti,
+
+    /// Testnet key for P2PKH or P2SH scripts, also used by descriptor-based wallets for any
+    /// script type.
+    #[display("tpub")]
+    #[cfg_attr(feature = "clap", value(name = "tpub"))]
+    Tpub,
+
+    /// Testnet key for P2WPKH-in-P2SH scripts.
+    #[display("upub")]
+    #[cfg_attr(feature = "clap", value(name = "upub"))]
+    Upub,
+
+    /// Testnet key for P2WPKH scripts.
+    #[display("vpub")]
+    #[cfg_attr(feature = "clap", value(name = "vpub"))]
+    Vpub,
+
+    /// Testnet key for multisig P2WSH-in-P2SH scripts.
+    #[display("Upub")]
+    #[cfg_attr(feature = "clap", value(name = "Upub"))]
+    UpubMulti,
+
+    /// Testnet key for multisig P2WSH scripts.
+    #[display("Vpub")]
+    #[cfg_attr(feature = "clap", value(name = "Vpub"))]
+    VpubMulti,
+}
+
+impl KeyVersion {
+    const ALL: [KeyVersion; 10] = [
+        KeyVersion::Xpub,
+        KeyVersion::Ypub,
+        KeyVersion::Zpub,
+        KeyVersion::YpubMulti,
+        KeyVersion::ZpubMulti,
+        KeyVersion::Tpub,
+        KeyVersion::Upub,
+        KeyVersion::Vpub,
+        KeyVersion::UpubMulti,
+        KeyVersion::VpubMulti,
+    ];
+
+    /// Version bytes starting the serialized key.
+    pub const fn magic(self) -> [u8; 4] {
+        match self {
+            KeyVersion::Xpub => [0x04, 0x88, 0xB2, 0x1E],
+            KeyVersion::Ypub => [0x04, 0x9D, 0x7C, 0xB2],
+            KeyVersion::Zpub => [0x04, 0xB2, 0x47, 0x46],
+            KeyVersion::YpubMulti => [0x02, 0x95, 0xB4, 0x3F],
+            KeyVersion::ZpubMulti => [0x02, 0xAA, 0x7E, 0xD3],
+            KeyVersion::Tpub => [0x04, 0x35, 0x87, 0xCF],
+            KeyVersion::Upub => [0x04, 0x4A, 0x52, 0x62],
+            KeyVersion::Vpub => [0x04, 0x5F, 0x1C, 0xF6],
+            KeyVersion::UpubMulti => [0x02, 0x42, 0x89, 0xEF],
+            KeyVersion::VpubMulti => [0x02, 0x57, 0x54, 0x83],
+        }
+    }
+
+    /// Version bytes starting the serialized extended private key, corresponding to this
+    /// version of the public key.
+    pub const fn private_magic(self) -> [u8; 4] {
+        match self {
+            KeyVersion::Xpub => [0x04, 0x88, 0xAD, 0xE4],
+            KeyVersion::Ypub => [0x04, 0x9D, 0x78, 0x78],
+            KeyVersion::Zpub => [0x04, 0xB2, 0x43, 0x0C],
+            KeyVersion::YpubMulti => [0x02, 0x95, 0xB0, 0x05],
+            KeyVersion::ZpubMulti => [0x02, 0xAA, 0x7A, 0x99],
+            KeyVersion::Tpub => [0x04, 0x35, 0x83, 0x94],
+            KeyVersion::Upub => [0x04, 0x4A, 0x4E, 0x28],
+            KeyVersion::Vpub => [0x04, 0x5F, 0x18, 0xBC],
+            KeyVersion::UpubMulti => [0x02, 0x42, 0x85, 0xB5],
+            KeyVersion::VpubMulti => [0x02, 0x57, 0x50, 0x48],
+        }
+    }
+
+    /// Detects key version from the version bytes of the serialized key.
+    pub fn from_magic(magic: [u8; 4]) -> Option<Self> {
+        Self::ALL.into_iter().find(|version| version.magic() == magic)
+    }
+
+    pub const fn is_testnet(self) -> bool {
+        matches!(
+            self,
+            KeyVersion::Tpub
+                | KeyVersion::Upub
+                | KeyVersion::Vpub
+                | KeyVersion::UpubMulti
+                | KeyVersion::VpubMulti
+        )
+    }
+
+    /// Derivation standard implied by the key version, if any.
+    ///
+    /// Standard `xpub` and `tpub` keys may be used with any script type and don't imply any
+    /// standard.
+    pub fn standard(self) -> Option<Bip43> {
+        match self {
+            KeyVersion::Xpub | KeyVersion::Tpub => None,
+            KeyVersion::Ypub | KeyVersion::Upub => Some(Bip43::WPKH_SH),
+            KeyVersion::Zpub | KeyVersion::Vpub => Some(Bip43::WPKH),
+            KeyVersion::YpubMulti | KeyVersion::UpubMulti => Some(Bip43::MULTI_WSH_SH),
+            KeyVersion::ZpubMulti | KeyVersion::VpubMulti => Some(Bip43::MULTI_WSH),
+        }
+    }
+
+    /// Key version used for the keys of the given derivation standard.
+    pub fn with_standard(standard: Option<Bip43>, testnet: bool) -> Self {
+        match (standard, testnet) {
+            (Some(Bip43::Bip49), false) => KeyVersion::Ypub,
+            (Some(Bip43::Bip84), false) => KeyVersion::Zpub,
+            (Some(Bip43::Bip48Nested), false) => KeyVersion::YpubMulti,
+            (Some(Bip43::Bip48Native), false) => KeyVersion::ZpubMulti,
+            (_, false) => KeyVersion::Xpub,
+            (Some(Bip43::Bip49), true) => KeyVersion::Upub,
+            (Some(Bip43::Bip84), true) => KeyVersion::Vpub,
+            (Some(Bip43::Bip48Nested), true) => KeyVersion::UpubMulti,
+            (Some(Bip43::Bip48Native), true) => KeyVersion::VpubMulti,
+            (_, true) => KeyVersion::Tpub,
+        }
+    }
+}
+
+/// Errors parsing or converting SLIP-132 extended public keys.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Slip132Error {
+    /// unknown extended public key version {0:02x?}.
+    UnknownVersion([u8; 4]),
+
+    /// {0} key can't be converted into {1} key since they are used on different networks.
+    NetworkMismatch(KeyVersion, KeyVersion),
+
+    #[from]
+    #[from(base58::Error)]
+    #[from(XkeyDecodeError)]
+    #[display(inner)]
+    Key(XkeyParseError),
+}
+
+/// Extended public key, serialized with SLIP-132 version.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Slip132Xpub {
+    pub xpub: Xpub,
+    pub version: KeyVersion,
+}
+
+impl FromStr for Slip132Xpub {
+    type Err = Slip132Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut data = base58::decode_check(s)?;
+        if data.len() < 4 {
+            return Err(XkeyDecodeError::WrongExtendedKeyLength(data.len()).into());
+        }
+        let mut magic = [0u8; 4];
+        magic.copy_from_slice(&data[..4]);
+        let version = KeyVersion::from_magic(magic).ok_or(Slip132Error::UnknownVersion(magic))?;
+        let standard = if version.is_testnet() { KeyVersion::Tpub } else { KeyVersion::Xpub };
+        data[..4].copy_from_slice(&standard.magic());
+        let xpub = Xpub::decode(data)?;
+        Ok(Slip132Xpub { xpub, version })
+    }
+}
+
+impl Slip132Xpub {
+    /// Serializes the key using a different version.
+    pub fn to_version(&self, version: KeyVersion) -> Result<String, Slip132Error> {
+        if version.is_testnet() != self.version.is_testnet() {
+            return Err(Slip132Error::NetworkMismatch(self.version, version));
+        }
+        let mut data = self.xpub.encode();
+        data[..4].copy_from_slice(&version.magic());
+        Ok(base58::encode_check(&data))
+    }
+}
+
+/// Serializes extended private key using the private version matching the given SLIP-132 public
+/// key version, like `zprv` for [`KeyVersion::Zpub`].
+pub fn xpriv_to_version(xpriv: &Xpriv, version: KeyVersion) -> Result<String, Slip132Error> {
+    if version.is_testnet() != xpriv.is_testnet() {
+        let own = if xpriv.is_testnet() { KeyVersion::Tpub } else { KeyVersion::Xpub };
+        return Err(Slip132Error::NetworkMismatch(own, version));
+    }
+    let mut data = xpriv.encode();
+    data[..4].copy_from_slice(&version.private_magic());
+    Ok(base58::encode_check(&data))
+}
+
+/// Replaces SLIP-132 extended key in a key expression (like `[fp/84h/0h/0h]zpub.../<0;1>/*`)
+/// with its standard `xpub` or `tpub` form, returning the original key version.
+pub fn convert_key_expr(
+    s: &str,
+    version: Option<KeyVersion>,
+) -> Result<(String, KeyVersion), Slip132Error> {
+    let (origin, rest) = match s.split_once(']') {
+        Some((origin, rest)) => (Some(origin), rest),
+        None => (None, s),
+    };
+    let (key, terminal) = match rest.split_once('/') {
+        Some((key, terminal)) => (key, Some(terminal)),
+        None => (rest, None),
+    };
+    let key = Slip132Xpub::from_str(key)?;
+    let to = version.unwrap_or(if key.version.is_testnet() {
+        KeyVersion::Tpub
+    } else {
+        KeyVersion::Xpub
+    });
+    let mut expr = origin.map(|origin| format!("{origin}]")).unwrap_or_default();
+    expr.push_str(&key.to_version(to)?);
+    if let Some(terminal) = terminal {
+        expr.push('/');
+        expr.push_str(terminal);
+    }
+    Ok((expr, key.version))
+}
+
+/// Parses extended public key with its origin and derivation terminal, like
+/// [`XpubDerivable::from_str`], accepting keys with SLIP-132 versions.
+pub fn parse_xpub_derivable(s: &str) -> Result<(XpubDerivable, KeyVersion), Slip132Error> {
+    // Report missing origin in the same way as `XpubDerivable` does.
+    if !s.starts_with('[') {
+        return Err(XkeyParseError::NoOrigin.into());
+    }
+    let (expr, version) = convert_key_expr(s, None)?;
+    Ok((XpubDerivable::from_str(&expr)?, version))
+}
+
+#[cfg(test)]
+mod test {
+    use bpstd::DerivationPath;
+
+    use super::*;
+    use crate::{parse_descriptor, AnyDescr, DerivationStandard, DescrParseError};
+
+    /// BIP-84 account key of the `abandon ... about` test mnemonic in all mainnet versions.
+    const ACCOUNT: [(KeyVersion, &str); 5] = [
+        (KeyVersion::Xpub, "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V"),
+        (KeyVersion::Ypub, "ypub6XR9pJPUsVBFKweLeV85HtwdxjjmKEuUr6djm9mNdkh47X7ASsD6byaXFotRAKByFoWgSzCuoTjaYdrv2yoJroLAPtBuHFjVm5vNmhyNehE"),
+        (KeyVersion::Zpub, "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs"),
+        (KeyVersion::YpubMulti, "Ypub6iKEwY7vSSjckWoib9b47yHSgXn2Xbb5ANHQgR2v1XXTjhg5DGbNh6ST4WqtikQsVGafKaD2gg85foUgLCxG1HSqEMJJgfCVepCFMi9sffi"),
+        (KeyVersion::ZpubMulti, "Zpub739WFCnqb8H6bozqRWNgL4NwrVvUUDaa5UodTovoPXuLnoVJTvkwKA6b5ioUif4ntuhU53ob9LUdZ66F3uNGoX8S6gzjGa1yvYFtkDRknR2"),
+    ];
+
+    /// Test wallet account key in all testnet versions.
+    const TESTNET: [(KeyVersion, &str); 5] = [
+        (KeyVersion::Tpub, "tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2"),
+        (KeyVersion::Upub, "upub5DWJ53kHHS6tb49VvQonukxc9ZRQvE9DQcXxrG6bZXemZ68sUgYiaD9emKkLjXyBgNbvuB4nUzyfj1W2ScW5UjFwrd8gsc811Ft9dp1ikuE"),
+        (KeyVersion::Vpub, "vpub5YLZNiRCS7eNSMLckmbR7r47KXZrrr8iKj4BdezUwY2ecBx6jLiHCGonnXhvjSd761ijeefLwfLDcJ7bAJv6GxwYixq7TWwVGywo2QXhZSm"),
+        (KeyVersion::UpubMulti, "Upub5QQPCHUirPfG1dJss5GmjqJQsMTg8apoitBdmXN8wJVBBGhnF5vzfL1aa2hpHyC5uqfumm4uNDNArB7njqf2dDNch6F6H1aztzA2DkYv8yv"),
+        (KeyVersion::VpubMulti, "Vpub5jEeVx9e15CjrvVzhS4PwvPv3Kc85CpJdzhrYvG2KJs4ENX1Vk6ZHPfibEfQHsr1KUniXEfTpsiijTjMTY53RT4DZRwWrvQVAiDfcKoc2N1"),
+    ];
+
+    #[test]
+    fn versions() {
+        for (version, key) in ACCOUNT.into_iter().chain(TESTNET) {
+            assert!(key.starts_with(&version.to_string()));
+            let slip132 = Slip132Xpub::from_str(key).unwrap();
+            assert_eq!(slip132.version, version);
+            let standard = if version.is_testnet() { TESTNET[0] } else { ACCOUNT[0] };
+            assert_eq!(slip132.xpub.to_string(), standard.1);
+            for (other, expected) in if version.is_testnet() { TESTNET } else { ACCOUNT } {
+                assert_eq!(slip132.to_version(other).unwrap(), expected);
+            }
+            let other = if version.is_testnet() { KeyVersion::Xpub } else { KeyVersion::Tpub };
+            assert_eq!(
+                slip132.to_version(other),
+                Err(Slip132Error::NetworkMismatch(version, other))
+            );
+            assert_eq!(
+                KeyVersion::with_standard(version.standard(), version.is_testnet()),
+                version
+            );
+        }
+
+        for (path, version) in [
+            ("84h/0h/0h", KeyVersion::Zpub),
+            ("49h/1h/0h", KeyVersion::Upub),
+            ("48h/0h/0h/1h", KeyVersion::YpubMulti),
+            ("48h/1h/0h/2h", KeyVersion::VpubMulti),
+            ("86h/0h/0h", KeyVersion::Xpub),
+        ] {
+            let standard = Bip43::deduce(&DerivationPath::from_str(path).unwrap());
+            assert_eq!(KeyVersion::with_standard(standard, version.is_testnet()), version);
+        }
+
+        let xprv = "xprv9ybY78BftS5UGANki6oSifuQEjkpyAC8ZmBvBNTshQnCBcxnefjHS7buPQnZ5EPTSRwso4VRg3da3uhZRctkJi4Q1YB6r26xZVyVd6bFGRo";
+        assert_eq!(
+            Slip132Xpub::from_str(xprv),
+            Err(Slip132Error::UnknownVersion([0x04, 0x88, 0xAD, 0xE4]))
+        );
+        // BIP-84 test vector for the account private key
+        let zprv = "zprvAdG4iTXWBoARxkkzNpNh8r6Qag3irQB8PzEMkAFeTRXxHpbF9z4QgEvBRmfvqWvGp42t42nvgGpNgYSJA9iefm1yYNZKEm7z6qUWCroSQnE";
+        let mut data = base58::decode_check(zprv).unwrap();
+        assert_eq!(data[..4], KeyVersion::Zpub.private_magic());
+        data[..4].copy_from_slice(&KeyVersion::Xpub.private_magic());
+        let xpriv = Xpriv::decode(data).unwrap();
+        assert_eq!(xpriv.to_xpub().to_string(), ACCOUNT[0].1);
+        assert_eq!(xpriv_to_version(&xpriv, KeyVersion::Xpub).unwrap(), xpriv.to_string());
+        assert_eq!(xpriv_to_version(&xpriv, KeyVersion::Zpub).unwrap(), zprv);
+        for (version, prefix) in [
+            (KeyVersion::Ypub, "yprv"),
+            (KeyVersion::YpubMulti, "Yprv"),
+            (KeyVersion::ZpubMulti, "Zprv"),
+        ] {
+            assert!(xpriv_to_version(&xpriv, version).unwrap().starts_with(prefix));
+        }
+        assert_eq!(
+            xpriv_to_version(&xpriv, KeyVersion::Vpub),
+            Err(Slip132Error::NetworkMismatch(KeyVersion::Xpub, KeyVersion::Vpub))
+        );
+        let tprv = Xpriv::new_master(true, &[1u8; 32]);
+        for (version, prefix) in [
+            (KeyVersion::Tpub, "tprv"),
+            (KeyVersion::Upub, "uprv"),
+            (KeyVersion::Vpub, "vprv"),
+            (KeyVersion::UpubMulti, "Uprv"),
+            (KeyVersion::VpubMulti, "Vprv"),
+        ] {
+            assert!(xpriv_to_version(&tprv, version).unwrap().starts_with(prefix));
+        }
+
+        let corrupted = ACCOUNT[2].1.replace("zpub6rFR", "zpub6rFS");
+        assert!(matches!(Slip132Xpub::from_str(&corrupted), Err(Slip132Error::Key(_))));
+    }
+
+    #[test]
+    fn key_expressions() {
+        let (zpub, xpub) = (ACCOUNT[2].1, ACCOUNT[0].1);
+        let expr = format!("[73c5da0a/84h/0h/0h]{zpub}/<0;1>/*");
+        let (converted, version) = convert_key_expr(&expr, None).unwrap();
+        assert_eq!(version, KeyVersion::Zpub);
+        assert_eq!(converted, format!("[73c5da0a/84h/0h/0h]{xpub}/<0;1>/*"));
+        assert_eq!(convert_key_expr(&converted, Some(KeyVersion::Zpub)).unwrap().0, expr);
+        assert_eq!(convert_key_expr(zpub, None).unwrap(), (xpub.to_owned(), KeyVersion::Zpub));
+
+        let (key, version) = parse_xpub_derivable(&expr).unwrap();
+        assert_eq!(version, KeyVersion::Zpub);
+        assert_eq!(key.to_string(), converted);
+        assert_eq!(
+            parse_xpub_derivable(&format!("{zpub}/<0;1>/*")),
+            Err(Slip132Error::Key(XkeyParseError::NoOrigin))
+        );
+    }
+
+    #[test]
+    fn descriptor_versions() {
+        let key = |version: KeyVersion, path: &str| {
+            let (_, xpub) = ACCOUNT.into_iter().find(|(v, _)| *v == version).unwrap();
+            format!("[73c5da0a/{path}]{xpub}/<0;1>/*")
+        };
+        let ypub = key(KeyVersion::Ypub, "49h/0h/0h");
+        assert!(matches!(parse_descriptor(&ypub), Ok(AnyDescr::ShWpkh(_))));
+        assert!(parse_descriptor(&format!("sh(wpkh({ypub}))")).is_ok());
+        let zpub = key(KeyVersion::Zpub, "84h/0h/0h");
+        assert!(matches!(parse_descriptor(&zpub), Ok(AnyDescr::Wpkh(_))));
+
+        let zpub_multi = key(KeyVersion::ZpubMulti, "48h/0h/0h/2h");
+        let ypub_multi = key(KeyVersion::YpubMulti, "48h/0h/0h/1h");
+        assert_eq!(
+            parse_descriptor(&zpub_multi),
+            Err(DescrParseError::MultisigKey(zpub_multi.clone()))
+        );
+        assert!(matches!(
+            parse_descriptor(&format!("wsh(sortedmulti(1,{zpub_multi},{ypub_multi}))")),
+            Ok(AnyDescr::Wsh(_))
+        ));
+
+        for (descr, key, version) in [
+            (format!("wpkh({ypub})"), &ypub, KeyVersion::Ypub),
+            (format!("pkh({zpub})"), &zpub, KeyVersion::Zpub),
+            (format!("tr({zpub})"), &zpub, KeyVersion::Zpub),
+            (format!("wpkh({zpub_multi})"), &zpub_multi, KeyVersion::ZpubMulti),
+            (format!("wsh(sortedmulti(1,{zpub},{zpub_multi}))"), &zpub, KeyVersion::Zpub),
+        ] {
+            assert_eq!(
+                parse_descriptor(&descr),
+                Err(DescrParseError::KeyVersion(key.clone(), version))
+            );
+        }
+    }
+}
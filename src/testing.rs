@@ -0,0 +1,64 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the unit tests.
+
+use std::str::FromStr;
+
+use bpstd::{
+    LockTime, Network, Outpoint, SeqNo, SigScript, Tx, TxIn, TxOut, TxVer, VarIntArray, Witness,
+    XpubDerivable,
+};
+use descriptors::Wpkh;
+
+use crate::{Wallet, WalletDescr};
+
+/// Testnet account key, which is used by all the test wallets.
+pub(crate) const TPUB: &str = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+pub(crate) type TestWallet = Wallet<XpubDerivable, Wpkh<XpubDerivable>>;
+
+pub(crate) fn xpub() -> XpubDerivable { XpubDerivable::from_str(TPUB).expect("test key") }
+
+pub(crate) fn descriptor() -> WalletDescr<XpubDerivable, Wpkh<XpubDerivable>> {
+    WalletDescr::new_standard(Wpkh::from(xpub()), Network::Regtest)
+}
+
+pub(crate) fn wallet() -> TestWallet { Wallet::new_layer1(Wpkh::from(xpub()), Network::Regtest) }
+
+/// Constructs unsigned transaction spending given outputs.
+pub(crate) fn tx(
+    inputs: impl IntoIterator<Item = Outpoint>,
+    outputs: impl IntoIterator<Item = TxOut>,
+) -> Tx {
+    Tx {
+        version: TxVer::V2,
+        inputs: VarIntArray::from_iter_checked(inputs.into_iter().map(|prev_output| TxIn {
+            prev_output,
+            sig_script: SigScript::new(),
+            sequence: SeqNo::from_consensus_u32(u32::MAX - 2),
+            witness: Witness::new(),
+        })),
+        outputs: VarIntArray::from_iter_checked(outputs),
+        lock_time: LockTime::ZERO,
+    }
+}
@@ -20,6 +20,68 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bpstd::{Address, AddressError, AddressNetwork, AddressPayload, ScriptHash, ScriptPubkey};
+
+/// Constructs address for a script pubkey.
+///
+/// Replaces [`Address::with`], which panics on P2SH scripts, since it takes the script hash
+/// together with the trailing `OP_EQUAL`.
+pub(crate) fn address_with(
+    script: &ScriptPubkey,
+    network: impl Into<AddressNetwork>,
+) -> Result<Address, AddressError> {
+    if script.is_p2sh() {
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(&script[2..22]);
+        return Ok(Address::new(AddressPayload::Sh(ScriptHash::from(hash)), network.into()));
+    }
+    Address::with(script, network)
+}
+
+/// Formats UNIX timestamp as a UTC date and time in `YYYY-MM-DD HH:MM:SS` format.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+    // Conversion of days since UNIX epoch into a civil date, see
+    // <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Parses UTC date in `YYYY-MM-DD` format into a UNIX timestamp of the midnight of that day.
+#[cfg(feature = "serde")]
+pub(crate) fn parse_date(s: &str) -> Option<u64> {
+    let mut parts = s.splitn(3, '-');
+    let year = parts.next()?.parse::<u64>().ok()?;
+    let month = parts.next()?.parse::<u64>().ok()?;
+    let day = parts.next()?.parse::<u64>().ok()?;
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Inverse of the conversion in `format_timestamp`, see
+    // <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year % 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days * 86400)
+}
+
 // TODO: Move to amplify library
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
@@ -24,20 +24,31 @@ use std::cmp;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::marker::PhantomData;
-use std::ops::{AddAssign, Deref, DerefMut};
+use std::ops::{Deref, DerefMut};
 #[cfg(feature = "fs")]
 use std::path::PathBuf;
 
 use bpstd::{
-    Address, AddressNetwork, DerivedAddr, Descriptor, Idx, IdxBase, Keychain, Network, NormalIndex,
-    Outpoint, Sats, Txid, Vout,
+    Address, AddressNetwork, DerivedAddr, Descriptor, Idx, IdxBase, Keychain, LockTime, Network,
+    NormalIndex, Outpoint, Sats, SeqNo, Terminal, TxOut, TxVer, Txid, Vout,
+};
+use psbt::{
+    Beneficiary, ConstructionError, Payment, Psbt, PsbtConstructor, PsbtMeta, TxParams, Utxo,
 };
-use psbt::{PsbtConstructor, Utxo};
 
+use crate::coinselect::{
+    tx_overhead_weight, txout_weight, Candidate, CoinSelector, LargestFirst, Selection,
+    SelectionError, SelectionParams,
+};
+use crate::util::address_with;
 use crate::{
-    BlockInfo, CoinRow, Indexer, Layer2, Layer2Cache, Layer2Data, Layer2Descriptor, MayError,
-    MiningInfo, NoLayer2, TxRow, WalletAddr, WalletTx, WalletUtxo,
+    vsize, BlockInfo, CoinRow, Counterparty, FeeRate, Indexer, LabelRef, Layer2, Layer2Cache,
+    Layer2Coin, Layer2Data, Layer2Descriptor, Layer2Tx, MayError, MiningInfo, NoLayer2, Party,
+    PsbtDescriptor, Timelock, TxDebit, TxRow, TxStatus, UtxoStatus, WalletAddr, WalletPolicy,
+    WalletTx, WalletUtxo,
 };
+#[cfg(feature = "serde")]
+use crate::{Bip329Record, HistoryRecord, LabelImport, LabelType, PriceTable};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
 #[display(doc_comments)]
@@ -50,6 +61,228 @@ pub enum NonWalletItem {
     NonWalletUtxo(Outpoint),
 }
 
+/// Maximal number of coin selection attempts made during the PSBT construction with a fee rate.
+const FEE_ITERATIONS: usize = 16;
+
+/// Number of consecutive unused addresses after which the address discovery on a keychain stops,
+/// unless the wallet specifies a different gap limit.
+pub const DEFAULT_GAP_LIMIT: u32 = 10;
+
+#[derive(Clone, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum PaymentError {
+    #[from]
+    CoinSelection(SelectionError),
+
+    #[from]
+    Construction(ConstructionError),
+
+    #[from]
+    NonWallet(NonWalletItem),
+
+    #[from]
+    TxOptions(TxOptionsError),
+
+    /// transaction output {0} is frozen and can't be spent.
+    #[display(doc_comments)]
+    FrozenCoin(Outpoint),
+
+    /// transaction output {0} is already spent.
+    #[display(doc_comments)]
+    SpentCoin(Outpoint),
+
+    /// transaction output {0} is both included and excluded from spending.
+    #[display(doc_comments)]
+    ExcludedCoin(Outpoint),
+
+    /// transaction output {0} can't be spent yet, since its {1} timelock has not expired.
+    #[display(doc_comments)]
+    ImmatureCoin(Outpoint, Timelock),
+}
+
+/// Coin control options defining which wallet outputs may or must be spent by a transaction.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct CoinControl {
+    /// Outputs which must be spent by the transaction.
+    pub include: BTreeSet<Outpoint>,
+    /// Addresses, all spendable outputs on which must be spent by the transaction.
+    pub include_addrs: BTreeSet<Address>,
+    /// Outputs which must not be spent by the transaction.
+    pub exclude: BTreeSet<Outpoint>,
+    /// Addresses, outputs on which must not be spent by the transaction.
+    pub exclude_addrs: BTreeSet<Address>,
+    /// Spend only the included outputs, not selecting any other outputs.
+    pub manual: bool,
+}
+
+impl CoinControl {
+    /// Coin control leaving the choice of the spent outputs to the coin selection.
+    pub fn auto() -> Self { default!() }
+
+    /// Coin control spending exactly the given outputs.
+    pub fn manual(include: impl IntoIterator<Item = Outpoint>) -> Self {
+        CoinControl {
+            include: include.into_iter().collect(),
+            manual: true,
+            ..default!()
+        }
+    }
+
+    pub fn is_included(&self, outpoint: Outpoint, addr: &Address) -> bool {
+        self.include.contains(&outpoint) || self.include_addrs.contains(addr)
+    }
+
+    pub fn is_excluded(&self, outpoint: Outpoint, addr: &Address) -> bool {
+        self.exclude.contains(&outpoint) || self.exclude_addrs.contains(addr)
+    }
+}
+
+#[derive(Clone, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum BumpError {
+    #[from]
+    #[display(inner)]
+    NonWallet(NonWalletItem),
+
+    /// transaction {0} is already mined and can't be replaced.
+    Mined(Txid),
+
+    /// transaction {0} is already replaced by transaction {1}.
+    Replaced(Txid, Txid),
+
+    /// transaction {0} spends output {1} which doesn't belong to the wallet and can't be
+    /// re-signed.
+    ForeignInput(Txid, Outpoint),
+
+    /// transaction output {0} has no beneficiary which can be re-created.
+    NonStandardOutput(Outpoint),
+
+    /// the requested fee rate {requested} must exceed the fee rate {original} of the transaction
+    /// being replaced.
+    FeeRateTooLow {
+        original: FeeRate,
+        requested: FeeRate,
+    },
+
+    #[from]
+    #[from(SelectionError)]
+    #[from(ConstructionError)]
+    #[display(inner)]
+    Payment(PaymentError),
+}
+
+#[derive(Clone, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum CpfpError {
+    #[from]
+    #[display(inner)]
+    NonWallet(NonWalletItem),
+
+    /// transaction output {0} is already confirmed and doesn't need to be accelerated.
+    Confirmed(Outpoint),
+
+    /// transaction output {0} is already spent.
+    Spent(Outpoint),
+
+    /// the requested fee rate {requested} must exceed the fee rate {parent} of the parent
+    /// transaction.
+    FeeRateTooLow { parent: FeeRate, requested: FeeRate },
+
+    #[from]
+    #[from(SelectionError)]
+    #[from(ConstructionError)]
+    #[display(inner)]
+    Payment(PaymentError),
+}
+
+/// Sequence number of the inputs which signal replaceability (BIP125) and allow lock time.
+pub const SEQ_NO_RBF: SeqNo = SeqNo::from_consensus_u32(0xFFFF_FFFD);
+/// Sequence number of the inputs which do not signal replaceability, but allow lock time.
+pub const SEQ_NO_FINAL_LOCK_TIME: SeqNo = SeqNo::from_consensus_u32(0xFFFF_FFFE);
+/// Sequence number of the inputs disabling both lock time and replaceability.
+pub const SEQ_NO_FINAL: SeqNo = SeqNo::from_consensus_u32(0xFFFF_FFFF);
+
+/// Lock time, sequence number and version options for a transaction which is being constructed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TxOptions {
+    /// Absolute lock time, which is either a block height or a UNIX timestamp.
+    pub lock_time: Option<LockTime>,
+    /// Set the lock time to the height of the current chain tip to discourage fee sniping. Ignored
+    /// if an explicit lock time is given.
+    pub anti_fee_sniping: bool,
+    /// Sequence number used for all the transaction inputs. Overrides `rbf` flag.
+    pub seq_no: Option<SeqNo>,
+    /// Signal replaceability of the transaction according to BIP125.
+    pub rbf: bool,
+    pub tx_version: TxVer,
+    /// Timelock of the wallet descriptor spending branch which will be used to sign the
+    /// transaction. The lock time or the sequence number of the inputs is set to satisfy the
+    /// timelock, and only the outputs for which a relative timelock has expired are selected.
+    pub timelock: Option<Timelock>,
+}
+
+impl Default for TxOptions {
+    fn default() -> Self {
+        TxOptions {
+            lock_time: None,
+            anti_fee_sniping: false,
+            seq_no: None,
+            rbf: true,
+            tx_version: TxVer::V2,
+            timelock: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TxOptionsError {
+    /// the wallet is not synchronized with the blockchain and the current chain tip is unknown.
+    UnknownTip,
+
+    /// lock time is set, but the sequence number {0:#010x} of the inputs disables it.
+    LockTimeDisabled(u32),
+
+    /// sequence number {0:#010x} sets a relative lock time, which requires transaction version 2.
+    RelativeLockTimeVersion(u32),
+
+    /// non-standard transaction version {0}.
+    NonStandardVersion(i32),
+
+    /// wallet descriptor has no spending branch with {0} timelock.
+    UnknownTimelock(Timelock),
+
+    /// {0} timelock can't be satisfied with the lock time or sequence number given in the
+    /// transaction options.
+    TimelockConflict(Timelock),
+}
+
+/// Changes made to the wallet by its synchronization with an indexer.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct UpdateInfo {
+    /// Number of the transactions which were added to the wallet or changed their status.
+    pub changed: usize,
+    /// Transactions rolled back by blockchain reorganizations.
+    pub reorged: BTreeSet<Txid>,
+}
+
+/// Fee paid by a transaction together with its estimated weight.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{fee} sats for {weight} WU")]
+pub struct FeeInfo {
+    pub fee: Sats,
+    /// Estimated weight of the signed transaction.
+    pub weight: u32,
+}
+
+impl FeeInfo {
+    #[inline]
+    pub fn vsize(&self) -> u32 { vsize(self.weight) }
+
+    #[inline]
+    pub fn fee_rate(&self) -> FeeRate { FeeRate::from_fee(self.fee, self.weight) }
+}
+
 pub struct AddrIter<'descr, K, D: Descriptor<K>> {
     generator: &'descr D,
     network: AddressNetwork,
@@ -62,7 +295,8 @@ impl<'descr, K, D: Descriptor<K>> Iterator for AddrIter<'descr, K, D> {
     type Item = DerivedAddr;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let addr = self.generator.derive_address(self.network, self.keychain, self.index).ok()?;
+        let script = self.generator.derive(self.keychain, self.index).to_script_pubkey();
+        let addr = address_with(&script, self.network).ok()?;
         let derived = DerivedAddr::new(addr, self.keychain, self.index);
         self.index.wrapping_inc_assign();
         Some(derived)
@@ -91,6 +325,10 @@ where
     #[getter(as_copy)]
     network: Network,
     layer2: L2,
+    /// Number of consecutive unused addresses after which the address discovery on a keychain
+    /// stops. Keychains which are not present use [`DEFAULT_GAP_LIMIT`].
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    gap_limits: BTreeMap<Keychain, u32>,
     #[cfg_attr(feature = "serde", serde(skip))]
     _phantom: PhantomData<K>,
 }
@@ -101,6 +339,7 @@ impl<K, D: Descriptor<K>> WalletDescr<K, D, NoLayer2> {
             generator: descr,
             network,
             layer2: None,
+            gap_limits: none!(),
             _phantom: PhantomData,
         }
     }
@@ -112,11 +351,31 @@ impl<K, D: Descriptor<K>, L2: Layer2Descriptor> WalletDescr<K, D, L2> {
             generator: descr,
             network,
             layer2,
+            gap_limits: none!(),
             _phantom: PhantomData,
         }
     }
 
-    pub fn addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<K, D> {
+    /// Returns the gap limit used for the address discovery on the keychain.
+    pub fn gap_limit(&self, keychain: impl Into<Keychain>) -> u32 {
+        self.gap_limits.get(&keychain.into()).copied().unwrap_or(DEFAULT_GAP_LIMIT)
+    }
+
+    /// Sets the gap limit used for the address discovery on the keychain, returning the previous
+    /// value. The gap limit can't be less than one.
+    pub fn set_gap_limit(&mut self, keychain: impl Into<Keychain>, gap_limit: u32) -> u32 {
+        let keychain = keychain.into();
+        let gap_limit = gap_limit.max(1);
+        let prev = self.gap_limit(keychain);
+        if gap_limit == DEFAULT_GAP_LIMIT {
+            self.gap_limits.remove(&keychain);
+        } else {
+            self.gap_limits.insert(keychain, gap_limit);
+        }
+        prev
+    }
+
+    pub fn addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<'_, K, D> {
         AddrIter {
             generator: &self.generator,
             network: self.network.into(),
@@ -155,6 +414,15 @@ pub struct WalletData<L2: Layer2Data> {
     pub addr_annotations: BTreeMap<Address, String>,
     pub layer2_annotations: L2,
     pub last_used: BTreeMap<Keychain, NormalIndex>,
+    /// Wallet outputs which are excluded from spending.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frozen_txos: BTreeSet<Outpoint>,
+    /// Wallet addresses, outputs on which are excluded from spending.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frozen_addrs: BTreeSet<Address>,
+    /// Spending policy the wallet descriptor was compiled from, if any.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub policy: Option<WalletPolicy>,
 }
 
 #[cfg_attr(
@@ -209,6 +477,14 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
         indexer.update::<K, D, L2>(descriptor, self)
     }
 
+    pub fn reorg<I: Indexer, K, D: Descriptor<K>, L2: Layer2<Cache = L2C>>(
+        &mut self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        indexer: &I,
+    ) -> MayError<BTreeSet<Txid>, Vec<I::Error>> {
+        indexer.reorg::<K, D, L2>(descriptor, self)
+    }
+
     pub fn addresses_on(&self, keychain: Keychain) -> &BTreeSet<WalletAddr> {
         self.addr.get(&keychain).unwrap_or_else(|| {
             panic!("keychain #{keychain} is not supported by the wallet descriptor")
@@ -222,30 +498,40 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
             .get(outpoint.vout.into_usize())
             .ok_or(NonWalletItem::NoOutput(outpoint.txid, outpoint.vout))?;
         let terminal = debit.derived_addr().ok_or(NonWalletItem::NonWalletUtxo(outpoint))?.terminal;
-        // TODO: Check whether TXO is spend
         Ok(WalletUtxo {
             outpoint,
             value: debit.value,
             terminal,
             status: tx.status,
+            state: self.txo_status(tx, debit),
         })
     }
 
+    /// Returns all wallet outputs created by the mined and mempool transactions, including the
+    /// ones which are already spent, with their [`UtxoStatus`].
     pub fn all_utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ {
-        self.utxo.iter().map(|outpoint| {
-            let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
-            let debit = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
-            let terminal =
-                debit.derived_addr().expect("UTXO doesn't belong to the wallet").terminal;
-            // TODO: Check whether TXO is spend
-            WalletUtxo {
-                outpoint: *outpoint,
-                value: debit.value,
-                terminal,
-                status: tx.status,
-            }
+        self.tx.values().filter(|tx| tx.is_live()).flat_map(move |tx| {
+            tx.outputs.iter().filter_map(move |debit| {
+                Some(WalletUtxo {
+                    outpoint: debit.outpoint,
+                    value: debit.value,
+                    terminal: debit.derived_addr()?.terminal,
+                    status: tx.status,
+                    state: self.txo_status(tx, debit),
+                })
+            })
         })
     }
+
+    /// Returns wallet outputs which can be spent, i.e. which are not spent by any of mined or
+    /// unconfirmed transactions.
+    pub fn utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ {
+        self.all_utxos().filter(WalletUtxo::is_spendable)
+    }
+
+    pub(crate) fn txo_status(&self, tx: &WalletTx, debit: &TxDebit) -> UtxoStatus {
+        UtxoStatus::with(tx.status, debit.spender().map(|spend| spend.status))
+    }
 }
 
 #[cfg(feature = "fs")]
@@ -358,6 +644,8 @@ where Self: Save
         }
     }
 
+    pub fn name(&self) -> &str { &self.data.name }
+
     pub fn set_name(&mut self, name: String) {
         self.data.name = name;
         self.set_dirty();
@@ -372,14 +660,71 @@ where Self: Save
         res
     }
 
-    pub fn update<I: Indexer>(&mut self, indexer: &I) -> MayError<(), Vec<I::Error>> {
-        // Not yet implemented:
-        // self.cache.update::<B, K, D, L2>(&self.descr, &self.indexer)
-
-        WalletCache::with::<_, K, _, L2>(&self.descr, indexer).map(|cache| {
-            self.cache = cache;
+    /// Synchronizes wallet cache with the indexer, first rolling back transactions affected by
+    /// blockchain reorganizations.
+    ///
+    /// If the reorganizations can't be detected, the synchronization is not performed, since
+    /// the cache may contain transactions from a stale chain; the returned errors are the ones
+    /// of the reorganization check then.
+    pub fn update<I: Indexer>(&mut self, indexer: &I) -> MayError<UpdateInfo, Vec<I::Error>> {
+        let (reorged, reorg_errors) = self.cache.reorg::<I, K, D, L2>(&self.descr, indexer).split();
+        if !reorged.is_empty() {
             self.set_dirty();
-        })
+        }
+        if let Some(errors) = reorg_errors.filter(|errors| !errors.is_empty()) {
+            return MayError::err(
+                UpdateInfo {
+                    changed: 0,
+                    reorged,
+                },
+                errors,
+            );
+        }
+        let (changed, update_errors) =
+            self.cache.update::<I, K, D, L2>(&self.descr, indexer).split();
+        self.set_dirty();
+        let info = UpdateInfo { changed, reorged };
+        match update_errors.filter(|errors| !errors.is_empty()) {
+            Some(errors) => MayError::err(info, errors),
+            None => MayError::ok(info),
+        }
+    }
+
+    /// Discards the wallet cache and re-discovers wallet addresses and transactions from scratch.
+    ///
+    /// If `gap_limit` is given, it is used for all keychains whose own gap limit is lower,
+    /// without changing the gap limits stored with the wallet.
+    ///
+    /// The cache is replaced only if the rescan has completed without errors; otherwise the
+    /// errors are returned and the existing cache is kept intact.
+    pub fn rescan<I: Indexer>(
+        &mut self,
+        indexer: &I,
+        gap_limit: Option<u32>,
+    ) -> MayError<(), Vec<I::Error>> {
+        let gap_limits = self.descr.gap_limits.clone();
+        if let Some(gap_limit) = gap_limit.map(|limit| limit.max(1)) {
+            for keychain in self.descr.keychains() {
+                if self.descr.gap_limit(keychain) < gap_limit {
+                    self.descr.gap_limits.insert(keychain, gap_limit);
+                }
+            }
+        }
+        let (mut cache, errors) = WalletCache::with::<I, K, D, L2>(&self.descr, indexer).split();
+        self.descr.gap_limits = gap_limits;
+        if let Some(errors) = errors {
+            return MayError::err((), errors);
+        }
+
+        // Keep the time the wallet has first seen the transactions
+        for (txid, tx) in &mut cache.tx {
+            if let Some(known) = self.cache.tx.get(txid) {
+                tx.first_seen = known.first_seen.or(tx.first_seen);
+            }
+        }
+        self.cache = cache;
+        self.set_dirty();
+        MayError::ok(())
     }
 
     pub fn to_deriver(&self) -> D
@@ -417,14 +762,22 @@ where Self: Save
             .addr
     }
 
+    /// Balance of all spendable outputs, including the unconfirmed ones.
     pub fn balance(&self) -> Sats { self.cache.coins().map(|utxo| utxo.amount).sum::<Sats>() }
 
+    /// Balance of the spendable outputs created by mined transactions.
+    pub fn confirmed_balance(&self) -> Sats {
+        self.utxos().filter(|utxo| utxo.state.is_confirmed()).map(|utxo| utxo.value).sum::<Sats>()
+    }
+
     #[inline]
     pub fn transactions(&self) -> &BTreeMap<Txid, WalletTx> { &self.cache.tx }
 
-    #[inline]
     pub fn coins(&self) -> impl Iterator<Item = CoinRow<<L2::Cache as Layer2Cache>::Coin>> + '_ {
-        self.cache.coins()
+        self.cache.coins().map(|mut row| {
+            row.frozen = self.is_frozen(row.outpoint);
+            row
+        })
     }
 
     pub fn address_coins(
@@ -446,25 +799,940 @@ where Self: Save
         self.cache.history()
     }
 
+    /// Returns wallet history with transaction labels and, if a price table is provided, fiat
+    /// values, ready for export.
+    #[cfg(feature = "serde")]
+    pub fn history_records(&self, prices: Option<&PriceTable>) -> Vec<HistoryRecord> {
+        self.history()
+            .map(|row| {
+                let label = self.data.tx_annotations.get(&row.txid).cloned();
+                HistoryRecord::with(row, label, prices)
+            })
+            .collect()
+    }
+
     pub fn all_utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ { self.cache.all_utxos() }
 
-    pub fn coinselect<'a>(
-        &'a self,
-        up_to: Sats,
-        selector: impl Fn(&WalletUtxo) -> bool + 'a,
-    ) -> impl Iterator<Item = Outpoint> + '_ {
-        let mut selected = Sats::ZERO;
-        self.all_utxos()
-            .filter(selector)
-            .take_while(move |utxo| {
-                if selected <= up_to {
-                    selected.add_assign(utxo.value);
-                    true
+    pub fn utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ { self.cache.utxos() }
+
+    /// Returns address of a wallet output.
+    pub fn utxo_address(&self, outpoint: Outpoint) -> Result<Address, NonWalletItem> {
+        let tx =
+            self.cache.tx.get(&outpoint.txid).ok_or(NonWalletItem::NonWalletTx(outpoint.txid))?;
+        let debit = tx
+            .outputs
+            .get(outpoint.vout_usize())
+            .ok_or(NonWalletItem::NoOutput(outpoint.txid, outpoint.vout))?;
+        Ok(debit.derived_addr().ok_or(NonWalletItem::NonWalletUtxo(outpoint))?.addr)
+    }
+
+    /// Checks whether a wallet output is frozen, either directly or by its address.
+    pub fn is_frozen(&self, outpoint: Outpoint) -> bool {
+        self.data.frozen_txos.contains(&outpoint)
+            || self
+                .utxo_address(outpoint)
+                .map(|addr| self.data.frozen_addrs.contains(&addr))
+                .unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn frozen_txos(&self) -> &BTreeSet<Outpoint> { &self.data.frozen_txos }
+
+    #[inline]
+    pub fn frozen_addrs(&self) -> &BTreeSet<Address> { &self.data.frozen_addrs }
+
+    /// Freezes a wallet output, excluding it from coin selection. Returns whether the output
+    /// was not frozen before.
+    pub fn freeze(&mut self, outpoint: Outpoint) -> Result<bool, NonWalletItem> {
+        self.cache.utxo(outpoint)?;
+        let res = self.data.frozen_txos.insert(outpoint);
+        self.set_dirty();
+        Ok(res)
+    }
+
+    /// Unfreezes a previously frozen wallet output. Returns whether the output was frozen.
+    pub fn unfreeze(&mut self, outpoint: Outpoint) -> bool {
+        let res = self.data.frozen_txos.remove(&outpoint);
+        self.set_dirty();
+        res
+    }
+
+    /// Freezes all outputs on a given address, excluding them from coin selection. Returns
+    /// whether the address was not frozen before.
+    pub fn freeze_addr(&mut self, addr: Address) -> bool {
+        let res = self.data.frozen_addrs.insert(addr);
+        self.set_dirty();
+        res
+    }
+
+    /// Unfreezes a previously frozen address. Returns whether the address was frozen.
+    pub fn unfreeze_addr(&mut self, addr: &Address) -> bool {
+        let res = self.data.frozen_addrs.remove(addr);
+        self.set_dirty();
+        res
+    }
+
+    /// Returns spending policy the wallet descriptor was compiled from, if any.
+    pub fn policy(&self) -> Option<&WalletPolicy> { self.data.policy.as_ref() }
+
+    /// Stores spending policy the wallet descriptor was compiled from.
+    pub fn set_policy(&mut self, policy: WalletPolicy) {
+        self.data.policy = Some(policy);
+        self.set_dirty();
+    }
+
+    /// Returns label assigned to a wallet item, if any.
+    pub fn label(&self, item: impl Into<LabelRef>) -> Option<&str> {
+        match &item.into() {
+            LabelRef::Tx(txid) => self.data.tx_annotations.get(txid),
+            LabelRef::Addr(addr) => self.data.addr_annotations.get(addr),
+            LabelRef::Input(inpoint) => self.data.txin_annotations.get(inpoint),
+            LabelRef::Output(outpoint) => self.data.txout_annotations.get(outpoint),
+        }
+        .map(String::as_str)
+    }
+
+    /// Checks whether a label of any of the given items contains the provided text, ignoring the
+    /// case.
+    pub fn is_labelled_with(
+        &self,
+        items: impl IntoIterator<Item = impl Into<LabelRef>>,
+        text: &str,
+    ) -> bool {
+        let text = text.to_lowercase();
+        items
+            .into_iter()
+            .filter_map(|item| self.label(item))
+            .any(|label| label.to_lowercase().contains(&text))
+    }
+
+    /// Checks whether a label of a wallet output, its address or the transaction which has
+    /// created it contains the provided text, ignoring the case.
+    pub fn is_coin_labelled_with<L2C: Layer2Coin>(&self, row: &CoinRow<L2C>, text: &str) -> bool {
+        self.is_labelled_with(
+            [
+                LabelRef::Output(row.outpoint),
+                LabelRef::Addr(row.address.addr),
+                LabelRef::Tx(row.outpoint.txid),
+            ],
+            text,
+        )
+    }
+
+    /// Checks whether a label of a transaction or any address it involves contains the provided
+    /// text, ignoring the case.
+    pub fn is_tx_labelled_with<L2T: Layer2Tx>(&self, row: &TxRow<L2T>, text: &str) -> bool {
+        let addrs = row.own.iter().map(|(derived, _)| derived.addr).chain(
+            row.counterparties.iter().filter_map(|(cp, _)| match cp {
+                Counterparty::Address(addr) => Some(*addr),
+                _ => None,
+            }),
+        );
+        self.is_labelled_with(
+            Some(LabelRef::Tx(row.txid)).into_iter().chain(addrs.map(LabelRef::Addr)),
+            text,
+        )
+    }
+
+    /// Assigns label to a wallet item, returning the previous label, if any.
+    pub fn set_label(&mut self, reference: LabelRef, label: impl Into<String>) -> Option<String> {
+        let label = label.into();
+        let res = match reference {
+            LabelRef::Tx(txid) => self.data.tx_annotations.insert(txid, label),
+            LabelRef::Addr(addr) => self.data.addr_annotations.insert(addr, label),
+            LabelRef::Input(inpoint) => self.data.txin_annotations.insert(inpoint, label),
+            LabelRef::Output(outpoint) => self.data.txout_annotations.insert(outpoint, label),
+        };
+        self.set_dirty();
+        res
+    }
+
+    /// Removes label from a wallet item, returning the removed label, if any.
+    pub fn remove_label(&mut self, reference: &LabelRef) -> Option<String> {
+        let res = match reference {
+            LabelRef::Tx(txid) => self.data.tx_annotations.remove(txid),
+            LabelRef::Addr(addr) => self.data.addr_annotations.remove(addr),
+            LabelRef::Input(inpoint) => self.data.txin_annotations.remove(inpoint),
+            LabelRef::Output(outpoint) => self.data.txout_annotations.remove(outpoint),
+        };
+        self.set_dirty();
+        res
+    }
+
+    /// Iterates over all labelled wallet items.
+    pub fn labels(&self) -> impl Iterator<Item = (LabelRef, &str)> + '_ {
+        let data = &self.data;
+        data.tx_annotations
+            .iter()
+            .map(|(txid, label)| (LabelRef::Tx(*txid), label))
+            .chain(data.addr_annotations.iter().map(|(addr, label)| (LabelRef::Addr(*addr), label)))
+            .chain(
+                data.txin_annotations
+                    .iter()
+                    .map(|(inpoint, label)| (LabelRef::Input(*inpoint), label)),
+            )
+            .chain(
+                data.txout_annotations
+                    .iter()
+                    .map(|(outpoint, label)| (LabelRef::Output(*outpoint), label)),
+            )
+            .map(|(reference, label)| (reference, label.as_str()))
+    }
+
+    /// Exports wallet labels as BIP-329 records. Frozen outputs are exported as unspendable.
+    #[cfg(feature = "serde")]
+    pub fn export_labels(&self) -> Vec<Bip329Record> {
+        let mut records = self
+            .labels()
+            .map(|(reference, label)| Bip329Record {
+                ty: reference.label_type(),
+                spendable: match reference {
+                    LabelRef::Output(outpoint) if self.data.frozen_txos.contains(&outpoint) => {
+                        Some(false)
+                    }
+                    _ => None,
+                },
+                reference: reference.to_string(),
+                label: Some(label.to_owned()),
+                origin: None,
+            })
+            .collect::<Vec<_>>();
+        records.extend(
+            self.data
+                .frozen_txos
+                .iter()
+                .filter(|outpoint| !self.data.txout_annotations.contains_key(outpoint))
+                .map(|outpoint| Bip329Record {
+                    ty: LabelType::Output,
+                    reference: outpoint.to_string(),
+                    label: None,
+                    origin: None,
+                    spendable: Some(false),
+                }),
+        );
+        records
+    }
+
+    /// Imports labels from BIP-329 records. Records referencing items not supported by the
+    /// wallet, like public keys, are skipped. Outputs marked as unspendable are frozen.
+    #[cfg(feature = "serde")]
+    pub fn import_labels(
+        &mut self,
+        records: impl IntoIterator<Item = Bip329Record>,
+    ) -> LabelImport {
+        let mut stats = LabelImport::default();
+        for record in records {
+            let Ok(reference) = LabelRef::with(record.ty, &record.reference) else {
+                stats.skipped += 1;
+                continue;
+            };
+            if let (LabelRef::Output(outpoint), Some(spendable)) = (&reference, record.spendable) {
+                if spendable {
+                    self.data.frozen_txos.remove(outpoint);
                 } else {
-                    false
+                    self.data.frozen_txos.insert(*outpoint);
                 }
+            }
+            match record.label {
+                Some(label) if !label.is_empty() => {
+                    self.set_label(reference, label);
+                }
+                _ => {}
+            }
+            stats.imported += 1;
+        }
+        self.set_dirty();
+        stats
+    }
+}
+
+impl<K, D: PsbtDescriptor<K>, L2: Layer2> Wallet<K, D, L2>
+where Self: Save
+{
+    /// Constructs coin selection candidate, estimating weight of the input spending the output
+    /// with the wallet descriptor.
+    fn candidate(&self, utxo: WalletUtxo) -> Candidate {
+        Candidate {
+            utxo,
+            weight: self.descr.generator.input_weight(utxo.terminal),
+        }
+    }
+
+    /// Returns spendable wallet outputs matching the filter as coin selection candidates, with
+    /// the input weights estimated from the wallet descriptor. Frozen outputs are never
+    /// returned.
+    pub fn coin_candidates(&self, filter: impl Fn(&WalletUtxo) -> bool) -> Vec<Candidate> {
+        self.utxos()
+            .filter(|utxo| !self.is_frozen(utxo.outpoint))
+            .filter(filter)
+            .map(|utxo| self.candidate(utxo))
+            .collect()
+    }
+
+    /// Resolves the outputs which must be spent according to the coin control options.
+    ///
+    /// Frozen and excluded outputs on the included addresses are skipped, while explicitly
+    /// included outputs which are frozen or excluded fail the payment.
+    pub fn coin_control_inputs(
+        &self,
+        control: &CoinControl,
+    ) -> Result<Vec<Candidate>, PaymentError> {
+        let mut outpoints = control.include.clone();
+        for utxo in self.utxos() {
+            let addr = self.utxo_address(utxo.outpoint)?;
+            if control.include_addrs.contains(&addr)
+                && !self.is_frozen(utxo.outpoint)
+                && !control.is_excluded(utxo.outpoint, &addr)
+            {
+                outpoints.insert(utxo.outpoint);
+            }
+        }
+        let mut inputs = Vec::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            let utxo = self.cache.utxo(outpoint)?;
+            if !utxo.is_spendable() {
+                return Err(PaymentError::SpentCoin(outpoint));
+            }
+            if self.is_frozen(outpoint) {
+                return Err(PaymentError::FrozenCoin(outpoint));
+            }
+            if control.is_excluded(outpoint, &self.utxo_address(outpoint)?) {
+                return Err(PaymentError::ExcludedCoin(outpoint));
+            }
+            inputs.push(self.candidate(utxo));
+        }
+        Ok(inputs)
+    }
+
+    /// Constructs coin selection parameters for a given payment amount and fee rate, using the
+    /// wallet descriptor for the change output.
+    pub fn selection_params(&self, target: Sats, fee_rate: FeeRate) -> SelectionParams {
+        let descriptor = &self.descr.generator;
+        let mut params = SelectionParams::with(target, fee_rate).change_descriptor(descriptor);
+        params.change_spend_weight =
+            descriptor.input_weight(Terminal::new(Keychain::INNER, NormalIndex::ZERO));
+        params
+    }
+
+    /// Selects spendable wallet outputs matching the filter using the provided coin selection
+    /// algorithm.
+    pub fn coinselect(
+        &self,
+        selector: &mut impl CoinSelector,
+        params: &SelectionParams,
+        filter: impl Fn(&WalletUtxo) -> bool,
+    ) -> Result<Selection, SelectionError> {
+        selector.select(&self.coin_candidates(filter), params)
+    }
+
+    /// Estimates weight of the fully signed transaction which will be extracted from the PSBT
+    /// spending the wallet outputs.
+    pub fn estimate_weight(&self, psbt: &Psbt) -> u32 {
+        let descriptor = &self.descr.generator;
+        let inputs = psbt
+            .inputs()
+            .map(|input| {
+                let terminal = self
+                    .cache
+                    .utxo(input.previous_outpoint)
+                    .map(|utxo| utxo.terminal)
+                    .unwrap_or(Terminal::new(Keychain::OUTER, NormalIndex::ZERO));
+                descriptor.input_weight(terminal)
             })
-            .map(|utxo| utxo.outpoint)
+            .sum::<u32>();
+        let outputs = psbt.outputs().map(|output| txout_weight(&output.script)).sum::<u32>();
+        tx_overhead_weight(descriptor, psbt.inputs().count(), psbt.outputs().count())
+            + inputs
+            + outputs
+    }
+
+    /// Returns median time of the last known block, falling back to the block timestamp if the
+    /// median time is not known.
+    fn tip_median_time(&self) -> u32 {
+        let tip = self.cache.last_block;
+        self.cache
+            .headers
+            .iter()
+            .rev()
+            .find(|info| info.mined.block_hash == tip.block_hash)
+            .map(|info| info.mediantime)
+            .filter(|time| *time > 0)
+            .unwrap_or(tip.time as u32)
+    }
+
+    /// Checks whether a transaction with a given lock time can be mined in the block following the
+    /// cached chain tip.
+    pub fn is_lock_time_reached(&self, lock_time: LockTime) -> bool {
+        let value = lock_time.to_consensus_u32();
+        if lock_time.is_height_based() {
+            value <= self.cache.last_block.height.get()
+        } else {
+            value < self.tip_median_time()
+        }
+    }
+
+    /// Checks whether a wallet output can be spent by a transaction input satisfying the
+    /// timelock: relative timelocks require the output to be mined at least the given number
+    /// of blocks or time units ago, while absolute timelocks depend only on the transaction lock
+    /// time and are always satisfied.
+    pub fn is_timelock_expired(&self, utxo: &WalletUtxo, timelock: Timelock) -> bool {
+        let Timelock::Older(lock) = timelock else {
+            return true;
+        };
+        let TxStatus::Mined(mined) = utxo.status else {
+            return false;
+        };
+        let value = lock & 0xFFFF;
+        if timelock.is_height_based() {
+            // The output can be spent in the block at the height of `mined.height + value`.
+            mined.height.get() + value <= self.cache.last_block.height.get() + 1
+        } else {
+            // BIP-68 counts time from the median time of the block preceding the one with the
+            // output, which never exceeds the block time used here.
+            mined.time + (value as u64) * 512 <= self.tip_median_time() as u64
+        }
+    }
+
+    /// Constructs PSBT construction parameters for a given fee and transaction options,
+    /// validating the consistency of the options.
+    ///
+    /// Lock times which are not reached yet are accepted, since a PSBT may be signed in advance
+    /// and published later; use [`Self::is_lock_time_reached`] to check whether the transaction
+    /// can be mined right away.
+    ///
+    /// The transaction version is not a part of the returned parameters; it is set by the PSBT
+    /// construction methods taking [`TxOptions`], and has to be set by the caller if the
+    /// parameters are used for a PSBT constructed in another way.
+    pub fn tx_params(&self, fee: Sats, options: TxOptions) -> Result<TxParams, TxOptionsError> {
+        let version = options.tx_version.to_consensus_i32();
+        if !(1..=2).contains(&version) {
+            return Err(TxOptionsError::NonStandardVersion(version));
+        }
+
+        let tip = self.cache.last_block;
+        let lock_time = match options.lock_time {
+            Some(lock_time) => Some(lock_time),
+            None if options.anti_fee_sniping => {
+                if tip == MiningInfo::genesis() {
+                    return Err(TxOptionsError::UnknownTip);
+                }
+                Some(LockTime::from_height(tip.height.get()).ok_or(TxOptionsError::UnknownTip)?)
+            }
+            None => None,
+        }
+        .filter(|lock_time| *lock_time != LockTime::ZERO);
+
+        let mut seq_no = match options.seq_no {
+            Some(seq_no) => seq_no,
+            None if options.rbf => SEQ_NO_RBF,
+            None => SEQ_NO_FINAL_LOCK_TIME,
+        };
+
+        let mut lock_time = lock_time;
+        if let Some(timelock) = options.timelock {
+            if !self.descr.generator.timelocks().contains(&timelock) {
+                return Err(TxOptionsError::UnknownTimelock(timelock));
+            }
+            let conflict = TxOptionsError::TimelockConflict(timelock);
+            match timelock {
+                Timelock::After(lock) => {
+                    let required = LockTime::from_consensus_u32(lock);
+                    lock_time = match lock_time {
+                        Some(lock_time)
+                            if lock_time.is_height_based() != required.is_height_based() =>
+                        {
+                            return Err(conflict)
+                        }
+                        // Anti-fee-sniping lock time is increased up to the required one.
+                        Some(lock_time) if options.lock_time.is_none() => {
+                            Some(cmp::max_by_key(lock_time, required, |lock_time| {
+                                lock_time.to_consensus_u32()
+                            }))
+                        }
+                        Some(lock_time) if lock_time.to_consensus_u32() < lock => {
+                            return Err(conflict)
+                        }
+                        Some(lock_time) => Some(lock_time),
+                        None => Some(required),
+                    };
+                }
+                Timelock::Older(lock) => {
+                    const DISABLE_FLAG: u32 = 1 << 31;
+                    const TYPE_FLAG: u32 = Timelock::RELATIVE_TIME_FLAG;
+                    match options.seq_no.map(|seq_no| seq_no.to_consensus_u32()) {
+                        Some(seq)
+                            if seq & DISABLE_FLAG != 0
+                                || seq & TYPE_FLAG != lock & TYPE_FLAG
+                                || seq & 0xFFFF < lock & 0xFFFF =>
+                        {
+                            return Err(conflict)
+                        }
+                        Some(_) => {}
+                        None => seq_no = SeqNo::from_consensus_u32(lock),
+                    }
+                }
+            }
+        }
+
+        let seq = seq_no.to_consensus_u32();
+        if lock_time.is_some() && seq_no == SEQ_NO_FINAL {
+            return Err(TxOptionsError::LockTimeDisabled(seq));
+        }
+        // Relative lock time is enabled when the disable flag (bit 31) is not set
+        if seq & (1 << 31) == 0 && seq & 0xFFFF != 0 && version < 2 {
+            return Err(TxOptionsError::RelativeLockTimeVersion(seq));
+        }
+
+        let mut params = TxParams::with(fee);
+        params.lock_time = lock_time;
+        params.seq_no = seq_no;
+        Ok(params)
+    }
+
+    /// Constructs PSBT paying to the beneficiaries with the fee computed from the fee rate and
+    /// the estimated weight of the final transaction.
+    ///
+    /// The outputs included by the coin control are always spent; unless the coin control is
+    /// manual, other inputs are selected with the provided coin selection algorithm from the
+    /// spendable wallet outputs which are not excluded or frozen. The selection is repeated with
+    /// an increased target if the selected outputs do not cover the fee for the transaction. If
+    /// any of the beneficiaries has [`psbt::Payment::Max`] amount, all the outputs which are
+    /// worth spending at the given fee rate are used instead.
+    ///
+    /// Lock time, sequence numbers and version of the transaction are taken from the options,
+    /// which are validated with [`Self::tx_params`].
+    pub fn construct_psbt_fee_rate<'b>(
+        &mut self,
+        beneficiaries: impl IntoIterator<Item = &'b Beneficiary>,
+        fee_rate: FeeRate,
+        selector: &mut impl CoinSelector,
+        control: &CoinControl,
+        options: TxOptions,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), PaymentError> {
+        self.construct_psbt_controlled(
+            beneficiaries,
+            fee_rate,
+            |weight| fee_rate.fee_for_weight(weight),
+            selector,
+            control,
+            options,
+        )
+    }
+
+    /// Constructs PSBT paying to the beneficiaries with a fixed absolute fee.
+    ///
+    /// The inputs are chosen in the same way as by [`Self::construct_psbt_fee_rate`].
+    pub fn construct_psbt_fee<'b>(
+        &mut self,
+        beneficiaries: impl IntoIterator<Item = &'b Beneficiary>,
+        fee: Sats,
+        selector: &mut impl CoinSelector,
+        control: &CoinControl,
+        options: TxOptions,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), PaymentError> {
+        self.construct_psbt_controlled(
+            beneficiaries,
+            FeeRate::ZERO,
+            |_| fee,
+            selector,
+            control,
+            options,
+        )
+    }
+
+    fn construct_psbt_controlled<'b>(
+        &mut self,
+        beneficiaries: impl IntoIterator<Item = &'b Beneficiary>,
+        fee_rate: FeeRate,
+        min_fee: impl Fn(u32) -> Sats,
+        selector: &mut impl CoinSelector,
+        control: &CoinControl,
+        options: TxOptions,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), PaymentError> {
+        let params = self.tx_params(Sats::ZERO, options)?;
+        let beneficiaries = beneficiaries.into_iter().collect::<Vec<_>>();
+        let inputs = self.coin_control_inputs(control)?;
+        if let Some(timelock) = options.timelock {
+            if let Some(input) =
+                inputs.iter().find(|c| !self.is_timelock_expired(&c.utxo, timelock))
+            {
+                return Err(PaymentError::ImmatureCoin(input.outpoint(), timelock));
+            }
+        }
+        // Included outputs are already spent as fixed inputs
+        let candidates = self.coin_candidates(|utxo| {
+            let Ok(addr) = self.utxo_address(utxo.outpoint) else {
+                return false;
+            };
+            !control.manual
+                && !control.is_included(utxo.outpoint, &addr)
+                && !control.is_excluded(utxo.outpoint, &addr)
+                && options.timelock.map_or(true, |lock| self.is_timelock_expired(utxo, lock))
+        });
+        let (mut psbt, meta, fee) = self.fund_psbt(
+            &beneficiaries,
+            &[],
+            inputs,
+            candidates,
+            fee_rate,
+            min_fee,
+            selector,
+            params,
+        )?;
+        psbt.tx_version = options.tx_version;
+        Ok((psbt, meta, fee))
+    }
+
+    /// Constructs PSBT sending all spendable wallet outputs which are not frozen to a single
+    /// address (sweep), with the fee computed from the fee rate.
+    ///
+    /// Used to move funds controlled by some external descriptor into another wallet: the
+    /// descriptor is wrapped into a temporary wallet, which is synced with the indexer, and the
+    /// address is provided by the receiving wallet.
+    pub fn construct_sweep(
+        &mut self,
+        address: Address,
+        fee_rate: FeeRate,
+        options: TxOptions,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), PaymentError> {
+        let beneficiary = Beneficiary::with_max(address);
+        self.construct_psbt_fee_rate(
+            [&beneficiary],
+            fee_rate,
+            &mut LargestFirst,
+            &CoinControl::auto(),
+            options,
+        )
+    }
+
+    /// Constructs PSBT replacing an unconfirmed wallet transaction with a new one paying a
+    /// higher fee rate (RBF).
+    ///
+    /// The new transaction spends all the inputs of the original one and pays the same amounts
+    /// to the same beneficiaries, including outputs without an address like `OP_RETURN`; the
+    /// change output is re-created with the reduced amount. If the original inputs do not cover
+    /// the increased fee, additional inputs are selected from the spendable wallet outputs, except
+    /// the ones created by the original transaction and its descendants, using the provided coin
+    /// selection algorithm. All inputs of the new transaction signal replaceability.
+    ///
+    /// The fee of the new transaction satisfies BIP125 rules: it exceeds the sum of fees paid by
+    /// the original transaction and all its known descendants at least by the minimal relay fee
+    /// for the size of the new transaction, and its fee rate is higher than the one of the
+    /// original transaction.
+    pub fn bump_fee(
+        &mut self,
+        txid: Txid,
+        fee_rate: FeeRate,
+        selector: &mut impl CoinSelector,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), BumpError> {
+        let tx = self.cache.tx.get(&txid).ok_or(NonWalletItem::NonWalletTx(txid))?;
+        if tx.status.is_mined() {
+            return Err(BumpError::Mined(txid));
+        }
+        if let Some(by) = tx.replaced_by {
+            return Err(BumpError::Replaced(txid, by));
+        }
+        let original = FeeRate::from_fee(tx.fee, tx.weight);
+        if fee_rate.sat_per_vb() <= original.sat_per_vb() {
+            return Err(BumpError::FeeRateTooLow {
+                original,
+                requested: fee_rate,
+            });
+        }
+
+        let mut inputs = Vec::with_capacity(tx.inputs.len());
+        for credit in &tx.inputs {
+            if credit.is_external() {
+                return Err(BumpError::ForeignInput(txid, credit.outpoint));
+            }
+            let utxo = self.cache.utxo(credit.outpoint)?;
+            inputs.push(self.candidate(utxo));
+        }
+
+        let mut beneficiaries = Vec::with_capacity(tx.outputs.len());
+        // Outputs without an address, like `OP_RETURN`, are re-created as is.
+        let mut scripts = vec![];
+        for debit in &tx.outputs {
+            let address = match &debit.beneficiary {
+                Party::Wallet(derived) if derived.terminal.keychain == Keychain::INNER => continue,
+                Party::Wallet(derived) => derived.addr,
+                Party::Counterparty(address) => *address,
+                Party::Unknown(script) => {
+                    scripts.push(TxOut::new(script.clone(), debit.value));
+                    continue;
+                }
+                Party::Subsidy => return Err(BumpError::NonStandardOutput(debit.outpoint)),
+            };
+            beneficiaries.push(Beneficiary::new(address, Payment::Fixed(debit.value)));
+        }
+
+        // BIP125 requires the replacement to pay for all the transactions it evicts from the
+        // mempool, which include all the descendants of the original transaction.
+        let mut replaced = self.descendants(txid);
+        let replaced_fee = replaced.iter().fold(tx.fee, |fee, txid| {
+            fee.saturating_add(self.cache.tx.get(txid).map(|tx| tx.fee).unwrap_or_default())
+        });
+        replaced.insert(txid);
+
+        let mut params = TxParams::with(Sats::ZERO);
+        params.seq_no = SEQ_NO_RBF;
+        params.lock_time = Some(tx.locktime).filter(|lock_time| *lock_time != LockTime::ZERO);
+        params.change_keychain = Keychain::INNER;
+
+        let beneficiaries = beneficiaries.iter().collect::<Vec<_>>();
+        let outpoints = inputs.iter().map(Candidate::outpoint).collect::<BTreeSet<_>>();
+        // Outputs of the replaced transactions disappear together with them, and BIP125 doesn't
+        // allow the replacement to add unconfirmed inputs.
+        let candidates = self.coin_candidates(|utxo| {
+            !outpoints.contains(&utxo.outpoint)
+                && !replaced.contains(&utxo.outpoint.txid)
+                && utxo.status.is_mined()
+        });
+        self.fund_psbt(
+            &beneficiaries,
+            &scripts,
+            inputs,
+            candidates,
+            fee_rate,
+            |weight| {
+                cmp::max(
+                    fee_rate.fee_for_weight(weight),
+                    replaced_fee + FeeRate::MIN_RELAY.fee_for_weight(weight),
+                )
+            },
+            selector,
+            params,
+        )
+        .map_err(BumpError::from)
+    }
+
+    /// Constructs PSBT spending an unconfirmed wallet output with a fee high enough for the
+    /// package of the parent transaction, its unconfirmed ancestors known to the wallet and the
+    /// new child transaction to have the given fee rate (CPFP).
+    ///
+    /// The whole value of the output, less the fee, is sent to a new change address. If the
+    /// output value doesn't cover the fee, additional inputs are selected from the confirmed
+    /// wallet outputs using the provided coin selection algorithm; unconfirmed outputs are not
+    /// used, since their ancestors would join the package.
+    pub fn cpfp(
+        &mut self,
+        outpoint: Outpoint,
+        fee_rate: FeeRate,
+        selector: &mut impl CoinSelector,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), CpfpError> {
+        let utxo = self.cache.utxo(outpoint)?;
+        match utxo.state {
+            UtxoStatus::Unconfirmed => {}
+            UtxoStatus::Confirmed => return Err(CpfpError::Confirmed(outpoint)),
+            UtxoStatus::SpentUnconfirmed | UtxoStatus::SpentConfirmed => {
+                return Err(CpfpError::Spent(outpoint))
+            }
+        }
+        // The parent is accelerated together with all its unconfirmed ancestors.
+        let mut package = self.ancestors(outpoint.txid);
+        package.insert(outpoint.txid);
+        let (package_fee, package_weight) =
+            package.iter().fold((Sats::ZERO, 0u32), |(fee, weight), txid| {
+                let tx = &self.cache.tx[txid];
+                (fee.saturating_add(tx.fee), weight + tx.weight)
+            });
+        let parent_rate = FeeRate::from_fee(package_fee, package_weight);
+        if fee_rate.sat_per_vb() <= parent_rate.sat_per_vb() {
+            return Err(CpfpError::FeeRateTooLow {
+                parent: parent_rate,
+                requested: fee_rate,
+            });
+        }
+
+        let mut params = TxParams::with(Sats::ZERO);
+        params.seq_no = SEQ_NO_RBF;
+        params.change_keychain = Keychain::INNER;
+
+        let input = self.candidate(utxo);
+        let candidates =
+            self.coin_candidates(|utxo| utxo.outpoint != outpoint && utxo.status.is_mined());
+        self.fund_psbt(
+            &[],
+            &[],
+            vec![input],
+            candidates,
+            fee_rate,
+            |weight| {
+                let total_fee = fee_rate.fee_for_weight(package_weight + weight);
+                cmp::max(
+                    total_fee.checked_sub(package_fee).unwrap_or_default(),
+                    FeeRate::MIN_RELAY.fee_for_weight(weight),
+                )
+            },
+            selector,
+            params,
+        )
+        .map_err(CpfpError::from)
+    }
+
+    /// Returns ids of all unconfirmed wallet transactions whose outputs are spent by the given
+    /// transaction directly or indirectly.
+    fn ancestors(&self, txid: Txid) -> BTreeSet<Txid> {
+        let mut ancestors = BTreeSet::new();
+        let mut queue = vec![txid];
+        while let Some(txid) = queue.pop() {
+            let Some(tx) = self.cache.tx.get(&txid) else {
+                continue;
+            };
+            for credit in &tx.inputs {
+                let parent = credit.outpoint.txid;
+                let unconfirmed =
+                    self.cache.tx.get(&parent).map(|tx| !tx.status.is_mined()).unwrap_or_default();
+                if unconfirmed && ancestors.insert(parent) {
+                    queue.push(parent);
+                }
+            }
+        }
+        ancestors
+    }
+
+    /// Returns ids of all unconfirmed wallet transactions which spend outputs of the given
+    /// transaction directly or indirectly.
+    fn descendants(&self, txid: Txid) -> BTreeSet<Txid> {
+        let mut descendants = BTreeSet::new();
+        let mut queue = vec![txid];
+        while let Some(txid) = queue.pop() {
+            let Some(tx) = self.cache.tx.get(&txid) else {
+                continue;
+            };
+            for spend in tx.outputs.iter().flat_map(|debit| &debit.spends) {
+                let child = spend.inpoint.txid;
+                if spend.is_live() && !spend.status.is_mined() && descendants.insert(child) {
+                    queue.push(child);
+                }
+            }
+        }
+        descendants
+    }
+
+    /// Constructs PSBT spending all the `fixed` inputs, adding more inputs from the `candidates`
+    /// when needed, such that the transaction pays at least `min_fee` for its estimated weight.
+    ///
+    /// Besides the beneficiaries, the transaction pays to the `scripts` outputs, which can't be
+    /// represented with an address.
+    #[allow(clippy::too_many_arguments)]
+    fn fund_psbt(
+        &mut self,
+        beneficiaries: &[&Beneficiary],
+        scripts: &[TxOut],
+        fixed: Vec<Candidate>,
+        candidates: Vec<Candidate>,
+        fee_rate: FeeRate,
+        min_fee: impl Fn(u32) -> Sats,
+        selector: &mut impl CoinSelector,
+        mut params: TxParams,
+    ) -> Result<(Psbt, PsbtMeta, FeeInfo), PaymentError> {
+        let scripts_value = scripts.iter().map(|txout| txout.value).sum::<Sats>();
+        let target = beneficiaries
+            .iter()
+            .map(|b| b.amount.unwrap_or(Sats::ZERO))
+            .fold(scripts_value, |sum, amount| sum.saturating_add(amount));
+        let sweep = beneficiaries.iter().any(|b| b.is_max());
+        let outputs_weight =
+            beneficiaries.iter().map(|b| txout_weight(&b.script_pubkey())).sum::<u32>()
+                + scripts.iter().map(|txout| txout_weight(&txout.script_pubkey)).sum::<u32>();
+        let fixed_value = fixed.iter().map(Candidate::value).sum::<Sats>();
+        let fixed_weight = fixed.iter().map(|c| c.weight).sum::<u32>();
+
+        let descriptor = &self.descr.generator;
+        // The coin selection has to cover only the part of the payment and fee which is not
+        // covered by the fixed inputs; thus we start from an empty selection and increase the
+        // selection target by the shortfall on each iteration.
+        let mut selection_params = self.selection_params(Sats::ZERO, fee_rate);
+
+        let mut selection = Selection::default();
+        if sweep {
+            selection.inputs =
+                candidates.iter().filter(|c| c.effective_value(fee_rate) > 0).copied().collect();
+        }
+        let mut required = target;
+        let mut result = None;
+        for _ in 0..FEE_ITERATIONS {
+            let value = fixed_value + selection.value();
+            let weight = tx_overhead_weight(
+                descriptor,
+                fixed.len() + selection.inputs.len(),
+                beneficiaries.len() + scripts.len(),
+            ) + outputs_weight
+                + fixed_weight
+                + selection.weight();
+            let fee = min_fee(weight);
+            required = target + fee;
+            let Some(excess) = value.checked_sub(required) else {
+                if sweep {
+                    break;
+                }
+                selection_params.fixed_fee += required - value;
+                match selector.select(&candidates, &selection_params) {
+                    Ok(s) => selection = s,
+                    Err(SelectionError::InsufficientFunds { .. }) => break,
+                    Err(err) => return Err(err.into()),
+                }
+                continue;
+            };
+            if sweep {
+                result = Some((selection, fee));
+                break;
+            }
+            // Change is added by the PSBT constructor only if it exceeds the dust limit;
+            // otherwise the whole excess goes to the fee.
+            let change_fee = min_fee(weight + selection_params.change_weight);
+            match value.checked_sub(target + change_fee) {
+                Some(change) if change > descriptor.class().dust_limit() => {
+                    result = Some((selection, change_fee))
+                }
+                _ => result = Some((selection, fee + excess)),
+            }
+            break;
+        }
+        let Some((selection, fee)) = result else {
+            return Err(SelectionError::InsufficientFunds {
+                available: fixed_value + candidates.iter().map(Candidate::value).sum::<Sats>(),
+                required,
+            }
+            .into());
+        };
+
+        // The constructor doesn't know about the script outputs, so their value is reserved as a
+        // part of the fee and they are added after the change.
+        params.fee = fee + scripts_value;
+        let outpoints = fixed.iter().chain(&selection.inputs).map(Candidate::outpoint);
+        let (mut psbt, meta) =
+            self.construct_psbt(outpoints, beneficiaries.iter().copied(), params)?;
+        self.complete_psbt(&mut psbt, &meta);
+        for txout in scripts {
+            psbt.construct_output_expect(txout.script_pubkey.clone(), txout.value);
+        }
+        let fee = FeeInfo {
+            fee: psbt.fee().unwrap_or(fee),
+            weight: self.estimate_weight(&psbt),
+        };
+        Ok((psbt, meta, fee))
+    }
+
+    /// Adds to the PSBT constructed from the wallet outputs the data which are not provided by
+    /// the PSBT constructor, like taproot script trees.
+    fn complete_psbt(&self, psbt: &mut Psbt, meta: &PsbtMeta) {
+        let descriptor = &self.descr.generator;
+        for input in psbt.inputs_mut() {
+            if let Ok(utxo) = self.cache.utxo(input.previous_outpoint) {
+                descriptor.complete_input(input, utxo.terminal);
+            }
+            if !descriptor.is_segwit() {
+                input.non_witness_tx =
+                    self.cache.tx.get(&input.previous_outpoint.txid).and_then(WalletTx::to_tx);
+            }
+        }
+        if let (Some(vout), Some(terminal)) = (meta.change_vout, meta.change_terminal) {
+            if let Some(output) = psbt.output_mut(vout.into_usize()) {
+                descriptor.complete_output(output, terminal);
+            }
+        }
     }
 }
 
@@ -650,3 +1918,691 @@ impl<K, D: Descriptor<K>, L2: Layer2> Save for Wallet<K, D, L2> {
         panic!("Attempt to save wallet with no file system support during compilation");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bpstd::{ScriptPubkey, Tx, TxOut, XpubDerivable};
+
+    use super::*;
+    use crate::indexers::memory::MemoryIndexer;
+    use crate::testing::{self, TestWallet};
+    use crate::{AnyDescr, Policy, PolicyTarget};
+
+    fn foreign() -> ScriptPubkey { ScriptPubkey::from_unsafe(vec![0x51]) }
+
+    fn state(wallet: &TestWallet, outpoint: Outpoint) -> Option<UtxoStatus> {
+        wallet.all_utxos().find(|utxo| utxo.outpoint == outpoint).map(|utxo| utxo.state)
+    }
+
+    #[test]
+    fn utxo_states() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script =
+            |wallet: &mut TestWallet| wallet.next_address(Keychain::OUTER, true).script_pubkey();
+
+        let confirmed = indexer.fund(script(&mut wallet), Sats::from_btc(1));
+        let spent_confirmed = indexer.fund(script(&mut wallet), Sats::from_btc(1));
+        let spent_unconfirmed = indexer.fund(script(&mut wallet), Sats::from_btc(1));
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        indexer
+            .publish(&testing::tx([spent_confirmed], [TxOut::new(foreign(), Sats::from_btc(1))]))
+            .unwrap();
+        indexer.mine();
+        indexer
+            .publish(&testing::tx([spent_unconfirmed], [TxOut::new(foreign(), Sats::from_btc(1))]))
+            .unwrap();
+        let incoming = testing::tx([funding], [TxOut::new(script(&mut wallet), Sats(1000))]);
+        indexer.publish(&incoming).unwrap();
+        let unconfirmed = Outpoint::new(incoming.txid(), 0);
+        wallet.update(&indexer).into_result().unwrap();
+
+        assert_eq!(state(&wallet, confirmed), Some(UtxoStatus::Confirmed));
+        assert_eq!(state(&wallet, unconfirmed), Some(UtxoStatus::Unconfirmed));
+        assert_eq!(state(&wallet, spent_unconfirmed), Some(UtxoStatus::SpentUnconfirmed));
+        assert_eq!(state(&wallet, spent_confirmed), Some(UtxoStatus::SpentConfirmed));
+        assert_eq!(state(&wallet, funding), None);
+        assert_eq!(wallet.all_utxos().count(), 4);
+
+        let spendable = wallet.utxos().map(|utxo| utxo.outpoint).collect::<BTreeSet<_>>();
+        assert_eq!(spendable, bset![confirmed, unconfirmed]);
+        assert_eq!(wallet.balance(), Sats(100_001_000));
+        assert_eq!(wallet.confirmed_balance(), Sats::from_btc(1));
+    }
+
+    /// Publishes and syncs a wallet transaction spending `input` to a foreign script and a change
+    /// output. Returns the transaction and the change outpoint.
+    fn spend(
+        wallet: &mut TestWallet,
+        indexer: &MemoryIndexer,
+        input: Outpoint,
+        payments: impl IntoIterator<Item = TxOut>,
+        change: Sats,
+    ) -> (Txid, Outpoint) {
+        let change_script = wallet.next_address(Keychain::INNER, true).script_pubkey();
+        let mut outputs = payments.into_iter().collect::<Vec<_>>();
+        outputs.push(TxOut::new(change_script, change));
+        let tx = testing::tx([input], outputs.clone());
+        indexer.publish(&tx).unwrap();
+        wallet.update(indexer).into_result().unwrap();
+        (tx.txid(), Outpoint::new(tx.txid(), outputs.len() as u32 - 1))
+    }
+
+    #[test]
+    fn bump_excludes_replaced_outputs() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let extra = indexer.fund(script, Sats(900_000));
+        wallet.update(&indexer).into_result().unwrap();
+
+        let payment = TxOut::new(foreign(), Sats(99_000_000));
+        let (txid, change) = spend(&mut wallet, &indexer, coin, [payment], Sats(990_000));
+        let (_, child_change) = spend(&mut wallet, &indexer, change, [], Sats(980_000));
+        assert_eq!(state(&wallet, child_change), Some(UtxoStatus::Unconfirmed));
+
+        // The original input can't pay the fee, and the largest spendable output is created by
+        // the descendant transaction, which is replaced together with the original one.
+        let fee_rate = FeeRate::from_sat_per_vb(11_500.0);
+        let (psbt, _, fee) = wallet.bump_fee(txid, fee_rate, &mut LargestFirst).unwrap();
+        let inputs = psbt.inputs().map(|input| input.previous_outpoint).collect::<BTreeSet<_>>();
+        assert_eq!(inputs, bset![coin, extra]);
+        assert_eq!(fee.fee, Sats(1_900_000));
+    }
+
+    #[test]
+    fn bump_skips_unconfirmed_inputs() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+
+        let payment = TxOut::new(foreign(), Sats(99_000_000));
+        let (txid, _) = spend(&mut wallet, &indexer, coin, [payment], Sats(990_000));
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let incoming = testing::tx([funding], [TxOut::new(script, Sats(900_000))]);
+        indexer.publish(&incoming).unwrap();
+        wallet.update(&indexer).into_result().unwrap();
+        let extra = Outpoint::new(incoming.txid(), 0);
+        assert_eq!(state(&wallet, extra), Some(UtxoStatus::Unconfirmed));
+
+        // The original input can't pay the fee, and the only other wallet output is unconfirmed,
+        // which BIP125 doesn't allow to be added to the replacement.
+        let fee_rate = FeeRate::from_sat_per_vb(11_500.0);
+        assert!(matches!(
+            wallet.bump_fee(txid, fee_rate, &mut LargestFirst),
+            Err(BumpError::Payment(PaymentError::CoinSelection(
+                SelectionError::InsufficientFunds { .. }
+            )))
+        ));
+
+        indexer.add_block(vec![incoming]).unwrap();
+        wallet.update(&indexer).into_result().unwrap();
+        let (psbt, _, _) = wallet.bump_fee(txid, fee_rate, &mut LargestFirst).unwrap();
+        let inputs = psbt.inputs().map(|input| input.previous_outpoint).collect::<BTreeSet<_>>();
+        assert_eq!(inputs, bset![coin, extra]);
+    }
+
+    #[test]
+    fn replacement_history() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+
+        let payment = TxOut::new(foreign(), Sats(50_000_000));
+        let (original, change) = spend(&mut wallet, &indexer, coin, [payment], Sats(49_990_000));
+        let (child, _) = spend(&mut wallet, &indexer, change, [], Sats(49_980_000));
+        assert_eq!(wallet.balance(), Sats(49_980_000));
+
+        let payment = TxOut::new(foreign(), Sats(50_000_000));
+        let (replacement, change) = spend(&mut wallet, &indexer, coin, [payment], Sats(49_950_000));
+
+        let txes = wallet.transactions();
+        assert_eq!(txes[&original].replaced_by, Some(replacement));
+        assert_eq!(txes[&child].replaced_by, Some(replacement));
+        assert_eq!(txes[&replacement].replaced_by, None);
+        assert_eq!(state(&wallet, change), Some(UtxoStatus::Unconfirmed));
+        assert_eq!(wallet.balance(), Sats(49_950_000));
+        assert_eq!(wallet.confirmed_balance(), Sats::ZERO);
+
+        let history = wallet.history().map(|row| (row.txid, row)).collect::<BTreeMap<_, _>>();
+        assert_eq!(history[&original].replaced_by, Some(replacement));
+        assert_eq!(history[&child].replaced_by, Some(replacement));
+        assert_eq!(history[&replacement].replaced_by, None);
+        // Replaced transactions don't affect the running balance.
+        let last = wallet.history().last().unwrap();
+        assert_eq!(last.balance, 49_950_000);
+
+        indexer.mine();
+        wallet.update(&indexer).into_result().unwrap();
+        assert_eq!(wallet.transactions()[&original].replaced_by, Some(replacement));
+        assert_eq!(wallet.confirmed_balance(), Sats(49_950_000));
+    }
+
+    #[test]
+    fn replacement_conflict_sets() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let first = indexer.fund(script, Sats::from_btc(1));
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let second = indexer.fund(script, Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+
+        // `both` conflicts with `high` on the first coin and with `low` on the second one. It
+        // loses to `high`, and thus can't replace `low`, even though it pays more.
+        let payment = TxOut::new(foreign(), Sats(199_990_000));
+        let tx = testing::tx([first, second], [payment]);
+        indexer.publish(&tx).unwrap();
+        wallet.update(&indexer).into_result().unwrap();
+        let both = tx.txid();
+        let payment = TxOut::new(foreign(), Sats(99_000_000));
+        let (high, _) = spend(&mut wallet, &indexer, first, [payment], Sats(980_000));
+        let payment = TxOut::new(foreign(), Sats(99_000_000));
+        let (low, _) = spend(&mut wallet, &indexer, second, [payment], Sats(999_000));
+
+        // Different servers may have seen different conflicting transactions.
+        wallet.cache.tx.get_mut(&both).unwrap().status = TxStatus::Mempool;
+        wallet.cache.reindex(wallet.network());
+
+        let txes = wallet.transactions();
+        assert_eq!(txes[&both].replaced_by, Some(high));
+        assert_eq!(txes[&high].replaced_by, None);
+        assert_eq!(txes[&low].replaced_by, None);
+        assert_eq!(wallet.balance(), Sats(980_000 + 999_000));
+    }
+
+    #[test]
+    fn cpfp_package_fee_rate() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let extra = indexer.fund(script, Sats::from_btc(1));
+        let funding = indexer.fund(foreign(), Sats::from_btc(3));
+        wallet.update(&indexer).into_result().unwrap();
+
+        // Unconfirmed grandparent and parent paying the minimal fee, and an unconfirmed incoming
+        // output which must not be used by the child.
+        let payment = TxOut::new(foreign(), Sats(50_000_000));
+        let (grandparent, change) = spend(&mut wallet, &indexer, coin, [payment], Sats(49_999_900));
+        let payment = TxOut::new(foreign(), Sats(49_989_800));
+        let (parent, outpoint) = spend(&mut wallet, &indexer, change, [payment], Sats(10_000));
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let incoming = testing::tx([funding], [TxOut::new(script, Sats::from_btc(2))]);
+        indexer.publish(&incoming).unwrap();
+        wallet.update(&indexer).into_result().unwrap();
+
+        let fee_rate = FeeRate::from_sat_per_vb(100.0);
+        let (psbt, _, fee) = wallet.cpfp(outpoint, fee_rate, &mut LargestFirst).unwrap();
+        let inputs = psbt.inputs().map(|input| input.previous_outpoint).collect::<BTreeSet<_>>();
+        assert_eq!(inputs, bset![outpoint, extra]);
+
+        let package = [grandparent, parent].map(|txid| &wallet.transactions()[&txid]);
+        let package_fee = package.iter().map(|tx| tx.fee).sum::<Sats>() + fee.fee;
+        let package_weight = package.iter().map(|tx| tx.weight).sum::<u32>() + fee.weight;
+        let package_rate = FeeRate::from_fee(package_fee, package_weight);
+        assert!(package_rate.sat_per_vb() >= fee_rate.sat_per_vb());
+        assert!(package_rate.sat_per_vb() < fee_rate.sat_per_vb() + 1.0);
+        // The child alone pays much more, since it accelerates both ancestors.
+        assert!(fee.fee_rate().sat_per_vb() > 2.0 * fee_rate.sat_per_vb());
+    }
+
+    #[test]
+    fn bump_keeps_script_outputs() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+
+        let op_return = ScriptPubkey::from_unsafe(vec![0x6a, 0x04, 0xde, 0xad, 0xbe, 0xef]);
+        let payments =
+            [TxOut::new(op_return.clone(), Sats::ZERO), TxOut::new(foreign(), Sats(50_000_000))];
+        let (txid, _) = spend(&mut wallet, &indexer, coin, payments, Sats(49_990_000));
+
+        let fee_rate = FeeRate::from_sat_per_vb(100.0);
+        let (psbt, meta, fee) = wallet.bump_fee(txid, fee_rate, &mut LargestFirst).unwrap();
+        let outputs =
+            psbt.outputs().map(|out| (out.script.clone(), out.amount)).collect::<Vec<_>>();
+        assert_eq!(outputs.len(), 3);
+        assert!(outputs.contains(&(op_return, Sats::ZERO)));
+        assert!(outputs.contains(&(foreign(), Sats(50_000_000))));
+        let change = meta.change_vout.expect("change output").into_usize();
+        assert_eq!(outputs[change].1, Sats::from_btc(1) - Sats(50_000_000) - fee.fee);
+        assert_eq!(psbt.fee(), Some(fee.fee));
+        assert!(fee.fee > Sats(10_000));
+    }
+
+    /// Indexer which fails all the requests.
+    struct FailingIndexer;
+
+    impl Indexer for FailingIndexer {
+        type Error = &'static str;
+
+        fn create<K, D: Descriptor<K>, L2: Layer2>(
+            &self,
+            _descr: &WalletDescr<K, D, L2::Descr>,
+        ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+            MayError::err(WalletCache::new(), vec!["connection failure"])
+        }
+
+        fn update<K, D: Descriptor<K>, L2: Layer2>(
+            &self,
+            _descr: &WalletDescr<K, D, L2::Descr>,
+            _cache: &mut WalletCache<L2::Cache>,
+        ) -> MayError<usize, Vec<Self::Error>> {
+            MayError::err(0, vec!["connection failure"])
+        }
+
+        fn reorg<K, D: Descriptor<K>, L2: Layer2>(
+            &self,
+            _descr: &WalletDescr<K, D, L2::Descr>,
+            _cache: &mut WalletCache<L2::Cache>,
+        ) -> MayError<BTreeSet<Txid>, Vec<Self::Error>> {
+            MayError::err(none!(), vec!["reorg failure"])
+        }
+
+        fn publish(&self, _tx: &Tx) -> Result<(), Self::Error> { Err("connection failure") }
+    }
+
+    #[test]
+    fn update_reports_reorgs() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let funding = indexer.fund(foreign(), Sats::from_btc(1));
+        let tx = testing::tx([funding], [TxOut::new(script, Sats(1000))]);
+        indexer.add_block(vec![tx.clone()]).unwrap();
+        let info = wallet.update(&indexer).into_result().unwrap();
+        assert_eq!(info, UpdateInfo {
+            changed: 1,
+            reorged: none!()
+        });
+
+        // The transaction returns to the mempool after the reorganization
+        indexer.disconnect(1).unwrap();
+        let info = wallet.update(&indexer).into_result().unwrap();
+        assert_eq!(info, UpdateInfo {
+            changed: 1,
+            reorged: bset![tx.txid()]
+        });
+        assert_eq!(wallet.transactions()[&tx.txid()].status, TxStatus::Mempool);
+
+        let info = wallet.update(&indexer).into_result().unwrap();
+        assert_eq!(info, UpdateInfo::default());
+    }
+
+    #[test]
+    fn update_stops_on_reorg_errors() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        indexer.fund(script, Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+        wallet.dirty = false;
+
+        // The update itself is not attempted, so only the reorg error is reported
+        let MayError { ok, err } = wallet.update(&FailingIndexer);
+        assert_eq!(ok, UpdateInfo::default());
+        assert_eq!(err, Some(vec!["reorg failure"]));
+        assert!(!wallet.dirty);
+        assert_eq!(wallet.balance(), Sats::from_btc(1));
+    }
+
+    #[test]
+    fn coin_control() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let addr = wallet.next_address(Keychain::OUTER, true);
+        let first = indexer.fund(addr.script_pubkey(), Sats::from_btc(1));
+        let second = indexer.fund(addr.script_pubkey(), Sats::from_btc(2));
+        let other_addr = wallet.next_address(Keychain::OUTER, true);
+        let other = indexer.fund(other_addr.script_pubkey(), Sats::from_btc(3));
+        wallet.update(&indexer).into_result().unwrap();
+
+        let to = wallet.next_address(Keychain::OUTER, true);
+        let inputs = |wallet: &mut TestWallet, amount: Sats, control: CoinControl| {
+            let beneficiary = Beneficiary::new(to, amount);
+            wallet
+                .construct_psbt_fee_rate(
+                    [&beneficiary],
+                    FeeRate::MIN_RELAY,
+                    &mut LargestFirst,
+                    &control,
+                    default!(),
+                )
+                .map(|(psbt, ..)| {
+                    psbt.inputs().map(|input| input.previous_outpoint).collect::<BTreeSet<_>>()
+                })
+        };
+
+        assert!(wallet.freeze(first).unwrap());
+        assert!(wallet.is_frozen(first));
+        let frozen = wallet.coins().filter(|row| row.frozen).map(|row| row.outpoint);
+        assert_eq!(frozen.collect::<Vec<_>>(), vec![first]);
+        let candidates = wallet.coin_candidates(|_| true);
+        assert!(candidates.iter().all(|candidate| candidate.outpoint() != first));
+
+        // Frozen outputs are skipped when all outputs on an address are included, but can't be
+        // included explicitly.
+        let control = CoinControl {
+            include_addrs: bset![addr],
+            manual: true,
+            ..default!()
+        };
+        assert_eq!(inputs(&mut wallet, Sats(10_000), control).unwrap(), bset![second]);
+        assert!(matches!(
+            inputs(&mut wallet, Sats(10_000), CoinControl::manual([first])),
+            Err(PaymentError::FrozenCoin(outpoint)) if outpoint == first
+        ));
+
+        // The same applies to the excluded outputs.
+        assert!(wallet.unfreeze(first));
+        let control = CoinControl {
+            include_addrs: bset![addr],
+            exclude: bset![second],
+            manual: true,
+            ..default!()
+        };
+        assert_eq!(inputs(&mut wallet, Sats(10_000), control).unwrap(), bset![first]);
+        let control = CoinControl {
+            include: bset![first],
+            exclude: bset![first],
+            ..default!()
+        };
+        assert!(matches!(
+            inputs(&mut wallet, Sats(10_000), control),
+            Err(PaymentError::ExcludedCoin(outpoint)) if outpoint == first
+        ));
+
+        // Coin selection doesn't use frozen and excluded addresses.
+        assert!(wallet.freeze_addr(other_addr));
+        assert!(wallet.is_frozen(other));
+        let amount = Sats::from_btc(2) + Sats(50_000_000);
+        assert_eq!(inputs(&mut wallet, amount, CoinControl::auto()).unwrap(), bset![first, second]);
+        assert!(wallet.unfreeze_addr(&other_addr));
+        let control = CoinControl {
+            exclude_addrs: bset![other_addr],
+            ..default!()
+        };
+        assert_eq!(inputs(&mut wallet, amount, control).unwrap(), bset![first, second]);
+        assert_eq!(inputs(&mut wallet, amount, CoinControl::auto()).unwrap(), bset![other]);
+    }
+
+    #[test]
+    fn tx_options() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        indexer.fund(script, Sats::from_btc(1));
+        indexer.mine_empty(9);
+        wallet.update(&indexer).into_result().unwrap();
+
+        let address = wallet.next_address(Keychain::OUTER, true);
+        let beneficiary = Beneficiary::new(address, Sats(10_000));
+        let lock_time = LockTime::from_height(100).unwrap();
+        assert!(!wallet.is_lock_time_reached(lock_time));
+        assert!(wallet.is_lock_time_reached(LockTime::from_height(10).unwrap()));
+        let options = TxOptions {
+            lock_time: Some(lock_time),
+            tx_version: TxVer::V1,
+            ..default!()
+        };
+        let (psbt, _, _) = wallet
+            .construct_psbt_fee_rate(
+                [&beneficiary],
+                FeeRate::MIN_RELAY,
+                &mut LargestFirst,
+                &CoinControl::auto(),
+                options,
+            )
+            .unwrap();
+        assert_eq!(psbt.tx_version, TxVer::V1);
+        assert_eq!(psbt.fallback_locktime, Some(lock_time));
+        assert!(psbt.inputs().all(|input| input.sequence_number == Some(SEQ_NO_RBF)));
+
+        let options = TxOptions {
+            tx_version: TxVer::from_consensus_i32(3),
+            ..default!()
+        };
+        assert!(matches!(
+            wallet.construct_psbt_fee_rate(
+                [&beneficiary],
+                FeeRate::MIN_RELAY,
+                &mut LargestFirst,
+                &CoinControl::auto(),
+                options,
+            ),
+            Err(PaymentError::TxOptions(TxOptionsError::NonStandardVersion(3)))
+        ));
+    }
+
+    #[test]
+    fn label_filters() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let external = |byte: u8| {
+            let mut script = vec![0x00, 0x14];
+            script.extend([byte; 20]);
+            ScriptPubkey::from_unsafe(script)
+        };
+        let received = wallet.next_address(Keychain::OUTER, true);
+        let saved = wallet.next_address(Keychain::OUTER, true);
+        let coin = indexer.fund(external(1), Sats::from_btc(3));
+        let funding = testing::tx([coin], [
+            TxOut::new(received.script_pubkey(), Sats::from_btc(1)),
+            TxOut::new(saved.script_pubkey(), Sats::from_btc(1)),
+            TxOut::new(external(1), Sats(99_990_000)),
+        ]);
+        indexer.publish(&funding).unwrap();
+        indexer.mine();
+        wallet.update(&indexer).into_result().unwrap();
+        let (spending, change) = spend(
+            &mut wallet,
+            &indexer,
+            Outpoint::new(funding.txid(), 0),
+            [TxOut::new(external(2), Sats(50_000_000))],
+            Sats(49_990_000),
+        );
+        let stored = Outpoint::new(funding.txid(), 1);
+        let payee = Address::with(&external(2), AddressNetwork::Regtest).unwrap();
+
+        let tx_row = |txid: Txid| wallet.history().find(|row| row.txid == txid).unwrap();
+        let coin_row = |outpoint: Outpoint| wallet.coins().find(|row| row.outpoint == outpoint);
+        let (funding_row, spending_row) = (tx_row(funding.txid()), tx_row(spending));
+        let (stored_row, change_row) = (coin_row(stored).unwrap(), coin_row(change).unwrap());
+
+        // transaction labels are matched ignoring the case and inherited by its outputs
+        wallet.set_label(LabelRef::Tx(funding.txid()), "Salary");
+        assert!(wallet.is_tx_labelled_with(&funding_row, "salary"));
+        assert!(!wallet.is_tx_labelled_with(&spending_row, "salary"));
+        assert!(wallet.is_coin_labelled_with(&stored_row, "SAL"));
+        assert!(!wallet.is_coin_labelled_with(&change_row, "salary"));
+
+        // address labels are inherited by the transactions involving the address and its coins
+        wallet.set_label(LabelRef::Addr(saved), "cold storage");
+        assert!(wallet.is_tx_labelled_with(&funding_row, "storage"));
+        assert!(!wallet.is_tx_labelled_with(&spending_row, "storage"));
+        assert!(wallet.is_coin_labelled_with(&stored_row, "storage"));
+        assert!(!wallet.is_coin_labelled_with(&change_row, "storage"));
+        wallet.set_label(LabelRef::Addr(received), "invoice #12");
+        assert!(wallet.is_tx_labelled_with(&spending_row, "#12"));
+        wallet.set_label(LabelRef::Addr(payee), "landlord");
+        assert!(wallet.is_tx_labelled_with(&spending_row, "land"));
+        assert!(!wallet.is_tx_labelled_with(&funding_row, "land"));
+
+        // output labels apply only to the coin itself
+        wallet.set_label(LabelRef::Output(change), "Change");
+        assert!(wallet.is_coin_labelled_with(&change_row, "change"));
+        assert!(!wallet.is_coin_labelled_with(&stored_row, "change"));
+        assert!(!wallet.is_tx_labelled_with(&spending_row, "change"));
+
+        assert!(!wallet.is_coin_labelled_with(&stored_row, "unknown"));
+        wallet.remove_label(&LabelRef::Tx(funding.txid()));
+        assert!(!wallet.is_coin_labelled_with(&stored_row, "salary"));
+    }
+
+    #[test]
+    fn legacy_inputs_provide_prev_tx() {
+        let mut indexer = MemoryIndexer::new();
+        let descr = AnyDescr::from_str(&format!("pkh({})", testing::xpub())).unwrap();
+        let mut wallet = Wallet::<XpubDerivable, AnyDescr>::new_layer1(descr, Network::Regtest);
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        let mut segwit = testing::wallet();
+        let script = segwit.next_address(Keychain::OUTER, true).script_pubkey();
+        indexer.fund(script, Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+        segwit.update(&indexer).into_result().unwrap();
+
+        let beneficiary =
+            Beneficiary::new(segwit.next_address(Keychain::OUTER, true), Sats(10_000));
+        let (psbt, _, _) = wallet
+            .construct_psbt_fee_rate(
+                [&beneficiary],
+                FeeRate::MIN_RELAY,
+                &mut LargestFirst,
+                &CoinControl::auto(),
+                default!(),
+            )
+            .unwrap();
+        let input = psbt.inputs().next().unwrap();
+        assert_eq!(input.previous_outpoint, coin);
+        assert!(input.non_witness_tx.is_some());
+        assert_eq!(input.non_witness_tx, indexer.tx(coin.txid));
+
+        let (psbt, _, _) = segwit
+            .construct_psbt_fee_rate(
+                [&beneficiary],
+                FeeRate::MIN_RELAY,
+                &mut LargestFirst,
+                &CoinControl::auto(),
+                default!(),
+            )
+            .unwrap();
+        assert!(psbt.inputs().all(|input| input.non_witness_tx.is_none()));
+    }
+
+    #[test]
+    fn timelocked_spending() {
+        let keys = bmap! { s!("A") => testing::xpub(), s!("B") => testing::xpub() };
+        let policy = Policy::from_str("or(pk(A),and(pk(B),older(10)))").unwrap();
+        let descr = policy.compile(&keys, PolicyTarget::Wsh).unwrap();
+        let mut wallet = Wallet::<XpubDerivable, AnyDescr>::new_layer1(descr, Network::Regtest);
+        let mut indexer = MemoryIndexer::new();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        indexer.mine_empty(8);
+        wallet.update(&indexer).into_result().unwrap();
+
+        let address = wallet.next_address(Keychain::OUTER, true);
+        let beneficiary = Beneficiary::new(address, Sats(10_000));
+        let construct = |wallet: &mut Wallet<XpubDerivable, AnyDescr>, options, control| {
+            wallet.construct_psbt_fee_rate(
+                [&beneficiary],
+                FeeRate::MIN_RELAY,
+                &mut LargestFirst,
+                &control,
+                options,
+            )
+        };
+        let options = TxOptions {
+            timelock: Some(Timelock::Older(10)),
+            ..default!()
+        };
+        let utxo = wallet.utxos().next().unwrap();
+        assert!(!wallet.is_timelock_expired(&utxo, Timelock::Older(10)));
+        assert!(wallet.is_timelock_expired(&utxo, Timelock::Older(9)));
+        assert!(matches!(
+            construct(&mut wallet, options, CoinControl::auto()),
+            Err(PaymentError::CoinSelection(SelectionError::InsufficientFunds { .. }))
+        ));
+        let control = CoinControl {
+            include: bset![coin],
+            ..default!()
+        };
+        assert!(matches!(
+            construct(&mut wallet, options, control),
+            Err(PaymentError::ImmatureCoin(outpoint, Timelock::Older(10))) if outpoint == coin
+        ));
+
+        indexer.mine_empty(1);
+        wallet.update(&indexer).into_result().unwrap();
+        let (psbt, _, _) = construct(&mut wallet, options, CoinControl::auto()).unwrap();
+        let seq_no = SeqNo::from_consensus_u32(10);
+        assert!(psbt.inputs().all(|input| input.sequence_number == Some(seq_no)));
+        assert!(psbt.inputs().all(|input| input.witness_script.is_some()));
+
+        let options = TxOptions {
+            timelock: Some(Timelock::Older(20)),
+            ..default!()
+        };
+        assert!(matches!(
+            construct(&mut wallet, options, CoinControl::auto()),
+            Err(PaymentError::TxOptions(TxOptionsError::UnknownTimelock(Timelock::Older(20))))
+        ));
+        let options = TxOptions {
+            timelock: Some(Timelock::Older(10)),
+            seq_no: Some(SeqNo::from_consensus_u32(5)),
+            ..default!()
+        };
+        assert!(matches!(
+            construct(&mut wallet, options, CoinControl::auto()),
+            Err(PaymentError::TxOptions(TxOptionsError::TimelockConflict(_)))
+        ));
+    }
+
+    #[test]
+    fn absolute_timelock() {
+        let keys = bmap! { s!("A") => testing::xpub() };
+        let policy = Policy::from_str("and(pk(A),after(100))").unwrap();
+        let descr = policy.compile(&keys, PolicyTarget::Tr).unwrap();
+        let wallet = Wallet::<XpubDerivable, AnyDescr>::new_layer1(descr, Network::Regtest);
+
+        let options = TxOptions {
+            timelock: Some(Timelock::After(100)),
+            ..default!()
+        };
+        let params = wallet.tx_params(Sats::ZERO, options).unwrap();
+        assert_eq!(params.lock_time, LockTime::from_height(100));
+        assert_eq!(params.seq_no, SEQ_NO_RBF);
+        let options = TxOptions {
+            lock_time: LockTime::from_height(50),
+            ..options
+        };
+        assert_eq!(
+            wallet.tx_params(Sats::ZERO, options).unwrap_err(),
+            TxOptionsError::TimelockConflict(Timelock::After(100))
+        );
+    }
+
+    #[test]
+    fn rescan_keeps_cache_on_errors() {
+        let mut indexer = MemoryIndexer::new();
+        let mut wallet = testing::wallet();
+        let script = wallet.next_address(Keychain::OUTER, true).script_pubkey();
+        let coin = indexer.fund(script, Sats::from_btc(1));
+        wallet.update(&indexer).into_result().unwrap();
+        wallet.dirty = false;
+
+        let errors = wallet.rescan(&FailingIndexer, Some(50)).unwrap_err();
+        assert_eq!(errors, vec!["connection failure"]);
+        assert!(!wallet.dirty);
+        assert_eq!(wallet.balance(), Sats::from_btc(1));
+        assert_eq!(wallet.utxos().map(|utxo| utxo.outpoint).collect::<Vec<_>>(), vec![coin]);
+
+        wallet.rescan(&indexer, None).into_result().unwrap();
+        assert!(wallet.dirty);
+        assert_eq!(wallet.balance(), Sats::from_btc(1));
+    }
+}